@@ -73,6 +73,7 @@ mod public_key;
 mod tests;
 
 pub mod either;
+pub mod metrics;
 pub mod muxing;
 pub mod nodes;
 pub mod protocols_handler;
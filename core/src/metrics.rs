@@ -0,0 +1,374 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Collection of metrics about the activity of the networking stack.
+//!
+//! Metrics are opt-in. In order to collect them, create a `Metrics` object, then pass clones of
+//! it to the various components that should report to it:
+//!
+//! - Wrapping a transport with `Transport::with_metrics` counts the connections opened and
+//!   closed, the substreams negotiated for each protocol, and the number of bytes exchanged with
+//!   each connected peer and for each protocol. The bytes are counted by each connection, and
+//!   only gathered when taking a snapshot.
+//! - Calling `Swarm::set_metrics` counts the dialing failures, grouped by reason.
+//!
+//! At any time, `Metrics::snapshot` returns a copy of the current values, which can be
+//! formatted in the Prometheus text format with `MetricsSnapshot::to_prometheus`.
+
+use crate::PeerId;
+use parking_lot::Mutex;
+use std::{collections::HashMap, fmt::Write, hash::Hash, sync::Arc};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Handle to a collection of metrics.
+///
+/// Cloning this object is cheap and returns a handle to the same collection.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<MetricsInner>>,
+}
+
+/// Content of a `Metrics`.
+#[derive(Debug, Default)]
+struct MetricsInner {
+    /// Values of the metrics, without the bytes exchanged on the connections that are still open.
+    totals: MetricsSnapshot,
+    /// Counters of the connections that are open. They are updated without locking the
+    /// `Metrics`, and added to the totals when the connection closes.
+    connections: Vec<Arc<ConnectionCounters>>,
+}
+
+/// Values of the metrics at a given point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Number of connections that have been opened, grouped by transport (eg. `tcp` or `ws`).
+    pub connections_opened: HashMap<String, u64>,
+    /// Number of connections that have been closed, grouped by transport.
+    pub connections_closed: HashMap<String, u64>,
+    /// Number of failed attempts to dial, grouped by reason.
+    pub dial_failures: HashMap<String, u64>,
+    /// Number of substreams that have been successfully negotiated, grouped by protocol name.
+    pub negotiated_substreams: HashMap<String, u64>,
+    /// Number of bytes exchanged, grouped by peer. Only contains the peers we are connected to;
+    /// the entry of a peer is removed once all its connections are closed.
+    pub peer_bandwidth: HashMap<PeerId, Bandwidth>,
+    /// Number of bytes exchanged on the substreams, grouped by protocol name. Includes the bytes
+    /// of the negotiation itself.
+    pub protocol_bandwidth: HashMap<String, Bandwidth>,
+}
+
+/// Number of bytes sent and received.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Bandwidth {
+    /// Number of bytes received.
+    pub inbound: u64,
+    /// Number of bytes sent.
+    pub outbound: u64,
+}
+
+/// Counters of the bytes exchanged on a connection.
+#[derive(Debug)]
+pub(crate) struct ConnectionCounters {
+    /// Peer the connection is with.
+    peer_id: PeerId,
+    /// Bytes exchanged on the connection.
+    total: AtomicBandwidth,
+    /// Bytes exchanged on the connection, grouped by protocol. Only locked when a substream has
+    /// been negotiated and when taking a snapshot.
+    protocols: Mutex<HashMap<String, Arc<AtomicBandwidth>>>,
+}
+
+/// Number of bytes sent and received, that can be updated concurrently.
+///
+/// The counters are 64 bits wide on every platform, so that they don't wrap after 4 GiB on
+/// 32-bit targets.
+#[derive(Debug, Default)]
+pub(crate) struct AtomicBandwidth {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+impl AtomicBandwidth {
+    /// Adds `bandwidth` to the counters.
+    pub(crate) fn add(&self, bandwidth: Bandwidth) {
+        self.inbound.fetch_add(bandwidth.inbound, Ordering::Relaxed);
+        self.outbound.fetch_add(bandwidth.outbound, Ordering::Relaxed);
+    }
+
+    /// Returns the current value of the counters.
+    fn load(&self) -> Bandwidth {
+        Bandwidth {
+            inbound: self.inbound.load(Ordering::Relaxed),
+            outbound: self.outbound.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Resets the counters to zero.
+    fn reset(&self) {
+        self.inbound.store(0, Ordering::Relaxed);
+        self.outbound.store(0, Ordering::Relaxed);
+    }
+}
+
+impl ConnectionCounters {
+    /// Reports bytes exchanged on the connection.
+    #[inline]
+    pub(crate) fn transferred(&self, bandwidth: Bandwidth) {
+        self.total.add(bandwidth)
+    }
+
+    /// Returns the counters of the bytes exchanged for the given protocol on the connection.
+    pub(crate) fn protocol(&self, protocol: &str) -> Arc<AtomicBandwidth> {
+        self.protocols.lock()
+            .entry(protocol.to_owned())
+            .or_insert_with(Default::default)
+            .clone()
+    }
+}
+
+impl Metrics {
+    /// Creates a new empty collection of metrics.
+    #[inline]
+    pub fn new() -> Metrics {
+        Default::default()
+    }
+
+    /// Returns a copy of the current value of the metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let inner = self.inner.lock();
+        let mut snapshot = inner.totals.clone();
+        for connection in inner.connections.iter() {
+            add_bandwidth(&mut snapshot.peer_bandwidth, &connection.peer_id, connection.total.load());
+            for (protocol, bandwidth) in connection.protocols.lock().iter() {
+                add_bandwidth(&mut snapshot.protocol_bandwidth, protocol.as_str(), bandwidth.load());
+            }
+        }
+        snapshot
+    }
+
+    /// Resets all the metrics to zero.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.totals = Default::default();
+        for connection in inner.connections.iter() {
+            connection.total.reset();
+            for bandwidth in connection.protocols.lock().values() {
+                bandwidth.reset();
+            }
+        }
+    }
+
+    /// Reports that a connection with the given peer has been opened over the given transport.
+    ///
+    /// Returns the counters of the bytes exchanged on the connection, which must be passed to
+    /// `connection_closed` once the connection is closed.
+    pub(crate) fn connection_opened(&self, transport: &str, peer_id: PeerId) -> Arc<ConnectionCounters> {
+        let counters = Arc::new(ConnectionCounters {
+            peer_id,
+            total: Default::default(),
+            protocols: Default::default(),
+        });
+
+        let mut inner = self.inner.lock();
+        increment(&mut inner.totals.connections_opened, transport);
+        inner.connections.push(counters.clone());
+        counters
+    }
+
+    /// Reports that a connection opened over the given transport has been closed.
+    pub(crate) fn connection_closed(&self, transport: &str, counters: &Arc<ConnectionCounters>) {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        increment(&mut inner.totals.connections_closed, transport);
+        inner.connections.retain(|c| !Arc::ptr_eq(c, counters));
+
+        // Only keep the bytes exchanged with the peer if it is still connected.
+        if inner.connections.iter().any(|c| c.peer_id == counters.peer_id) {
+            add_bandwidth(&mut inner.totals.peer_bandwidth, &counters.peer_id, counters.total.load());
+        } else {
+            inner.totals.peer_bandwidth.remove(&counters.peer_id);
+        }
+
+        for (protocol, bandwidth) in counters.protocols.lock().iter() {
+            add_bandwidth(&mut inner.totals.protocol_bandwidth, protocol.as_str(), bandwidth.load());
+        }
+    }
+
+    /// Reports that an attempt to dial failed for the given reason.
+    pub(crate) fn dial_failure(&self, reason: &str) {
+        increment(&mut self.inner.lock().totals.dial_failures, reason);
+    }
+
+    /// Reports that a substream has been negotiated for the given protocol.
+    pub(crate) fn substream_negotiated(&self, protocol: &str) {
+        increment(&mut self.inner.lock().totals.negotiated_substreams, protocol);
+    }
+}
+
+impl MetricsSnapshot {
+    /// Formats the metrics in the Prometheus text exposition format.
+    ///
+    /// The entries of each metric are sorted by label, so that the output is deterministic.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        write_counter(&mut out, "libp2p_connections_opened_total",
+            "Number of connections opened.", "transport", &self.connections_opened);
+        write_counter(&mut out, "libp2p_connections_closed_total",
+            "Number of connections closed.", "transport", &self.connections_closed);
+        write_counter(&mut out, "libp2p_dial_failures_total",
+            "Number of failed dialing attempts.", "reason", &self.dial_failures);
+        write_counter(&mut out, "libp2p_negotiated_substreams_total",
+            "Number of substreams negotiated.", "protocol", &self.negotiated_substreams);
+
+        let peer_bandwidth = self.peer_bandwidth.iter()
+            .map(|(peer_id, bandwidth)| (peer_id.to_base58(), *bandwidth))
+            .collect::<HashMap<_, _>>();
+        write_bandwidth(&mut out, "libp2p_peer_bytes_total",
+            "Number of bytes exchanged with each connected peer.", "peer", &peer_bandwidth);
+        write_bandwidth(&mut out, "libp2p_protocol_bytes_total",
+            "Number of bytes exchanged for each protocol.", "protocol", &self.protocol_bandwidth);
+
+        out
+    }
+}
+
+/// Adds one to the entry of `map` corresponding to `key`.
+fn increment(map: &mut HashMap<String, u64>, key: &str) {
+    if let Some(value) = map.get_mut(key) {
+        *value += 1;
+        return;
+    }
+    map.insert(key.to_owned(), 1);
+}
+
+/// Adds `bandwidth` to the entry of `map` corresponding to `key`.
+fn add_bandwidth<K, Q>(map: &mut HashMap<K, Bandwidth>, key: &Q, bandwidth: Bandwidth)
+where
+    K: Eq + Hash + std::borrow::Borrow<Q>,
+    Q: Eq + Hash + ToOwned<Owned = K> + ?Sized,
+{
+    if let Some(value) = map.get_mut(key) {
+        value.inbound += bandwidth.inbound;
+        value.outbound += bandwidth.outbound;
+        return;
+    }
+    map.insert(key.to_owned(), bandwidth);
+}
+
+/// Writes a Prometheus counter with one label.
+fn write_counter(out: &mut String, name: &str, help: &str, label: &str, values: &HashMap<String, u64>) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (key, value) in sorted(values) {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, escape_label(key), value);
+    }
+}
+
+/// Writes a Prometheus counter with one label plus a `direction` label for the bandwidth.
+fn write_bandwidth(out: &mut String, name: &str, help: &str, label: &str, values: &HashMap<String, Bandwidth>) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (key, bandwidth) in sorted(values) {
+        let key = escape_label(key);
+        let _ = writeln!(out, "{}{{{}=\"{}\",direction=\"in\"}} {}", name, label, key, bandwidth.inbound);
+        let _ = writeln!(out, "{}{{{}=\"{}\",direction=\"out\"}} {}", name, label, key, bandwidth.outbound);
+    }
+}
+
+/// Returns the entries of `map` sorted by key.
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Escapes a label value as required by the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_reflects_reports() {
+        let metrics = Metrics::new();
+        let peer_id = PeerId::random();
+        let connection1 = metrics.connection_opened("tcp", peer_id.clone());
+        let connection2 = metrics.connection_opened("tcp", peer_id.clone());
+        connection1.protocol("/foo").add(Bandwidth { inbound: 3, outbound: 5 });
+        connection1.transferred(Bandwidth { inbound: 3, outbound: 5 });
+        connection2.transferred(Bandwidth { inbound: 1, outbound: 0 });
+        metrics.connection_closed("tcp", &connection1);
+
+        let snapshot = metrics.clone().snapshot();
+        assert_eq!(snapshot.connections_opened["tcp"], 2);
+        assert_eq!(snapshot.connections_closed["tcp"], 1);
+        assert_eq!(snapshot.peer_bandwidth[&peer_id], Bandwidth { inbound: 4, outbound: 5 });
+        assert_eq!(snapshot.protocol_bandwidth["/foo"], Bandwidth { inbound: 3, outbound: 5 });
+
+        metrics.clear();
+        assert_eq!(metrics.snapshot().peer_bandwidth[&peer_id], Bandwidth::default());
+        metrics.connection_closed("tcp", &connection2);
+        let mut expected = MetricsSnapshot::default();
+        expected.connections_closed.insert("tcp".to_owned(), 1);
+        assert_eq!(metrics.snapshot(), expected);
+    }
+
+    #[test]
+    fn disconnected_peers_are_removed() {
+        let metrics = Metrics::new();
+        let peer_id = PeerId::random();
+        let connection = metrics.connection_opened("tcp", peer_id.clone());
+        connection.transferred(Bandwidth { inbound: 1, outbound: 2 });
+        connection.protocol("/foo").add(Bandwidth { inbound: 1, outbound: 2 });
+        metrics.connection_closed("tcp", &connection);
+
+        let snapshot = metrics.snapshot();
+        assert!(snapshot.peer_bandwidth.is_empty());
+        assert_eq!(snapshot.protocol_bandwidth["/foo"], Bandwidth { inbound: 1, outbound: 2 });
+    }
+
+    #[test]
+    fn counters_exceed_32_bits() {
+        let bandwidth = AtomicBandwidth::default();
+        bandwidth.add(Bandwidth { inbound: u64::from(u32::max_value()), outbound: 1 });
+        bandwidth.add(Bandwidth { inbound: 2, outbound: u64::from(u32::max_value()) });
+        let expected = u64::from(u32::max_value()) + 1;
+        assert_eq!(bandwidth.load(), Bandwidth { inbound: expected + 1, outbound: expected });
+    }
+
+    #[test]
+    fn prometheus_format() {
+        let metrics = Metrics::new();
+        metrics.dial_failure("ConnectionRefused");
+        metrics.dial_failure("banned");
+        metrics.dial_failure("ConnectionRefused");
+        metrics.substream_negotiated("/ipfs/ping/1.0.0");
+
+        let out = metrics.snapshot().to_prometheus();
+        assert!(out.contains("# TYPE libp2p_dial_failures_total counter\n\
+            libp2p_dial_failures_total{reason=\"ConnectionRefused\"} 2\n\
+            libp2p_dial_failures_total{reason=\"banned\"} 1\n"));
+        assert!(out.contains("libp2p_negotiated_substreams_total{protocol=\"/ipfs/ping/1.0.0\"} 1\n"));
+    }
+}
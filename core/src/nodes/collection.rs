@@ -30,7 +30,8 @@ use crate::{
 };
 use fnv::FnvHashMap;
 use futures::prelude::*;
-use std::{collections::hash_map::Entry, error, fmt, mem};
+use smallvec::SmallVec;
+use std::{collections::hash_map::{Entry, OccupiedEntry}, error, fmt, mem};
 
 // TODO: make generic over PeerId
 
@@ -38,9 +39,10 @@ use std::{collections::hash_map::Entry, error, fmt, mem};
 pub struct CollectionStream<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> {
     /// Object that handles the tasks.
     inner: HandledNodesTasks<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr>,
-    /// List of nodes, with the task id that handles this node. The corresponding entry in `tasks`
-    /// must always be in the `Connected` state.
-    nodes: FnvHashMap<PeerId, TaskId>,
+    /// List of nodes, with the ids of the tasks that handle the connections to this node, from
+    /// the oldest to the newest. The corresponding entries in `tasks` must always be in the
+    /// `Connected` state, and the lists are never empty.
+    nodes: FnvHashMap<PeerId, SmallVec<[TaskId; 2]>>,
     /// List of tasks and their state. If `Connected`, then a corresponding entry must be present
     /// in `nodes`.
     tasks: FnvHashMap<TaskId, TaskState>,
@@ -85,6 +87,8 @@ pub enum CollectionEvent<'a, TInEvent:'a , TOutEvent: 'a, THandler: 'a, TReachEr
     NodeClosed {
         /// Identifier of the node.
         peer_id: PeerId,
+        /// Identifier of the connection that has been closed.
+        connection_id: ConnectionId,
     },
 
     /// A connection to a node has errored.
@@ -93,6 +97,8 @@ pub enum CollectionEvent<'a, TInEvent:'a , TOutEvent: 'a, THandler: 'a, TReachEr
     NodeError {
         /// Identifier of the node.
         peer_id: PeerId,
+        /// Identifier of the connection that has errored.
+        connection_id: ConnectionId,
         /// The error that happened.
         error: HandledNodeError<THandlerErr>,
     },
@@ -111,6 +117,8 @@ pub enum CollectionEvent<'a, TInEvent:'a , TOutEvent: 'a, THandler: 'a, TReachEr
     NodeEvent {
         /// Identifier of the node.
         peer_id: PeerId,
+        /// Identifier of the connection that produced the event.
+        connection_id: ConnectionId,
         /// The produced event.
         event: TOutEvent,
     },
@@ -128,14 +136,16 @@ where TOutEvent: fmt::Debug,
                 .field(inner)
                 .finish()
             },
-            CollectionEvent::NodeClosed { ref peer_id } => {
+            CollectionEvent::NodeClosed { ref peer_id, ref connection_id } => {
                 f.debug_struct("CollectionEvent::NodeClosed")
                 .field("peer_id", peer_id)
+                .field("connection_id", connection_id)
                 .finish()
             },
-            CollectionEvent::NodeError { ref peer_id, ref error } => {
+            CollectionEvent::NodeError { ref peer_id, ref connection_id, ref error } => {
                 f.debug_struct("CollectionEvent::NodeError")
                 .field("peer_id", peer_id)
+                .field("connection_id", connection_id)
                 .field("error", error)
                 .finish()
             },
@@ -145,9 +155,10 @@ where TOutEvent: fmt::Debug,
                 .field("error", error)
                 .finish()
             },
-            CollectionEvent::NodeEvent { ref peer_id, ref event } => {
+            CollectionEvent::NodeEvent { ref peer_id, ref connection_id, ref event } => {
                 f.debug_struct("CollectionEvent::NodeEvent")
                 .field("peer_id", peer_id)
+                .field("connection_id", connection_id)
                 .field("event", event)
                 .finish()
             },
@@ -179,37 +190,23 @@ impl<'a, TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> CollectionReachE
         ReachAttemptId(self.id)
     }
 
-    /// Returns `true` if accepting this reached node would replace an existing connection to that
-    /// node.
+    /// Returns the number of connections we already have to this node.
     #[inline]
-    pub fn would_replace(&self) -> bool {
-        self.parent.nodes.contains_key(&self.peer_id)
+    pub fn num_existing_connections(&self) -> usize {
+        self.parent.nodes.get(&self.peer_id).map(|tasks| tasks.len()).unwrap_or(0)
     }
 
     /// Accepts the new node.
-    pub fn accept(self) -> (CollectionNodeAccept, PeerId) {
+    ///
+    /// The connection is added to the existing connections to this node, if any.
+    pub fn accept(self) -> (ConnectionId, PeerId) {
         // Set the state of the task to `Connected`.
-        let former_task_id = self.parent.nodes.insert(self.peer_id.clone(), self.id);
+        self.parent.nodes.entry(self.peer_id.clone()).or_default().push(self.id);
         let _former_state = self.parent.tasks.insert(self.id, TaskState::Connected(self.peer_id.clone()));
         debug_assert_eq!(_former_state, Some(TaskState::Pending));
 
-        // It is possible that we already have a task connected to the same peer. In this
-        // case, we need to emit a `NodeReplaced` event.
-        let ret_value = if let Some(former_task_id) = former_task_id {
-            self.parent.inner.task(former_task_id)
-                .expect("whenever we receive a TaskClosed event or close a node, we remove the \
-                         corresponding entry from self.nodes; therefore all elements in \
-                         self.nodes are valid tasks in the HandledNodesTasks; QED")
-                .close();
-            let _former_other_state = self.parent.tasks.remove(&former_task_id);
-            debug_assert_eq!(_former_other_state, Some(TaskState::Connected(self.peer_id.clone())));
-
-            // TODO: we unfortunately have to clone the peer id here
-            (CollectionNodeAccept::ReplacedExisting, self.peer_id.clone())
-        } else {
-            // TODO: we unfortunately have to clone the peer id here
-            (CollectionNodeAccept::NewEntry, self.peer_id.clone())
-        };
+        // TODO: we unfortunately have to clone the peer id here
+        let ret_value = (ConnectionId(self.id), self.peer_id.clone());
 
         // Don't run the destructor.
         mem::forget(self);
@@ -251,19 +248,17 @@ impl<'a, TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> Drop for Collect
     }
 }
 
-/// Outcome of accepting a node.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum CollectionNodeAccept {
-    /// We replaced an existing node.
-    ReplacedExisting,
-    /// We didn't replace anything existing.
-    NewEntry,
-}
-
 /// Identifier for a future that attempts to reach a node.
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ReachAttemptId(TaskId);
 
+/// Identifier of a connection to a node.
+///
+/// Identifiers are never reused within a `CollectionStream`, even after the connection has been
+/// closed.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConnectionId(TaskId);
+
 impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> CollectionStream<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> {
     /// Creates a new empty collection.
     #[inline]
@@ -335,19 +330,26 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> CollectionStream<TIn
     ///
     /// Returns `None` if we don't have a connection to this peer.
     #[inline]
-    pub fn peer_mut(&mut self, id: &PeerId) -> Option<PeerMut<TInEvent>> {
-        let task = match self.nodes.get(id) {
-            Some(&task) => task,
-            None => return None,
-        };
-
-        match self.inner.task(task) {
-            Some(inner) => Some(PeerMut {
-                inner,
+    pub fn peer_mut(&mut self, id: &PeerId) -> Option<PeerMut<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr>> {
+        match self.nodes.entry(id.clone()) {
+            Entry::Occupied(nodes) => Some(PeerMut {
+                inner: &mut self.inner,
                 tasks: &mut self.tasks,
-                nodes: &mut self.nodes,
+                nodes,
             }),
-            None => None,
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    /// Starts a graceful shutdown of all the established connections.
+    ///
+    /// The handlers are asked to shut down, and the connections keep generating events until
+    /// they are closed, at which point a `NodeClosed` or `NodeError` event is generated.
+    pub fn shutdown_connections(&mut self) {
+        for task_id in self.nodes.values().flat_map(|tasks| tasks.iter()) {
+            if let Some(mut task) = self.inner.task(*task_id) {
+                task.shutdown();
+            }
         }
     }
 
@@ -359,7 +361,8 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> CollectionStream<TIn
         self.nodes.contains_key(id)
     }
 
-    /// Returns a list of all the active connections.
+    /// Returns a list of all the nodes we are connected to. Each node is only produced once,
+    /// even if we have multiple connections to it.
     ///
     /// Does not include reach attempts that haven't reached any target yet.
     #[inline]
@@ -405,18 +408,18 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> CollectionStream<TIn
                     },
                     (Some(TaskState::Connected(peer_id)), Ok(()), _handler) => {
                         debug_assert!(_handler.is_none());
-                        let _node_task_id = self.nodes.remove(&peer_id);
-                        debug_assert_eq!(_node_task_id, Some(id));
+                        remove_node_task(&mut self.nodes, &peer_id, id);
                         Async::Ready(CollectionEvent::NodeClosed {
                             peer_id,
+                            connection_id: ConnectionId(id),
                         })
                     },
                     (Some(TaskState::Connected(peer_id)), Err(TaskClosedEvent::Node(err)), _handler) => {
                         debug_assert!(_handler.is_none());
-                        let _node_task_id = self.nodes.remove(&peer_id);
-                        debug_assert_eq!(_node_task_id, Some(id));
+                        remove_node_task(&mut self.nodes, &peer_id, id);
                         Async::Ready(CollectionEvent::NodeError {
                             peer_id,
+                            connection_id: ConnectionId(id),
                             error: err,
                         })
                     },
@@ -450,6 +453,7 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> CollectionStream<TIn
 
                 Async::Ready(CollectionEvent::NodeEvent {
                     peer_id,
+                    connection_id: ConnectionId(id),
                     event,
                 })
             }
@@ -457,6 +461,32 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> CollectionStream<TIn
     }
 }
 
+/// Removes the given task from the list of connections of `peer_id` in `nodes`, and removes the
+/// node altogether if this was its last connection.
+fn remove_node_task(nodes: &mut FnvHashMap<PeerId, SmallVec<[TaskId; 2]>>, peer_id: &PeerId, id: TaskId) {
+    if let Entry::Occupied(mut entry) = nodes.entry(peer_id.clone()) {
+        let _was_in = remove_task(entry.get_mut(), id);
+        debug_assert!(_was_in);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+    } else {
+        panic!("We insert in nodes whenever a task switches to the Connected state, and remove \
+                only when the task is closed; QED");
+    }
+}
+
+/// Removes `id` from `tasks`. Returns `false` if it wasn't in the list.
+fn remove_task(tasks: &mut SmallVec<[TaskId; 2]>, id: TaskId) -> bool {
+    match tasks.iter().position(|t| *t == id) {
+        Some(pos) => {
+            tasks.remove(pos);
+            true
+        },
+        None => false,
+    }
+}
+
 /// Reach attempt interrupt errors. 
 #[derive(Debug)]
 pub enum InterruptError {
@@ -484,33 +514,79 @@ impl fmt::Display for InterruptError {
 impl error::Error for InterruptError {}
 
 /// Access to a peer in the collection.
-pub struct PeerMut<'a, TInEvent: 'a> {
-    inner: HandledNodesTask<'a, TInEvent>,
+pub struct PeerMut<'a, TInEvent: 'a, TOutEvent: 'a, THandler: 'a, TReachErr: 'a, THandlerErr: 'a> {
+    inner: &'a mut HandledNodesTasks<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr>,
     tasks: &'a mut FnvHashMap<TaskId, TaskState>,
-    nodes: &'a mut FnvHashMap<PeerId, TaskId>,
+    nodes: OccupiedEntry<'a, PeerId, SmallVec<[TaskId; 2]>>,
 }
 
-impl<'a, TInEvent> PeerMut<'a, TInEvent> {
-    /// Sends an event to the given node.
+impl<'a, TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> PeerMut<'a, TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> {
+    /// Returns the list of connections to this node, from the oldest to the newest.
+    #[inline]
+    pub fn connections<'b>(&'b self) -> impl Iterator<Item = ConnectionId> + 'b {
+        self.nodes.get().iter().map(|id| ConnectionId(*id))
+    }
+
+    /// Sends an event to the node, through any of its connections.
     #[inline]
     pub fn send_event(&mut self, event: TInEvent) {
-        self.inner.send_event(event)
+        let id = self.nodes.get()[0];
+        self.task(id).send_event(event)
+    }
+
+    /// Sends an event to the node through the given connection.
+    ///
+    /// Returns back the event if the connection doesn't belong to this node or is closed.
+    pub fn send_event_to(&mut self, connection: ConnectionId, event: TInEvent) -> Result<(), TInEvent> {
+        if !self.nodes.get().contains(&connection.0) {
+            return Err(event);
+        }
+
+        self.task(connection.0).send_event(event);
+        Ok(())
     }
 
-    /// Closes the connections to this node.
+    /// Closes the given connection to this node. Returns `false` if the connection doesn't
+    /// belong to this node.
+    ///
+    /// If this is the last connection to the node, the node is removed from the collection.
+    /// No further event will be generated for this connection.
+    pub fn close_connection(mut self, connection: ConnectionId) -> bool {
+        if !remove_task(self.nodes.get_mut(), connection.0) {
+            return false;
+        }
+
+        let _task_state = self.tasks.remove(&connection.0);
+        debug_assert!(if let Some(TaskState::Connected(_)) = _task_state { true } else { false });
+        self.task(connection.0).close();
+
+        if self.nodes.get().is_empty() {
+            self.nodes.remove();
+        }
+        true
+    }
+
+    /// Closes all the connections to this node.
     ///
     /// No further event will be generated for this node.
     pub fn close(self) {
-        let task_state = self.tasks.remove(&self.inner.id());
-        if let Some(TaskState::Connected(peer_id)) = task_state {
-            let old_task_id = self.nodes.remove(&peer_id);
-            debug_assert_eq!(old_task_id, Some(self.inner.id()));
-        } else {
-            panic!("a PeerMut can only be created if an entry is present in nodes; an entry in \
-                    nodes always matched a Connected entry in tasks; QED");
-        };
+        for id in self.nodes.remove() {
+            let _task_state = self.tasks.remove(&id);
+            debug_assert!(if let Some(TaskState::Connected(_)) = _task_state { true } else { false });
+            self.inner.task(id)
+                .expect("whenever we receive a TaskClosed event or close a node, we remove the \
+                         corresponding entry from self.nodes; therefore all elements in \
+                         self.nodes are valid tasks in the HandledNodesTasks; QED")
+                .close();
+        }
+    }
 
-        self.inner.close();
+    /// Grants access to the task of a connection to this node.
+    fn task(&mut self, id: TaskId) -> HandledNodesTask<'_, TInEvent> {
+        self.inner.task(id)
+            .expect("whenever we receive a TaskClosed event or close a node, we remove the \
+                     corresponding entry from self.nodes; therefore all elements in \
+                     self.nodes are valid tasks in the HandledNodesTasks; QED")
     }
 }
 
@@ -589,7 +665,7 @@ mod tests {
         let mut cs = TestCollectionStream::new();
         let peer_id = PeerId::random();
         let fut = future::ok((peer_id.clone(), DummyMuxer::new()));
-        cs.add_reach_attempt(fut, Handler::default());
+        let reach_attempt_id = cs.add_reach_attempt(fut, Handler::default());
 
         let mut rt = Runtime::new().unwrap();
         let mut poll_count = 0;
@@ -605,9 +681,9 @@ mod tests {
                     2 => {
                         assert_matches!(event, Async::Ready(CollectionEvent::NodeReached(reach_ev)) => {
                             assert_matches!(reach_ev.parent, CollectionStream{..});
-                            let (accept_ev, accepted_peer_id) = reach_ev.accept();
+                            let (connection_id, accepted_peer_id) = reach_ev.accept();
                             assert_eq!(accepted_peer_id, peer_id);
-                            assert_matches!(accept_ev, CollectionNodeAccept::NewEntry);
+                            assert_eq!(connection_id, ConnectionId(reach_attempt_id.0));
                         });
                     }
                     _ => unreachable!()
@@ -621,6 +697,50 @@ mod tests {
         rt.block_on(fut).expect("running the future works");
     }
 
+    #[test]
+    fn multiple_connections_to_the_same_node_are_kept() {
+        let cs = Arc::new(Mutex::new(TestCollectionStream::new()));
+        let peer_id = PeerId::random();
+        for _ in 0..2 {
+            let fut = future::ok((peer_id.clone(), DummyMuxer::new()));
+            cs.lock().add_reach_attempt(fut, Handler::default());
+        }
+
+        let mut rt = Builder::new().core_threads(1).build().unwrap();
+        let cs_fut = cs.clone();
+        let mut accepted = Vec::new();
+        let accepted = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+            let mut cs = cs_fut.lock();
+            loop {
+                match cs.poll() {
+                    Async::Ready(CollectionEvent::NodeReached(reach_ev)) => {
+                        assert_eq!(reach_ev.num_existing_connections(), accepted.len());
+                        let (connection_id, _) = reach_ev.accept();
+                        accepted.push(connection_id);
+                        if accepted.len() == 2 {
+                            return Ok(Async::Ready(accepted.clone()));
+                        }
+                    },
+                    Async::Ready(_) => panic!("unexpected event"),
+                    Async::NotReady => return Ok(Async::NotReady),
+                }
+            }
+        })).expect("tokio works");
+
+        let mut cs = cs.lock();
+        assert_ne!(accepted[0], accepted[1]);
+        assert_eq!(cs.connections().collect::<Vec<_>>(), vec![&peer_id]);
+        assert_eq!(cs.peer_mut(&peer_id).unwrap().connections().collect::<Vec<_>>(), accepted);
+
+        assert!(cs.peer_mut(&peer_id).unwrap().close_connection(accepted[0]));
+        assert!(cs.has_connection(&peer_id));
+        assert_matches!(cs.peer_mut(&peer_id).unwrap().send_event_to(accepted[0], InEvent::NextState), Err(_));
+        assert_matches!(cs.peer_mut(&peer_id).unwrap().send_event_to(accepted[1], InEvent::NextState), Ok(()));
+
+        assert!(cs.peer_mut(&peer_id).unwrap().close_connection(accepted[1]));
+        assert!(!cs.has_connection(&peer_id));
+    }
+
     #[test]
     fn events_in_a_node_reaches_the_collection_stream() {
         let cs = Arc::new(Mutex::new(TestCollectionStream::new()));
@@ -666,7 +786,7 @@ mod tests {
         rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
             let mut cs = cs_fut.lock();
             cs.broadcast_event(&InEvent::NextState);
-            assert_matches!(cs.poll(), Async::Ready(CollectionEvent::NodeEvent{peer_id: _, event}) => {
+            assert_matches!(cs.poll(), Async::Ready(CollectionEvent::NodeEvent{peer_id: _, event, ..}) => {
                 assert_matches!(event, OutEvent::Custom("init"));
             });
             Ok(Async::Ready(()))
@@ -677,7 +797,7 @@ mod tests {
         rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
             let mut cs = cs_fut.lock();
             cs.broadcast_event(&InEvent::NextState);
-            assert_matches!(cs.poll(), Async::Ready(CollectionEvent::NodeEvent{peer_id: _, event}) => {
+            assert_matches!(cs.poll(), Async::Ready(CollectionEvent::NodeEvent{peer_id: _, event, ..}) => {
                 assert_matches!(event, OutEvent::Custom("from handler 1"));
             });
             Ok(Async::Ready(()))
@@ -687,7 +807,7 @@ mod tests {
         rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
             let mut cs = cs_fut.lock();
             cs.broadcast_event(&InEvent::NextState);
-            assert_matches!(cs.poll(), Async::Ready(CollectionEvent::NodeEvent{peer_id: _, event}) => {
+            assert_matches!(cs.poll(), Async::Ready(CollectionEvent::NodeEvent{peer_id: _, event, ..}) => {
                 assert_matches!(event, OutEvent::Custom("from handler 2"));
             });
             Ok(Async::Ready(()))
@@ -810,7 +930,7 @@ mod tests {
         rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
             let mut cs = cs_fut.lock();
             // Node is closed normally: TaskClosed, Ok(())
-            assert_matches!(cs.poll(), Async::Ready(CollectionEvent::NodeClosed{ peer_id: peer_id_in_event }) => {
+            assert_matches!(cs.poll(), Async::Ready(CollectionEvent::NodeClosed{ peer_id: peer_id_in_event, .. }) => {
                 assert_eq!(peer_id_in_event, peer_id);
             });
             Ok(Async::Ready(()))
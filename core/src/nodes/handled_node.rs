@@ -163,7 +163,9 @@ where
     /// If true, `handler` has returned `Ready(None)` and therefore shouldn't be polled again.
     handler_is_done: bool,
     // True, if the node is shutting down.
-    is_shutting_down: bool
+    is_shutting_down: bool,
+    /// If true, the shutdown of the muxer has been started.
+    node_is_shutting_down: bool,
 }
 
 impl<TMuxer, THandler> fmt::Debug for HandledNode<TMuxer, THandler>
@@ -177,6 +179,7 @@ where
             .field("handler", &self.handler)
             .field("handler_is_done", &self.handler_is_done)
             .field("is_shutting_down", &self.is_shutting_down)
+            .field("node_is_shutting_down", &self.node_is_shutting_down)
             .finish()
     }
 }
@@ -193,7 +196,8 @@ where
             node: NodeStream::new(muxer).fuse(),
             handler,
             handler_is_done: false,
-            is_shutting_down: false
+            is_shutting_down: false,
+            node_is_shutting_down: false,
         }
     }
 
@@ -246,6 +250,24 @@ where
         }
         self.handler.shutdown();
         self.is_shutting_down = true;
+        self.node_is_shutting_down = true;
+    }
+
+    /// Indicates to the handler that it should shut down, but leaves the muxer open so that the
+    /// handler can finish its substreams. Once the handler has shut down, the muxer is shut down
+    /// and the `Stream` ends.
+    ///
+    /// After this method returns, `is_shutting_down()` should return true.
+    pub fn graceful_shutdown(&mut self) {
+        if self.is_shutting_down {
+            return;
+        }
+
+        for user_data in self.node.get_mut().cancel_outgoing() {
+            self.handler.inject_outbound_closed(user_data);
+        }
+        self.handler.shutdown();
+        self.is_shutting_down = true;
     }
 }
 
@@ -311,8 +333,9 @@ where
                 }
                 Async::Ready(NodeHandlerEvent::Shutdown) => {
                     self.handler_is_done = true;
-                    if !self.is_shutting_down {
+                    if !self.node_is_shutting_down {
                         self.is_shutting_down = true;
+                        self.node_is_shutting_down = true;
                         self.node.get_mut().cancel_outgoing();
                         self.node.get_mut().shutdown_all();
                     }
@@ -526,6 +549,26 @@ mod tests {
         assert!(handled.is_shutting_down());
     }
 
+    #[test]
+    fn graceful_shutdown_closes_the_muxer_after_the_handler() {
+        let mut handled = TestBuilder::new()
+            .with_muxer_inbound_state(DummyConnectionState::Pending)
+            .with_muxer_outbound_state(DummyConnectionState::Pending)
+            .with_handler_state(HandlerState::NotReady)
+            .handled_node();
+        handled.poll().expect("poll should work");
+
+        handled.graceful_shutdown();
+        assert!(handled.is_shutting_down());
+        assert!(handled.is_inbound_open());
+        assert!(handled.is_outbound_open());
+
+        // The dummy handler finishes as soon as it's polled after `shutdown()`.
+        handled.poll().expect("poll should work");
+        assert!(!handled.is_inbound_open());
+        assert!(!handled.is_outbound_open());
+    }
+
     #[test]
     fn is_shutting_down_is_true_when_in_and_outbounds_are_closed() {
         let mut handled = TestBuilder::new()
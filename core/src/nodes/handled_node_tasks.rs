@@ -61,7 +61,7 @@ pub struct HandledNodesTasks<TInEvent, TOutEvent, THandler, TReachErr, THandlerE
     /// A map between active tasks to an unbounded sender, used to control the task. Closing the sender interrupts
     /// the task. It is possible that we receive messages from tasks that used to be in this list
     /// but no longer are, in which case we should ignore them.
-    tasks: FnvHashMap<TaskId, mpsc::UnboundedSender<TInEvent>>,
+    tasks: FnvHashMap<TaskId, mpsc::UnboundedSender<ExtToInMessage<TInEvent>>>,
 
     /// Identifier for the next task to spawn.
     next_task_id: TaskId,
@@ -199,6 +199,7 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> HandledNodesTasks<TI
                 future,
                 handler,
                 events_buffer: Vec::new(),
+                shutdown: false,
             },
             events_tx: self.events_tx.clone(),
             in_events_rx: rx.fuse(),
@@ -217,7 +218,7 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> HandledNodesTasks<TI
             // Note: it is possible that sending an event fails if the background task has already
             // finished, but the local state hasn't reflected that yet because it hasn't been
             // polled. This is not an error situation.
-            let _ = sender.unbounded_send(event.clone());
+            let _ = sender.unbounded_send(ExtToInMessage::HandlerEvent(event.clone()));
         }
     }
 
@@ -289,7 +290,7 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> HandledNodesTasks<TI
 
 /// Access to a task in the collection.
 pub struct Task<'a, TInEvent: 'a> {
-    inner: OccupiedEntry<'a, TaskId, mpsc::UnboundedSender<TInEvent>>,
+    inner: OccupiedEntry<'a, TaskId, mpsc::UnboundedSender<ExtToInMessage<TInEvent>>>,
 }
 
 impl<'a, TInEvent> Task<'a, TInEvent> {
@@ -300,7 +301,16 @@ impl<'a, TInEvent> Task<'a, TInEvent> {
         // It is possible that the sender is closed if the background task has already finished
         // but the local state hasn't been updated yet because we haven't been polled in the
         // meanwhile.
-        let _ = self.inner.get_mut().unbounded_send(event);
+        let _ = self.inner.get_mut().unbounded_send(ExtToInMessage::HandlerEvent(event));
+    }
+
+    /// Starts a graceful shutdown of the node.
+    ///
+    /// The handler is asked to shut down, and the muxer is closed once the handler has finished.
+    /// Contrary to `close()`, the task continues to generate events until it closes.
+    #[inline]
+    pub fn shutdown(&mut self) {
+        let _ = self.inner.get_mut().unbounded_send(ExtToInMessage::Shutdown);
     }
 
     /// Returns the task id.
@@ -335,6 +345,15 @@ impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr> Stream for HandledNo
     }
 }
 
+/// Message to transmit from the public API to a task.
+#[derive(Debug)]
+enum ExtToInMessage<TInEvent> {
+    /// An event to transmit to the handler.
+    HandlerEvent(TInEvent),
+    /// The node should start shutting down gracefully.
+    Shutdown,
+}
+
 /// Message to transmit from a task to the public API.
 #[derive(Debug)]
 enum InToExtMessage<TOutEvent, THandler, TReachErr, THandlerErr> {
@@ -356,7 +375,7 @@ where
     /// Sender to transmit events to the outside.
     events_tx: mpsc::UnboundedSender<(InToExtMessage<TOutEvent, THandler, TReachErr, THandler::Error>, TaskId)>,
     /// Receiving end for events sent from the main `HandledNodesTasks`.
-    in_events_rx: stream::Fuse<mpsc::UnboundedReceiver<TInEvent>>,
+    in_events_rx: stream::Fuse<mpsc::UnboundedReceiver<ExtToInMessage<TInEvent>>>,
     /// Inner state of the `NodeTask`.
     inner: NodeTaskInner<TFut, TMuxer, THandler, TInEvent>,
     /// Identifier of the attempt.
@@ -378,6 +397,8 @@ where
         /// `in_events_rx` so that they get delivered once dialing succeeds. We can't simply leave
         /// events in `in_events_rx` because we have to detect if it gets closed.
         events_buffer: Vec<TInEvent>,
+        /// If true, the node should start shutting down as soon as it is reached.
+        shutdown: bool,
     },
 
     /// Fully functional node.
@@ -401,12 +422,14 @@ where
         loop {
             match mem::replace(&mut self.inner, NodeTaskInner::Poisoned) {
                 // First possibility: we are still trying to reach a node.
-                NodeTaskInner::Future { mut future, handler, mut events_buffer } => {
+                NodeTaskInner::Future { mut future, handler, mut events_buffer, mut shutdown } => {
                     // If self.in_events_rx is closed, we stop the task.
                     loop {
                         match self.in_events_rx.poll() {
                             Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
-                            Ok(Async::Ready(Some(event))) => events_buffer.push(event),
+                            Ok(Async::Ready(Some(ExtToInMessage::HandlerEvent(event)))) =>
+                                events_buffer.push(event),
+                            Ok(Async::Ready(Some(ExtToInMessage::Shutdown))) => shutdown = true,
                             Ok(Async::NotReady) => break,
                             Err(_) => unreachable!("An UnboundedReceiver never errors"),
                         }
@@ -419,13 +442,16 @@ where
                             for event in events_buffer {
                                 node.inject_event(event);
                             }
+                            if shutdown {
+                                node.graceful_shutdown();
+                            }
                             if self.events_tx.unbounded_send((event, self.id)).is_err() {
                                 node.shutdown();
                             }
                             self.inner = NodeTaskInner::Node(node);
                         }
                         Ok(Async::NotReady) => {
-                            self.inner = NodeTaskInner::Future { future, handler, events_buffer };
+                            self.inner = NodeTaskInner::Future { future, handler, events_buffer, shutdown };
                             return Ok(Async::NotReady);
                         },
                         Err(err) => {
@@ -444,9 +470,12 @@ where
                         loop {
                             match self.in_events_rx.poll() {
                                 Ok(Async::NotReady) => break,
-                                Ok(Async::Ready(Some(event))) => {
+                                Ok(Async::Ready(Some(ExtToInMessage::HandlerEvent(event)))) => {
                                     node.inject_event(event)
                                 },
+                                Ok(Async::Ready(Some(ExtToInMessage::Shutdown))) => {
+                                    node.graceful_shutdown();
+                                },
                                 Ok(Async::Ready(None)) => {
                                     // Node closed by the external API; start shutdown process.
                                     node.shutdown();
@@ -548,11 +577,11 @@ mod tests {
 
         fn node_task(&mut self) -> (
             TestNodeTask,
-            UnboundedSender<InEvent>,
+            UnboundedSender<ExtToInMessage<InEvent>>,
             UnboundedReceiver<(InToExtMessage<OutEvent, Handler, io::Error, io::Error>, TaskId)>,
         ) {
             let (events_from_node_task_tx, events_from_node_task_rx) = mpsc::unbounded::<(InToExtMessage<OutEvent, Handler, _, _>, TaskId)>();
-            let (events_to_node_task_tx, events_to_node_task_rx) = mpsc::unbounded::<InEvent>();
+            let (events_to_node_task_tx, events_to_node_task_rx) = mpsc::unbounded::<ExtToInMessage<InEvent>>();
             let inner = if self.inner_node.is_some() {
                 NodeTaskInner::Node(self.inner_node.take().unwrap())
             } else {
@@ -560,6 +589,7 @@ mod tests {
                     future: self.inner_fut.take().unwrap(),
                     handler: Handler::default(),
                     events_buffer: Vec::new(),
+                    shutdown: false,
                 }
             };
             let node_task = NodeTask {
@@ -632,7 +662,7 @@ mod tests {
             .with_task_id(890)
             .node_task();
 
-        tx.unbounded_send(InEvent::Custom("beef")).expect("send to NodeTask should work");
+        tx.unbounded_send(ExtToInMessage::HandlerEvent(InEvent::Custom("beef"))).expect("send to NodeTask should work");
         let mut rt = Runtime::new().unwrap();
         rt.spawn(node_task);
         let events = rt.block_on(rx.by_ref().take(2).collect()).expect("reading on rx should work");
@@ -699,7 +729,7 @@ mod tests {
         // Async::Ready(()). QED.
 
         let create_outbound_substream_event = InEvent::Substream(Some(135));
-        tx.unbounded_send(create_outbound_substream_event).expect("send msg works");
+        tx.unbounded_send(ExtToInMessage::HandlerEvent(create_outbound_substream_event)).expect("send msg works");
         rt.spawn(node_task);
         let events = rt.block_on(rx.collect()).expect("rx failed");
 
@@ -821,22 +851,39 @@ mod tests {
         let mut events = rt.block_on(handled_nodes_tasks.into_future()).unwrap();
         assert_matches!(events.0.unwrap(), HandledNodesEvent::NodeReached{..});
 
-        tx.unbounded_send(InEvent::NextState).expect("send works");
+        tx.unbounded_send(ExtToInMessage::HandlerEvent(InEvent::NextState)).expect("send works");
         events = rt.block_on(events.1.into_future()).unwrap();
         assert_matches!(events.0.unwrap(), HandledNodesEvent::NodeEvent{id: _, event} => {
             assert_matches!(event, OutEvent::Custom("from handler"));
         });
 
-        tx.unbounded_send(InEvent::NextState).expect("send works");
+        tx.unbounded_send(ExtToInMessage::HandlerEvent(InEvent::NextState)).expect("send works");
         events = rt.block_on(events.1.into_future()).unwrap();
         assert_matches!(events.0.unwrap(), HandledNodesEvent::NodeEvent{id: _, event} => {
             assert_matches!(event, OutEvent::Custom("from handler2"));
         });
 
-        tx.unbounded_send(InEvent::NextState).expect("send works");
+        tx.unbounded_send(ExtToInMessage::HandlerEvent(InEvent::NextState)).expect("send works");
         events = rt.block_on(events.1.into_future()).unwrap();
         assert_matches!(events.0.unwrap(), HandledNodesEvent::TaskClosed{id: _, result, handler: _} => {
             assert_matches!(result, Err(_));
         });
     }
+
+    #[test]
+    fn task_closes_after_graceful_shutdown() {
+        let (handled_nodes_tasks, _) = HandledNodeTaskTestBuilder::new()
+            .with_tasks(1)
+            .with_muxer_inbound_state(DummyConnectionState::Pending)
+            .with_muxer_outbound_state(DummyConnectionState::Pending)
+            .handled_nodes_tasks();
+
+        let mut rt = Builder::new().core_threads(1).build().unwrap();
+        let (event, mut handled_nodes_tasks) = rt.block_on(handled_nodes_tasks.into_future()).unwrap();
+        assert_matches!(event.unwrap(), HandledNodesEvent::NodeReached{..});
+
+        handled_nodes_tasks.task(TaskId(0)).unwrap().shutdown();
+        let (event, _) = rt.block_on(handled_nodes_tasks.into_future()).unwrap();
+        assert_matches!(event.unwrap(), HandledNodesEvent::TaskClosed{id: TaskId(0), result: Ok(()), ..});
+    }
 }
@@ -21,10 +21,18 @@
 //! Manage listening on multiple multiaddresses at once.
 
 use futures::prelude::*;
-use std::fmt;
+use log::debug;
+use multiaddr::Protocol;
+use std::{fmt, io, iter};
 use void::Void;
 use crate::{Multiaddr, Transport};
 use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio_timer::Interval;
+
+/// Interval between two checks of the network interfaces, for the listeners whose address has an
+/// unspecified IP address.
+const INTERFACES_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 /// Implementation of `futures::Stream` that allows listening on multiaddresses.
 ///
@@ -34,6 +42,13 @@ use std::collections::VecDeque;
 ///
 /// Then, call `ListenerStream::listen_on` for all addresses you want to start listening on.
 ///
+/// Each listener is identified by the `ListenerId` returned by `listen_on`, which can later be
+/// passed to `remove_listener` in order to close it.
+///
+/// A listener on an unspecified IP address (`0.0.0.0` or `::`) is reported as one address per
+/// network interface. The interfaces are checked periodically, and `NewAddress` and
+/// `AddressExpired` events are generated when they change.
+///
 /// The `ListenersStream` never ends and never produces errors. If a listener errors or closes,
 /// an event is generated on the stream and the listener is then dropped, but the `ListenersStream`
 /// itself continues.
@@ -60,7 +75,13 @@ use std::collections::VecDeque;
 /// // The `listeners` will now generate events when polled.
 /// let future = listeners.for_each(move |event| {
 ///     match event {
-///         ListenersEvent::Closed { listen_addr, listener, result } => {
+///         ListenersEvent::NewAddress { listen_addr, .. } => {
+///             println!("Listening on {}", listen_addr);
+///         },
+///         ListenersEvent::AddressExpired { listen_addr, .. } => {
+///             println!("No longer listening on {}", listen_addr);
+///         },
+///         ListenersEvent::Closed { listen_addr, result, .. } => {
 ///             println!("Listener {} has been closed: {:?}", listen_addr, result);
 ///         },
 ///         ListenersEvent::Incoming { upgrade, listen_addr, .. } => {
@@ -85,18 +106,34 @@ where
     transport: TTrans,
     /// All the active listeners.
     listeners: VecDeque<Listener<TTrans>>,
+    /// Identifier to assign to the next listener.
+    next_id: ListenerId,
+    /// Address events waiting to be returned by `poll`.
+    pending_events: VecDeque<ListenersEvent<TTrans>>,
+    /// Timer for checking the network interfaces. `None` if no listener is on an unspecified IP
+    /// address.
+    interfaces_check: Option<Interval>,
 }
 
+/// Identifier of a listener of a `ListenersStream`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ListenerId(u64);
+
 /// A single active listener.
 #[derive(Debug)]
 struct Listener<TTrans>
 where
     TTrans: Transport,
 {
+    /// Identifier of the listener.
+    id: ListenerId,
     /// The object that actually listens.
     listener: TTrans::Listener,
     /// Address it is listening on.
     address: Multiaddr,
+    /// Addresses that have been reported with `NewAddress`. Contains one address per network
+    /// interface if `address` has an unspecified IP address, and `address` itself otherwise.
+    addresses: Vec<Multiaddr>,
 }
 
 /// Event that can happen on the `ListenersStream`.
@@ -114,8 +151,34 @@ where
         send_back_addr: Multiaddr,
     },
 
+    /// A listener has started listening on a new address.
+    ///
+    /// Produced for the address returned by the transport, which is where the actual port is
+    /// known when listening on port 0. If that address has an unspecified IP address, produced
+    /// instead for each network interface, including the ones that appear later.
+    NewAddress {
+        /// Identifier of the listener.
+        listener_id: ListenerId,
+        /// The new address.
+        listen_addr: Multiaddr,
+    },
+
+    /// A listener is no longer listening on an address, because it has been removed with
+    /// `remove_listener`, because it has closed, or because the network interface has gone away.
+    AddressExpired {
+        /// Identifier of the listener.
+        listener_id: ListenerId,
+        /// The address that has expired.
+        listen_addr: Multiaddr,
+    },
+
     /// A listener has closed, either gracefully or with an error.
+    ///
+    /// Its address is no longer listened on. This event is followed by an `AddressExpired` event
+    /// for each of the addresses that were reported for it.
     Closed {
+        /// Identifier of the listener which closed.
+        listener_id: ListenerId,
         /// Address of the listener which closed.
         listen_addr: Multiaddr,
         /// The listener that closed.
@@ -135,9 +198,23 @@ where
         ListenersStream {
             transport,
             listeners: VecDeque::new(),
+            next_id: ListenerId(0),
+            pending_events: VecDeque::new(),
+            interfaces_check: None,
         }
     }
 
+    /// Closes all the listeners.
+    ///
+    /// No event is generated for them, and the events that haven't been returned yet are
+    /// discarded.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.listeners.clear();
+        self.pending_events.clear();
+        self.interfaces_check = None;
+    }
+
     /// Same as `new`, but pre-allocates enough memory for the given number of
     /// simultaneous listeners.
     #[inline]
@@ -145,13 +222,17 @@ where
         ListenersStream {
             transport,
             listeners: VecDeque::with_capacity(capacity),
+            next_id: ListenerId(0),
+            pending_events: VecDeque::new(),
+            interfaces_check: None,
         }
     }
 
     /// Start listening on a multiaddress.
     ///
-    /// Returns an error if the transport doesn't support the given multiaddress.
-    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<Multiaddr, Multiaddr>
+    /// Returns an error if the transport doesn't support the given multiaddress. On success,
+    /// `NewAddress` events are later produced with the addresses actually listened on.
+    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<ListenerId, Multiaddr>
     where
         TTrans: Clone,
     {
@@ -161,12 +242,102 @@ where
             .listen_on(addr)
             .map_err(|(_, addr)| addr)?;
 
+        let id = self.next_id;
+        self.next_id = ListenerId(id.0 + 1);
+
+        let addresses = if is_unspecified(&new_addr) {
+            if self.interfaces_check.is_none() {
+                let start = Instant::now() + INTERFACES_CHECK_INTERVAL;
+                self.interfaces_check = Some(Interval::new(start, INTERFACES_CHECK_INTERVAL));
+            }
+            interface_addresses(&new_addr).unwrap_or_else(|err| {
+                debug!("Failed to list the network interfaces: {:?}", err);
+                vec![new_addr.clone()]
+            })
+        } else {
+            vec![new_addr.clone()]
+        };
+
+        for listen_addr in &addresses {
+            self.pending_events.push_back(ListenersEvent::NewAddress {
+                listener_id: id,
+                listen_addr: listen_addr.clone(),
+            });
+        }
+
         self.listeners.push_back(Listener {
+            id,
             listener,
-            address: new_addr.clone(),
+            address: new_addr,
+            addresses,
         });
 
-        Ok(new_addr)
+        Ok(id)
+    }
+
+    /// Closes the listener with the given identifier.
+    ///
+    /// `AddressExpired` events are later produced for its addresses. Returns `false` if no
+    /// listener with this identifier exists.
+    pub fn remove_listener(&mut self, id: ListenerId) -> bool {
+        let pos = match self.listeners.iter().position(|l| l.id == id) {
+            Some(pos) => pos,
+            None => return false,
+        };
+
+        let listener = self.listeners.remove(pos).expect("index returned by position; QED");
+        self.push_expired(id, listener.addresses);
+        true
+    }
+
+    /// Queues an `AddressExpired` event for each of the given addresses.
+    fn push_expired(&mut self, listener_id: ListenerId, addresses: Vec<Multiaddr>) {
+        for listen_addr in addresses {
+            self.pending_events.push_back(ListenersEvent::AddressExpired {
+                listener_id,
+                listen_addr,
+            });
+        }
+    }
+
+    /// Lists the network interfaces again for the listeners on an unspecified IP address, and
+    /// queues events for the addresses that have appeared or disappeared.
+    fn refresh_addresses(&mut self) {
+        let mut any_unspecified = false;
+
+        for listener in self.listeners.iter_mut() {
+            if !is_unspecified(&listener.address) {
+                continue;
+            }
+
+            any_unspecified = true;
+            let current = match interface_addresses(&listener.address) {
+                Ok(current) => current,
+                Err(err) => {
+                    debug!("Failed to list the network interfaces: {:?}", err);
+                    continue;
+                }
+            };
+
+            for listen_addr in listener.addresses.iter().filter(|a| !current.contains(a)) {
+                self.pending_events.push_back(ListenersEvent::AddressExpired {
+                    listener_id: listener.id,
+                    listen_addr: listen_addr.clone(),
+                });
+            }
+            for listen_addr in current.iter().filter(|a| !listener.addresses.contains(a)) {
+                self.pending_events.push_back(ListenersEvent::NewAddress {
+                    listener_id: listener.id,
+                    listen_addr: listen_addr.clone(),
+                });
+            }
+
+            listener.addresses = current;
+        }
+
+        if !any_unspecified {
+            self.interfaces_check = None;
+        }
     }
 
     /// Returns the transport passed when building this object.
@@ -181,8 +352,36 @@ where
         self.listeners.iter().map(|l| &l.address)
     }
 
+    /// Returns an iterator that produces the identifier and address of each active listener.
+    #[inline]
+    pub fn listeners_with_ids(&self) -> impl Iterator<Item = (ListenerId, &Multiaddr)> {
+        self.listeners.iter().map(|l| (l.id, &l.address))
+    }
+
     /// Provides an API similar to `Stream`, except that it cannot error.
     pub fn poll(&mut self) -> Async<ListenersEvent<TTrans>> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Async::Ready(event);
+        }
+
+        let mut refresh = false;
+        while let Some(ref mut interval) = self.interfaces_check {
+            match interval.poll() {
+                Ok(Async::Ready(_)) => refresh = true,
+                Ok(Async::NotReady) => break,
+                Err(err) => {
+                    debug!("Failed to poll the network interfaces timer: {:?}", err);
+                    self.interfaces_check = None;
+                }
+            }
+        }
+        if refresh {
+            self.refresh_addresses();
+            if let Some(event) = self.pending_events.pop_front() {
+                return Async::Ready(event);
+            }
+        }
+
         // We remove each element from `listeners` one by one and add them back.
         let mut remaining = self.listeners.len();
         while let Some(mut listener) = self.listeners.pop_back() {
@@ -202,14 +401,18 @@ where
                     });
                 }
                 Ok(Async::Ready(None)) => {
+                    self.push_expired(listener.id, listener.addresses);
                     return Async::Ready(ListenersEvent::Closed {
+                        listener_id: listener.id,
                         listen_addr: listener.address,
                         listener: listener.listener,
                         result: Ok(()),
                     });
                 }
                 Err(err) => {
+                    self.push_expired(listener.id, listener.addresses);
                     return Async::Ready(ListenersEvent::Closed {
+                        listener_id: listener.id,
                         listen_addr: listener.address,
                         listener: listener.listener,
                         result: Err(err),
@@ -223,6 +426,39 @@ where
     }
 }
 
+/// Returns true if the IP address of `addr` is unspecified.
+fn is_unspecified(addr: &Multiaddr) -> bool {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => ip.is_unspecified(),
+        Some(Protocol::Ip6(ip)) => ip.is_unspecified(),
+        _ => false,
+    }
+}
+
+/// Returns `addr` with its unspecified IP address replaced with the address of each network
+/// interface of the same IP version.
+#[cfg(not(any(target_os = "emscripten", target_os = "unknown")))]
+fn interface_addresses(addr: &Multiaddr) -> Result<Vec<Multiaddr>, io::Error> {
+    let ipv4 = match addr.iter().next() {
+        Some(Protocol::Ip4(_)) => true,
+        _ => false,
+    };
+
+    let addresses = get_if_addrs::get_if_addrs()?
+        .into_iter()
+        .map(|interface| interface.ip())
+        .filter(|ip| ip.is_ipv4() == ipv4)
+        .map(|ip| iter::once(Protocol::from(ip)).chain(addr.iter().skip(1)).collect())
+        .collect();
+    Ok(addresses)
+}
+
+/// Returns `addr` itself, as the network interfaces can't be listed on this platform.
+#[cfg(any(target_os = "emscripten", target_os = "unknown"))]
+fn interface_addresses(addr: &Multiaddr) -> Result<Vec<Multiaddr>, io::Error> {
+    Ok(vec![addr.clone()])
+}
+
 impl<TTrans> Stream for ListenersStream<TTrans>
 where
     TTrans: Transport,
@@ -261,12 +497,30 @@ where
                 .debug_struct("ListenersEvent::Incoming")
                 .field("listen_addr", listen_addr)
                 .finish(),
+            ListenersEvent::NewAddress {
+                ref listener_id,
+                ref listen_addr,
+            } => f
+                .debug_struct("ListenersEvent::NewAddress")
+                .field("listener_id", listener_id)
+                .field("listen_addr", listen_addr)
+                .finish(),
+            ListenersEvent::AddressExpired {
+                ref listener_id,
+                ref listen_addr,
+            } => f
+                .debug_struct("ListenersEvent::AddressExpired")
+                .field("listener_id", listener_id)
+                .field("listen_addr", listen_addr)
+                .finish(),
             ListenersEvent::Closed {
+                ref listener_id,
                 ref listen_addr,
                 ref result,
                 ..
             } => f
                 .debug_struct("ListenersEvent::Closed")
+                .field("listener_id", listener_id)
                 .field("listen_addr", listen_addr)
                 .field("result", result)
                 .finish(),
@@ -317,6 +571,12 @@ mod tests {
             };
     }
 
+    fn drain_new_addresses<T: Transport>(ls: &mut ListenersStream<T>) {
+        while let Some(event) = ls.pending_events.pop_front() {
+            assert_matches!(event, ListenersEvent::NewAddress { .. });
+        }
+    }
+
     #[test]
     fn incoming_event() {
         let (tx, rx) = transport::connector();
@@ -326,6 +586,7 @@ mod tests {
 
         let dial = tx.dial("/memory".parse().unwrap()).unwrap_or_else(|_| panic!());
 
+        drain_new_addresses(&mut listeners);
         let future = listeners
             .into_future()
             .map_err(|(err, _)| err)
@@ -382,6 +643,7 @@ mod tests {
         let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
         let mut ls = ListenersStream::new(t);
         ls.listen_on(addr).expect("listen_on failed");
+        drain_new_addresses(&mut ls);
         set_listener_state(&mut ls, 0, ListenerState::Ok(Async::NotReady));
         assert_matches!(ls.poll(), Async::NotReady);
         assert_eq!(ls.listeners.len(), 1); // listener is still there
@@ -403,6 +665,7 @@ mod tests {
         ls.listen_on(addr2).expect("listen_on works");
         assert_eq!(ls.listeners.len(), 2);
 
+        drain_new_addresses(&mut ls);
         assert_matches!(ls.poll(), Async::Ready(listeners_event) => {
             assert_matches!(listeners_event, ListenersEvent::Incoming{mut upgrade, listen_addr, ..} => {
                 assert_eq!(listen_addr.to_string(), "/ip4/127.0.0.2/tcp/4321");
@@ -439,6 +702,7 @@ mod tests {
         let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
         let mut ls = ListenersStream::new(t);
         ls.listen_on(addr).expect("listen_on failed");
+        drain_new_addresses(&mut ls);
         set_listener_state(&mut ls, 0, ListenerState::Ok(Async::Ready(None)));
         assert_matches!(ls.poll(), Async::Ready(listeners_event) => {
             assert_matches!(listeners_event, ListenersEvent::Closed{..})
@@ -455,6 +719,7 @@ mod tests {
         let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
         let mut ls = ListenersStream::new(t);
         ls.listen_on(addr).expect("listen_on failed");
+        drain_new_addresses(&mut ls);
         set_listener_state(&mut ls, 0, ListenerState::Error); // simulate an error on the socket
         assert_matches!(ls.poll(), Async::Ready(listeners_event) => {
             assert_matches!(listeners_event, ListenersEvent::Closed{..})
@@ -477,6 +742,7 @@ mod tests {
 
         // Poll() processes listeners in reverse order. Each listener is polled
         // in turn.
+        drain_new_addresses(&mut ls);
         for n in (0..4).rev() {
             assert_matches!(ls.poll(), Async::Ready(ListenersEvent::Incoming{listen_addr, ..}) => {
                 assert_eq!(listen_addr.to_string(), format!("/ip4/127.0.0.{}/tcp/{}", n, n))
@@ -530,6 +796,7 @@ mod tests {
             ls.listen_on(addr).expect("listen_on failed");
         }
 
+        drain_new_addresses(&mut ls);
         for n in (0..4).rev() {
             assert_matches!(ls.poll(), Async::Ready(ListenersEvent::Incoming{listen_addr, ..}) => {
                 assert_eq!(listen_addr.to_string(), format!("/ip4/127.0.0.{}/tcp/{}", n, n));
@@ -539,4 +806,103 @@ mod tests {
         // All Listeners are NotReady, so poll yields NotReady
         assert_matches!(ls.poll(), Async::NotReady);
     }
+
+    #[test]
+    fn listen_on_produces_new_address_event() {
+        let t = DummyTransport::new();
+        let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        let mut ls = ListenersStream::new(t);
+        let id = ls.listen_on(addr.clone()).expect("listen_on failed");
+        set_listener_state(&mut ls, 0, ListenerState::Ok(Async::NotReady));
+        assert_matches!(ls.poll(), Async::Ready(ListenersEvent::NewAddress { listener_id, listen_addr }) => {
+            assert_eq!(listener_id, id);
+            assert_eq!(listen_addr, addr);
+        });
+        assert_matches!(ls.poll(), Async::NotReady);
+    }
+
+    #[test]
+    fn remove_listener_produces_address_expired_event() {
+        let t = DummyTransport::new();
+        let addr1 = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        let addr2 = "/ip4/127.0.0.1/tcp/4321".parse::<Multiaddr>().expect("bad multiaddr");
+        let mut ls = ListenersStream::new(t);
+        let id1 = ls.listen_on(addr1.clone()).expect("listen_on failed");
+        let id2 = ls.listen_on(addr2.clone()).expect("listen_on failed");
+        assert_ne!(id1, id2);
+        drain_new_addresses(&mut ls);
+
+        assert!(ls.remove_listener(id1));
+        assert!(!ls.remove_listener(id1));
+        assert_eq!(ls.listeners_with_ids().collect::<Vec<_>>(), vec![(id2, &addr2)]);
+        assert_matches!(ls.poll(), Async::Ready(ListenersEvent::AddressExpired { listener_id, listen_addr }) => {
+            assert_eq!(listener_id, id1);
+            assert_eq!(listen_addr, addr1);
+        });
+    }
+
+    #[test]
+    fn closed_listener_produces_address_expired_event() {
+        let t = DummyTransport::new();
+        let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        let mut ls = ListenersStream::new(t);
+        let id = ls.listen_on(addr.clone()).expect("listen_on failed");
+        drain_new_addresses(&mut ls);
+        set_listener_state(&mut ls, 0, ListenerState::Ok(Async::Ready(None)));
+        assert_matches!(ls.poll(), Async::Ready(ListenersEvent::Closed { .. }));
+        assert_matches!(ls.poll(), Async::Ready(ListenersEvent::AddressExpired { listener_id, listen_addr }) => {
+            assert_eq!(listener_id, id);
+            assert_eq!(listen_addr, addr);
+        });
+    }
+
+    #[test]
+    fn unspecified_address_is_reported_per_interface() {
+        let t = DummyTransport::new();
+        let addr = "/ip4/0.0.0.0/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        let loopback = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        let mut ls = ListenersStream::new(t);
+        ls.listen_on(addr.clone()).expect("listen_on failed");
+
+        let mut reported = Vec::new();
+        while let Some(event) = ls.pending_events.pop_front() {
+            assert_matches!(event, ListenersEvent::NewAddress { listen_addr, .. } => {
+                reported.push(listen_addr)
+            });
+        }
+        assert!(reported.contains(&loopback));
+        assert!(!reported.contains(&addr));
+        assert!(reported.iter().all(|a| !is_unspecified(a)));
+        assert_eq!(ls.listeners().collect::<Vec<_>>(), vec![&addr]);
+    }
+
+    #[test]
+    fn interface_changes_produce_events() {
+        let t = DummyTransport::new();
+        let addr = "/ip4/0.0.0.0/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        let loopback = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        let gone = "/ip4/192.0.2.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        let mut ls = ListenersStream::new(t);
+        let id = ls.listen_on(addr).expect("listen_on failed");
+        drain_new_addresses(&mut ls);
+
+        // Pretend that the loopback interface has just appeared and that another one went away.
+        ls.listeners[0].addresses.retain(|a| a != &loopback);
+        ls.listeners[0].addresses.push(gone.clone());
+        ls.refresh_addresses();
+
+        assert_matches!(ls.pending_events.pop_front(), Some(ListenersEvent::AddressExpired { listener_id, listen_addr }) => {
+            assert_eq!(listener_id, id);
+            assert_eq!(listen_addr, gone);
+        });
+        assert_matches!(ls.pending_events.pop_front(), Some(ListenersEvent::NewAddress { listener_id, listen_addr }) => {
+            assert_eq!(listener_id, id);
+            assert_eq!(listen_addr, loopback);
+        });
+        assert!(ls.pending_events.is_empty());
+
+        // Nothing changes if the interfaces are the same.
+        ls.refresh_addresses();
+        assert!(ls.pending_events.is_empty());
+    }
 }
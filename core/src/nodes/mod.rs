@@ -33,6 +33,7 @@ pub mod listeners;
 pub mod node;
 pub mod raw_swarm;
 
+pub use self::collection::ConnectionId;
 pub use self::node::Substream;
 pub use self::handled_node::{NodeHandlerEvent, NodeHandlerEndpoint};
-pub use self::raw_swarm::{ConnectedPoint, Peer, RawSwarm, RawSwarmEvent};
+pub use self::raw_swarm::{ConnectedPoint, ConnectionLimit, ConnectionLimits, DialConfig, Peer, RawSwarm, RawSwarmEvent};
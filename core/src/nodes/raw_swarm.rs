@@ -24,9 +24,9 @@ use crate::{
     nodes::{
         collection::{
             CollectionEvent,
-            CollectionNodeAccept,
             CollectionReachEvent,
             CollectionStream,
+            ConnectionId,
             PeerMut as CollecPeerMut,
             ReachAttemptId
         },
@@ -36,16 +36,20 @@ use crate::{
         },
         node::Substream
     },
-    nodes::listeners::{ListenersEvent, ListenersStream},
+    nodes::listeners::{ListenerId, ListenersEvent, ListenersStream},
     transport::Transport
 };
-use fnv::FnvHashMap;
-use futures::{prelude::*, future};
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::{prelude::*, sync::{mpsc, oneshot}};
+use multiaddr::Protocol;
+use smallvec::SmallVec;
 use std::{
-    collections::hash_map::{Entry, OccupiedEntry},
+    cmp,
+    collections::{VecDeque, hash_map::{Entry, OccupiedEntry}},
     error,
     fmt,
-    io::{Error as IoError, ErrorKind as IoErrorKind}
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    net::IpAddr
 };
 
 /// Implementation of `Stream` that handles the nodes.
@@ -63,6 +67,18 @@ where
     /// The reach attempts of the swarm.
     /// This needs to be a separate struct in order to handle multiple mutable borrows issues.
     reach_attempts: ReachAttempts,
+
+    /// Limits on the number of connections we accept to maintain.
+    limits: ConnectionLimits,
+
+    /// Dialing attempts whose addresses are handed out by the swarm.
+    dials: FnvHashMap<ReachAttemptId, ScheduledDial<TTrans>>,
+
+    /// Dialing attempts that have addresses left to hand out, in the order they were started.
+    dial_queue: VecDeque<ReachAttemptId>,
+
+    /// How we dial out.
+    dial_config: DialConfig,
 }
 
 #[derive(Debug)]
@@ -77,8 +93,307 @@ struct ReachAttempts {
     /// the peer ID.
     other_reach_attempts: Vec<(ReachAttemptId, ConnectedPoint)>,
 
-    /// For each peer ID we're connected to, contains the endpoint we're connected to.
-    connected_points: FnvHashMap<PeerId, ConnectedPoint>,
+    /// For each peer ID we're connected to, contains the connections to this peer and the
+    /// endpoint of each of them, from the oldest to the newest. The lists are never empty.
+    connected_points: FnvHashMap<PeerId, SmallVec<[(ConnectionId, ConnectedPoint); 2]>>,
+}
+
+impl ReachAttempts {
+    /// Returns the number of established connections.
+    fn num_established(&self) -> usize {
+        self.connected_points.values().map(|c| c.len()).sum()
+    }
+
+    /// Returns the number of established connections to the given peer.
+    fn num_established_with(&self, peer_id: &PeerId) -> usize {
+        self.connected_points.get(peer_id).map(|c| c.len()).unwrap_or(0)
+    }
+
+    /// Returns the number of incoming connections that are being negotiated.
+    fn num_pending_incoming(&self) -> usize {
+        self.other_reach_attempts
+            .iter()
+            .filter(|&(_, endpoint)| endpoint.is_listener())
+            .count()
+    }
+
+    /// Returns the number of outgoing connections that are being negotiated.
+    fn num_pending_outgoing(&self) -> usize {
+        let unknown_peer = self.other_reach_attempts
+            .iter()
+            .filter(|&(_, endpoint)| endpoint.is_dialer())
+            .count();
+        self.out_reach_attempts.len() + unknown_peer
+    }
+
+    /// Returns the number of established or pending connections whose remote has the given IP
+    /// address.
+    fn num_with_ip(&self, ip: &IpAddr) -> usize {
+        self.connected_points
+            .values()
+            .flat_map(|c| c.iter().map(|(_, endpoint)| endpoint))
+            .chain(self.other_reach_attempts.iter().map(|(_, endpoint)| endpoint))
+            .filter(|endpoint| endpoint.remote_ip().as_ref() == Some(ip))
+            .count()
+    }
+}
+
+/// Limits on the number of connections that a `RawSwarm` accepts to maintain.
+///
+/// Connections that would exceed one of these limits are closed, and a `ConnectionRejected`
+/// event is generated. By default, no limit is enforced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    max_established: Option<usize>,
+    max_established_per_peer: Option<usize>,
+    max_pending_incoming: Option<usize>,
+    max_pending_outgoing: Option<usize>,
+    max_per_ip: Option<usize>,
+}
+
+impl ConnectionLimits {
+    /// Builds a `ConnectionLimits` that doesn't enforce any limit.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the maximum number of established connections, incoming and outgoing combined.
+    #[inline]
+    pub fn max_established(mut self, value: usize) -> Self {
+        self.max_established = Some(value);
+        self
+    }
+
+    /// Sets the maximum number of established connections to a single peer.
+    ///
+    /// When this limit is reached, a new connection to the peer is refused instead of
+    /// replacing the existing one.
+    #[inline]
+    pub fn max_established_per_peer(mut self, value: usize) -> Self {
+        self.max_established_per_peer = Some(value);
+        self
+    }
+
+    /// Sets the maximum number of incoming connections that can be negotiated at the same time.
+    #[inline]
+    pub fn max_pending_incoming(mut self, value: usize) -> Self {
+        self.max_pending_incoming = Some(value);
+        self
+    }
+
+    /// Sets the maximum number of dialing attempts that can be in progress at the same time.
+    #[inline]
+    pub fn max_pending_outgoing(mut self, value: usize) -> Self {
+        self.max_pending_outgoing = Some(value);
+        self
+    }
+
+    /// Sets the maximum number of established and pending connections with a remote that has a
+    /// given IP address.
+    ///
+    /// This limit is only enforced on incoming connections.
+    #[inline]
+    pub fn max_per_ip(mut self, value: usize) -> Self {
+        self.max_per_ip = Some(value);
+        self
+    }
+
+    /// Returns the limit that an additional incoming connection from `send_back_addr` would
+    /// exceed, if any.
+    fn check_incoming(&self, reach_attempts: &ReachAttempts, send_back_addr: &Multiaddr)
+        -> Option<ConnectionLimit>
+    {
+        if let Some(max) = self.max_pending_incoming {
+            if reach_attempts.num_pending_incoming() >= max {
+                return Some(ConnectionLimit::PendingIncoming(max));
+            }
+        }
+
+        if let (Some(max), Some(ip)) = (self.max_per_ip, multiaddr_ip(send_back_addr)) {
+            if reach_attempts.num_with_ip(&ip) >= max {
+                return Some(ConnectionLimit::PerIp(max));
+            }
+        }
+
+        None
+    }
+
+    /// Returns the limit that an additional dialing attempt would exceed, if any.
+    fn check_outgoing(&self, reach_attempts: &ReachAttempts) -> Option<ConnectionLimit> {
+        if let Some(max) = self.max_pending_outgoing {
+            if reach_attempts.num_pending_outgoing() >= max {
+                return Some(ConnectionLimit::PendingOutgoing(max));
+            }
+        }
+
+        if let Some(max) = self.max_established {
+            if reach_attempts.num_established() >= max {
+                return Some(ConnectionLimit::Established(max));
+            }
+        }
+
+        None
+    }
+
+    /// Returns the limit that accepting a newly-established connection to `peer_id` would
+    /// exceed, if any.
+    fn check_established(&self, reach_attempts: &ReachAttempts, peer_id: &PeerId)
+        -> Option<ConnectionLimit>
+    {
+        if let Some(max) = self.max_established_per_peer {
+            if reach_attempts.num_established_with(peer_id) >= max {
+                return Some(ConnectionLimit::EstablishedPerPeer(max));
+            }
+        }
+
+        if let Some(max) = self.max_established {
+            if reach_attempts.num_established() >= max {
+                return Some(ConnectionLimit::Established(max));
+            }
+        }
+
+        None
+    }
+}
+
+/// Limit of a `ConnectionLimits` that has been reached. Contains the value of the limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionLimit {
+    /// Maximum number of established connections.
+    Established(usize),
+    /// Maximum number of established connections to a single peer.
+    EstablishedPerPeer(usize),
+    /// Maximum number of incoming connections being negotiated.
+    PendingIncoming(usize),
+    /// Maximum number of dialing attempts in progress.
+    PendingOutgoing(usize),
+    /// Maximum number of connections with the same remote IP address.
+    PerIp(usize),
+}
+
+impl fmt::Display for ConnectionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectionLimit::Established(max) =>
+                write!(f, "Maximum number of established connections ({}) reached", max),
+            ConnectionLimit::EstablishedPerPeer(max) =>
+                write!(f, "Maximum number of connections per peer ({}) reached", max),
+            ConnectionLimit::PendingIncoming(max) =>
+                write!(f, "Maximum number of pending incoming connections ({}) reached", max),
+            ConnectionLimit::PendingOutgoing(max) =>
+                write!(f, "Maximum number of pending outgoing connections ({}) reached", max),
+            ConnectionLimit::PerIp(max) =>
+                write!(f, "Maximum number of connections per IP address ({}) reached", max),
+        }
+    }
+}
+
+/// Configuration of how a `RawSwarm` dials out.
+///
+/// By default, the addresses of a peer are tried one by one and no limit is put on the number of
+/// dials in progress at the same time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialConfig {
+    max_dials: Option<usize>,
+    max_dials_per_peer: usize,
+    interleave_addresses: bool,
+}
+
+impl Default for DialConfig {
+    fn default() -> Self {
+        DialConfig {
+            max_dials: None,
+            max_dials_per_peer: 1,
+            interleave_addresses: false,
+        }
+    }
+}
+
+impl DialConfig {
+    /// Builds the default `DialConfig`.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the maximum number of addresses that are dialed at the same time, all peers combined.
+    ///
+    /// Addresses beyond this limit wait for another dial to finish before being dialed. A value
+    /// of zero is treated as one.
+    #[inline]
+    pub fn max_dials(mut self, value: usize) -> Self {
+        self.max_dials = Some(cmp::max(value, 1));
+        self
+    }
+
+    /// Sets the maximum number of addresses of a single peer that are dialed at the same time.
+    ///
+    /// As soon as one of these dials succeeds, the other ones are cancelled. The default value
+    /// of one tries the addresses one by one. A value of zero is treated as one.
+    #[inline]
+    pub fn max_dials_per_peer(mut self, value: usize) -> Self {
+        self.max_dials_per_peer = cmp::max(value, 1);
+        self
+    }
+
+    /// If true, the addresses of a peer are reordered so that consecutive addresses alternate
+    /// between IPv4 and IPv6 and between TCP and WebSockets, while preserving the order of the
+    /// addresses of the same kind. Combined with `max_dials_per_peer`, this dials the different
+    /// kinds of addresses in parallel. Disabled by default, as it changes the order in which
+    /// the addresses were given.
+    #[inline]
+    pub fn interleave_addresses(mut self, value: bool) -> Self {
+        self.interleave_addresses = value;
+        self
+    }
+}
+
+/// Reorders `addrs` so that consecutive addresses are of a different kind whenever possible.
+/// Addresses of the same kind keep their relative order.
+fn interleave_addresses(addrs: Vec<Multiaddr>) -> Vec<Multiaddr> {
+    let num_addrs = addrs.len();
+    let mut groups: Vec<((u8, u8), VecDeque<Multiaddr>)> = Vec::new();
+    for addr in addrs {
+        let kind = address_kind(&addr);
+        match groups.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, group)) => group.push_back(addr),
+            None => groups.push((kind, Some(addr).into_iter().collect())),
+        }
+    }
+
+    let mut interleaved = Vec::with_capacity(num_addrs);
+    while interleaved.len() < num_addrs {
+        for (_, group) in groups.iter_mut() {
+            interleaved.extend(group.pop_front());
+        }
+    }
+    interleaved
+}
+
+/// Returns the IP version and the transport of an address, as used by `interleave_addresses`.
+fn address_kind(addr: &Multiaddr) -> (u8, u8) {
+    let mut ip_version = 0;
+    let mut transport = 0;
+    for proto in addr.iter() {
+        match proto {
+            Protocol::Ip4(_) | Protocol::Dns4(_) => ip_version = 4,
+            Protocol::Ip6(_) | Protocol::Dns6(_) => ip_version = 6,
+            Protocol::Tcp(_) => transport = cmp::max(transport, 1),
+            Protocol::Ws | Protocol::Wss => transport = 2,
+            _ => (),
+        }
+    }
+    (ip_version, transport)
+}
+
+/// Returns the IP address contained in a multiaddress, if any.
+fn multiaddr_ip(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().filter_map(|proto| match proto {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    }).next()
 }
 
 /// Attempt to reach a peer.
@@ -92,13 +407,253 @@ struct OutReachAttempt {
     next_attempts: Vec<Multiaddr>,
 }
 
+/// Dialing attempt whose addresses are handed out by the `RawSwarm`.
+///
+/// The dials themselves, including the upgrades of the transport, run inside the node task of
+/// the reach attempt as a `DialTask`. The swarm only decides when each address gets dialed, in
+/// order to respect the limits of the `DialConfig`.
+struct ScheduledDial<TTrans>
+where
+    TTrans: Transport,
+{
+    /// Peer we are trying to reach, or `None` if unknown. The addresses of a known peer that
+    /// haven't been dialed yet are in its `OutReachAttempt`.
+    peer_id: Option<PeerId>,
+    /// Address to dial before the ones of the `OutReachAttempt`, if any.
+    queued_addr: Option<Multiaddr>,
+    /// Maximum number of addresses dialed at the same time, from the `DialConfig` in use when
+    /// the attempt started.
+    max_in_flight: usize,
+    /// Number of addresses handed out to the `DialTask` whose outcome hasn't been reported yet.
+    num_in_flight: usize,
+    /// Hands out addresses to the `DialTask`, with their dial. The dial is `None` if the
+    /// transport doesn't support the address. Dropped once all the addresses have been handed
+    /// out, which lets the task know when its last dial has failed.
+    addrs: Option<mpsc::UnboundedSender<(Multiaddr, Option<TTrans::Dial>)>>,
+    /// Outcome of each dial of the `DialTask`.
+    reports: mpsc::UnboundedReceiver<DialReport>,
+    /// Dropped once the final `DialReport` has been processed, which lets the `DialTask`
+    /// resolve. Only used if `max_in_flight` is greater than one.
+    ack: Option<oneshot::Sender<()>>,
+}
+
+impl<TTrans> fmt::Debug for ScheduledDial<TTrans>
+where
+    TTrans: Transport,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("ScheduledDial")
+            .field("peer_id", &self.peer_id)
+            .field("queued_addr", &self.queued_addr)
+            .field("num_in_flight", &self.num_in_flight)
+            .finish()
+    }
+}
+
+/// Outcome of a dial of a `DialTask`.
+#[derive(Debug)]
+enum DialReport {
+    /// A dial has failed, and other addresses are being dialed or remain to be dialed.
+    Failed {
+        /// The address that failed.
+        addr: Multiaddr,
+        /// The error that happened.
+        error: RawSwarmReachError,
+    },
+    /// The dialing attempt has finished, either because the dial of this address has succeeded
+    /// or because it was the last one and has failed. The outcome itself is reported by the
+    /// collection of nodes.
+    Finished {
+        /// The address whose dial has finished the attempt.
+        addr: Multiaddr,
+    },
+}
+
+/// Future passed to the collection of nodes for a `ScheduledDial`. Dials the addresses handed
+/// out by the swarm and resolves once one of the dials has succeeded or once all of them have
+/// failed.
+///
+/// If several addresses can be dialed at the same time, the task waits for the swarm to have
+/// processed all of its reports before resolving, so that the swarm knows which address has
+/// finished the attempt. Otherwise, the swarm can't hand out an address before having processed
+/// the failure of the previous one, and the last address handed out is the one that finishes
+/// the attempt.
+struct DialTask<TDial>
+where
+    TDial: Future,
+{
+    /// Peer we are trying to reach, or `None` if unknown.
+    peer_id: Option<PeerId>,
+    /// Addresses handed out by the swarm.
+    addrs: mpsc::UnboundedReceiver<(Multiaddr, Option<TDial>)>,
+    /// True if the swarm won't hand out any more address.
+    addrs_done: bool,
+    /// Dials in progress, with their address.
+    in_flight: SmallVec<[(Multiaddr, Option<TDial>); 2]>,
+    /// Reports the outcome of each dial to the swarm.
+    reports: mpsc::UnboundedSender<DialReport>,
+    /// Resolves once the swarm has processed our final report. `None` if we don't need to wait
+    /// for it.
+    ack: Option<oneshot::Receiver<()>>,
+    /// Outcome of the attempt, once known.
+    outcome: Option<Result<TDial::Item, RawSwarmReachError>>,
+}
+
+impl<TDial, TMuxer> Future for DialTask<TDial>
+where
+    TDial: Future<Item = (PeerId, TMuxer), Error = IoError>,
+{
+    type Item = (PeerId, TMuxer);
+    type Error = RawSwarmReachError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.outcome.is_none() {
+            self.poll_dials();
+        }
+
+        if self.outcome.is_none() {
+            if self.addrs_done && self.in_flight.is_empty() {
+                // Only happens if the swarm has forgotten about this attempt.
+                let err = IoError::new(IoErrorKind::Other, "dialing attempt aborted");
+                return Err(RawSwarmReachError::Transport(err));
+            }
+            return Ok(Async::NotReady);
+        }
+
+        if let Some(ref mut ack) = self.ack {
+            if let Ok(Async::NotReady) = ack.poll() {
+                return Ok(Async::NotReady);
+            }
+        }
+
+        match self.outcome.take().expect("outcome is Some, as checked above") {
+            Ok(output) => Ok(Async::Ready(output)),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<TDial, TMuxer> DialTask<TDial>
+where
+    TDial: Future<Item = (PeerId, TMuxer), Error = IoError>,
+{
+    /// Starts the dials handed out by the swarm and polls the ones in progress, until the
+    /// outcome of the attempt is known or nothing can make progress.
+    fn poll_dials(&mut self) {
+        while !self.addrs_done {
+            match self.addrs.poll() {
+                Ok(Async::Ready(Some(dial))) => self.in_flight.push(dial),
+                Ok(Async::Ready(None)) | Err(()) => self.addrs_done = true,
+                Ok(Async::NotReady) => break,
+            }
+        }
+
+        let mut n = 0;
+        while n < self.in_flight.len() {
+            let result = match self.in_flight[n].1 {
+                Some(ref mut future) => match future.poll() {
+                    Ok(Async::NotReady) => {
+                        n += 1;
+                        continue;
+                    },
+                    Ok(Async::Ready((peer_id, muxer))) => match self.peer_id {
+                        Some(ref expected) if *expected != peer_id =>
+                            Err(RawSwarmReachError::PeerIdMismatch { obtained: peer_id }),
+                        _ => Ok((peer_id, muxer)),
+                    },
+                    Err(err) => Err(RawSwarmReachError::Transport(err)),
+                },
+                None => {
+                    // TODO: better error reporting
+                    let msg = format!("unsupported multiaddr {}", self.in_flight[n].0);
+                    Err(RawSwarmReachError::Transport(IoError::new(IoErrorKind::Other, msg)))
+                },
+            };
+
+            let (addr, _) = self.in_flight.remove(n);
+            let report = match result {
+                Ok(output) => {
+                    // Dropping the other dials cancels them.
+                    self.in_flight.clear();
+                    self.outcome = Some(Ok(output));
+                    DialReport::Finished { addr }
+                },
+                Err(error) => if self.addrs_done && self.in_flight.is_empty() {
+                    self.outcome = Some(Err(error));
+                    DialReport::Finished { addr }
+                } else {
+                    DialReport::Failed { addr, error }
+                },
+            };
+
+            let _ = self.reports.unbounded_send(report);
+        }
+    }
+}
+
+/// Builds a `ScheduledDial` and the `DialTask` that goes with it.
+fn scheduled_dial<TTrans>(peer_id: Option<PeerId>, max_in_flight: usize)
+    -> (ScheduledDial<TTrans>, DialTask<TTrans::Dial>)
+where
+    TTrans: Transport,
+{
+    let (addrs_tx, addrs_rx) = mpsc::unbounded();
+    let (reports_tx, reports_rx) = mpsc::unbounded();
+    let (ack_tx, ack_rx) = if max_in_flight > 1 {
+        let (tx, rx) = oneshot::channel();
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+
+    let dial = ScheduledDial {
+        peer_id: peer_id.clone(),
+        queued_addr: None,
+        max_in_flight,
+        num_in_flight: 0,
+        addrs: Some(addrs_tx),
+        reports: reports_rx,
+        ack: ack_tx,
+    };
+
+    let task = DialTask {
+        peer_id,
+        addrs: addrs_rx,
+        addrs_done: false,
+        in_flight: SmallVec::new(),
+        reports: reports_tx,
+        ack: ack_rx,
+        outcome: None,
+    };
+
+    (dial, task)
+}
+
 /// Event that can happen on the `RawSwarm`.
 pub enum RawSwarmEvent<'a, TTrans: 'a, TInEvent: 'a, TOutEvent: 'a, THandler: 'a, THandlerErr: 'a>
 where
     TTrans: Transport,
 {
-    /// One of the listeners gracefully closed.
+    /// One of the listeners has started listening on a new address.
+    NewListenerAddress {
+        /// Identifier of the listener.
+        listener_id: ListenerId,
+        /// The new address.
+        listen_addr: Multiaddr,
+    },
+
+    /// One of the listeners is no longer listening on an address.
+    ExpiredListenerAddress {
+        /// Identifier of the listener.
+        listener_id: ListenerId,
+        /// The address that has expired.
+        listen_addr: Multiaddr,
+    },
+
+    /// One of the listeners closed, either gracefully or with an error.
     ListenerClosed {
+        /// Identifier of the listener which closed.
+        listener_id: ListenerId,
         /// Address of the listener which closed.
         listen_addr: Multiaddr,
         /// The listener which closed.
@@ -124,20 +679,13 @@ where
     },
 
     /// A new connection to a peer has been opened.
+    ///
+    /// This connection is added to the existing connections to this peer, if any.
     Connected {
         /// Id of the peer.
         peer_id: PeerId,
-        /// If `Listener`, then we received the connection. If `Dial`, then it's a connection that
-        /// we opened.
-        endpoint: ConnectedPoint,
-    },
-
-    /// A connection to a peer has been replaced with a new one.
-    Replaced {
-        /// Id of the peer.
-        peer_id: PeerId,
-        /// Endpoint we were connected to.
-        closed_endpoint: ConnectedPoint,
+        /// Identifier of the new connection.
+        connection_id: ConnectionId,
         /// If `Listener`, then we received the connection. If `Dial`, then it's a connection that
         /// we opened.
         endpoint: ConnectedPoint,
@@ -150,6 +698,8 @@ where
     NodeClosed {
         /// Identifier of the node.
         peer_id: PeerId,
+        /// Identifier of the connection that has been closed.
+        connection_id: ConnectionId,
         /// Endpoint we were connected to.
         endpoint: ConnectedPoint,
     },
@@ -158,6 +708,8 @@ where
     NodeError {
         /// Identifier of the node.
         peer_id: PeerId,
+        /// Identifier of the connection that has errored.
+        connection_id: ConnectionId,
         /// Endpoint we were connected to.
         endpoint: ConnectedPoint,
         /// The error that happened.
@@ -197,9 +749,22 @@ where
     NodeEvent {
         /// Id of the node that produced the event.
         peer_id: PeerId,
+        /// Identifier of the connection that produced the event.
+        connection_id: ConnectionId,
         /// Event that was produced by the node.
         event: TOutEvent,
     },
+
+    /// A connection has been closed because it would have exceeded one of the
+    /// `ConnectionLimits` of the swarm.
+    ConnectionRejected {
+        /// Endpoint of the connection that has been rejected.
+        endpoint: ConnectedPoint,
+        /// Id of the peer, if the connection was rejected after it has been negotiated.
+        peer_id: Option<PeerId>,
+        /// The limit that has been reached.
+        limit: ConnectionLimit,
+    },
 }
 
 impl<'a, TTrans, TInEvent, TOutEvent, THandler, THandlerErr> fmt::Debug for RawSwarmEvent<'a, TTrans, TInEvent, TOutEvent, THandler, THandlerErr>
@@ -210,8 +775,21 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match *self {
-            RawSwarmEvent::ListenerClosed { ref listen_addr, listener: _, ref result } => {
+            RawSwarmEvent::NewListenerAddress { ref listener_id, ref listen_addr } => {
+                f.debug_struct("NewListenerAddress")
+                    .field("listener_id", listener_id)
+                    .field("listen_addr", listen_addr)
+                    .finish()
+            }
+            RawSwarmEvent::ExpiredListenerAddress { ref listener_id, ref listen_addr } => {
+                f.debug_struct("ExpiredListenerAddress")
+                    .field("listener_id", listener_id)
+                    .field("listen_addr", listen_addr)
+                    .finish()
+            }
+            RawSwarmEvent::ListenerClosed { ref listener_id, ref listen_addr, listener: _, ref result } => {
                 f.debug_struct("ListenerClosed")
+                    .field("listener_id", listener_id)
                     .field("listen_addr", listen_addr)
                     .field("result", result)
                     .finish()
@@ -229,28 +807,24 @@ where
                     .field("error", error)
                     .finish()
             }
-            RawSwarmEvent::Connected { ref peer_id, ref endpoint } => {
+            RawSwarmEvent::Connected { ref peer_id, ref connection_id, ref endpoint } => {
                 f.debug_struct("Connected")
                     .field("peer_id", peer_id)
+                    .field("connection_id", connection_id)
                     .field("endpoint", endpoint)
                     .finish()
             }
-            RawSwarmEvent::Replaced { ref peer_id, ref closed_endpoint, ref endpoint } => {
-                f.debug_struct("Replaced")
-                    .field("peer_id", peer_id)
-                    .field("closed_endpoint", closed_endpoint)
-                    .field("endpoint", endpoint)
-                    .finish()
-            }
-            RawSwarmEvent::NodeClosed { ref peer_id, ref endpoint } => {
+            RawSwarmEvent::NodeClosed { ref peer_id, ref connection_id, ref endpoint } => {
                 f.debug_struct("NodeClosed")
                     .field("peer_id", peer_id)
+                    .field("connection_id", connection_id)
                     .field("endpoint", endpoint)
                     .finish()
             }
-            RawSwarmEvent::NodeError { ref peer_id, ref endpoint, ref error } => {
+            RawSwarmEvent::NodeError { ref peer_id, ref connection_id, ref endpoint, ref error } => {
                 f.debug_struct("NodeError")
                     .field("peer_id", peer_id)
+                    .field("connection_id", connection_id)
                     .field("endpoint", endpoint)
                     .field("error", error)
                     .finish()
@@ -269,12 +843,20 @@ where
                     .field("error", error)
                     .finish()
             }
-            RawSwarmEvent::NodeEvent { ref peer_id, ref event } => {
+            RawSwarmEvent::NodeEvent { ref peer_id, ref connection_id, ref event } => {
                 f.debug_struct("NodeEvent")
                     .field("peer_id", peer_id)
+                    .field("connection_id", connection_id)
                     .field("event", event)
                     .finish()
             }
+            RawSwarmEvent::ConnectionRejected { ref endpoint, ref peer_id, ref limit } => {
+                f.debug_struct("ConnectionRejected")
+                    .field("endpoint", endpoint)
+                    .field("peer_id", peer_id)
+                    .field("limit", limit)
+                    .finish()
+            }
         }
     }
 }
@@ -453,6 +1035,14 @@ impl ConnectedPoint {
             ConnectedPoint::Listener { .. } => true,
         }
     }
+
+    /// Returns the IP address of the remote, if it is known.
+    fn remote_ip(&self) -> Option<IpAddr> {
+        match *self {
+            ConnectedPoint::Dialer { ref address } => multiaddr_ip(address),
+            ConnectedPoint::Listener { ref send_back_addr, .. } => multiaddr_ip(send_back_addr),
+        }
+    }
 }
 
 /// Information about an incoming connection currently being negotiated.
@@ -497,9 +1087,50 @@ where
                 other_reach_attempts: Vec::new(),
                 connected_points: Default::default(),
             },
+            limits: ConnectionLimits::default(),
+            dials: Default::default(),
+            dial_queue: VecDeque::new(),
+            dial_config: DialConfig::default(),
         }
     }
 
+    /// Same as `new`, but enforces the given limits on the number of connections.
+    #[inline]
+    pub fn with_limits(transport: TTrans, local_peer_id: PeerId, limits: ConnectionLimits) -> Self {
+        let mut swarm = RawSwarm::new(transport, local_peer_id);
+        swarm.limits = limits;
+        swarm
+    }
+
+    /// Returns the limits on the number of connections.
+    #[inline]
+    pub fn connection_limits(&self) -> &ConnectionLimits {
+        &self.limits
+    }
+
+    /// Modifies the limits on the number of connections.
+    ///
+    /// Existing connections are not affected; the new limits only apply to new connections.
+    #[inline]
+    pub fn set_connection_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns the configuration of how we dial out.
+    #[inline]
+    pub fn dial_config(&self) -> &DialConfig {
+        &self.dial_config
+    }
+
+    /// Modifies the configuration of how we dial out.
+    ///
+    /// The addresses that are already being dialed are not affected, and the dialing attempts
+    /// in progress keep the maximum number of addresses per peer they were started with.
+    #[inline]
+    pub fn set_dial_config(&mut self, config: DialConfig) {
+        self.dial_config = config;
+    }
+
     /// Returns the transport passed when building this object.
     #[inline]
     pub fn transport(&self) -> &TTrans {
@@ -507,11 +1138,23 @@ where
     }
 
     /// Start listening on the given multiaddress.
+    ///
+    /// On success, a `NewListenerAddress` event is later produced with the address actually
+    /// listened on.
     #[inline]
-    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<Multiaddr, Multiaddr> {
+    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<ListenerId, Multiaddr> {
         self.listeners.listen_on(addr)
     }
 
+    /// Closes the listener with the given identifier.
+    ///
+    /// An `ExpiredListenerAddress` event is later produced for its address. Returns `false` if
+    /// no listener with this identifier exists.
+    #[inline]
+    pub fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.listeners.remove_listener(id)
+    }
+
     /// Returns an iterator that produces the list of addresses we're listening on.
     #[inline]
     pub fn listeners(&self) -> impl Iterator<Item = &Multiaddr> {
@@ -545,9 +1188,35 @@ where
         &self.reach_attempts.local_peer_id
     }
 
+    /// Starts shutting down the swarm.
+    ///
+    /// Closes all the listeners, interrupts the pending dialing attempts and incoming
+    /// connections, and starts a graceful shutdown of the established connections. The
+    /// connections keep generating events until they are closed, at which point a `NodeClosed`
+    /// or `NodeError` event is generated for each of them.
+    pub fn start_shutdown(&mut self) {
+        self.listeners.clear();
+
+        for (_, attempt) in self.reach_attempts.out_reach_attempts.drain() {
+            let _ = self.active_nodes.interrupt(attempt.id);
+        }
+        for (id, _) in self.reach_attempts.other_reach_attempts.drain(..) {
+            let _ = self.active_nodes.interrupt(id);
+        }
+        self.dials.clear();
+        self.dial_queue.clear();
+
+        self.active_nodes.shutdown_connections();
+    }
+
     /// Dials a multiaddress without knowing the peer ID we're going to obtain.
     ///
     /// The second parameter is the handler to use if we manage to reach a node.
+    ///
+    /// Returns an error if the address is not supported by the transport, or if dialing would
+    /// exceed the `ConnectionLimits` of the swarm. If the maximum number of simultaneous dials of
+    /// the `DialConfig` is reached, the address is dialed later, and an unsupported address
+    /// results in an `UnknownPeerDialError` event.
     pub fn dial(&mut self, addr: Multiaddr, handler: THandler) -> Result<(), Multiaddr>
     where
         TTrans: Transport<Output = (PeerId, TMuxer)>,
@@ -558,14 +1227,32 @@ where
         TInEvent: Send + 'static,
         TOutEvent: Send + 'static,
     {
-        let future = match self.transport().clone().dial(addr.clone()) {
-            Ok(fut) => fut,
-            Err((_, addr)) => return Err(addr),
-        };
+        if self.limits.check_outgoing(&self.reach_attempts).is_some() {
+            return Err(addr);
+        }
+
+        let (mut dial, task) = scheduled_dial(None, 1);
+        if self.num_dials() < self.dial_config.max_dials.unwrap_or(usize::max_value()) {
+            match self.transport().clone().dial(addr.clone()) {
+                Ok(fut) => {
+                    if let Some(addrs) = dial.addrs.take() {
+                        let _ = addrs.unbounded_send((addr.clone(), Some(fut)));
+                    }
+                    dial.num_in_flight = 1;
+                },
+                Err((_, addr)) => return Err(addr),
+            }
+        } else {
+            dial.queued_addr = Some(addr.clone());
+        }
 
         let connected_point = ConnectedPoint::Dialer { address: addr };
-        let reach_id = self.active_nodes.add_reach_attempt(future.map_err(RawSwarmReachError::Transport), handler);
+        let reach_id = self.active_nodes.add_reach_attempt(task, handler);
         self.reach_attempts.other_reach_attempts.push((reach_id, connected_point));
+        if dial.queued_addr.is_some() {
+            self.dial_queue.push_back(reach_id);
+        }
+        self.dials.insert(reach_id, dial);
         Ok(())
     }
 
@@ -603,6 +1290,28 @@ where
             })
     }
 
+    /// Returns the list of connections, with the peer and the endpoint of each connection.
+    ///
+    /// A peer is produced multiple times if we have multiple connections to it.
+    #[inline]
+    pub fn connections(&self) -> impl Iterator<Item = (&PeerId, ConnectionId, &ConnectedPoint)> {
+        self.reach_attempts.connected_points
+            .iter()
+            .flat_map(|(peer_id, c)| c.iter().map(move |(id, endpoint)| (peer_id, *id, endpoint)))
+    }
+
+    /// Returns true if we have at least one connection to the given peer.
+    #[inline]
+    pub fn is_connected(&self, peer_id: &PeerId) -> bool {
+        self.reach_attempts.connected_points.contains_key(peer_id)
+    }
+
+    /// Returns the number of connections we have to the given peer.
+    #[inline]
+    pub fn num_connections_to(&self, peer_id: &PeerId) -> usize {
+        self.reach_attempts.num_established_with(peer_id)
+    }
+
     /// Sends an event to all nodes.
     #[inline]
     pub fn broadcast_event(&mut self, event: &TInEvent)
@@ -662,38 +1371,148 @@ where
         TInEvent: Send + 'static,
         TOutEvent: Send + 'static,
     {
-        let reach_id = match self.transport().clone().dial(first.clone()) {
-            Ok(fut) => {
-                let expected_peer_id = peer_id.clone();
-                let fut = fut
-                    .map_err(RawSwarmReachError::Transport)
-                    .and_then(move |(actual_peer_id, muxer)| {
-                        if actual_peer_id == expected_peer_id {
-                            Ok((actual_peer_id, muxer))
-                        } else {
-                            Err(RawSwarmReachError::PeerIdMismatch { obtained: actual_peer_id })
-                        }
-                    });
-                self.active_nodes.add_reach_attempt(fut, handler)
-            },
-            Err((_, addr)) => {
-                // TODO: better error reporting
-                let msg = format!("unsupported multiaddr {}", addr);
-                let fut = future::err(RawSwarmReachError::Transport(IoError::new(IoErrorKind::Other, msg)));
-                self.active_nodes.add_reach_attempt(fut, handler)
-            },
+        let (first, rest) = if self.dial_config.interleave_addresses && !rest.is_empty() {
+            let mut addrs = interleave_addresses(Some(first).into_iter().chain(rest).collect());
+            let first = addrs.remove(0);
+            (first, addrs)
+        } else {
+            (first, rest)
         };
 
+        let (mut dial, task) = scheduled_dial(Some(peer_id.clone()), self.dial_config.max_dials_per_peer);
+        dial.queued_addr = Some(first.clone());
+        let reach_id = self.active_nodes.add_reach_attempt(task, handler);
+
         let former = self.reach_attempts.out_reach_attempts.insert(
-            peer_id,
+            peer_id.clone(),
             OutReachAttempt {
                 id: reach_id,
-                cur_attempted: first,
+                cur_attempted: first.clone(),
                 next_attempts: rest,
             },
         );
-
         debug_assert!(former.is_none());
+
+        self.dials.insert(reach_id, dial);
+        self.dial_queue.push_back(reach_id);
+        self.start_dials();
+    }
+
+    /// Returns the number of addresses being dialed.
+    fn num_dials(&self) -> usize {
+        self.dials.values().map(|dial| dial.num_in_flight).sum()
+    }
+
+    /// Hands out the addresses that are waiting to be dialed, within the limits of the
+    /// `DialConfig`. The attempts are served in the order they were started.
+    fn start_dials(&mut self) {
+        let max_dials = self.dial_config.max_dials.unwrap_or(usize::max_value());
+        let mut num_dials = self.num_dials();
+        let out_reach_attempts = &mut self.reach_attempts.out_reach_attempts;
+        let transport = self.listeners.transport();
+
+        let mut n = 0;
+        while n < self.dial_queue.len() && num_dials < max_dials {
+            let reach_id = self.dial_queue[n];
+            let dial = match self.dials.get_mut(&reach_id) {
+                Some(dial) => dial,
+                None => {
+                    self.dial_queue.remove(n);
+                    continue;
+                },
+            };
+            let mut attempt = dial.peer_id.as_ref()
+                .and_then(|peer_id| out_reach_attempts.get_mut(peer_id))
+                .filter(|attempt| attempt.id == reach_id);
+
+            while num_dials < max_dials && dial.num_in_flight < dial.max_in_flight {
+                let addr = match (dial.queued_addr.take(), attempt.as_mut()) {
+                    (Some(addr), _) => addr,
+                    (None, Some(attempt)) if !attempt.next_attempts.is_empty() => {
+                        let addr = attempt.next_attempts.remove(0);
+                        attempt.cur_attempted = addr.clone();
+                        addr
+                    },
+                    (None, _) => break,
+                };
+
+                let future = transport.clone().dial(addr.clone()).ok();
+                if let Some(ref addrs) = dial.addrs {
+                    let _ = addrs.unbounded_send((addr, future));
+                }
+                dial.num_in_flight += 1;
+                num_dials += 1;
+            }
+
+            let exhausted = dial.queued_addr.is_none() &&
+                attempt.map(|attempt| attempt.next_attempts.is_empty()).unwrap_or(true);
+            if exhausted {
+                // Lets the `DialTask` know that there is no address left.
+                dial.addrs = None;
+                self.dial_queue.remove(n);
+            } else {
+                n += 1;
+            }
+        }
+    }
+
+    /// Processes the reports of the `DialTask`s and hands out new addresses as dials finish.
+    ///
+    /// Returns the peer, the address, the error and the number of remaining addresses of a
+    /// failed dial that isn't the last one of its attempt. The last dial of an attempt, whether
+    /// it succeeds or fails, is reported by the collection of nodes.
+    fn poll_dials(&mut self) -> Option<(PeerId, Multiaddr, RawSwarmReachError, usize)> {
+        // Drop the dials whose attempt has been interrupted or has finished. The attempts
+        // without a peer ID are gathered in a set first, so that checking each dial is cheap.
+        let reach_attempts = &self.reach_attempts;
+        let other_reach_ids = if self.dials.values().any(|dial| dial.peer_id.is_none()) {
+            reach_attempts.other_reach_attempts.iter().map(|(id, _)| *id).collect()
+        } else {
+            FnvHashSet::default()
+        };
+        self.dials.retain(|reach_id, dial| match dial.peer_id {
+            Some(ref peer_id) => reach_attempts.out_reach_attempts.get(peer_id)
+                .map(|attempt| attempt.id == *reach_id)
+                .unwrap_or(false),
+            None => other_reach_ids.contains(reach_id),
+        });
+        let dials = &self.dials;
+        self.dial_queue.retain(|reach_id| dials.contains_key(reach_id));
+
+        let mut failure = None;
+        let out_reach_attempts = &mut self.reach_attempts.out_reach_attempts;
+        for (reach_id, dial) in self.dials.iter_mut() {
+            while failure.is_none() {
+                let (addr, error) = match dial.reports.poll() {
+                    Ok(Async::Ready(Some(DialReport::Failed { addr, error }))) => (addr, error),
+                    Ok(Async::Ready(Some(DialReport::Finished { addr }))) => {
+                        if let Some(attempt) = dial.peer_id.as_ref()
+                            .and_then(|peer_id| out_reach_attempts.get_mut(peer_id))
+                            .filter(|attempt| attempt.id == *reach_id)
+                        {
+                            attempt.cur_attempted = addr;
+                        }
+                        dial.num_in_flight = 0;
+                        dial.ack = None;
+                        break;
+                    },
+                    Ok(Async::Ready(None)) | Ok(Async::NotReady) | Err(()) => break,
+                };
+
+                dial.num_in_flight -= 1;
+                if let Some(ref peer_id) = dial.peer_id {
+                    let next_attempts = out_reach_attempts.get(peer_id)
+                        .filter(|attempt| attempt.id == *reach_id)
+                        .map(|attempt| attempt.next_attempts.len())
+                        .unwrap_or(0);
+                    let remaining = next_attempts + dial.num_in_flight + dial.queued_addr.iter().count();
+                    failure = Some((peer_id.clone(), addr, error, remaining));
+                }
+            }
+        }
+
+        self.start_dials();
+        failure
     }
 
     /// Provides an API similar to `Stream`, except that it cannot error.
@@ -715,6 +1534,16 @@ where
         match self.listeners.poll() {
             Async::NotReady => (),
             Async::Ready(ListenersEvent::Incoming { upgrade, listen_addr, send_back_addr }) => {
+                if let Some(limit) = self.limits.check_incoming(&self.reach_attempts, &send_back_addr) {
+                    // Dropping the upgrade closes the connection.
+                    drop(upgrade);
+                    return Async::Ready(RawSwarmEvent::ConnectionRejected {
+                        endpoint: ConnectedPoint::Listener { listen_addr, send_back_addr },
+                        peer_id: None,
+                        limit,
+                    });
+                }
+
                 let event = IncomingConnectionEvent {
                     upgrade,
                     listen_addr,
@@ -724,8 +1553,15 @@ where
                 };
                 return Async::Ready(RawSwarmEvent::IncomingConnection(event));
             }
-            Async::Ready(ListenersEvent::Closed { listen_addr, listener, result }) => {
+            Async::Ready(ListenersEvent::NewAddress { listener_id, listen_addr }) => {
+                return Async::Ready(RawSwarmEvent::NewListenerAddress { listener_id, listen_addr });
+            }
+            Async::Ready(ListenersEvent::AddressExpired { listener_id, listen_addr }) => {
+                return Async::Ready(RawSwarmEvent::ExpiredListenerAddress { listener_id, listen_addr });
+            }
+            Async::Ready(ListenersEvent::Closed { listener_id, listen_addr, listener, result }) => {
                 return Async::Ready(RawSwarmEvent::ListenerClosed {
+                    listener_id,
                     listen_addr,
                     listener,
                     result,
@@ -733,12 +1569,22 @@ where
             }
         }
 
+        // Drive the dials forward.
+        if let Some((peer_id, multiaddr, error, remain_addrs_attempt)) = self.poll_dials() {
+            return Async::Ready(RawSwarmEvent::DialError {
+                remain_addrs_attempt,
+                peer_id,
+                multiaddr,
+                error,
+            });
+        }
+
         // Poll the existing nodes.
         let (action, out_event);
         match self.active_nodes.poll() {
             Async::NotReady => return Async::NotReady,
             Async::Ready(CollectionEvent::NodeReached(reach_event)) => {
-                let (a, e) = handle_node_reached(&mut self.reach_attempts, reach_event);
+                let (a, e) = handle_node_reached(&mut self.reach_attempts, &self.limits, reach_event);
                 action = a;
                 out_event = e;
             }
@@ -749,9 +1595,10 @@ where
             }
             Async::Ready(CollectionEvent::NodeError {
                 peer_id,
+                connection_id,
                 error,
             }) => {
-                let endpoint = self.reach_attempts.connected_points.remove(&peer_id)
+                let endpoint = remove_connected_point(&mut self.reach_attempts.connected_points, &peer_id, connection_id)
                     .expect("We insert into connected_points whenever a connection is \
                              opened and remove only when a connection is closed; the \
                              underlying API is guaranteed to always deliver a connection \
@@ -761,12 +1608,13 @@ where
                 action = Default::default();
                 out_event = RawSwarmEvent::NodeError {
                     peer_id,
+                    connection_id,
                     endpoint,
                     error,
                 };
             }
-            Async::Ready(CollectionEvent::NodeClosed { peer_id }) => {
-                let endpoint = self.reach_attempts.connected_points.remove(&peer_id)
+            Async::Ready(CollectionEvent::NodeClosed { peer_id, connection_id }) => {
+                let endpoint = remove_connected_point(&mut self.reach_attempts.connected_points, &peer_id, connection_id)
                     .expect("We insert into connected_points whenever a connection is \
                              opened and remove only when a connection is closed; the \
                              underlying API is guaranteed to always deliver a connection \
@@ -774,11 +1622,11 @@ where
                              messages; QED");
                 debug_assert!(!self.reach_attempts.out_reach_attempts.contains_key(&peer_id));
                 action = Default::default();
-                out_event = RawSwarmEvent::NodeClosed { peer_id, endpoint };
+                out_event = RawSwarmEvent::NodeClosed { peer_id, connection_id, endpoint };
             }
-            Async::Ready(CollectionEvent::NodeEvent { peer_id, event }) => {
+            Async::Ready(CollectionEvent::NodeEvent { peer_id, connection_id, event }) => {
                 action = Default::default();
-                out_event = RawSwarmEvent::NodeEvent { peer_id, event };
+                out_event = RawSwarmEvent::NodeEvent { peer_id, connection_id, event };
             }
         }
 
@@ -827,6 +1675,7 @@ impl<THandler> Default for ActionItem<THandler> {
 /// >           panics will likely happen.
 fn handle_node_reached<'a, TTrans, TMuxer, TInEvent, TOutEvent, THandler, THandlerErr>(
     reach_attempts: &mut ReachAttempts,
+    limits: &ConnectionLimits,
     event: CollectionReachEvent<TInEvent, TOutEvent, THandler, RawSwarmReachError, THandlerErr>
 ) -> (ActionItem<THandler>, RawSwarmEvent<'a, TTrans, TInEvent, TOutEvent, THandler, THandlerErr>)
 where
@@ -846,24 +1695,15 @@ where
     {
         let (_, opened_endpoint) = reach_attempts.other_reach_attempts.swap_remove(in_pos);
 
-        // If we already have an active connection to this peer, a priority system comes into play.
-        // If we have a lower peer ID than the incoming one, we drop an incoming connection.
-        if event.would_replace() && has_dial_prio(&reach_attempts.local_peer_id, event.peer_id()) {
-            if let Some(ConnectedPoint::Dialer { .. }) = reach_attempts.connected_points.get(event.peer_id()) {
-                if let ConnectedPoint::Listener { listen_addr, send_back_addr } = opened_endpoint {
-                    return (Default::default(), RawSwarmEvent::IncomingConnectionError {
-                        listen_addr,
-                        send_back_addr,
-                        error: IoError::new(IoErrorKind::PermissionDenied,
-                                            "refused incoming connection".to_string()),
-                    });
-                }
-            }
+        if let Some(limit) = limits.check_established(reach_attempts, event.peer_id()) {
+            let peer_id = event.deny();
+            return (Default::default(), RawSwarmEvent::ConnectionRejected {
+                endpoint: opened_endpoint,
+                peer_id: Some(peer_id),
+                limit,
+            });
         }
 
-        // Set the endpoint for this peer.
-        let closed_endpoint = reach_attempts.connected_points.insert(event.peer_id().clone(), opened_endpoint.clone());
-
         // Cancel any outgoing attempt to this peer.
         let action = if let Some(attempt) = reach_attempts.out_reach_attempts.remove(&event.peer_id()) {
             debug_assert_ne!(attempt.id, event.reach_attempt_id());
@@ -875,21 +1715,12 @@ where
             ActionItem::default()
         };
 
-        let (outcome, peer_id) = event.accept();
-        if outcome == CollectionNodeAccept::ReplacedExisting {
-            let closed_endpoint = closed_endpoint
-                .expect("We insert into connected_points whenever a connection is opened and \
-                         remove only when a connection is closed; the underlying API is \
-                         guaranteed to always deliver a connection closed message after it has \
-                         been opened, and no two closed messages; QED");
-            return (action, RawSwarmEvent::Replaced {
-                peer_id,
-                endpoint: opened_endpoint,
-                closed_endpoint,
-            });
-        } else {
-            return (action, RawSwarmEvent::Connected { peer_id, endpoint: opened_endpoint });
-        }
+        let (connection_id, peer_id) = event.accept();
+        reach_attempts.connected_points
+            .entry(peer_id.clone())
+            .or_default()
+            .push((connection_id, opened_endpoint.clone()));
+        return (action, RawSwarmEvent::Connected { peer_id, connection_id, endpoint: opened_endpoint });
     }
 
     // Otherwise, try for outgoing attempts.
@@ -910,24 +1741,21 @@ where
             address: attempt.cur_attempted,
         };
 
-        let closed_endpoint = reach_attempts.connected_points
-            .insert(event.peer_id().clone(), opened_endpoint.clone());
-
-        let (outcome, peer_id) = event.accept();
-        if outcome == CollectionNodeAccept::ReplacedExisting {
-            let closed_endpoint = closed_endpoint
-                .expect("We insert into connected_points whenever a connection is opened and \
-                        remove only when a connection is closed; the underlying API is guaranteed \
-                        to always deliver a connection closed message after it has been opened, \
-                        and no two closed messages; QED");
-            return (Default::default(), RawSwarmEvent::Replaced {
-                peer_id,
+        if let Some(limit) = limits.check_established(reach_attempts, event.peer_id()) {
+            let peer_id = event.deny();
+            return (Default::default(), RawSwarmEvent::ConnectionRejected {
                 endpoint: opened_endpoint,
-                closed_endpoint,
+                peer_id: Some(peer_id),
+                limit,
             });
-        } else {
-            return (Default::default(), RawSwarmEvent::Connected { peer_id, endpoint: opened_endpoint });
         }
+
+        let (connection_id, peer_id) = event.accept();
+        reach_attempts.connected_points
+            .entry(peer_id.clone())
+            .or_default()
+            .push((connection_id, opened_endpoint.clone()));
+        return (Default::default(), RawSwarmEvent::Connected { peer_id, connection_id, endpoint: opened_endpoint });
     }
 
     // We didn't find any entry in neither the outgoing connections not ingoing connections.
@@ -939,13 +1767,28 @@ where
             find back this ID in either of these two sets");
 }
 
-/// Returns true if `local` has dialing priority over `other`.
-///
-/// This means that if `local` and `other` both dial each other, the connection from `local` should
-/// be kept and the one from `other` will be dropped.
-#[inline]
-fn has_dial_prio(local: &PeerId, other: &PeerId) -> bool {
-    local.as_bytes() < other.as_bytes()
+/// Removes a connection from `connected_points`, and removes the peer altogether if this was its
+/// last connection. Returns the endpoint of the connection, or `None` if it wasn't found.
+fn remove_connected_point(
+    connected_points: &mut FnvHashMap<PeerId, SmallVec<[(ConnectionId, ConnectedPoint); 2]>>,
+    peer_id: &PeerId,
+    connection_id: ConnectionId,
+) -> Option<ConnectedPoint> {
+    let mut entry = match connected_points.entry(peer_id.clone()) {
+        Entry::Occupied(entry) => entry,
+        Entry::Vacant(_) => return None,
+    };
+
+    let endpoint = match entry.get().iter().position(|(id, _)| *id == connection_id) {
+        Some(pos) => entry.get_mut().remove(pos).1,
+        None => return None,
+    };
+
+    if entry.get().is_empty() {
+        entry.remove();
+    }
+
+    Some(endpoint)
 }
 
 /// Handles a reach error event from the collection.
@@ -1036,7 +1879,7 @@ where
     TTrans: Transport,
 {
     /// We are connected to this peer.
-    Connected(PeerConnected<'a, TInEvent>),
+    Connected(PeerConnected<'a, TInEvent, TOutEvent, THandler, THandlerErr>),
 
     /// We are currently attempting to connect to this peer.
     PendingConnect(PeerPendingConnect<'a, TInEvent, TOutEvent, THandler, THandlerErr>),
@@ -1091,7 +1934,7 @@ where
 {
     /// If we are connected, returns the `PeerConnected`.
     #[inline]
-    pub fn as_connected(self) -> Option<PeerConnected<'a, TInEvent>> {
+    pub fn as_connected(self) -> Option<PeerConnected<'a, TInEvent, TOutEvent, THandler, THandlerErr>> {
         match self {
             Peer::Connected(peer) => Some(peer),
             _ => None,
@@ -1163,7 +2006,7 @@ where
 /// Peer we are potentially going to connect to.
 pub enum PeerPotentialConnect<'a, TInEvent: 'a, TOutEvent: 'a, THandler: 'a, THandlerErr: 'a> {
     /// We are connected to this peer.
-    Connected(PeerConnected<'a, TInEvent>),
+    Connected(PeerConnected<'a, TInEvent, TOutEvent, THandler, THandlerErr>),
 
     /// We are currently attempting to connect to this peer.
     PendingConnect(PeerPendingConnect<'a, TInEvent, TOutEvent, THandler, THandlerErr>),
@@ -1185,7 +2028,7 @@ impl<'a, TInEvent, TOutEvent, THandler, THandlerErr> PeerPotentialConnect<'a, TI
 
     /// If we are connected, returns the `PeerConnected`.
     #[inline]
-    pub fn as_connected(self) -> Option<PeerConnected<'a, TInEvent>> {
+    pub fn as_connected(self) -> Option<PeerConnected<'a, TInEvent, TOutEvent, THandler, THandlerErr>> {
         match self {
             PeerPotentialConnect::Connected(peer) => Some(peer),
             _ => None,
@@ -1203,15 +2046,15 @@ impl<'a, TInEvent, TOutEvent, THandler, THandlerErr> PeerPotentialConnect<'a, TI
 }
 
 /// Access to a peer we are connected to.
-pub struct PeerConnected<'a, TInEvent: 'a> {
-    peer: CollecPeerMut<'a, TInEvent>,
+pub struct PeerConnected<'a, TInEvent: 'a, TOutEvent: 'a, THandler: 'a, THandlerErr: 'a> {
+    peer: CollecPeerMut<'a, TInEvent, TOutEvent, THandler, RawSwarmReachError, THandlerErr>,
     /// Reference to the `connected_points` field of the parent.
-    connected_points: &'a mut FnvHashMap<PeerId, ConnectedPoint>,
+    connected_points: &'a mut FnvHashMap<PeerId, SmallVec<[(ConnectionId, ConnectedPoint); 2]>>,
     peer_id: PeerId,
 }
 
-impl<'a, TInEvent> PeerConnected<'a, TInEvent> {
-    /// Closes the connection to this node.
+impl<'a, TInEvent, TOutEvent, THandler, THandlerErr> PeerConnected<'a, TInEvent, TOutEvent, THandler, THandlerErr> {
+    /// Closes all the connections to this node.
     ///
     /// No `NodeClosed` message will be generated for this node.
     // TODO: consider returning a `PeerNotConnected`; however this makes all the borrows things
@@ -1221,21 +2064,49 @@ impl<'a, TInEvent> PeerConnected<'a, TInEvent> {
         self.peer.close()
     }
 
-    /// Returns the endpoint we're connected to.
+    /// Closes one of the connections to this node, and returns the endpoint of this connection.
+    /// Returns `None` if the connection doesn't belong to this node.
+    ///
+    /// No `NodeClosed` message will be generated for this connection.
+    pub fn close_connection(self, connection: ConnectionId) -> Option<ConnectedPoint> {
+        let endpoint = remove_connected_point(self.connected_points, &self.peer_id, connection)?;
+        let _closed = self.peer.close_connection(connection);
+        debug_assert!(_closed);
+        Some(endpoint)
+    }
+
+    /// Returns the endpoint of the oldest connection to this node.
     #[inline]
     pub fn endpoint(&self) -> &ConnectedPoint {
-        self.connected_points.get(&self.peer_id)
+        &self.connected_points.get(&self.peer_id)
             .expect("We insert into connected_points whenever a connection is opened and remove \
                      only when a connection is closed; the underlying API is guaranteed to always \
                      deliver a connection closed message after it has been opened, and no two \
-                     closed messages; QED")
+                     closed messages; QED")[0].1
+    }
+
+    /// Returns the list of connections to this node with their endpoint, from the oldest to the
+    /// newest.
+    #[inline]
+    pub fn connections(&self) -> impl Iterator<Item = (ConnectionId, &ConnectedPoint)> {
+        self.connected_points.get(&self.peer_id)
+            .into_iter()
+            .flat_map(|c| c.iter().map(|(id, endpoint)| (*id, endpoint)))
     }
 
-    /// Sends an event to the node.
+    /// Sends an event to the node, through any of its connections.
     #[inline]
     pub fn send_event(&mut self, event: TInEvent) {
         self.peer.send_event(event)
     }
+
+    /// Sends an event to the node through the given connection.
+    ///
+    /// Returns back the event if the connection doesn't belong to this node.
+    #[inline]
+    pub fn send_event_to(&mut self, connection: ConnectionId, event: TInEvent) -> Result<(), TInEvent> {
+        self.peer.send_event_to(connection, event)
+    }
 }
 
 /// Access to a peer we are attempting to connect to.
@@ -1256,19 +2127,21 @@ impl<'a, TInEvent, TOutEvent, THandler, THandlerErr> PeerPendingConnect<'a, TInE
             // TODO: improve proof or remove; this is too complicated right now
             panic!("We retreived this attempt.id from out_reach_attempts. We insert in \
                     out_reach_attempts only at the same time as we call add_reach_attempt. \
-                    Whenever we receive a NodeReached, NodeReplaced or ReachError event, which \
+                    Whenever we receive a NodeReached or ReachError event, which \
                     invalidate the attempt.id, we also remove the corresponding entry in \
                     out_reach_attempts.");
         }
     }
 
-    /// Returns the multiaddress we're currently trying to dial.
+    /// Returns the multiaddress we've most recently started dialing.
+    ///
+    /// Other addresses may be dialed at the same time, depending on the `DialConfig`.
     #[inline]
     pub fn attempted_multiaddr(&self) -> &Multiaddr {
         &self.attempt.get().cur_attempted
     }
 
-    /// Returns a list of the multiaddresses we're going to try if the current dialing fails.
+    /// Returns a list of the multiaddresses we haven't started dialing yet.
     #[inline]
     pub fn pending_multiaddrs(&self) -> impl Iterator<Item = &Multiaddr> {
         self.attempt.get().next_attempts.iter()
@@ -1314,6 +2187,8 @@ where
     ///
     /// If we reach a peer but the `PeerId` doesn't correspond to the one we're expecting, then
     /// the whole connection is immediately closed.
+    ///
+    /// Returns an error if dialing would exceed the `ConnectionLimits` of the swarm.
     #[inline]
     pub fn connect(self, addr: Multiaddr, handler: THandler) -> Result<PeerPendingConnect<'a, TInEvent, TOutEvent, THandler, THandlerErr>, Self> {
         self.connect_inner(handler, addr, Vec::new())
@@ -1325,6 +2200,8 @@ where
     ///
     /// If the iterator is empty, TODO: what to do? at the moment we unwrap
     ///
+    /// Returns an error if dialing would exceed the `ConnectionLimits` of the swarm.
+    ///
     /// If we reach a peer but the `PeerId` doesn't correspond to the one we're expecting, then
     /// the whole connection is immediately closed.
     #[inline]
@@ -1346,6 +2223,10 @@ where
     fn connect_inner(self, handler: THandler, first: Multiaddr, rest: Vec<Multiaddr>)
         -> Result<PeerPendingConnect<'a, TInEvent, TOutEvent, THandler, THandlerErr>, Self>
     {
+        if self.nodes.limits.check_outgoing(&self.nodes.reach_attempts).is_some() {
+            return Err(self);
+        }
+
         self.nodes.start_dial_out(self.peer_id.clone(), handler, first, rest);
         Ok(PeerPendingConnect {
             attempt: match self.nodes.reach_attempts.out_reach_attempts.entry(self.peer_id) {
@@ -1368,9 +2249,11 @@ mod tests {
     use crate::tests::dummy_muxer::{DummyMuxer, DummyConnectionState};
     use crate::nodes::NodeHandlerEvent;
     use assert_matches::assert_matches;
+    use futures::future;
     use parking_lot::Mutex;
     use std::sync::Arc;
     use tokio::runtime::{Builder, Runtime};
+    use tokio_mock_task::MockTask;
 
     #[test]
     fn query_transport() {
@@ -1472,6 +2355,7 @@ mod tests {
         let swarm_fut = swarm.clone();
         let fut = future::poll_fn(move || -> Poll<_, ()> {
             let mut swarm_fut = swarm_fut.lock();
+            assert_matches!(swarm_fut.poll(), Async::Ready(RawSwarmEvent::NewListenerAddress { .. }));
             assert_matches!(swarm_fut.poll(), Async::Ready(RawSwarmEvent::IncomingConnection(incoming)) => {
                 incoming.accept(Handler::default());
             });
@@ -1519,7 +2403,7 @@ mod tests {
                 let mut swarm = swarm_fut.lock();
                 match swarm.poll() {
                     Async::Ready(event) => {
-                        assert_matches!(event, RawSwarmEvent::NodeEvent { peer_id: _, event: inner_event } => {
+                        assert_matches!(event, RawSwarmEvent::NodeEvent { peer_id: _, event: inner_event, .. } => {
                             // The event we sent reached the node and triggered sending the out event we told it to return
                             assert_matches!(inner_event, OutEvent::Custom("from handler 1"));
                         });
@@ -1598,6 +2482,7 @@ mod tests {
         let swarm_fut = swarm.clone();
         let fut = future::poll_fn(move || -> Poll<_, ()> {
             let mut swarm = swarm_fut.lock();
+            assert_matches!(swarm.poll(), Async::Ready(RawSwarmEvent::NewListenerAddress { .. }));
             assert_matches!(swarm.poll(), Async::Ready(RawSwarmEvent::ListenerClosed { .. } ));
             Ok(Async::Ready(()))
         });
@@ -1783,11 +2668,363 @@ mod tests {
     }
 
     #[test]
-    fn local_prio_equivalence_relation() {
-        for _ in 0..1000 {
-            let a = PeerId::random();
-            let b = PeerId::random();
-            assert_ne!(has_dial_prio(&a, &b), has_dial_prio(&b, &a));
+    fn incoming_connections_above_pending_limit_are_rejected() {
+        let mut transport = DummyTransport::new();
+        let muxer = DummyMuxer::new();
+        transport.set_initial_listener_state(ListenerState::Ok(Async::Ready(Some((PeerId::random(), muxer)))));
+
+        let limits = ConnectionLimits::new().max_pending_incoming(1);
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::with_limits(transport, PeerId::random(), limits);
+        swarm.listen_on("/memory".parse().unwrap()).unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let swarm = Arc::new(Mutex::new(swarm));
+        let swarm_fut = swarm.clone();
+        let fut = future::poll_fn(move || -> Poll<_, ()> {
+            let mut swarm_fut = swarm_fut.lock();
+            assert_matches!(swarm_fut.poll(), Async::Ready(RawSwarmEvent::NewListenerAddress { .. }));
+            assert_matches!(swarm_fut.poll(), Async::Ready(RawSwarmEvent::IncomingConnection(incoming)) => {
+                incoming.accept(Handler::default());
+            });
+            assert_matches!(swarm_fut.poll(), Async::Ready(RawSwarmEvent::ConnectionRejected { peer_id: None, limit, .. }) => {
+                assert_eq!(limit, ConnectionLimit::PendingIncoming(1));
+            });
+            Ok(Async::Ready(()))
+        });
+        rt.block_on(fut).expect("tokio works");
+        assert_eq!(swarm.lock().incoming_negotiated().count(), 1);
+    }
+
+    #[test]
+    fn incoming_connections_above_ip_limit_are_rejected() {
+        let mut transport = DummyTransport::new();
+        let muxer = DummyMuxer::new();
+        transport.set_initial_listener_state(ListenerState::Ok(Async::Ready(Some((PeerId::random(), muxer)))));
+
+        let limits = ConnectionLimits::new().max_per_ip(1);
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::with_limits(transport, PeerId::random(), limits);
+        swarm.listen_on("/ip4/127.0.0.1/tcp/1234".parse().unwrap()).unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let swarm_fut = Arc::new(Mutex::new(swarm));
+        let fut = future::poll_fn(move || -> Poll<_, ()> {
+            let mut swarm_fut = swarm_fut.lock();
+            assert_matches!(swarm_fut.poll(), Async::Ready(RawSwarmEvent::NewListenerAddress { .. }));
+            assert_matches!(swarm_fut.poll(), Async::Ready(RawSwarmEvent::IncomingConnection(incoming)) => {
+                incoming.accept(Handler::default());
+            });
+            assert_matches!(swarm_fut.poll(), Async::Ready(RawSwarmEvent::ConnectionRejected { limit, .. }) => {
+                assert_eq!(limit, ConnectionLimit::PerIp(1));
+            });
+            Ok(Async::Ready(()))
+        });
+        rt.block_on(fut).expect("tokio works");
+    }
+
+    #[test]
+    fn dialing_above_pending_outgoing_limit_fails() {
+        let limits = ConnectionLimits::new().max_pending_outgoing(1);
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::with_limits(DummyTransport::new(), PeerId::random(), limits);
+        let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        assert!(swarm.dial(addr.clone(), Handler::default()).is_ok());
+        assert_eq!(swarm.dial(addr.clone(), Handler::default()), Err(addr.clone()));
+
+        let peer = swarm.peer(PeerId::random()).as_not_connected().unwrap();
+        assert!(peer.connect(addr, Handler::default()).is_err());
+    }
+
+    #[test]
+    fn established_connections_above_limit_are_rejected() {
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(DummyTransport::new(), PeerId::random());
+        swarm.set_connection_limits(ConnectionLimits::new().max_established(1));
+        let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        swarm.dial(addr.clone(), Handler::default()).expect("dialing works");
+        swarm.dial(addr, Handler::default()).expect("dialing works");
+
+        let swarm = Arc::new(Mutex::new(swarm));
+        let mut rt = Runtime::new().unwrap();
+        let mut events = Vec::new();
+        while events.len() < 2 {
+            let swarm_fut = swarm.clone();
+            let event = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+                let mut swarm = swarm_fut.lock();
+                match swarm.poll() {
+                    Async::Ready(RawSwarmEvent::Connected { .. }) => Ok(Async::Ready(Some(true))),
+                    Async::Ready(RawSwarmEvent::ConnectionRejected { limit, .. }) => {
+                        assert_eq!(limit, ConnectionLimit::Established(1));
+                        Ok(Async::Ready(Some(false)))
+                    },
+                    _ => Ok(Async::Ready(None)),
+                }
+            })).expect("tokio works");
+            events.extend(event);
+        }
+
+        assert_eq!(events.iter().filter(|connected| **connected).count(), 1);
+    }
+
+    #[test]
+    fn multiple_connections_to_the_same_peer_are_kept() {
+        let peer_id = PeerId::random();
+        let mut transport = DummyTransport::new();
+        transport.set_next_peer_id(&peer_id);
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(transport, PeerId::random());
+        let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        swarm.dial(addr.clone(), Handler::default()).expect("dialing works");
+        swarm.dial(addr, Handler::default()).expect("dialing works");
+
+        let swarm = Arc::new(Mutex::new(swarm));
+        let mut rt = Runtime::new().unwrap();
+        let mut connections = Vec::new();
+        while connections.len() < 2 {
+            let swarm_fut = swarm.clone();
+            let connection = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+                let mut swarm = swarm_fut.lock();
+                match swarm.poll() {
+                    Async::Ready(RawSwarmEvent::Connected { connection_id, .. }) => Ok(Async::Ready(Some(connection_id))),
+                    _ => Ok(Async::Ready(None)),
+                }
+            })).expect("tokio works");
+            connections.extend(connection);
+        }
+
+        let mut swarm = swarm.lock();
+        assert_ne!(connections[0], connections[1]);
+        assert_eq!(swarm.connections().count(), 2);
+        {
+            let peer = swarm.peer(peer_id.clone()).as_connected().unwrap();
+            assert_eq!(peer.connections().map(|(id, _)| id).collect::<Vec<_>>(), connections);
+            assert_matches!(peer.close_connection(connections[0]), Some(ConnectedPoint::Dialer { .. }));
+        }
+        assert!(swarm.is_connected(&peer_id));
+        let mut peer = swarm.peer(peer_id.clone()).as_connected().unwrap();
+        assert_matches!(peer.send_event_to(connections[0], InEvent::NextState), Err(_));
+        assert_matches!(peer.send_event_to(connections[1], InEvent::NextState), Ok(()));
+    }
+
+    #[test]
+    fn established_connections_above_per_peer_limit_are_rejected() {
+        let mut transport = DummyTransport::new();
+        transport.set_next_peer_id(&PeerId::random());
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(transport, PeerId::random());
+        swarm.set_connection_limits(ConnectionLimits::new().max_established_per_peer(1));
+        let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        swarm.dial(addr.clone(), Handler::default()).expect("dialing works");
+        swarm.dial(addr, Handler::default()).expect("dialing works");
+
+        let swarm = Arc::new(Mutex::new(swarm));
+        let mut rt = Runtime::new().unwrap();
+        let mut events = Vec::new();
+        while events.len() < 2 {
+            let swarm_fut = swarm.clone();
+            let event = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+                let mut swarm = swarm_fut.lock();
+                match swarm.poll() {
+                    Async::Ready(RawSwarmEvent::Connected { .. }) => Ok(Async::Ready(Some(true))),
+                    Async::Ready(RawSwarmEvent::ConnectionRejected { limit, .. }) => {
+                        assert_eq!(limit, ConnectionLimit::EstablishedPerPeer(1));
+                        Ok(Async::Ready(Some(false)))
+                    },
+                    _ => Ok(Async::Ready(None)),
+                }
+            })).expect("tokio works");
+            events.extend(event);
+        }
+
+        assert_eq!(events.iter().filter(|connected| **connected).count(), 1);
+    }
+
+    #[test]
+    fn shutdown_closes_established_connections() {
+        let peer_id = PeerId::random();
+        let mut transport = DummyTransport::new();
+        transport.set_next_peer_id(&peer_id);
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(transport, PeerId::random());
+        let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().expect("bad multiaddr");
+        swarm.dial(addr, Handler::default()).expect("dialing works");
+
+        let swarm = Arc::new(Mutex::new(swarm));
+        let mut rt = Runtime::new().unwrap();
+        loop {
+            let swarm_fut = swarm.clone();
+            let connected = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+                let mut swarm = swarm_fut.lock();
+                match swarm.poll() {
+                    Async::Ready(RawSwarmEvent::Connected { .. }) => Ok(Async::Ready(true)),
+                    _ => Ok(Async::Ready(false)),
+                }
+            })).expect("tokio works");
+            if connected {
+                break;
+            }
+        }
+
+        swarm.lock().start_shutdown();
+        let swarm_fut = swarm.clone();
+        let closed_peer = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+            let mut swarm = swarm_fut.lock();
+            match swarm.poll() {
+                Async::Ready(RawSwarmEvent::NodeClosed { peer_id, .. }) => Ok(Async::Ready(peer_id)),
+                Async::Ready(_) => panic!("unexpected event"),
+                Async::NotReady => Ok(Async::NotReady),
+            }
+        })).expect("tokio works");
+
+        assert_eq!(closed_peer, peer_id);
+        assert!(!swarm.lock().is_connected(&peer_id));
+    }
+
+    #[test]
+    fn interleaved_addresses_alternate_kinds() {
+        let addrs = vec![
+            "/ip6/::1/tcp/1".parse::<Multiaddr>().unwrap(),
+            "/ip6/::1/tcp/2".parse().unwrap(),
+            "/ip4/127.0.0.1/tcp/3".parse().unwrap(),
+            "/ip4/127.0.0.1/tcp/4/ws".parse().unwrap(),
+            "/ip4/127.0.0.1/tcp/5".parse().unwrap(),
+        ];
+        let ports = interleave_addresses(addrs)
+            .into_iter()
+            .map(|addr| addr.iter().filter_map(|p| match p {
+                Protocol::Tcp(port) => Some(port),
+                _ => None,
+            }).next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ports, vec![1, 3, 4, 2, 5]);
+    }
+
+    #[test]
+    fn dials_are_capped_globally_and_per_peer() {
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(DummyTransport::new(), PeerId::random());
+        swarm.set_dial_config(DialConfig::new().max_dials(3).max_dials_per_peer(2));
+        let addrs = (1..4)
+            .map(|n| format!("/ip4/127.0.0.1/tcp/{}", n).parse::<Multiaddr>().unwrap())
+            .collect::<Vec<_>>();
+
+        let peer1 = PeerId::random();
+        let peer = swarm.peer(peer1.clone()).as_not_connected().unwrap();
+        let pending = peer.connect_iter(addrs.clone(), Handler::default()).unwrap();
+        assert_eq!(pending.pending_multiaddrs().count(), 1);
+        assert_eq!(swarm.num_dials(), 2);
+
+        let peer2 = PeerId::random();
+        let peer = swarm.peer(peer2.clone()).as_not_connected().unwrap();
+        let pending = peer.connect_iter(addrs, Handler::default()).unwrap();
+        assert_eq!(pending.pending_multiaddrs().count(), 2);
+        assert_eq!(swarm.num_dials(), 3);
+
+        swarm.peer(peer1).as_pending_connect().unwrap().interrupt();
+        swarm.start_dials();
+        assert_eq!(swarm.num_dials(), 3);
+    }
+
+    #[test]
+    fn queued_dials_are_started_in_order() {
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(DummyTransport::new(), PeerId::random());
+        swarm.set_dial_config(DialConfig::new().max_dials(1));
+        let addr = "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().unwrap();
+
+        let peers = (0..5).map(|_| PeerId::random()).collect::<Vec<_>>();
+        for peer_id in &peers {
+            let peer = swarm.peer(peer_id.clone()).as_not_connected().unwrap();
+            peer.connect(addr.clone(), Handler::default()).unwrap();
+        }
+
+        let mut task = MockTask::new();
+        for (n, peer_id) in peers.iter().enumerate() {
+            let reach_id = swarm.reach_attempts.out_reach_attempts[peer_id].id;
+            assert_eq!(swarm.dials[&reach_id].num_in_flight, 1);
+            assert_eq!(swarm.num_dials(), 1);
+            assert_eq!(swarm.dial_queue.len(), peers.len() - n - 1);
+
+            swarm.peer(peer_id.clone()).as_pending_connect().unwrap().interrupt();
+            task.enter(|| assert!(swarm.poll_dials().is_none()));
+        }
+        assert_eq!(swarm.num_dials(), 0);
+    }
+
+    #[test]
+    fn addresses_keep_their_order_by_default() {
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(DummyTransport::new(), PeerId::random());
+        let addrs = vec![
+            "/ip6/::1/tcp/1".parse::<Multiaddr>().unwrap(),
+            "/ip6/::1/tcp/2".parse().unwrap(),
+            "/ip4/127.0.0.1/tcp/3".parse().unwrap(),
+        ];
+        let peer = swarm.peer(PeerId::random()).as_not_connected().unwrap();
+        let pending = peer.connect_iter(addrs.clone(), Handler::default()).unwrap();
+        assert_eq!(pending.attempted_multiaddr(), &addrs[0]);
+        assert_eq!(pending.pending_multiaddrs().collect::<Vec<_>>(), vec![&addrs[1], &addrs[2]]);
+    }
+
+    #[test]
+    fn parallel_dial_failures_are_all_reported() {
+        let mut transport = DummyTransport::new();
+        let peer_id = PeerId::random();
+        transport.set_next_peer_id(&peer_id);
+        transport.make_dial_fail();
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(transport, PeerId::random());
+        swarm.set_dial_config(DialConfig::new().max_dials_per_peer(2));
+        let addrs = vec![
+            "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().unwrap(),
+            "/ip6/::1/tcp/1234".parse::<Multiaddr>().unwrap(),
+        ];
+        let peer = swarm.peer(peer_id.clone()).as_not_connected().unwrap();
+        assert!(peer.connect_iter(addrs, Handler::default()).is_ok());
+
+        let swarm = Arc::new(Mutex::new(swarm));
+        let mut rt = Runtime::new().unwrap();
+        let mut remaining = Vec::new();
+        while remaining.last() != Some(&0) {
+            let swarm_fut = swarm.clone();
+            let event = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+                match swarm_fut.lock().poll() {
+                    Async::NotReady => Ok(Async::Ready(None)),
+                    Async::Ready(RawSwarmEvent::DialError { remain_addrs_attempt, .. }) =>
+                        Ok(Async::Ready(Some(remain_addrs_attempt))),
+                    Async::Ready(_) => panic!("unexpected event"),
+                }
+            })).expect("tokio works");
+            remaining.extend(event);
+        }
+        assert_eq!(remaining, vec![1, 0]);
+    }
+
+    #[test]
+    fn successful_parallel_dial_cancels_the_other_dials() {
+        let mut transport = DummyTransport::new();
+        let peer_id = PeerId::random();
+        transport.set_next_peer_id(&peer_id);
+        let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(transport, PeerId::random());
+        swarm.set_dial_config(DialConfig::new().max_dials_per_peer(2));
+        let addrs = vec![
+            "/ip4/127.0.0.1/tcp/1234".parse::<Multiaddr>().unwrap(),
+            "/ip6/::1/tcp/1234".parse::<Multiaddr>().unwrap(),
+        ];
+        let peer = swarm.peer(peer_id.clone()).as_not_connected().unwrap();
+        assert!(peer.connect_iter(addrs, Handler::default()).is_ok());
+
+        let swarm = Arc::new(Mutex::new(swarm));
+        let mut rt = Runtime::new().unwrap();
+        let mut connected = false;
+        while !connected {
+            let swarm_fut = swarm.clone();
+            connected = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+                match swarm_fut.lock().poll() {
+                    Async::NotReady => Ok(Async::Ready(false)),
+                    Async::Ready(RawSwarmEvent::Connected { endpoint, .. }) => {
+                        assert_matches!(endpoint, ConnectedPoint::Dialer { address } => {
+                            assert_eq!(address, "/ip4/127.0.0.1/tcp/1234".parse().unwrap());
+                        });
+                        Ok(Async::Ready(true))
+                    },
+                    Async::Ready(_) => panic!("unexpected event"),
+                }
+            })).expect("tokio works");
         }
+
+        let swarm = swarm.lock();
+        assert_eq!(swarm.num_dials(), 0);
+        assert_eq!(swarm.num_connections_to(&peer_id), 1);
     }
 }
@@ -24,7 +24,7 @@ use protobuf::{self, Message};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 
 /// Public key used by the remote.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum PublicKey {
     /// DER format.
     Rsa(Vec<u8>),
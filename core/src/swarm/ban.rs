@@ -0,0 +1,331 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Peers and IP addresses that the `Swarm` refuses to be connected to.
+
+use crate::{Multiaddr, PeerId, multiaddr::Protocol};
+use fnv::FnvHashMap;
+use std::{error, fmt, net::IpAddr, str::FromStr, time::Instant};
+
+/// List of banned peers and IP ranges.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BanList {
+    /// Banned peers, with the moment when the ban expires. `None` means that the ban never
+    /// expires.
+    peers: FnvHashMap<PeerId, Option<Instant>>,
+    /// If non-empty, only the addresses in one of these ranges are allowed.
+    allowed: Vec<IpRange>,
+    /// Ranges that are never allowed.
+    denied: Vec<IpRange>,
+}
+
+impl BanList {
+    /// Bans a peer until the given moment, or forever if `None`.
+    ///
+    /// Removes the bans that have expired.
+    pub fn ban_peer(&mut self, peer_id: PeerId, until: Option<Instant>) {
+        let now = Instant::now();
+        self.peers.retain(|_, until| until.map(|until| now < until).unwrap_or(true));
+        self.peers.insert(peer_id, until);
+    }
+
+    /// Lifts the ban of a peer. Returns `true` if the peer was banned.
+    pub fn unban_peer(&mut self, peer_id: &PeerId) -> bool {
+        self.peers.remove(peer_id).is_some()
+    }
+
+    /// Returns true if the given peer is banned.
+    pub fn is_peer_banned(&self, peer_id: &PeerId) -> bool {
+        match self.peers.get(peer_id) {
+            Some(Some(until)) => Instant::now() < *until,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Returns the list of banned peers, with the moment when the ban expires. The bans that
+    /// have expired are skipped.
+    pub fn banned_peers(&self) -> impl Iterator<Item = (&PeerId, Option<Instant>)> {
+        let now = Instant::now();
+        self.peers
+            .iter()
+            .filter(move |(_, until)| until.map(|until| now < until).unwrap_or(true))
+            .map(|(peer, until)| (peer, *until))
+    }
+
+    /// Adds a range to the list of allowed ranges.
+    pub fn allow_range(&mut self, range: IpRange) {
+        if !self.allowed.contains(&range) {
+            self.allowed.push(range);
+        }
+    }
+
+    /// Removes a range from the list of allowed ranges. Returns `true` if it was in the list.
+    pub fn remove_allowed_range(&mut self, range: &IpRange) -> bool {
+        let len = self.allowed.len();
+        self.allowed.retain(|r| r != range);
+        self.allowed.len() != len
+    }
+
+    /// Returns the list of allowed ranges.
+    pub fn allowed_ranges(&self) -> impl Iterator<Item = &IpRange> {
+        self.allowed.iter()
+    }
+
+    /// Adds a range to the list of denied ranges.
+    pub fn deny_range(&mut self, range: IpRange) {
+        if !self.denied.contains(&range) {
+            self.denied.push(range);
+        }
+    }
+
+    /// Removes a range from the list of denied ranges. Returns `true` if it was in the list.
+    pub fn remove_denied_range(&mut self, range: &IpRange) -> bool {
+        let len = self.denied.len();
+        self.denied.retain(|r| r != range);
+        self.denied.len() != len
+    }
+
+    /// Returns the list of denied ranges.
+    pub fn denied_ranges(&self) -> impl Iterator<Item = &IpRange> {
+        self.denied.iter()
+    }
+
+    /// Returns true if the given IP address is denied, or not allowed.
+    pub fn is_ip_banned(&self, ip: &IpAddr) -> bool {
+        if self.denied.iter().any(|range| range.contains(ip)) {
+            return true;
+        }
+
+        !self.allowed.is_empty() && !self.allowed.iter().any(|range| range.contains(ip))
+    }
+
+    /// Returns true if the IP address contained in the given multiaddress is banned.
+    ///
+    /// Multiaddresses that don't contain any IP address are never banned.
+    pub fn is_addr_banned(&self, addr: &Multiaddr) -> bool {
+        addr.iter().any(|proto| match proto {
+            Protocol::Ip4(ip) => self.is_ip_banned(&IpAddr::V4(ip)),
+            Protocol::Ip6(ip) => self.is_ip_banned(&IpAddr::V6(ip)),
+            _ => false,
+        })
+    }
+}
+
+/// Range of IP addresses, in the CIDR notation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct IpRange {
+    /// First address of the range. All the bits after the prefix are zero.
+    network: IpAddr,
+    /// Number of leading bits that are common to all the addresses of the range.
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Builds the range of addresses that share the first `prefix_len` bits with `addr`.
+    ///
+    /// Returns an error if `prefix_len` is larger than the number of bits of the address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<IpRange, IpRangeParseError> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        if prefix_len > max_len {
+            return Err(IpRangeParseError::InvalidPrefixLength);
+        }
+
+        let network = match addr {
+            IpAddr::V4(ip) => IpAddr::V4(From::from(u32::from(ip) & mask_u32(prefix_len))),
+            IpAddr::V6(ip) => IpAddr::V6(From::from(u128::from(ip) & mask_u128(prefix_len))),
+        };
+
+        Ok(IpRange { network, prefix_len })
+    }
+
+    /// Builds a range that contains a single address.
+    #[inline]
+    pub fn single(addr: IpAddr) -> IpRange {
+        let prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        IpRange { network: addr, prefix_len }
+    }
+
+    /// Returns the first address of the range.
+    #[inline]
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    /// Returns the number of leading bits that are common to all the addresses of the range.
+    #[inline]
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns true if the address is part of the range.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.network, addr) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                u32::from(*ip) & mask_u32(self.prefix_len) == u32::from(net)
+            },
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                u128::from(*ip) & mask_u128(self.prefix_len) == u128::from(net)
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Returns a mask whose `len` leading bits are set.
+#[inline]
+fn mask_u32(len: u8) -> u32 {
+    u32::max_value().checked_shl(32 - u32::from(len)).unwrap_or(0)
+}
+
+/// Returns a mask whose `len` leading bits are set.
+#[inline]
+fn mask_u128(len: u8) -> u128 {
+    u128::max_value().checked_shl(128 - u32::from(len)).unwrap_or(0)
+}
+
+impl From<IpAddr> for IpRange {
+    #[inline]
+    fn from(addr: IpAddr) -> IpRange {
+        IpRange::single(addr)
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = IpRangeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+        let addr = parts.next()
+            .unwrap_or("")
+            .parse::<IpAddr>()
+            .map_err(|_| IpRangeParseError::InvalidAddress)?;
+        match parts.next() {
+            Some(len) => {
+                let len = len.parse().map_err(|_| IpRangeParseError::InvalidPrefixLength)?;
+                IpRange::new(addr, len)
+            },
+            None => Ok(IpRange::single(addr)),
+        }
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Error when parsing an `IpRange`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IpRangeParseError {
+    /// The address part is not a valid IP address.
+    InvalidAddress,
+    /// The prefix length is not a number, or is too large for the address.
+    InvalidPrefixLength,
+}
+
+impl fmt::Display for IpRangeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpRangeParseError::InvalidAddress => write!(f, "Invalid IP address"),
+            IpRangeParseError::InvalidPrefixLength => write!(f, "Invalid prefix length"),
+        }
+    }
+}
+
+impl error::Error for IpRangeParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn range_contains_addresses() {
+        let range: IpRange = "192.168.1.17/16".parse().unwrap();
+        assert_eq!(range.to_string(), "192.168.0.0/16");
+        assert!(range.contains(&"192.168.200.3".parse().unwrap()));
+        assert!(!range.contains(&"192.169.0.1".parse().unwrap()));
+        assert!(!range.contains(&"::1".parse().unwrap()));
+
+        let range: IpRange = "fe80::/10".parse().unwrap();
+        assert!(range.contains(&"fe80::1".parse().unwrap()));
+        assert!(!range.contains(&"::1".parse().unwrap()));
+
+        let everything: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains(&"8.8.8.8".parse().unwrap()));
+
+        let single: IpRange = "10.0.0.1".parse().unwrap();
+        assert!(single.contains(&"10.0.0.1".parse().unwrap()));
+        assert!(!single.contains(&"10.0.0.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert_eq!("10.0.0.0/33".parse::<IpRange>(), Err(IpRangeParseError::InvalidPrefixLength));
+        assert_eq!("10.0.0.0/a".parse::<IpRange>(), Err(IpRangeParseError::InvalidPrefixLength));
+        assert_eq!("foo/8".parse::<IpRange>(), Err(IpRangeParseError::InvalidAddress));
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let mut bans = BanList::default();
+        let addr = "/ip4/10.1.2.3/tcp/30333".parse::<Multiaddr>().unwrap();
+        let other = "/ip4/172.16.0.1/tcp/30333".parse::<Multiaddr>().unwrap();
+        assert!(!bans.is_addr_banned(&addr));
+
+        bans.allow_range("10.0.0.0/8".parse().unwrap());
+        assert!(!bans.is_addr_banned(&addr));
+        assert!(bans.is_addr_banned(&other));
+        assert!(!bans.is_addr_banned(&"/memory".parse().unwrap()));
+
+        bans.deny_range("10.1.0.0/16".parse().unwrap());
+        assert!(bans.is_addr_banned(&addr));
+
+        assert!(bans.remove_denied_range(&"10.1.0.0/16".parse().unwrap()));
+        assert!(!bans.is_addr_banned(&addr));
+    }
+
+    #[test]
+    fn peer_bans_expire() {
+        let mut bans = BanList::default();
+        let forever = PeerId::random();
+        let expired = PeerId::random();
+        bans.ban_peer(forever.clone(), None);
+        bans.ban_peer(expired.clone(), Some(Instant::now() - Duration::from_secs(1)));
+
+        assert!(bans.is_peer_banned(&forever));
+        assert!(!bans.is_peer_banned(&expired));
+        assert_eq!(bans.banned_peers().map(|(p, _)| p.clone()).collect::<Vec<_>>(), vec![forever.clone()]);
+
+        assert!(bans.unban_peer(&forever));
+        assert!(!bans.is_peer_banned(&forever));
+    }
+}
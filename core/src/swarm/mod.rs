@@ -0,0 +1,1256 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! High level manager of the network.
+//!
+//! The `Swarm` struct contains the state of the network as a whole. The entire behaviour of a
+//! libp2p network can be controlled through the `Swarm`.
+//!
+//! # Initializing a Swarm
+//!
+//! Creating a `Swarm` requires three things:
+//! 
+//! - An implementation of the `Transport` trait. This is the type that will be used in order to
+//!   reach nodes on the network based on their address. See the `transport` module for more
+//!   information.
+//! - An implementation of the `NetworkBehaviour` trait. This is a state machine that defines how
+//!   the swarm should behave once it is connected to a node.
+//! - An implementation of the `Topology` trait. This is a container that holds the list of nodes
+//!   that we think are part of the network. See the `topology` module for more information.
+//!
+//! # Network behaviour
+//!
+//! The `NetworkBehaviour` trait is implemented on types that indicate to the swarm how it should
+//! behave. This includes which protocols are supported and which nodes to try to connect to.
+//!
+//! The swarm can maintain multiple connections to the same node, for example one over TCP and
+//! one over WebSockets. Each connection is identified by a `ConnectionId` and has its own
+//! protocols handler. The behaviour is informed of each connection individually, and can choose
+//! to send an event either to a specific connection or to any connection of a node.
+//!
+//! Additional connections are either opened by the remote, or dialed with `Swarm::dial_addr`.
+//! `Swarm::dial` and `NetworkBehaviourAction::DialPeer` only dial nodes that we aren't connected
+//! to.
+//!
+//! # Bans
+//!
+//! The `Swarm` can refuse to be connected to some peers, or to some ranges of IP addresses.
+//! Banning a peer or an IP range closes the existing connections to it, prevents new dialing
+//! attempts, and rejects the incoming connections as soon as the remote is known.
+//!
+//! # Metrics
+//!
+//! If a `Metrics` is passed to `Swarm::set_metrics`, the `Swarm` reports to it the failed
+//! attempts to dial. See the `metrics` module for the other metrics available.
+//!
+//! # Shutdown
+//!
+//! `Swarm::shutdown` consumes the `Swarm` and returns a future that closes the listeners, lets
+//! each protocols handler shut down its substreams, then closes the connections. Connections
+//! that are still open after the given timeout are closed abruptly.
+//!
+
+use crate::{
+    Transport, Multiaddr, PublicKey, PeerId, InboundUpgrade, OutboundUpgrade, UpgradeInfo, ProtocolName,
+    metrics::Metrics,
+    muxing::StreamMuxer,
+    nodes::{
+        handled_node::NodeHandler,
+        node::Substream,
+        raw_swarm::{ConnectionLimits, DialConfig, RawSwarm, RawSwarmEvent, RawSwarmReachError}
+    },
+    protocols_handler::{NodeHandlerWrapper, ProtocolsHandler},
+    topology::Topology
+};
+use futures::prelude::*;
+use log::debug;
+use smallvec::SmallVec;
+use std::{error, fmt, io, ops::{Deref, DerefMut}, time::{Duration, Instant}};
+use tokio_timer::Delay;
+
+pub use crate::nodes::collection::ConnectionId;
+pub use crate::nodes::listeners::ListenerId;
+pub use crate::nodes::raw_swarm::{ConnectedPoint, IncomingInfo};
+pub use self::ban::{IpRange, IpRangeParseError};
+
+mod ban;
+
+/// Contains the state of the network, plus the way it should behave.
+pub struct Swarm<TTransport, TBehaviour, TTopology>
+where TTransport: Transport,
+      TBehaviour: NetworkBehaviour<TTopology>,
+{
+    raw_swarm: RawSwarm<
+        TTransport,
+        <<TBehaviour as NetworkBehaviour<TTopology>>::ProtocolsHandler as ProtocolsHandler>::InEvent,
+        <<TBehaviour as NetworkBehaviour<TTopology>>::ProtocolsHandler as ProtocolsHandler>::OutEvent,
+        NodeHandlerWrapper<TBehaviour::ProtocolsHandler>,
+        <<TBehaviour as NetworkBehaviour<TTopology>>::ProtocolsHandler as ProtocolsHandler>::Error,
+    >,
+
+    /// Handles which nodes to connect to and how to handle the events sent back by the protocol
+    /// handlers.
+    behaviour: TBehaviour,
+
+    /// Holds the topology of the network. In other words all the nodes that we think exist, even
+    /// if we're not connected to them.
+    topology: TTopology,
+
+    /// List of protocols that the behaviour says it supports.
+    supported_protocols: SmallVec<[Vec<u8>; 16]>,
+
+    /// List of multiaddresses we're listening on.
+    listened_addrs: SmallVec<[Multiaddr; 8]>,
+
+    /// Peers and IP addresses we refuse to be connected to.
+    bans: ban::BanList,
+
+    /// Where to report the failed dialing attempts, if anywhere.
+    metrics: Option<Metrics>,
+}
+
+impl<TTransport, TBehaviour, TTopology> Deref for Swarm<TTransport, TBehaviour, TTopology>
+where TTransport: Transport,
+      TBehaviour: NetworkBehaviour<TTopology>,
+{
+    type Target = TBehaviour;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.behaviour
+    }
+}
+
+impl<TTransport, TBehaviour, TTopology> DerefMut for Swarm<TTransport, TBehaviour, TTopology>
+where TTransport: Transport,
+      TBehaviour: NetworkBehaviour<TTopology>,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.behaviour
+    }
+}
+
+impl<TTransport, TBehaviour, TMuxer, TTopology> Swarm<TTransport, TBehaviour, TTopology>
+where TBehaviour: NetworkBehaviour<TTopology>,
+      TMuxer: StreamMuxer + Send + Sync + 'static,
+      <TMuxer as StreamMuxer>::OutboundSubstream: Send + 'static,
+      <TMuxer as StreamMuxer>::Substream: Send + 'static,
+      TTransport: Transport<Output = (PeerId, TMuxer)> + Clone,
+      TTransport::Listener: Send + 'static,
+      TTransport::ListenerUpgrade: Send + 'static,
+      TTransport::Dial: Send + 'static,
+      TBehaviour::ProtocolsHandler: ProtocolsHandler<Substream = Substream<TMuxer>> + Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::InEvent: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutEvent: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::Error: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol: InboundUpgrade<Substream<TMuxer>> + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::Info: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
+      <<<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol: OutboundUpgrade<Substream<TMuxer>> + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::Info: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
+      <<<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
+      <NodeHandlerWrapper<TBehaviour::ProtocolsHandler> as NodeHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+      TTopology: Topology,
+{
+    /// Builds a new `Swarm`.
+    #[inline]
+    pub fn new(transport: TTransport, mut behaviour: TBehaviour, topology: TTopology) -> Self {
+        let supported_protocols = behaviour
+            .new_handler()
+            .listen_protocol()
+            .protocol_info()
+            .into_iter()
+            .map(|info| info.protocol_name().to_vec())
+            .collect();
+
+        let raw_swarm = RawSwarm::new(transport, topology.local_peer_id().clone());
+
+        Swarm {
+            raw_swarm,
+            behaviour,
+            topology,
+            supported_protocols,
+            listened_addrs: SmallVec::new(),
+            bans: Default::default(),
+            metrics: None,
+        }
+    }
+
+    /// Returns the transport passed when building this object.
+    #[inline]
+    pub fn transport(me: &Self) -> &TTransport {
+        me.raw_swarm.transport()
+    }
+
+    /// Starts listening on the given address.
+    ///
+    /// Returns an error if the address is not supported. On success, returns the identifier of
+    /// the new listener. The address actually listened on is passed to
+    /// `NetworkBehaviour::inject_new_listen_addr` once the swarm is polled.
+    #[inline]
+    pub fn listen_on(me: &mut Self, addr: Multiaddr) -> Result<ListenerId, Multiaddr> {
+        me.raw_swarm.listen_on(addr)
+    }
+
+    /// Stops the listener with the given identifier.
+    ///
+    /// Its address is passed to `NetworkBehaviour::inject_expired_listen_addr` once the swarm is
+    /// polled. Returns `false` if no listener with this identifier exists.
+    #[inline]
+    pub fn remove_listener(me: &mut Self, id: ListenerId) -> bool {
+        me.raw_swarm.remove_listener(id)
+    }
+
+    /// Tries to dial the given address.
+    ///
+    /// If the node at this address is already connected, the new connection is kept alongside
+    /// the existing ones. This is the only way to open an additional connection to a node.
+    ///
+    /// Returns an error if the address is not supported, or if dialing would exceed the
+    /// connection limits.
+    #[inline]
+    pub fn dial_addr(me: &mut Self, addr: Multiaddr) -> Result<(), Multiaddr> {
+        if me.bans.is_addr_banned(&addr) {
+            report_dial_failure(&me.metrics, "banned");
+            return Err(addr);
+        }
+
+        let handler = me.behaviour.new_handler();
+        me.raw_swarm.dial(addr, handler.into_node_handler())
+    }
+
+    /// Tries to reach the given peer using the elements in the topology.
+    ///
+    /// Has no effect if we are already connected to that peer, or if we are already dialing it.
+    /// Use `dial_addr` to open an additional connection to a connected peer.
+    ///
+    /// If the peer is banned, or if no address that isn't banned is known for the peer,
+    /// `inject_dial_failure` is called on the behaviour.
+    #[inline]
+    pub fn dial(me: &mut Self, peer_id: PeerId) {
+        if me.bans.is_peer_banned(&peer_id) {
+            report_dial_failure(&me.metrics, "banned");
+            me.behaviour.inject_dial_failure(&peer_id);
+            return;
+        }
+
+        let bans = &me.bans;
+        let addrs = me.topology.addresses_of_peer(&peer_id)
+            .into_iter()
+            .filter(|addr| !bans.is_addr_banned(addr))
+            .collect::<Vec<_>>();
+        let handler = me.behaviour.new_handler().into_node_handler();
+        if let Some(peer) = me.raw_swarm.peer(peer_id.clone()).as_not_connected() {
+            if peer.connect_iter(addrs, handler).is_err() {
+                report_dial_failure(&me.metrics, "no_address");
+                me.behaviour.inject_dial_failure(&peer_id);
+            }
+        }
+    }
+
+    /// Returns an iterator that produces the list of addresses we're listening on.
+    #[inline]
+    pub fn listeners(me: &Self) -> impl Iterator<Item = &Multiaddr> {
+        RawSwarm::listeners(&me.raw_swarm)
+    }
+
+    /// Returns the peer ID of the swarm passed as parameter.
+    #[inline]
+    pub fn local_peer_id(me: &Self) -> &PeerId {
+        &me.raw_swarm.local_peer_id()
+    }
+
+    /// Returns the limits on the number of connections.
+    #[inline]
+    pub fn connection_limits(me: &Self) -> &ConnectionLimits {
+        me.raw_swarm.connection_limits()
+    }
+
+    /// Modifies the limits on the number of connections.
+    ///
+    /// Existing connections are not affected; the new limits only apply to new connections.
+    #[inline]
+    pub fn set_connection_limits(me: &mut Self, limits: ConnectionLimits) {
+        me.raw_swarm.set_connection_limits(limits)
+    }
+
+    /// Returns the configuration of how the swarm dials out.
+    #[inline]
+    pub fn dial_config(me: &Self) -> &DialConfig {
+        me.raw_swarm.dial_config()
+    }
+
+    /// Modifies the configuration of how the swarm dials out.
+    ///
+    /// The addresses that are already being dialed are not affected, and the dialing attempts
+    /// in progress keep the maximum number of addresses per peer they were started with.
+    #[inline]
+    pub fn set_dial_config(me: &mut Self, config: DialConfig) {
+        me.raw_swarm.set_dial_config(config)
+    }
+
+    /// Returns the `Metrics` the `Swarm` reports to, if any.
+    #[inline]
+    pub fn metrics(me: &Self) -> Option<&Metrics> {
+        me.metrics.as_ref()
+    }
+
+    /// Reports the failed dialing attempts to the given `Metrics`.
+    ///
+    /// In order to collect metrics about the connections themselves, the transport should also
+    /// be wrapped with `Transport::with_metrics`.
+    #[inline]
+    pub fn set_metrics(me: &mut Self, metrics: Metrics) {
+        me.metrics = Some(metrics);
+    }
+
+    /// Bans a peer. Closes the existing connection to it, if any, and refuses any new connection
+    /// until it is unbanned.
+    #[inline]
+    pub fn ban_peer_id(me: &mut Self, peer_id: PeerId) {
+        me.bans.ban_peer(peer_id.clone(), None);
+        Swarm::disconnect_banned(me, Some(peer_id));
+    }
+
+    /// Same as `ban_peer_id`, but the ban is automatically lifted after the given duration.
+    #[inline]
+    pub fn ban_peer_id_for(me: &mut Self, peer_id: PeerId, duration: Duration) {
+        me.bans.ban_peer(peer_id.clone(), Some(Instant::now() + duration));
+        Swarm::disconnect_banned(me, Some(peer_id));
+    }
+
+    /// Lifts the ban of a peer. Returns `true` if the peer was banned.
+    #[inline]
+    pub fn unban_peer_id(me: &mut Self, peer_id: &PeerId) -> bool {
+        me.bans.unban_peer(peer_id)
+    }
+
+    /// Returns true if the given peer is currently banned.
+    #[inline]
+    pub fn is_peer_id_banned(me: &Self, peer_id: &PeerId) -> bool {
+        me.bans.is_peer_banned(peer_id)
+    }
+
+    /// Returns the list of banned peers, with the moment their ban expires. `None` means that
+    /// the ban never expires.
+    #[inline]
+    pub fn banned_peer_ids(me: &Self) -> impl Iterator<Item = (&PeerId, Option<Instant>)> {
+        me.bans.banned_peers()
+    }
+
+    /// Refuses all connections with an IP address in the given range, and closes the existing
+    /// ones.
+    ///
+    /// Denied ranges take precedence over allowed ranges.
+    #[inline]
+    pub fn deny_ip_range(me: &mut Self, range: IpRange) {
+        me.bans.deny_range(range);
+        Swarm::disconnect_banned(me, None);
+    }
+
+    /// Removes a range previously passed to `deny_ip_range`. Returns `true` if it was denied.
+    #[inline]
+    pub fn remove_denied_ip_range(me: &mut Self, range: &IpRange) -> bool {
+        me.bans.remove_denied_range(range)
+    }
+
+    /// Returns the list of ranges passed to `deny_ip_range`.
+    #[inline]
+    pub fn denied_ip_ranges(me: &Self) -> impl Iterator<Item = &IpRange> {
+        me.bans.denied_ranges()
+    }
+
+    /// Adds a range to the list of allowed IP ranges.
+    ///
+    /// As long as this list isn't empty, connections with an IP address outside of all the
+    /// allowed ranges are refused, and the existing ones are closed. Addresses that don't contain
+    /// an IP address, such as DNS addresses, are not affected.
+    #[inline]
+    pub fn allow_ip_range(me: &mut Self, range: IpRange) {
+        me.bans.allow_range(range);
+        Swarm::disconnect_banned(me, None);
+    }
+
+    /// Removes a range previously passed to `allow_ip_range`. Returns `true` if it was allowed.
+    ///
+    /// > **Note**: Removing the last allowed range allows all the IP addresses again.
+    #[inline]
+    pub fn remove_allowed_ip_range(me: &mut Self, range: &IpRange) -> bool {
+        let removed = me.bans.remove_allowed_range(range);
+        Swarm::disconnect_banned(me, None);
+        removed
+    }
+
+    /// Returns the list of ranges passed to `allow_ip_range`.
+    #[inline]
+    pub fn allowed_ip_ranges(me: &Self) -> impl Iterator<Item = &IpRange> {
+        me.bans.allowed_ranges()
+    }
+
+    /// Returns true if connections with the given address are refused.
+    #[inline]
+    pub fn is_addr_banned(me: &Self, addr: &Multiaddr) -> bool {
+        me.bans.is_addr_banned(addr)
+    }
+
+    /// Closes the connections to banned peers or IP addresses. If `peer_id` is `Some`, only
+    /// this peer is checked.
+    fn disconnect_banned(me: &mut Self, peer_id: Option<PeerId>) {
+        let to_close = {
+            let bans = &me.bans;
+            me.raw_swarm.connections()
+                .filter(|(peer, _, _)| peer_id.as_ref().map(|p| p == *peer).unwrap_or(true))
+                .filter(|(peer, _, endpoint)| bans.is_peer_banned(peer) || is_endpoint_banned(bans, endpoint))
+                .map(|(peer, connection, _)| (peer.clone(), connection))
+                .collect::<Vec<_>>()
+        };
+
+        for (peer_id, connection) in to_close {
+            Swarm::close_connection(me, &peer_id, connection);
+        }
+
+        if let Some(peer_id) = peer_id {
+            if let Some(peer) = me.raw_swarm.peer(peer_id).as_pending_connect() {
+                peer.interrupt();
+            }
+        }
+    }
+
+    /// Closes a connection immediately and informs the behaviour.
+    fn close_connection(me: &mut Self, peer_id: &PeerId, connection: ConnectionId) {
+        let endpoint = match me.raw_swarm.peer(peer_id.clone()).as_connected() {
+            Some(peer) => peer.close_connection(connection),
+            None => None,
+        };
+
+        if let Some(endpoint) = endpoint {
+            me.behaviour.inject_connection_closed(peer_id, connection, &endpoint);
+            if !me.raw_swarm.is_connected(peer_id) {
+                me.behaviour.inject_disconnected(peer_id, endpoint);
+            }
+        }
+    }
+
+    /// Shuts down the swarm gracefully.
+    ///
+    /// Stops all the listeners and pending dialing attempts, then asks the protocols handler of
+    /// each connection to shut down. Once a handler has finished, the muxer of its connection
+    /// is shut down. The returned future resolves when all the connections are closed.
+    ///
+    /// If some connections are still open after `timeout`, they are closed without waiting for
+    /// their handler and the future resolves.
+    pub fn shutdown(mut me: Self, timeout: Duration) -> SwarmShutdown<TTransport, TBehaviour, TTopology> {
+        me.raw_swarm.start_shutdown();
+        me.listened_addrs.clear();
+
+        SwarmShutdown {
+            swarm: Some(me),
+            deadline: Delay::new(Instant::now() + timeout),
+        }
+    }
+
+    /// Returns the topology of the swarm.
+    #[inline]
+    pub fn topology(me: &Self) -> &TTopology {
+        &me.topology
+    }
+
+    /// Returns the topology of the swarm.
+    #[inline]
+    pub fn topology_mut(me: &mut Self) -> &mut TTopology {
+        &mut me.topology
+    }
+}
+
+impl<TTransport, TBehaviour, TMuxer, TTopology> Stream for Swarm<TTransport, TBehaviour, TTopology>
+where TBehaviour: NetworkBehaviour<TTopology>,
+      TMuxer: StreamMuxer + Send + Sync + 'static,
+      <TMuxer as StreamMuxer>::OutboundSubstream: Send + 'static,
+      <TMuxer as StreamMuxer>::Substream: Send + 'static,
+      TTransport: Transport<Output = (PeerId, TMuxer)> + Clone,
+      TTransport::Listener: Send + 'static,
+      TTransport::ListenerUpgrade: Send + 'static,
+      TTransport::Dial: Send + 'static,
+      TBehaviour::ProtocolsHandler: ProtocolsHandler<Substream = Substream<TMuxer>> + Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::InEvent: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutEvent: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::Error: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol: InboundUpgrade<Substream<TMuxer>> + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::Info: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
+      <<<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol: OutboundUpgrade<Substream<TMuxer>> + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::Info: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
+      <<<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
+      <NodeHandlerWrapper<TBehaviour::ProtocolsHandler> as NodeHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+      TTopology: Topology,
+{
+    type Item = TBehaviour::OutEvent;
+    type Error = io::Error;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<TBehaviour::OutEvent>, io::Error> {
+        loop {
+            let mut raw_swarm_not_ready = false;
+
+            match self.raw_swarm.poll() {
+                Async::NotReady => raw_swarm_not_ready = true,
+                Async::Ready(RawSwarmEvent::NodeEvent { peer_id, connection_id, event }) => {
+                    self.behaviour.inject_node_event(peer_id, connection_id, event);
+                },
+                Async::Ready(RawSwarmEvent::Connected { peer_id, connection_id, endpoint }) => {
+                    if self.bans.is_peer_banned(&peer_id) || is_endpoint_banned(&self.bans, &endpoint) {
+                        if let Some(peer) = self.raw_swarm.peer(peer_id).as_connected() {
+                            peer.close_connection(connection_id);
+                        }
+                    } else {
+                        if let ConnectedPoint::Dialer { ref address } = endpoint {
+                            self.topology.report_dial_success(&peer_id, address);
+                        }
+                        self.behaviour.inject_connection_established(&peer_id, connection_id, &endpoint);
+                        if self.raw_swarm.num_connections_to(&peer_id) == 1 {
+                            self.behaviour.inject_connected(peer_id, endpoint);
+                        }
+                    }
+                },
+                Async::Ready(RawSwarmEvent::NodeClosed { peer_id, connection_id, endpoint }) |
+                Async::Ready(RawSwarmEvent::NodeError { peer_id, connection_id, endpoint, .. }) => {
+                    self.behaviour.inject_connection_closed(&peer_id, connection_id, &endpoint);
+                    if !self.raw_swarm.is_connected(&peer_id) {
+                        self.behaviour.inject_disconnected(&peer_id, endpoint);
+                    }
+                },
+                Async::Ready(RawSwarmEvent::IncomingConnection(incoming)) => {
+                    // Dropping the incoming connection without accepting it closes it.
+                    if !self.bans.is_addr_banned(incoming.send_back_addr()) &&
+                        self.behaviour.accept_incoming(incoming.info())
+                    {
+                        let handler = self.behaviour.new_handler();
+                        incoming.accept(handler.into_node_handler());
+                    }
+                },
+                Async::Ready(RawSwarmEvent::NewListenerAddress { listen_addr, .. }) => {
+                    if !self.listened_addrs.contains(&listen_addr) {
+                        self.listened_addrs.push(listen_addr.clone());
+                    }
+                    self.behaviour.inject_new_listen_addr(&listen_addr);
+                },
+                Async::Ready(RawSwarmEvent::ExpiredListenerAddress { listen_addr, .. }) => {
+                    self.listened_addrs.retain(|a| a != &listen_addr);
+                    self.behaviour.inject_expired_listen_addr(&listen_addr);
+                },
+                Async::Ready(RawSwarmEvent::ListenerClosed { listen_addr, result, .. }) => {
+                    self.listened_addrs.retain(|a| a != &listen_addr);
+                    self.behaviour.inject_listener_closed(&listen_addr, result.as_ref().map(|_| ()));
+                },
+                Async::Ready(RawSwarmEvent::IncomingConnectionError { listen_addr, send_back_addr, error }) => {
+                    self.behaviour.inject_incoming_connection_error(&listen_addr, &send_back_addr, &error);
+                },
+                Async::Ready(RawSwarmEvent::DialError { remain_addrs_attempt, peer_id, multiaddr, error }) => {
+                    self.topology.report_dial_failure(&peer_id, &multiaddr);
+                    match error {
+                        RawSwarmReachError::Transport(ref err) =>
+                            report_dial_failure(&self.metrics, &format!("{:?}", err.kind())),
+                        RawSwarmReachError::PeerIdMismatch { .. } =>
+                            report_dial_failure(&self.metrics, "peer_id_mismatch"),
+                    }
+                    self.behaviour.inject_addr_reach_failure(Some(&peer_id), &multiaddr, &error);
+                    if remain_addrs_attempt == 0 {
+                        self.behaviour.inject_dial_failure(&peer_id);
+                    }
+                },
+                Async::Ready(RawSwarmEvent::UnknownPeerDialError { multiaddr, error, .. }) => {
+                    report_dial_failure(&self.metrics, &format!("{:?}", error.kind()));
+                    self.behaviour.inject_addr_reach_failure(None, &multiaddr, &error);
+                },
+                Async::Ready(RawSwarmEvent::ConnectionRejected { endpoint, peer_id, .. }) => {
+                    if let ConnectedPoint::Dialer { .. } = endpoint {
+                        report_dial_failure(&self.metrics, "connection_limit");
+                        if let Some(peer_id) = peer_id {
+                            self.behaviour.inject_dial_failure(&peer_id);
+                        }
+                    }
+                },
+            }
+
+            let behaviour_poll = {
+                let transport = self.raw_swarm.transport();
+                let mut parameters = PollParameters {
+                    topology: &mut self.topology,
+                    supported_protocols: &self.supported_protocols,
+                    listened_addrs: &self.listened_addrs,
+                    nat_traversal: &move |a, b| transport.nat_traversal(a, b),
+                };
+                self.behaviour.poll(&mut parameters)
+            };
+
+            match behaviour_poll {
+                Async::NotReady if raw_swarm_not_ready => return Ok(Async::NotReady),
+                Async::NotReady => (),
+                Async::Ready(NetworkBehaviourAction::GenerateEvent(event)) => {
+                    return Ok(Async::Ready(Some(event)));
+                },
+                Async::Ready(NetworkBehaviourAction::DialAddress { address }) => {
+                    let _ = Swarm::dial_addr(self, address);
+                },
+                Async::Ready(NetworkBehaviourAction::DialPeer { peer_id }) => {
+                    Swarm::dial(self, peer_id)
+                },
+                Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, connection, event }) => {
+                    if let Some(mut peer) = self.raw_swarm.peer(peer_id).as_connected() {
+                        match connection {
+                            Some(connection) => {
+                                let _ = peer.send_event_to(connection, event);
+                            },
+                            None => peer.send_event(event),
+                        }
+                    }
+                },
+                Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address }) => {
+                    self.topology.add_local_external_addrs(self.raw_swarm.nat_traversal(&address));
+                },
+            }
+        }
+    }
+}
+
+/// Future returned by `Swarm::shutdown`. Resolves when the swarm is shut down.
+#[must_use = "futures do nothing unless polled"]
+pub struct SwarmShutdown<TTransport, TBehaviour, TTopology>
+where TTransport: Transport,
+      TBehaviour: NetworkBehaviour<TTopology>,
+{
+    /// The swarm being shut down. `None` once the future has resolved.
+    swarm: Option<Swarm<TTransport, TBehaviour, TTopology>>,
+    /// When to stop waiting for the connections to close gracefully.
+    deadline: Delay,
+}
+
+impl<TTransport, TBehaviour, TMuxer, TTopology> Future for SwarmShutdown<TTransport, TBehaviour, TTopology>
+where TBehaviour: NetworkBehaviour<TTopology>,
+      TMuxer: StreamMuxer + Send + Sync + 'static,
+      <TMuxer as StreamMuxer>::OutboundSubstream: Send + 'static,
+      <TMuxer as StreamMuxer>::Substream: Send + 'static,
+      TTransport: Transport<Output = (PeerId, TMuxer)> + Clone,
+      TTransport::Listener: Send + 'static,
+      TTransport::ListenerUpgrade: Send + 'static,
+      TTransport::Dial: Send + 'static,
+      TBehaviour::ProtocolsHandler: ProtocolsHandler<Substream = Substream<TMuxer>> + Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::InEvent: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutEvent: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::Error: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol: InboundUpgrade<Substream<TMuxer>> + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::Info: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
+      <<<TBehaviour::ProtocolsHandler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
+      <TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol: OutboundUpgrade<Substream<TMuxer>> + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::Info: Send + 'static,
+      <<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
+      <<<TBehaviour::ProtocolsHandler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
+      <NodeHandlerWrapper<TBehaviour::ProtocolsHandler> as NodeHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+      TTopology: Topology,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        let swarm = self.swarm.as_mut().expect("future polled after it has resolved");
+
+        loop {
+            if swarm.raw_swarm.connections().next().is_none() {
+                break;
+            }
+
+            match self.deadline.poll() {
+                Ok(Async::NotReady) => (),
+                Ok(Async::Ready(())) | Err(_) => {
+                    debug!("Closing the remaining connections after the shutdown deadline");
+                    let remaining = swarm.raw_swarm.connections()
+                        .map(|(peer_id, connection, _)| (peer_id.clone(), connection))
+                        .collect::<Vec<_>>();
+                    for (peer_id, connection) in remaining {
+                        Swarm::close_connection(swarm, &peer_id, connection);
+                    }
+                    break;
+                },
+            }
+
+            match swarm.raw_swarm.poll() {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(RawSwarmEvent::NodeEvent { peer_id, connection_id, event }) => {
+                    swarm.behaviour.inject_node_event(peer_id, connection_id, event);
+                },
+                Async::Ready(RawSwarmEvent::NodeClosed { peer_id, connection_id, endpoint }) |
+                Async::Ready(RawSwarmEvent::NodeError { peer_id, connection_id, endpoint, .. }) => {
+                    swarm.behaviour.inject_connection_closed(&peer_id, connection_id, &endpoint);
+                    if !swarm.raw_swarm.is_connected(&peer_id) {
+                        swarm.behaviour.inject_disconnected(&peer_id, endpoint);
+                    }
+                },
+                // The dialing attempts and incoming connections have all been interrupted by
+                // `start_shutdown`, so no new connection can be established. Dropping an
+                // incoming connection refuses it.
+                Async::Ready(_) => (),
+            }
+        }
+
+        self.swarm = None;
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Returns true if the remote address of the given endpoint is banned.
+fn is_endpoint_banned(bans: &ban::BanList, endpoint: &ConnectedPoint) -> bool {
+    match endpoint {
+        ConnectedPoint::Dialer { address } => bans.is_addr_banned(address),
+        ConnectedPoint::Listener { send_back_addr, .. } => bans.is_addr_banned(send_back_addr),
+    }
+}
+
+/// Reports a failed dialing attempt to the metrics, if any.
+fn report_dial_failure(metrics: &Option<Metrics>, reason: &str) {
+    if let Some(metrics) = metrics {
+        metrics.dial_failure(reason);
+    }
+}
+
+/// A behaviour for the network. Allows customizing the swarm.
+///
+/// This trait has been designed to be composable. Multiple implementations can be combined into
+/// one that handles all the behaviours at once.
+pub trait NetworkBehaviour<TTopology> {
+    /// Handler for all the protocols the network supports.
+    type ProtocolsHandler: ProtocolsHandler;
+    /// Event generated by the swarm.
+    type OutEvent;
+
+    /// Builds a new `ProtocolsHandler`.
+    fn new_handler(&mut self) -> Self::ProtocolsHandler;
+
+    /// Indicates the behaviour that we connected to the node with the given peer id through the
+    /// given endpoint.
+    ///
+    /// This is only called for the first connection to a node. Additional connections are
+    /// reported through `inject_connection_established`.
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint);
+
+    /// Indicates the behaviour that we disconnected from the node with the given peer id. The
+    /// endpoint is the one we used to be connected to.
+    ///
+    /// This is only called once the last connection to the node has been closed.
+    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint);
+
+    /// Indicates the behaviour that a new connection to the given node has been established.
+    ///
+    /// This is called for every connection, including the first one, before `inject_connected`.
+    #[inline]
+    fn inject_connection_established(&mut self, _peer_id: &PeerId, _connection: ConnectionId, _endpoint: &ConnectedPoint) {
+    }
+
+    /// Indicates the behaviour that a connection to the given node has been closed.
+    ///
+    /// This is called for every connection, including the last one, before `inject_disconnected`.
+    #[inline]
+    fn inject_connection_closed(&mut self, _peer_id: &PeerId, _connection: ConnectionId, _endpoint: &ConnectedPoint) {
+    }
+
+    /// Called when a connection arrives on one of our listeners, before it is negotiated.
+    ///
+    /// Returning `false` closes the connection. The default implementation accepts all the
+    /// incoming connections.
+    #[inline]
+    fn accept_incoming(&mut self, _info: IncomingInfo) -> bool {
+        true
+    }
+
+    /// Indicates the behaviour that the node with the given peer id has generated an event for
+    /// us, through the given connection.
+    ///
+    /// > **Note**: This method is only called for events generated by the protocols handler.
+    fn inject_node_event(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent
+    );
+
+    /// Indicates the behaviour that we tried to reach an address, but failed.
+    ///
+    /// If we were trying to reach a specific node, its ID is passed as parameter. If this is the
+    /// last address to attempt for the given node, then `inject_dial_failure` is called
+    /// afterwards.
+    #[inline]
+    fn inject_addr_reach_failure(&mut self, _peer_id: Option<&PeerId>, _addr: &Multiaddr, _error: &dyn error::Error) {
+    }
+
+    /// Indicates the behaviour that we tried to dial all the addresses known for a node, but
+    /// failed.
+    ///
+    /// This is also called if the swarm refused to dial the node, for example because it is
+    /// banned or because no address is known for it.
+    #[inline]
+    fn inject_dial_failure(&mut self, _peer_id: &PeerId) {
+    }
+
+    /// Indicates the behaviour that we have started listening on a new multiaddr.
+    ///
+    /// When listening on an unspecified IP address, this is called for the address of each
+    /// network interface, including the interfaces that appear later.
+    #[inline]
+    fn inject_new_listen_addr(&mut self, _addr: &Multiaddr) {
+    }
+
+    /// Indicates the behaviour that we are no longer listening on a multiaddr, because its
+    /// listener has been removed or has closed, or because its network interface has gone away.
+    #[inline]
+    fn inject_expired_listen_addr(&mut self, _addr: &Multiaddr) {
+    }
+
+    /// Indicates the behaviour that a listener has closed, either gracefully or with an error.
+    ///
+    /// The address is no longer part of the addresses we're listening on.
+    #[inline]
+    fn inject_listener_closed(&mut self, _addr: &Multiaddr, _result: Result<(), &io::Error>) {
+    }
+
+    /// Indicates the behaviour that an error happened while negotiating an incoming connection.
+    #[inline]
+    fn inject_incoming_connection_error(&mut self, _listen_addr: &Multiaddr, _send_back_addr: &Multiaddr, _error: &io::Error) {
+    }
+
+    /// Polls for things that swarm should do.
+    ///
+    /// This API mimics the API of the `Stream` trait.
+    fn poll(&mut self, topology: &mut PollParameters<TTopology>) -> Async<NetworkBehaviourAction<<Self::ProtocolsHandler as ProtocolsHandler>::InEvent, Self::OutEvent>>;
+}
+
+/// Used when deriving `NetworkBehaviour`. When deriving `NetworkBehaviour`, must be implemented
+/// for all the possible event types generated by the various fields.
+// TODO: document how the custom behaviour works and link this here
+pub trait NetworkBehaviourEventProcess<TEvent> {
+    /// Called when one of the fields of the type you're deriving `NetworkBehaviour` on generates
+    /// an event.
+    fn inject_event(&mut self, event: TEvent);
+}
+
+/// Parameters passed to `poll()`, that the `NetworkBehaviour` has access to.
+// TODO: #[derive(Debug)]
+pub struct PollParameters<'a, TTopology: 'a> {
+    topology: &'a mut TTopology,
+    supported_protocols: &'a [Vec<u8>],
+    listened_addrs: &'a [Multiaddr],
+    nat_traversal: &'a dyn Fn(&Multiaddr, &Multiaddr) -> Option<Multiaddr>,
+}
+
+impl<'a, TTopology> PollParameters<'a, TTopology> {
+    /// Returns a reference to the topology of the network.
+    #[inline]
+    pub fn topology(&mut self) -> &mut TTopology {
+        &mut self.topology
+    }
+
+    /// Returns the list of protocol the behaviour supports when a remote negotiates a protocol on
+    /// an inbound substream.
+    ///
+    /// The iterator's elements are the ASCII names as reported on the wire.
+    ///
+    /// Note that the list is computed once at initialization and never refreshed.
+    #[inline]
+    pub fn supported_protocols(&self) -> impl ExactSizeIterator<Item = &[u8]> {
+        self.supported_protocols.iter().map(AsRef::as_ref)
+    }
+
+    /// Returns the list of the addresses we're listening on.
+    #[inline]
+    pub fn listened_addresses(&self) -> impl ExactSizeIterator<Item = &Multiaddr> {
+        self.listened_addrs.iter()
+    }
+
+    /// Returns the list of the addresses nodes can use to reach us.
+    #[inline]
+    pub fn external_addresses<'b>(&'b mut self) -> impl ExactSizeIterator<Item = Multiaddr> + 'b
+    where TTopology: Topology
+    {
+        let local_peer_id = self.topology.local_peer_id().clone();
+        self.topology.addresses_of_peer(&local_peer_id).into_iter()
+    }
+
+    /// Returns the public key of the local node.
+    #[inline]
+    pub fn local_public_key(&self) -> &PublicKey
+    where TTopology: Topology
+    {
+        self.topology.local_public_key()
+    }
+
+    /// Returns the peer id of the local node.
+    #[inline]
+    pub fn local_peer_id(&self) -> &PeerId
+    where TTopology: Topology
+    {
+        self.topology.local_peer_id()
+    }
+
+    /// Calls the `nat_traversal` method on the underlying transport of the `Swarm`.
+    #[inline]
+    pub fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        (self.nat_traversal)(server, observed)
+    }
+}
+
+/// Action to perform.
+#[derive(Debug, Clone)]
+pub enum NetworkBehaviourAction<TInEvent, TOutEvent> {
+    /// Generate an event for the outside.
+    GenerateEvent(TOutEvent),
+
+    // TODO: report new raw connection for usage after intercepting an address dial
+
+    /// Instructs the swarm to dial the given multiaddress without any expectation of a peer id.
+    DialAddress {
+        /// The address to dial.
+        address: Multiaddr,
+    },
+
+    /// Instructs the swarm to try reach the given peer.
+    ///
+    /// Has no effect if we are already connected to the peer.
+    DialPeer {
+        /// The peer to try reach.
+        peer_id: PeerId,
+    },
+
+    /// If we're connected to the given peer, sends a message to the protocol handler.
+    ///
+    /// If we're not connected to this peer, does nothing. If necessary, the implementation of
+    /// `NetworkBehaviour` is supposed to track which peers we are connected to.
+    SendEvent {
+        /// The peer which to send the message to.
+        peer_id: PeerId,
+        /// The connection to send the message through. If `None`, the message is sent through
+        /// any of the connections to the peer. If the connection is closed, does nothing.
+        connection: Option<ConnectionId>,
+        /// Event to send to the peer.
+        event: TInEvent,
+    },
+
+    /// Reports that a remote observes us as this address.
+    ///
+    /// The swarm will pass this address through the transport's NAT traversal.
+    ReportObservedAddr {
+        /// The address we're being observed as.
+        address: Multiaddr,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Multiaddr, PeerId, PublicKey};
+    use crate::nodes::node::Substream;
+    use crate::protocols_handler::{DummyProtocolsHandler, ProtocolsHandler, ProtocolsHandlerEvent, ProtocolsHandlerUpgrErr};
+    use crate::tests::dummy_muxer::DummyMuxer;
+    use crate::tests::dummy_transport::DummyTransport;
+    use crate::topology::{AddressSource, MemoryTopology};
+    use crate::upgrade::DeniedUpgrade;
+    use futures::{future, prelude::*};
+    use rand::random;
+    use std::{error, thread, time::{Duration, Instant}};
+    use super::{ConnectedPoint, ConnectionId, NetworkBehaviour, NetworkBehaviourAction, PollParameters, Swarm};
+    use tokio::runtime::current_thread::Runtime;
+    use void::Void;
+
+    /// Behaviour that records the dialing failures it is informed of.
+    #[derive(Default)]
+    struct DialFailures {
+        addr_failures: Vec<(Option<PeerId>, Multiaddr)>,
+        dial_failures: Vec<PeerId>,
+    }
+
+    impl<TTopology> NetworkBehaviour<TTopology> for DialFailures {
+        type ProtocolsHandler = DummyProtocolsHandler<Substream<DummyMuxer>>;
+        type OutEvent = Void;
+
+        fn new_handler(&mut self) -> Self::ProtocolsHandler {
+            DummyProtocolsHandler::default()
+        }
+
+        fn inject_connected(&mut self, _: PeerId, _: ConnectedPoint) {}
+
+        fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {}
+
+        fn inject_node_event(&mut self, _: PeerId, _: ConnectionId, _: Void) {}
+
+        fn inject_addr_reach_failure(&mut self, peer_id: Option<&PeerId>, addr: &Multiaddr, _: &dyn error::Error) {
+            self.addr_failures.push((peer_id.cloned(), addr.clone()));
+        }
+
+        fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+            self.dial_failures.push(peer_id.clone());
+        }
+
+        fn poll(&mut self, _: &mut PollParameters<TTopology>) -> Async<NetworkBehaviourAction<<Self::ProtocolsHandler as ProtocolsHandler>::InEvent, Void>> {
+            Async::NotReady
+        }
+    }
+
+    #[test]
+    fn dial_failure_is_reported_to_the_behaviour() {
+        let mut transport = DummyTransport::new();
+        transport.make_dial_fail();
+        let local_key = PublicKey::Ed25519((0..32).map(|_| random()).collect());
+        let mut topology = MemoryTopology::empty(local_key);
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/1234".parse().unwrap();
+        topology.add_address(peer_id.clone(), addr.clone(), AddressSource::Manual);
+
+        let mut swarm = Swarm::new(transport, DialFailures::default(), topology);
+        Swarm::dial(&mut swarm, peer_id.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(future::poll_fn(|| -> Poll<(), ()> {
+            while let Async::Ready(_) = swarm.poll().unwrap() {}
+            if swarm.dial_failures.is_empty() {
+                Ok(Async::NotReady)
+            } else {
+                Ok(Async::Ready(()))
+            }
+        })).unwrap();
+
+        assert_eq!(swarm.addr_failures, vec![(Some(peer_id.clone()), addr)]);
+        assert_eq!(swarm.dial_failures, vec![peer_id]);
+    }
+
+    #[test]
+    fn dial_failure_without_address() {
+        let local_key = PublicKey::Ed25519((0..32).map(|_| random()).collect());
+        let topology = MemoryTopology::empty(local_key);
+        let mut swarm = Swarm::new(DummyTransport::new(), DialFailures::default(), topology);
+
+        // No address is known for this peer, so the failure is reported immediately.
+        let peer_id = PeerId::random();
+        Swarm::dial(&mut swarm, peer_id.clone());
+        assert!(swarm.addr_failures.is_empty());
+        assert_eq!(swarm.dial_failures, vec![peer_id]);
+    }
+
+    /// Handler that behaves like `DummyProtocolsHandler`, except that it optionally never
+    /// finishes shutting down.
+    struct StubbornHandler {
+        inner: DummyProtocolsHandler<Substream<DummyMuxer>>,
+        ignore_shutdown: bool,
+    }
+
+    impl ProtocolsHandler for StubbornHandler {
+        type InEvent = Void;
+        type OutEvent = Void;
+        type Error = Void;
+        type Substream = Substream<DummyMuxer>;
+        type InboundProtocol = DeniedUpgrade;
+        type OutboundProtocol = DeniedUpgrade;
+        type OutboundOpenInfo = Void;
+
+        fn listen_protocol(&self) -> Self::InboundProtocol {
+            self.inner.listen_protocol()
+        }
+
+        fn inject_fully_negotiated_inbound(&mut self, out: Void) {
+            void::unreachable(out)
+        }
+
+        fn inject_fully_negotiated_outbound(&mut self, out: Void, _: Void) {
+            void::unreachable(out)
+        }
+
+        fn inject_event(&mut self, event: Void) {
+            void::unreachable(event)
+        }
+
+        fn inject_dial_upgrade_error(&mut self, info: Void, _: ProtocolsHandlerUpgrErr<Void>) {
+            void::unreachable(info)
+        }
+
+        fn inject_inbound_closed(&mut self) {}
+
+        fn connection_keep_alive(&self) -> bool {
+            self.ignore_shutdown
+        }
+
+        fn shutdown(&mut self) {
+            if !self.ignore_shutdown {
+                self.inner.shutdown();
+            }
+        }
+
+        fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<DeniedUpgrade, Void, Void>, Void> {
+            self.inner.poll()
+        }
+    }
+
+    /// Behaviour that records the connections and disconnections it is informed of.
+    #[derive(Default)]
+    struct Connections {
+        connected: Vec<PeerId>,
+        disconnected: Vec<PeerId>,
+        dial_failures: Vec<PeerId>,
+        /// If true, the handlers never finish shutting down.
+        ignore_shutdown: bool,
+    }
+
+    impl<TTopology> NetworkBehaviour<TTopology> for Connections {
+        type ProtocolsHandler = StubbornHandler;
+        type OutEvent = Void;
+
+        fn new_handler(&mut self) -> Self::ProtocolsHandler {
+            StubbornHandler {
+                inner: DummyProtocolsHandler::default(),
+                ignore_shutdown: self.ignore_shutdown,
+            }
+        }
+
+        fn inject_connected(&mut self, peer_id: PeerId, _: ConnectedPoint) {
+            self.connected.push(peer_id);
+        }
+
+        fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
+            self.disconnected.push(peer_id.clone());
+        }
+
+        fn inject_node_event(&mut self, _: PeerId, _: ConnectionId, _: Void) {}
+
+        fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+            self.dial_failures.push(peer_id.clone());
+        }
+
+        fn poll(&mut self, _: &mut PollParameters<TTopology>) -> Async<NetworkBehaviourAction<<Self::ProtocolsHandler as ProtocolsHandler>::InEvent, Void>> {
+            Async::NotReady
+        }
+    }
+
+    /// Builds a swarm that knows an address of `peer_id`, and dials it successfully.
+    fn connections_swarm(peer_id: &PeerId, behaviour: Connections) -> Swarm<DummyTransport, Connections, MemoryTopology> {
+        let mut transport = DummyTransport::new();
+        transport.set_next_peer_id(peer_id);
+        let local_key = PublicKey::Ed25519((0..32).map(|_| random()).collect());
+        let mut topology = MemoryTopology::empty(local_key);
+        topology.add_address(peer_id.clone(), "/ip4/127.0.0.1/tcp/1234".parse().unwrap(), AddressSource::Manual);
+        Swarm::new(transport, behaviour, topology)
+    }
+
+    /// Polls `swarm` until `num_connected` connections have been reported to the behaviour.
+    fn wait_connected(rt: &mut Runtime, swarm: &mut Swarm<DummyTransport, Connections, MemoryTopology>, num_connected: usize) {
+        rt.block_on(future::poll_fn(|| -> Poll<(), ()> {
+            while let Async::Ready(_) = swarm.poll().unwrap() {}
+            if swarm.connected.len() < num_connected {
+                Ok(Async::NotReady)
+            } else {
+                Ok(Async::Ready(()))
+            }
+        })).unwrap();
+    }
+
+    #[test]
+    fn banned_peer_is_disconnected_and_refused() {
+        let peer_id = PeerId::random();
+        let mut swarm = connections_swarm(&peer_id, Connections::default());
+        let mut rt = Runtime::new().unwrap();
+
+        Swarm::dial(&mut swarm, peer_id.clone());
+        wait_connected(&mut rt, &mut swarm, 1);
+
+        // Banning the peer closes the connection, and it can't be dialed any more.
+        Swarm::ban_peer_id(&mut swarm, peer_id.clone());
+        assert_eq!(swarm.disconnected, vec![peer_id.clone()]);
+        assert!(!swarm.raw_swarm.is_connected(&peer_id));
+        assert_eq!(Swarm::banned_peer_ids(&swarm).map(|(p, _)| p.clone()).collect::<Vec<_>>(), vec![peer_id.clone()]);
+        Swarm::dial(&mut swarm, peer_id.clone());
+        assert_eq!(swarm.dial_failures, vec![peer_id.clone()]);
+
+        // Once unbanned, the peer can be dialed again.
+        assert!(Swarm::unban_peer_id(&mut swarm, &peer_id));
+        Swarm::dial(&mut swarm, peer_id.clone());
+        wait_connected(&mut rt, &mut swarm, 2);
+
+        // Same with a ban that expires.
+        Swarm::ban_peer_id_for(&mut swarm, peer_id.clone(), Duration::from_millis(50));
+        assert_eq!(swarm.disconnected.len(), 2);
+        Swarm::dial(&mut swarm, peer_id.clone());
+        assert_eq!(swarm.dial_failures.len(), 2);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(Swarm::banned_peer_ids(&swarm).count(), 0);
+        Swarm::dial(&mut swarm, peer_id.clone());
+        wait_connected(&mut rt, &mut swarm, 3);
+    }
+
+    #[test]
+    fn shutdown_waits_for_the_handlers() {
+        let peer_id = PeerId::random();
+        let mut swarm = connections_swarm(&peer_id, Connections::default());
+        let mut rt = Runtime::new().unwrap();
+        Swarm::dial(&mut swarm, peer_id.clone());
+        wait_connected(&mut rt, &mut swarm, 1);
+
+        // The handler shuts down immediately, so the deadline isn't reached.
+        let start = Instant::now();
+        rt.block_on(Swarm::shutdown(swarm, Duration::from_secs(10))).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn shutdown_closes_the_connections_at_the_deadline() {
+        let peer_id = PeerId::random();
+        let behaviour = Connections { ignore_shutdown: true, ..Connections::default() };
+        let mut swarm = connections_swarm(&peer_id, behaviour);
+        let mut rt = Runtime::new().unwrap();
+        Swarm::dial(&mut swarm, peer_id.clone());
+        wait_connected(&mut rt, &mut swarm, 1);
+
+        // The handler never finishes shutting down, so the connection stays open until the
+        // deadline, where it is closed abruptly and the future resolves.
+        let timeout = Duration::from_millis(200);
+        let start = Instant::now();
+        let mut shutdown = Swarm::shutdown(swarm, timeout);
+        rt.block_on(future::poll_fn(|| -> Poll<(), ()> {
+            assert!(shutdown.poll().unwrap().is_not_ready());
+            Ok(Async::Ready(()))
+        })).unwrap();
+        assert_eq!(shutdown.swarm.as_ref().unwrap().raw_swarm.connections().count(), 1);
+        rt.block_on(shutdown).unwrap();
+        assert!(start.elapsed() >= timeout);
+    }
+}
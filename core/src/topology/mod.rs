@@ -30,10 +30,21 @@
 //!
 //! While the `MemoryTopology` is provided as a ready-to-go topology that is suitable for quick
 //! prototyping, it shouldn't be used in an actual high-performance production software.
+//!
+//! The `PersistentTopology` stores the known addresses in a file, so that they survive a restart
+//! of the node.
+//!
+//! Both topologies keep track of where each address comes from and of whether dialing it
+//! succeeded or failed, and return the addresses that are the most likely to work first. The
+//! `Swarm` reports the outcome of each dial to the topology.
 
-use std::collections::HashMap;
+use std::{cmp::Ordering, collections::HashMap, error, fmt, str::FromStr, time::{Duration, SystemTime}};
 use crate::{Multiaddr, PeerId, PublicKey};
 
+pub use self::persistent::PersistentTopology;
+
+mod persistent;
+
 /// Storage for the network topology.
 ///
 /// The topology should also store information about the local node, including its public key, its
@@ -56,11 +67,233 @@ pub trait Topology {
 
     /// Returns the public key of the local node.
     fn local_public_key(&self) -> &PublicKey;
+
+    /// Reports that we successfully connected to `peer` by dialing `addr`.
+    ///
+    /// The default implementation does nothing.
+    fn report_dial_success(&mut self, _peer: &PeerId, _addr: &Multiaddr) {
+    }
+
+    /// Reports that we failed to reach `peer` by dialing `addr`.
+    ///
+    /// The default implementation does nothing.
+    fn report_dial_failure(&mut self, _peer: &PeerId, _addr: &Multiaddr) {
+    }
+}
+
+/// How an address of a peer has been discovered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AddressSource {
+    /// The address has been added by the user.
+    ///
+    /// These addresses, such as bootstrap nodes, never expire and aren't removed after failing
+    /// to be dialed. They remain until they are explicitly removed.
+    Manual,
+    /// The address has been discovered through mDNS.
+    Mdns,
+    /// The address has been reported by the peer itself through the identify protocol.
+    Identify,
+    /// The address has been discovered through Kademlia.
+    Kademlia,
+}
+
+impl AddressSource {
+    /// Returns the name of the source, as written in the file of a `PersistentTopology`.
+    fn as_str(&self) -> &'static str {
+        match *self {
+            AddressSource::Manual => "manual",
+            AddressSource::Mdns => "mdns",
+            AddressSource::Identify => "identify",
+            AddressSource::Kademlia => "kad",
+        }
+    }
+
+}
+
+impl fmt::Display for AddressSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AddressSource {
+    type Err = AddressSourceParseError;
+
+    /// Parses the output of `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manual" => Ok(AddressSource::Manual),
+            "mdns" => Ok(AddressSource::Mdns),
+            "identify" => Ok(AddressSource::Identify),
+            "kad" => Ok(AddressSource::Kademlia),
+            _ => Err(AddressSourceParseError),
+        }
+    }
+}
+
+/// Error when parsing an `AddressSource`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AddressSourceParseError;
+
+impl fmt::Display for AddressSourceParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("unknown address source")
+    }
+}
+
+impl error::Error for AddressSourceParseError {}
+
+/// Address of a peer stored in a topology, along with information about how reliable it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressRecord {
+    addr: Multiaddr,
+    source: AddressSource,
+    last_seen: SystemTime,
+    ttl: Duration,
+    last_success: Option<SystemTime>,
+    failures: u32,
+}
+
+impl AddressRecord {
+    /// Creates a record for an address that has just been discovered.
+    fn new(addr: Multiaddr, source: AddressSource, ttl: Duration) -> AddressRecord {
+        AddressRecord {
+            addr,
+            source,
+            last_seen: SystemTime::now(),
+            ttl,
+            last_success: None,
+            failures: 0,
+        }
+    }
+
+    /// Returns the address.
+    #[inline]
+    pub fn addr(&self) -> &Multiaddr {
+        &self.addr
+    }
+
+    /// Returns how the address has been discovered, or rediscovered most recently.
+    #[inline]
+    pub fn source(&self) -> AddressSource {
+        self.source
+    }
+
+    /// Returns when the address was last discovered or successfully dialed.
+    #[inline]
+    pub fn last_seen(&self) -> SystemTime {
+        self.last_seen
+    }
+
+    /// Returns how long after `last_seen` the address remains valid.
+    #[inline]
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns when the address was last successfully dialed, if ever.
+    #[inline]
+    pub fn last_success(&self) -> Option<SystemTime> {
+        self.last_success
+    }
+
+    /// Returns the number of times in a row dialing the address has failed.
+    #[inline]
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Returns true if the address is no longer valid at the given time.
+    ///
+    /// Addresses whose source is `AddressSource::Manual` never expire.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        if self.source == AddressSource::Manual {
+            return false;
+        }
+
+        match now.duration_since(self.last_seen) {
+            Ok(elapsed) => elapsed > self.ttl,
+            // `last_seen` is in the future, for example because the clock has changed.
+            Err(_) => false,
+        }
+    }
+
+    /// Compares the records by how likely dialing the address is to succeed. The best record is
+    /// `Ordering::Less` than the other.
+    ///
+    /// Addresses that failed the least times in a row come first, then the ones that were
+    /// successfully dialed the most recently, then the ones that were discovered the most
+    /// recently.
+    pub fn cmp_score(&self, other: &AddressRecord) -> Ordering {
+        self.failures.cmp(&other.failures)
+            .then_with(|| other.last_success.cmp(&self.last_success))
+            .then_with(|| other.last_seen.cmp(&self.last_seen))
+    }
+}
+
+/// Adds an address to a list of records, or updates the existing record for this address.
+fn insert_record(addrs: &mut Vec<AddressRecord>, addr: Multiaddr, source: AddressSource, ttl: Duration) {
+    if let Some(existing) = addrs.iter_mut().find(|r| r.addr == addr) {
+        // Rediscovering an address added by the user must not make it expirable.
+        if existing.source != AddressSource::Manual {
+            existing.source = source;
+        }
+        existing.last_seen = SystemTime::now();
+        existing.ttl = ttl;
+    } else {
+        addrs.push(AddressRecord::new(addr, source, ttl));
+    }
+}
+
+/// Records a successful dial of `addr`. Returns true if the address was in the list.
+fn report_success(addrs: &mut [AddressRecord], addr: &Multiaddr) -> bool {
+    match addrs.iter_mut().find(|r| &r.addr == addr) {
+        Some(record) => {
+            let now = SystemTime::now();
+            record.last_seen = now;
+            record.last_success = Some(now);
+            record.failures = 0;
+            true
+        },
+        None => false,
+    }
+}
+
+/// Records a failed dial of `addr`, and removes the address if it has failed `max_failures`
+/// times in a row, unless it has been added by the user. Returns true if the address was in the
+/// list.
+fn report_failure(addrs: &mut Vec<AddressRecord>, addr: &Multiaddr, max_failures: u32) -> bool {
+    let pos = match addrs.iter().position(|r| &r.addr == addr) {
+        Some(pos) => pos,
+        None => return false,
+    };
+
+    addrs[pos].failures = addrs[pos].failures.saturating_add(1);
+    if addrs[pos].failures >= max_failures && addrs[pos].source != AddressSource::Manual {
+        addrs.remove(pos);
+    }
+    true
+}
+
+/// Returns the non-expired addresses of a list of records, best score first.
+fn addresses_by_score(addrs: &[AddressRecord]) -> Vec<Multiaddr> {
+    let now = SystemTime::now();
+    let mut records = addrs.iter().filter(|r| !r.is_expired(now)).collect::<Vec<_>>();
+    records.sort_by(|a, b| a.cmp_score(b));
+    records.into_iter().map(|r| r.addr.clone()).collect()
 }
 
 /// Topology of the network stored in memory.
+///
+/// Each address is stored along with how it has been discovered and how reliable it has been so
+/// far. Addresses expire after a configurable time-to-live, and are removed after failing to be
+/// dialed too many times in a row. The addresses added with `AddressSource::Manual` are exempt
+/// from both, and remain until `remove_address` is called.
 pub struct MemoryTopology {
-    list: HashMap<PeerId, Vec<Multiaddr>>,
+    list: HashMap<PeerId, Vec<AddressRecord>>,
+    local_addrs: Vec<Multiaddr>,
+    default_ttl: Duration,
+    max_failures: u32,
     local_peer_id: PeerId,
     local_public_key: PublicKey,
 }
@@ -73,24 +306,70 @@ impl MemoryTopology {
 
         MemoryTopology {
             list: Default::default(),
+            local_addrs: Vec::new(),
+            default_ttl: Duration::from_secs(3600),
+            max_failures: 3,
             local_peer_id,
             local_public_key: pubkey,
         }
     }
 
+    /// Sets the time-to-live of the addresses added without an explicit TTL. Defaults to one
+    /// hour.
+    #[inline]
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    /// Sets the number of dialing failures in a row after which an address is removed. Defaults
+    /// to 3.
+    #[inline]
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
     /// Returns true if the topology is empty.
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.list.is_empty()
     }
 
-    /// Adds an address to the topology.
+    /// Adds an address to the topology, with the default time-to-live.
+    ///
+    /// If the address is already known, its source and last-seen time are updated.
+    #[inline]
+    pub fn add_address(&mut self, peer: PeerId, addr: Multiaddr, source: AddressSource) {
+        let ttl = self.default_ttl;
+        self.add_address_with_ttl(peer, addr, source, ttl)
+    }
+
+    /// Adds an address to the topology, valid for the given duration.
+    ///
+    /// If the address is already known, its source, last-seen time and time-to-live are updated.
     #[inline]
-    pub fn add_address(&mut self, peer: PeerId, addr: Multiaddr) {
-        let addrs = self.list.entry(peer).or_insert_with(|| Vec::new());
-        if addrs.iter().all(|a| a != &addr) {
-            addrs.push(addr);
+    pub fn add_address_with_ttl(&mut self, peer: PeerId, addr: Multiaddr, source: AddressSource, ttl: Duration) {
+        let addrs = self.list.entry(peer).or_insert_with(Vec::new);
+        insert_record(addrs, addr, source, ttl);
+    }
+
+    /// Removes an address of a peer. Returns true if the address was known.
+    pub fn remove_address(&mut self, peer: &PeerId, addr: &Multiaddr) -> bool {
+        let (removed, now_empty) = match self.list.get_mut(peer) {
+            Some(addrs) => {
+                let len_before = addrs.len();
+                addrs.retain(|r| &r.addr != addr);
+                (addrs.len() != len_before, addrs.is_empty())
+            },
+            None => return false,
+        };
+
+        if now_empty {
+            self.list.remove(peer);
         }
+
+        removed
     }
 
     /// Returns a list of all the known peers in the topology.
@@ -99,24 +378,40 @@ impl MemoryTopology {
         self.list.keys()
     }
 
-    /// Returns an iterator to all the entries in the topology.
+    /// Returns an iterator to all the entries in the topology, including the expired ones that
+    /// haven't been pruned yet.
     #[inline]
-    pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &Multiaddr)> {
-        self.list.iter().flat_map(|(p, l)| l.iter().map(move |ma| (p, ma)))
+    pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &AddressRecord)> {
+        self.list.iter().flat_map(|(p, l)| l.iter().map(move |r| (p, r)))
+    }
+
+    /// Removes the addresses whose time-to-live has expired, and the peers that no longer have
+    /// any address.
+    pub fn prune(&mut self) {
+        let now = SystemTime::now();
+        self.list.retain(|_, addrs| {
+            addrs.retain(|r| !r.is_expired(now));
+            !addrs.is_empty()
+        });
     }
 }
 
 impl Topology for MemoryTopology {
     fn addresses_of_peer(&mut self, peer: &PeerId) -> Vec<Multiaddr> {
-        self.list.get(peer).map(|v| v.clone()).unwrap_or(Vec::new())
+        if peer == &self.local_peer_id {
+            return self.local_addrs.clone();
+        }
+
+        self.list.get(peer).map(|addrs| addresses_by_score(addrs)).unwrap_or_default()
     }
 
     fn add_local_external_addrs<TIter>(&mut self, addrs: TIter)
     where TIter: Iterator<Item = Multiaddr>
     {
         for addr in addrs {
-            let id = self.local_peer_id.clone();
-            self.add_address(id, addr);
+            if self.local_addrs.iter().all(|a| a != &addr) {
+                self.local_addrs.push(addr);
+            }
         }
     }
 
@@ -129,4 +424,118 @@ impl Topology for MemoryTopology {
     fn local_public_key(&self) -> &PublicKey {
         &self.local_public_key
     }
+
+    fn report_dial_success(&mut self, peer: &PeerId, addr: &Multiaddr) {
+        if let Some(addrs) = self.list.get_mut(peer) {
+            report_success(addrs, addr);
+        }
+    }
+
+    fn report_dial_failure(&mut self, peer: &PeerId, addr: &Multiaddr) {
+        let now_empty = match self.list.get_mut(peer) {
+            Some(addrs) => {
+                report_failure(addrs, addr, self.max_failures);
+                addrs.is_empty()
+            },
+            None => return,
+        };
+
+        if now_empty {
+            self.list.remove(peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::random;
+
+    fn topology() -> MemoryTopology {
+        MemoryTopology::empty(PublicKey::Rsa((0 .. 2048).map(|_| -> u8 { random() }).collect()))
+    }
+
+    #[test]
+    fn address_source_from_str() {
+        for source in &[AddressSource::Manual, AddressSource::Mdns, AddressSource::Identify, AddressSource::Kademlia] {
+            assert_eq!(source.to_string().parse::<AddressSource>(), Ok(*source));
+        }
+        assert_eq!("foo".parse::<AddressSource>(), Err(AddressSourceParseError));
+    }
+
+    #[test]
+    fn addresses_ordered_by_score() {
+        let mut topology = topology();
+        let peer_id = PeerId::random();
+        let addr1: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        let addr2: Multiaddr = "/ip4/6.7.8.9/tcp/10".parse().unwrap();
+        let addr3: Multiaddr = "/ip4/11.12.13.14/tcp/15".parse().unwrap();
+        topology.add_address(peer_id.clone(), addr1.clone(), AddressSource::Kademlia);
+        topology.add_address(peer_id.clone(), addr2.clone(), AddressSource::Mdns);
+        topology.add_address(peer_id.clone(), addr3.clone(), AddressSource::Identify);
+
+        topology.report_dial_failure(&peer_id, &addr2);
+        topology.report_dial_success(&peer_id, &addr3);
+        assert_eq!(topology.addresses_of_peer(&peer_id), vec![addr3, addr1, addr2]);
+    }
+
+    #[test]
+    fn failing_addresses_are_removed() {
+        let mut topology = topology().max_failures(2);
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        topology.add_address(peer_id.clone(), addr.clone(), AddressSource::Kademlia);
+
+        topology.report_dial_failure(&peer_id, &addr);
+        assert_eq!(topology.addresses_of_peer(&peer_id), vec![addr.clone()]);
+        topology.report_dial_failure(&peer_id, &addr);
+        assert!(topology.addresses_of_peer(&peer_id).is_empty());
+        assert!(topology.is_empty());
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let mut topology = topology();
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        topology.add_address(peer_id.clone(), addr.clone(), AddressSource::Manual);
+
+        topology.report_dial_failure(&peer_id, &addr);
+        topology.report_dial_success(&peer_id, &addr);
+        let (_, record) = topology.iter().next().unwrap();
+        assert_eq!(record.failures(), 0);
+        assert!(record.last_success().is_some());
+    }
+
+    #[test]
+    fn expired_addresses_are_ignored() {
+        let mut topology = topology();
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        topology.add_address_with_ttl(peer_id.clone(), addr, AddressSource::Kademlia, Duration::from_secs(0));
+        topology.list.get_mut(&peer_id).unwrap()[0].last_seen -= Duration::from_secs(1);
+        assert!(topology.addresses_of_peer(&peer_id).is_empty());
+
+        topology.prune();
+        assert!(topology.is_empty());
+    }
+
+    #[test]
+    fn manual_addresses_are_kept() {
+        let mut topology = topology().max_failures(1);
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        topology.add_address_with_ttl(peer_id.clone(), addr.clone(), AddressSource::Manual, Duration::from_secs(0));
+        topology.list.get_mut(&peer_id).unwrap()[0].last_seen -= Duration::from_secs(1);
+
+        // Neither failures nor expiration remove the address, even if it's rediscovered.
+        topology.report_dial_failure(&peer_id, &addr);
+        topology.add_address(peer_id.clone(), addr.clone(), AddressSource::Kademlia);
+        topology.report_dial_failure(&peer_id, &addr);
+        topology.prune();
+        assert_eq!(topology.addresses_of_peer(&peer_id), vec![addr.clone()]);
+
+        assert!(topology.remove_address(&peer_id, &addr));
+        assert!(topology.is_empty());
+    }
 }
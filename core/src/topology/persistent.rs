@@ -0,0 +1,445 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{Multiaddr, PeerId, PublicKey};
+use crate::topology::{AddressRecord, AddressSource, Topology};
+use crate::topology::{addresses_by_score, insert_record, report_failure, report_success};
+use log::warn;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// First line of the files written by a `PersistentTopology`.
+const FILE_HEADER: &str = "# libp2p topology v1";
+
+/// Topology of the network stored in memory and saved in a file.
+///
+/// Each address is stored along with how it has been discovered, when it was last seen, how long
+/// it remains valid after that, and how reliable it has been so far. Addresses whose
+/// time-to-live has expired are pruned, and addresses that failed to be dialed too many times in
+/// a row are removed. The addresses added with `AddressSource::Manual` are exempt from both.
+///
+/// The content of the topology is loaded from the file when the topology is opened, and written
+/// back when calling `flush()` and when the topology is destroyed. Writes are atomic: the file
+/// is either left untouched or entirely replaced.
+///
+/// The external addresses of the local node are not saved, as they are only valid while the
+/// node is running.
+pub struct PersistentTopology {
+    /// Path to the file where the topology is saved.
+    path: PathBuf,
+    /// Addresses of each known peer.
+    list: HashMap<PeerId, Vec<AddressRecord>>,
+    /// Addresses that other nodes can use to reach the local node.
+    local_addrs: Vec<Multiaddr>,
+    /// Time-to-live of the addresses added without an explicit TTL.
+    default_ttl: Duration,
+    /// Number of dialing failures in a row after which an address is removed.
+    max_failures: u32,
+    /// True if `list` has been modified since it was last written to the file.
+    dirty: bool,
+    local_peer_id: PeerId,
+    local_public_key: PublicKey,
+}
+
+impl PersistentTopology {
+    /// Opens the topology stored in the given file. If the file doesn't exist, the topology is
+    /// empty and the file is created on the first write.
+    ///
+    /// Expired addresses are pruned when loading.
+    pub fn open(path: impl Into<PathBuf>, pubkey: PublicKey) -> io::Result<PersistentTopology> {
+        let path = path.into();
+        let local_peer_id = pubkey.clone().into_peer_id();
+
+        let list = match fs::File::open(&path) {
+            Ok(file) => load(BufReader::new(file))?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut topology = PersistentTopology {
+            path,
+            list,
+            local_addrs: Vec::new(),
+            default_ttl: Duration::from_secs(7 * 24 * 3600),
+            max_failures: 3,
+            dirty: false,
+            local_peer_id,
+            local_public_key: pubkey,
+        };
+        topology.prune();
+        Ok(topology)
+    }
+
+    /// Sets the time-to-live of the addresses added without an explicit TTL. Defaults to one
+    /// week.
+    #[inline]
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    /// Sets the number of dialing failures in a row after which an address is removed. Defaults
+    /// to 3.
+    #[inline]
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Returns the path to the file where the topology is saved.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true if the topology is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Adds an address to the topology, with the default time-to-live.
+    ///
+    /// If the address is already known, its source and last-seen time are updated.
+    #[inline]
+    pub fn add_address(&mut self, peer: PeerId, addr: Multiaddr, source: AddressSource) {
+        let ttl = self.default_ttl;
+        self.add_address_with_ttl(peer, addr, source, ttl)
+    }
+
+    /// Adds an address to the topology, valid for the given duration.
+    ///
+    /// If the address is already known, its source, last-seen time and time-to-live are updated.
+    pub fn add_address_with_ttl(&mut self, peer: PeerId, addr: Multiaddr, source: AddressSource, ttl: Duration) {
+        let addrs = self.list.entry(peer).or_insert_with(Vec::new);
+        insert_record(addrs, addr, source, ttl);
+        self.dirty = true;
+    }
+
+    /// Removes an address of a peer. Returns true if the address was known.
+    pub fn remove_address(&mut self, peer: &PeerId, addr: &Multiaddr) -> bool {
+        let (removed, now_empty) = match self.list.get_mut(peer) {
+            Some(addrs) => {
+                let len_before = addrs.len();
+                addrs.retain(|r| &r.addr != addr);
+                (addrs.len() != len_before, addrs.is_empty())
+            },
+            None => return false,
+        };
+
+        if now_empty {
+            self.list.remove(peer);
+        }
+
+        self.dirty |= removed;
+        removed
+    }
+
+    /// Removes all the addresses of a peer. Returns true if the peer was known.
+    pub fn remove_peer(&mut self, peer: &PeerId) -> bool {
+        let removed = self.list.remove(peer).is_some();
+        self.dirty |= removed;
+        removed
+    }
+
+    /// Returns a list of all the known peers in the topology.
+    #[inline]
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.list.keys()
+    }
+
+    /// Returns an iterator to all the entries in the topology, including the expired ones that
+    /// haven't been pruned yet.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &AddressRecord)> {
+        self.list.iter().flat_map(|(p, l)| l.iter().map(move |r| (p, r)))
+    }
+
+    /// Removes the addresses whose time-to-live has expired, and the peers that no longer have
+    /// any address.
+    pub fn prune(&mut self) {
+        let now = SystemTime::now();
+        let mut pruned = false;
+        self.list.retain(|_, addrs| {
+            let len_before = addrs.len();
+            addrs.retain(|r| !r.is_expired(now));
+            pruned |= addrs.len() != len_before;
+            !addrs.is_empty()
+        });
+        self.dirty |= pruned;
+    }
+
+    /// Prunes the expired addresses and writes the topology to its file, if it has been modified
+    /// since the last write.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.prune();
+        if !self.dirty {
+            return Ok(());
+        }
+
+        // Write to a temporary file first, then move it in place of the actual file, so that we
+        // never leave a partially-written file behind.
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        {
+            let file = fs::File::create(&tmp_path)?;
+            let mut writer = BufWriter::new(file);
+            save(&mut writer, &self.list)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+
+        fs::rename(&tmp_path, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Topology for PersistentTopology {
+    fn addresses_of_peer(&mut self, peer: &PeerId) -> Vec<Multiaddr> {
+        if peer == &self.local_peer_id {
+            return self.local_addrs.clone();
+        }
+
+        self.list.get(peer).map(|addrs| addresses_by_score(addrs)).unwrap_or_default()
+    }
+
+    fn add_local_external_addrs<TIter>(&mut self, addrs: TIter)
+    where TIter: Iterator<Item = Multiaddr>
+    {
+        for addr in addrs {
+            if self.local_addrs.iter().all(|a| a != &addr) {
+                self.local_addrs.push(addr);
+            }
+        }
+    }
+
+    #[inline]
+    fn local_peer_id(&self) -> &PeerId {
+        &self.local_peer_id
+    }
+
+    #[inline]
+    fn local_public_key(&self) -> &PublicKey {
+        &self.local_public_key
+    }
+
+    fn report_dial_success(&mut self, peer: &PeerId, addr: &Multiaddr) {
+        if let Some(addrs) = self.list.get_mut(peer) {
+            self.dirty |= report_success(addrs, addr);
+        }
+    }
+
+    fn report_dial_failure(&mut self, peer: &PeerId, addr: &Multiaddr) {
+        let now_empty = match self.list.get_mut(peer) {
+            Some(addrs) => {
+                self.dirty |= report_failure(addrs, addr, self.max_failures);
+                addrs.is_empty()
+            },
+            None => return,
+        };
+
+        if now_empty {
+            self.list.remove(peer);
+        }
+    }
+}
+
+impl Drop for PersistentTopology {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Failed to save the topology to {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Writes the addresses in the format expected by `load`.
+///
+/// Each address is written on its own line, as the peer ID, the address, the source, the
+/// last-seen time in seconds since the UNIX epoch, the time-to-live in seconds, the time of the
+/// last successful dial in seconds since the UNIX epoch or `-`, and the number of failed dials in
+/// a row.
+fn save(mut writer: impl Write, list: &HashMap<PeerId, Vec<AddressRecord>>) -> io::Result<()> {
+    writeln!(writer, "{}", FILE_HEADER)?;
+    for (peer_id, addrs) in list {
+        for record in addrs {
+            let last_success = match record.last_success {
+                Some(time) => unix_secs(time).to_string(),
+                None => "-".to_owned(),
+            };
+            writeln!(writer, "{} {} {} {} {} {} {}",
+                peer_id.to_base58(),
+                record.addr,
+                record.source.as_str(),
+                unix_secs(record.last_seen),
+                record.ttl.as_secs(),
+                last_success,
+                record.failures)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the addresses written by `save`.
+fn load(reader: impl BufRead) -> io::Result<HashMap<PeerId, Vec<AddressRecord>>> {
+    let mut list = HashMap::<PeerId, Vec<AddressRecord>>::new();
+    let mut lines = reader.lines();
+
+    match lines.next() {
+        Some(Ok(ref header)) if header == FILE_HEADER => (),
+        Some(Err(err)) => return Err(err),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized topology file header")),
+    }
+
+    for (line_num, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (peer_id, record) = parse_line(&line).ok_or_else(|| {
+            let msg = format!("invalid entry on line {} of topology file", line_num + 2);
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })?;
+        list.entry(peer_id).or_insert_with(Vec::new).push(record);
+    }
+
+    Ok(list)
+}
+
+/// Parses one line of a file written by `save`.
+fn parse_line(line: &str) -> Option<(PeerId, AddressRecord)> {
+    let mut fields = line.split_whitespace();
+    let peer_id = fields.next()?.parse::<PeerId>().ok()?;
+    let addr = fields.next()?.parse::<Multiaddr>().ok()?;
+    let source = fields.next()?.parse::<AddressSource>().ok()?;
+    let last_seen = fields.next()?.parse::<u64>().ok()?;
+    let ttl = fields.next()?.parse::<u64>().ok()?;
+    let last_success = match fields.next()? {
+        "-" => None,
+        secs => Some(UNIX_EPOCH + Duration::from_secs(secs.parse::<u64>().ok()?)),
+    };
+    let failures = fields.next()?.parse::<u32>().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+
+    Some((peer_id, AddressRecord {
+        addr,
+        source,
+        last_seen: UNIX_EPOCH + Duration::from_secs(last_seen),
+        ttl: Duration::from_secs(ttl),
+        last_success,
+        failures,
+    }))
+}
+
+/// Returns the number of seconds between the UNIX epoch and `time`.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::random;
+
+    fn local_key() -> PublicKey {
+        PublicKey::Rsa((0 .. 2048).map(|_| -> u8 { random() }).collect())
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("libp2p-topology-test-{}", random::<u64>()))
+    }
+
+    #[test]
+    fn addresses_survive_a_restart() {
+        let path = temp_path();
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+
+        {
+            let mut topology = PersistentTopology::open(path.clone(), local_key()).unwrap();
+            assert!(topology.is_empty());
+            topology.add_address(peer_id.clone(), addr.clone(), AddressSource::Kademlia);
+            topology.report_dial_success(&peer_id, &addr);
+            topology.report_dial_failure(&peer_id, &addr);
+            topology.flush().unwrap();
+        }
+
+        let mut topology = PersistentTopology::open(path.clone(), local_key()).unwrap();
+        assert_eq!(topology.addresses_of_peer(&peer_id), vec![addr]);
+        let (_, record) = topology.iter().next().unwrap();
+        assert_eq!(record.source(), AddressSource::Kademlia);
+        assert!(record.last_success().is_some());
+        assert_eq!(record.failures(), 1);
+        drop(topology);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn expired_addresses_are_pruned() {
+        let path = temp_path();
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+
+        let mut topology = PersistentTopology::open(path.clone(), local_key()).unwrap();
+        topology.add_address_with_ttl(peer_id.clone(), addr.clone(), AddressSource::Kademlia, Duration::from_secs(0));
+        topology.list.get_mut(&peer_id).unwrap()[0].last_seen -= Duration::from_secs(1);
+        assert!(topology.addresses_of_peer(&peer_id).is_empty());
+
+        topology.prune();
+        assert!(topology.is_empty());
+        drop(topology);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn most_recently_seen_addresses_first() {
+        let mut topology = PersistentTopology::open(temp_path(), local_key()).unwrap();
+        let peer_id = PeerId::random();
+        let addr1: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        let addr2: Multiaddr = "/ip4/6.7.8.9/tcp/10".parse().unwrap();
+        topology.add_address(peer_id.clone(), addr1.clone(), AddressSource::Mdns);
+        topology.add_address(peer_id.clone(), addr2.clone(), AddressSource::Identify);
+        topology.list.get_mut(&peer_id).unwrap()[0].last_seen -= Duration::from_secs(60);
+        assert_eq!(topology.addresses_of_peer(&peer_id), vec![addr2, addr1]);
+
+        // Don't write anything to the disk.
+        topology.list.clear();
+        topology.dirty = false;
+    }
+
+    #[test]
+    fn invalid_file_is_refused() {
+        let path = temp_path();
+        fs::write(&path, format!("{}\nfoo bar\n", FILE_HEADER)).unwrap();
+        let err = PersistentTopology::open(path.clone(), local_key()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,537 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Wraps around a `Transport` and reports the activity of the connections to a `Metrics`.
+//!
+//! The wrapped transport must produce a `PeerId` and a `StreamMuxer`, which is normally the case
+//! of the transport passed to the `Swarm`. The connections are grouped by transport using the
+//! last protocol of their multiaddress, for example `tcp` or `ws`.
+//!
+//! In order to know which protocol each substream uses, the negotiation performed by
+//! *multistream-select* at the beginning of each substream is inspected as the data flows.
+
+use crate::{Multiaddr, PeerId, Transport, metrics::{AtomicBandwidth, Bandwidth, ConnectionCounters, Metrics}};
+use crate::muxing::{Shutdown, StreamMuxer};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use multiaddr::Protocol;
+use std::{io::Error as IoError, sync::Arc};
+
+/// Wraps around a `Transport` and reports the activity of the connections to a `Metrics`.
+#[derive(Debug, Clone)]
+pub struct Metered<TInner> {
+    inner: TInner,
+    metrics: Metrics,
+}
+
+impl<TInner> Metered<TInner> {
+    /// Wraps around a `Transport`.
+    #[inline]
+    pub fn new(inner: TInner, metrics: Metrics) -> Self {
+        Metered { inner, metrics }
+    }
+
+    /// Returns the `Metrics` the connections report to.
+    #[inline]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl<TInner, TMuxer> Transport for Metered<TInner>
+where
+    TInner: Transport<Output = (PeerId, TMuxer)>,
+    TMuxer: StreamMuxer,
+{
+    type Output = (PeerId, MeteredMuxer<TMuxer>);
+    type Listener = MeteredListener<TInner::Listener>;
+    type ListenerUpgrade = MeteredFuture<TInner::ListenerUpgrade>;
+    type Dial = MeteredFuture<TInner::Dial>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let metrics = self.metrics;
+        match self.inner.listen_on(addr) {
+            Ok((inner, addr)) => Ok((MeteredListener { inner, metrics }, addr)),
+            Err((inner, addr)) => Err((Metered { inner, metrics }, addr)),
+        }
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let metrics = self.metrics;
+        let transport = transport_name(&addr);
+        match self.inner.dial(addr) {
+            Ok(inner) => Ok(MeteredFuture { inner, transport, metrics }),
+            Err((inner, addr)) => Err((Metered { inner, metrics }, addr)),
+        }
+    }
+
+    #[inline]
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
+
+/// Listener for the `Metered` transport.
+// TODO: can be removed and replaced with an `impl Stream` once impl Trait is fully stable
+//       in Rust (https://github.com/rust-lang/rust/issues/34511)
+pub struct MeteredListener<TInner> {
+    inner: TInner,
+    metrics: Metrics,
+}
+
+impl<TInner, TUpgr> Stream for MeteredListener<TInner>
+where
+    TInner: Stream<Item = (TUpgr, Multiaddr)>,
+{
+    type Item = (MeteredFuture<TUpgr>, Multiaddr);
+    type Error = TInner::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match try_ready!(self.inner.poll()) {
+            Some((inner, addr)) => {
+                let upgrade = MeteredFuture {
+                    inner,
+                    transport: transport_name(&addr),
+                    metrics: self.metrics.clone(),
+                };
+                Ok(Async::Ready(Some((upgrade, addr))))
+            },
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+/// Future that produces a connection of the `Metered` transport.
+// TODO: can be replaced with `impl Future` once `impl Trait` are fully stable in Rust
+//       (https://github.com/rust-lang/rust/issues/34511)
+#[must_use = "futures do nothing unless polled"]
+pub struct MeteredFuture<TInner> {
+    inner: TInner,
+    transport: String,
+    metrics: Metrics,
+}
+
+impl<TInner, TMuxer> Future for MeteredFuture<TInner>
+where
+    TInner: Future<Item = (PeerId, TMuxer), Error = IoError>,
+{
+    type Item = (PeerId, MeteredMuxer<TMuxer>);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (peer_id, inner) = try_ready!(self.inner.poll());
+        let counters = self.metrics.connection_opened(&self.transport, peer_id.clone());
+        let muxer = MeteredMuxer {
+            inner,
+            transport: self.transport.clone(),
+            metrics: self.metrics.clone(),
+            counters,
+        };
+        Ok(Async::Ready((peer_id, muxer)))
+    }
+}
+
+/// Muxer of a connection opened by the `Metered` transport.
+///
+/// Counts the bytes exchanged on each substream. Reports the connection as closed when dropped.
+pub struct MeteredMuxer<TInner> {
+    inner: TInner,
+    transport: String,
+    metrics: Metrics,
+    /// Counters of the connection. Updating them doesn't lock the `Metrics`.
+    counters: Arc<ConnectionCounters>,
+}
+
+impl<TInner> MeteredMuxer<TInner> {
+    /// Reports bytes exchanged on a substream, and updates the state of its negotiation.
+    fn transferred(&self, substream: &mut MeteredSubstream<impl Sized>, data: &[u8], inbound: bool) {
+        let bandwidth = if inbound {
+            Bandwidth { inbound: data.len() as u64, outbound: 0 }
+        } else {
+            Bandwidth { inbound: 0, outbound: data.len() as u64 }
+        };
+
+        self.counters.transferred(bandwidth);
+        if let Some(ref protocol) = substream.protocol {
+            protocol.add(bandwidth);
+            return;
+        }
+
+        let protocol = match substream.negotiation {
+            Some(ref mut negotiation) => {
+                substream.pending.inbound += bandwidth.inbound;
+                substream.pending.outbound += bandwidth.outbound;
+                match negotiation.inject_data(data, inbound) {
+                    NegotiationState::InProgress => return,
+                    NegotiationState::Failed => None,
+                    NegotiationState::Success(protocol) => Some(protocol),
+                }
+            },
+            None => return,
+        };
+
+        substream.negotiation = None;
+        if let Some(protocol) = protocol {
+            self.metrics.substream_negotiated(&protocol);
+            let counters = self.counters.protocol(&protocol);
+            counters.add(substream.pending);
+            substream.protocol = Some(counters);
+        }
+    }
+}
+
+impl<TInner> StreamMuxer for MeteredMuxer<TInner>
+where
+    TInner: StreamMuxer,
+{
+    type Substream = MeteredSubstream<TInner::Substream>;
+    type OutboundSubstream = TInner::OutboundSubstream;
+
+    #[inline]
+    fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> {
+        let substream = try_ready!(self.inner.poll_inbound());
+        Ok(Async::Ready(substream.map(MeteredSubstream::new)))
+    }
+
+    #[inline]
+    fn open_outbound(&self) -> Self::OutboundSubstream {
+        self.inner.open_outbound()
+    }
+
+    #[inline]
+    fn poll_outbound(&self, s: &mut Self::OutboundSubstream) -> Poll<Option<Self::Substream>, IoError> {
+        let substream = try_ready!(self.inner.poll_outbound(s));
+        Ok(Async::Ready(substream.map(MeteredSubstream::new)))
+    }
+
+    #[inline]
+    fn destroy_outbound(&self, s: Self::OutboundSubstream) {
+        self.inner.destroy_outbound(s)
+    }
+
+    fn read_substream(&self, s: &mut Self::Substream, buf: &mut [u8]) -> Poll<usize, IoError> {
+        let num_read = try_ready!(self.inner.read_substream(&mut s.inner, buf));
+        self.transferred(s, &buf[..num_read], true);
+        Ok(Async::Ready(num_read))
+    }
+
+    fn write_substream(&self, s: &mut Self::Substream, buf: &[u8]) -> Poll<usize, IoError> {
+        let num_written = try_ready!(self.inner.write_substream(&mut s.inner, buf));
+        self.transferred(s, &buf[..num_written], false);
+        Ok(Async::Ready(num_written))
+    }
+
+    #[inline]
+    fn flush_substream(&self, s: &mut Self::Substream) -> Poll<(), IoError> {
+        self.inner.flush_substream(&mut s.inner)
+    }
+
+    #[inline]
+    fn shutdown_substream(&self, s: &mut Self::Substream, kind: Shutdown) -> Poll<(), IoError> {
+        self.inner.shutdown_substream(&mut s.inner, kind)
+    }
+
+    #[inline]
+    fn destroy_substream(&self, s: Self::Substream) {
+        self.inner.destroy_substream(s.inner)
+    }
+
+    #[inline]
+    fn shutdown(&self, kind: Shutdown) -> Poll<(), IoError> {
+        self.inner.shutdown(kind)
+    }
+
+    #[inline]
+    fn flush_all(&self) -> Poll<(), IoError> {
+        self.inner.flush_all()
+    }
+}
+
+impl<TInner> Drop for MeteredMuxer<TInner> {
+    fn drop(&mut self) {
+        self.metrics.connection_closed(&self.transport, &self.counters);
+    }
+}
+
+/// Substream of a `MeteredMuxer`.
+pub struct MeteredSubstream<TInner> {
+    inner: TInner,
+    /// Counters of the protocol negotiated on the substream, if known.
+    protocol: Option<Arc<AtomicBandwidth>>,
+    /// State of the negotiation. `None` once the negotiation is over, successfully or not.
+    negotiation: Option<Negotiation>,
+    /// Bytes exchanged before the protocol is known.
+    pending: Bandwidth,
+}
+
+impl<TInner> MeteredSubstream<TInner> {
+    fn new(inner: TInner) -> Self {
+        MeteredSubstream {
+            inner,
+            protocol: None,
+            negotiation: Some(Negotiation::default()),
+            pending: Bandwidth::default(),
+        }
+    }
+}
+
+/// Maximum number of bytes of a negotiation we buffer in each direction before giving up.
+const MAX_NEGOTIATION_LEN: usize = 8 * 1024;
+
+/// Passive observer of a *multistream-select* negotiation.
+///
+/// Each side sends length-prefixed messages. The negotiation succeeds when a protocol name sent
+/// by one side is echoed by the other side.
+#[derive(Debug, Default)]
+struct Negotiation {
+    /// Bytes received that don't form a complete message yet.
+    inbound_buf: Vec<u8>,
+    /// Bytes sent that don't form a complete message yet.
+    outbound_buf: Vec<u8>,
+    /// Protocol names received.
+    received: Vec<Vec<u8>>,
+    /// Protocol names sent.
+    sent: Vec<Vec<u8>>,
+}
+
+/// Outcome of injecting data in a `Negotiation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NegotiationState {
+    /// We don't know the protocol yet.
+    InProgress,
+    /// The data doesn't look like a negotiation, or is too long.
+    Failed,
+    /// Both sides agreed on this protocol.
+    Success(String),
+}
+
+impl Negotiation {
+    /// Injects data that has been received or sent on the substream.
+    fn inject_data(&mut self, data: &[u8], inbound: bool) -> NegotiationState {
+        let (buf, messages) = if inbound {
+            (&mut self.inbound_buf, &mut self.received)
+        } else {
+            (&mut self.outbound_buf, &mut self.sent)
+        };
+
+        buf.extend_from_slice(data);
+        if buf.len() > MAX_NEGOTIATION_LEN {
+            return NegotiationState::Failed;
+        }
+
+        loop {
+            let (len, prefix_len) = match decode_varint(&buf) {
+                Ok(Some(v)) => v,
+                Ok(None) => break,
+                Err(()) => return NegotiationState::Failed,
+            };
+            if buf.len() < prefix_len + len {
+                break;
+            }
+
+            let message = buf[prefix_len .. prefix_len + len].to_vec();
+            buf.drain(.. prefix_len + len);
+            match &message[..] {
+                b"/multistream/1.0.0\n" | b"na\n" | b"ls\n" => (),
+                _ => messages.push(message),
+            }
+        }
+
+        let agreed = self.received.iter().find(|m| self.sent.contains(m));
+        match agreed {
+            Some(message) => {
+                let name = if message.last() == Some(&b'\n') {
+                    &message[.. message.len() - 1]
+                } else {
+                    &message[..]
+                };
+                NegotiationState::Success(String::from_utf8_lossy(name).into_owned())
+            },
+            None => NegotiationState::InProgress,
+        }
+    }
+}
+
+/// Decodes the unsigned varint at the start of `buf`. Returns the value and the number of bytes
+/// it occupies, or `None` if `buf` doesn't contain the full varint.
+fn decode_varint(buf: &[u8]) -> Result<Option<(usize, usize)>, ()> {
+    let mut value = 0usize;
+    for (n, byte) in buf.iter().enumerate() {
+        // Negotiation messages are short; two bytes are enough for their length.
+        if n >= 2 {
+            return Err(());
+        }
+        value |= usize::from(byte & 0x7f) << (7 * n);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, n + 1)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the name of the transport to use as a label for connections to the given address.
+fn transport_name(addr: &Multiaddr) -> String {
+    let last = addr.iter()
+        .filter(|p| match p {
+            Protocol::P2p(_) => false,
+            _ => true,
+        })
+        .last();
+
+    match last {
+        Some(protocol) => protocol.to_string().split('/').nth(1).unwrap_or("").to_owned(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::dummy_transport::DummyTransport;
+    use parking_lot::Mutex;
+
+    /// Muxer whose substreams accept everything written to them, and read the bytes queued in
+    /// `inbound`.
+    struct ScriptedMuxer {
+        inbound: Mutex<Vec<u8>>,
+    }
+
+    impl StreamMuxer for ScriptedMuxer {
+        type Substream = ();
+        type OutboundSubstream = ();
+
+        fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> {
+            Ok(Async::NotReady)
+        }
+        fn open_outbound(&self) -> Self::OutboundSubstream {}
+        fn poll_outbound(&self, _: &mut Self::OutboundSubstream) -> Poll<Option<Self::Substream>, IoError> {
+            Ok(Async::Ready(Some(())))
+        }
+        fn destroy_outbound(&self, _: Self::OutboundSubstream) {}
+        fn read_substream(&self, _: &mut Self::Substream, buf: &mut [u8]) -> Poll<usize, IoError> {
+            let mut inbound = self.inbound.lock();
+            let len = std::cmp::min(buf.len(), inbound.len());
+            buf[..len].copy_from_slice(&inbound[..len]);
+            inbound.drain(..len);
+            Ok(Async::Ready(len))
+        }
+        fn write_substream(&self, _: &mut Self::Substream, buf: &[u8]) -> Poll<usize, IoError> {
+            Ok(Async::Ready(buf.len()))
+        }
+        fn flush_substream(&self, _: &mut Self::Substream) -> Poll<(), IoError> {
+            Ok(Async::Ready(()))
+        }
+        fn shutdown_substream(&self, _: &mut Self::Substream, _: Shutdown) -> Poll<(), IoError> {
+            Ok(Async::Ready(()))
+        }
+        fn destroy_substream(&self, _: Self::Substream) {}
+        fn shutdown(&self, _: Shutdown) -> Poll<(), IoError> {
+            Ok(Async::Ready(()))
+        }
+        fn flush_all(&self) -> Poll<(), IoError> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn message(content: &[u8]) -> Vec<u8> {
+        let mut out = vec![content.len() as u8];
+        out.extend_from_slice(content);
+        out
+    }
+
+    #[test]
+    fn negotiation_success() {
+        let mut negotiation = Negotiation::default();
+        let mut sent = message(b"/multistream/1.0.0\n");
+        sent.extend(message(b"/foo/1.0.0\n"));
+        assert_eq!(negotiation.inject_data(&sent, false), NegotiationState::InProgress);
+        assert_eq!(negotiation.inject_data(&message(b"/multistream/1.0.0\n"), true), NegotiationState::InProgress);
+        assert_eq!(negotiation.inject_data(&message(b"na\n"), true), NegotiationState::InProgress);
+        assert_eq!(negotiation.inject_data(&message(b"/bar/1.0.0\n"), false), NegotiationState::InProgress);
+
+        // Data received in multiple chunks.
+        let reply = message(b"/bar/1.0.0\n");
+        assert_eq!(negotiation.inject_data(&reply[..4], true), NegotiationState::InProgress);
+        assert_eq!(negotiation.inject_data(&reply[4..], true), NegotiationState::Success("/bar/1.0.0".to_owned()));
+    }
+
+    #[test]
+    fn negotiation_failure() {
+        let mut negotiation = Negotiation::default();
+        assert_eq!(negotiation.inject_data(&[0xff, 0xff, 0xff], true), NegotiationState::Failed);
+    }
+
+    #[test]
+    fn bytes_are_counted() {
+        let metrics = Metrics::new();
+        let peer_id = PeerId::random();
+        let mut transport = DummyTransport::new();
+        transport.set_next_peer_id(&peer_id);
+        let transport = transport
+            .map(|(peer_id, _), _| {
+                let mut inbound = message(b"/multistream/1.0.0\n");
+                inbound.extend(message(b"/foo/1.0.0\n"));
+                inbound.extend_from_slice(b"pong");
+                (peer_id, ScriptedMuxer { inbound: Mutex::new(inbound) })
+            })
+            .with_metrics(metrics.clone());
+
+        let (_, muxer) = transport.dial("/memory".parse().unwrap())
+            .unwrap_or_else(|_| panic!())
+            .wait()
+            .unwrap();
+        let mut substream = match muxer.poll_outbound(&mut muxer.open_outbound()) {
+            Ok(Async::Ready(Some(substream))) => substream,
+            _ => panic!("the outbound substream should be open"),
+        };
+
+        let mut request = message(b"/multistream/1.0.0\n");
+        request.extend(message(b"/foo/1.0.0\n"));
+        let negotiation_len = request.len() as u64;
+        assert_eq!(muxer.write_substream(&mut substream, &request).unwrap(), Async::Ready(request.len()));
+        let mut buf = [0; 64];
+        assert_eq!(muxer.read_substream(&mut substream, &mut buf[..negotiation_len as usize]).unwrap(),
+                   Async::Ready(negotiation_len as usize));
+        assert_eq!(muxer.write_substream(&mut substream, b"ping!").unwrap(), Async::Ready(5));
+        assert_eq!(muxer.read_substream(&mut substream, &mut buf).unwrap(), Async::Ready(4));
+
+        let expected = Bandwidth { inbound: negotiation_len + 4, outbound: negotiation_len + 5 };
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.connections_opened["memory"], 1);
+        assert_eq!(snapshot.negotiated_substreams["/foo/1.0.0"], 1);
+        assert_eq!(snapshot.peer_bandwidth[&peer_id], expected);
+        assert_eq!(snapshot.protocol_bandwidth["/foo/1.0.0"], expected);
+
+        drop(muxer);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.connections_closed["memory"], 1);
+        assert!(snapshot.peer_bandwidth.is_empty());
+        assert_eq!(snapshot.protocol_bandwidth["/foo/1.0.0"], expected);
+    }
+
+    #[test]
+    fn transport_names() {
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        assert_eq!(transport_name(&addr), "tcp");
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5/ws/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ".parse().unwrap();
+        assert_eq!(transport_name(&addr), "ws");
+        let addr: Multiaddr = "/memory".parse().unwrap();
+        assert_eq!(transport_name(&addr), "memory");
+    }
+}
@@ -40,6 +40,7 @@ pub mod map;
 pub mod map_err;
 pub mod map_err_dial;
 pub mod memory;
+pub mod metered;
 pub mod timeout;
 pub mod upgrade;
 
@@ -227,4 +228,16 @@ pub trait Transport {
     {
         timeout::TransportTimeout::with_ingoing_timeout(self, timeout)
     }
+
+    /// Reports the connections opened by the transport, and the data exchanged over them, to
+    /// the given `Metrics`.
+    ///
+    /// The transport must produce a `PeerId` and a `StreamMuxer`.
+    #[inline]
+    fn with_metrics(self, metrics: crate::metrics::Metrics) -> metered::Metered<Self>
+    where
+        Self: Sized,
+    {
+        metered::Metered::new(self, metrics)
+    }
 }
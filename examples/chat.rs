@@ -57,7 +57,11 @@ extern crate void;
 
 use futures::prelude::*;
 use libp2p::{
+    Multiaddr,
     NetworkBehaviour,
+    PeerId,
+    core::protocols_handler::DummyProtocolsHandler,
+    core::swarm::{ConnectedPoint, ConnectionId, NetworkBehaviourAction, PollParameters},
     secio,
     tokio_codec::{FramedRead, LinesCodec}
 };
@@ -82,6 +86,43 @@ fn main() {
     struct MyBehaviour<TSubstream: libp2p::tokio_io::AsyncRead + libp2p::tokio_io::AsyncWrite> {
         floodsub: libp2p::floodsub::Floodsub<TSubstream>,
         mdns: libp2p::mdns::Mdns<TSubstream>,
+        listen_addrs: PrintListenAddrs<TSubstream>,
+    }
+
+    // A network behaviour that doesn't handle any protocol, and only prints the addresses we
+    // are listening on as the swarm reports them.
+    struct PrintListenAddrs<TSubstream>(std::marker::PhantomData<TSubstream>);
+
+    impl<TSubstream, TTopology> libp2p::core::swarm::NetworkBehaviour<TTopology> for PrintListenAddrs<TSubstream>
+    where
+        TSubstream: libp2p::tokio_io::AsyncRead + libp2p::tokio_io::AsyncWrite,
+    {
+        type ProtocolsHandler = DummyProtocolsHandler<TSubstream>;
+        type OutEvent = void::Void;
+
+        fn new_handler(&mut self) -> Self::ProtocolsHandler {
+            DummyProtocolsHandler::default()
+        }
+
+        fn inject_connected(&mut self, _: PeerId, _: ConnectedPoint) {}
+
+        fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {}
+
+        fn inject_node_event(&mut self, _: PeerId, _: ConnectionId, ev: void::Void) {
+            void::unreachable(ev)
+        }
+
+        fn inject_new_listen_addr(&mut self, addr: &Multiaddr) {
+            println!("Listening on {}", addr);
+        }
+
+        fn inject_expired_listen_addr(&mut self, addr: &Multiaddr) {
+            println!("No longer listening on {}", addr);
+        }
+
+        fn poll(&mut self, _: &mut PollParameters<TTopology>) -> Async<NetworkBehaviourAction<void::Void, void::Void>> {
+            Async::NotReady
+        }
     }
 
     impl<TSubstream: libp2p::tokio_io::AsyncRead + libp2p::tokio_io::AsyncWrite> libp2p::core::swarm::NetworkBehaviourEventProcess<void::Void> for MyBehaviour<TSubstream> {
@@ -104,6 +145,7 @@ fn main() {
         let mut behaviour = MyBehaviour {
             floodsub: libp2p::floodsub::Floodsub::new(local_pub_key.clone().into_peer_id()),
             mdns: libp2p::mdns::Mdns::new().expect("Failed to create mDNS service"),
+            listen_addrs: PrintListenAddrs(std::marker::PhantomData),
         };
 
         behaviour.floodsub.subscribe(floodsub_topic.clone());
@@ -111,8 +153,7 @@ fn main() {
     };
 
     // Listen on all interfaces and whatever port the OS assigns
-    let addr = libp2p::Swarm::listen_on(&mut swarm, "/ip4/0.0.0.0/tcp/0".parse().unwrap()).unwrap();
-    println!("Listening on {:?}", addr);
+    libp2p::Swarm::listen_on(&mut swarm, "/ip4/0.0.0.0/tcp/0".parse().unwrap()).unwrap();
 
     // Reach out to another node if specified
     if let Some(to_dial) = std::env::args().nth(1) {
@@ -57,6 +57,9 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
     let proto_select_ident = quote!{::libp2p::core::protocols_handler::ProtocolsHandlerSelect};
     let peer_id = quote!{::libp2p::core::PeerId};
     let connected_point = quote!{::libp2p::core::swarm::ConnectedPoint};
+    let incoming_info = quote!{::libp2p::core::swarm::IncomingInfo};
+    let connection_id = quote!{::libp2p::core::swarm::ConnectionId};
+    let multiaddr = quote!{::libp2p::core::Multiaddr};
 
     // Name of the type parameter that represents the substream.
     let substream_generic = {
@@ -182,6 +185,47 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
         })
     };
 
+    // Build the list of expressions to put in the body of `accept_incoming()`.
+    //
+    // An incoming connection is accepted only if all the fields accept it.
+    let accept_incoming_stmts = data_struct.fields.iter().enumerate().filter_map(|(field_n, field)| {
+        if is_ignored(&field) {
+            return None;
+        }
+
+        Some(match field.ident {
+            Some(ref i) => quote!{ self.#i.accept_incoming(info) },
+            None => quote!{ self.#field_n.accept_incoming(info) },
+        })
+    });
+
+    // Builds the list of statements that call the method named `method` with the given
+    // arguments on all the fields.
+    let forward_stmts = |method: &str, args: &[&str]| {
+        let method = Ident::new(method, name.span());
+        let args = args.iter().map(|a| Ident::new(a, name.span())).collect::<Vec<_>>();
+        data_struct.fields.iter().enumerate().filter_map(|(field_n, field)| {
+            if is_ignored(&field) {
+                return None;
+            }
+
+            let args = &args;
+            Some(match field.ident {
+                Some(ref i) => quote!{ self.#i.#method(#(#args),*); },
+                None => quote!{ self.#field_n.#method(#(#args),*); },
+            })
+        }).collect::<Vec<_>>()
+    };
+
+    let inject_connection_established_stmts = forward_stmts("inject_connection_established", &["peer_id", "connection", "endpoint"]);
+    let inject_connection_closed_stmts = forward_stmts("inject_connection_closed", &["peer_id", "connection", "endpoint"]);
+    let inject_addr_reach_failure_stmts = forward_stmts("inject_addr_reach_failure", &["peer_id", "addr", "error"]);
+    let inject_dial_failure_stmts = forward_stmts("inject_dial_failure", &["peer_id"]);
+    let inject_new_listen_addr_stmts = forward_stmts("inject_new_listen_addr", &["addr"]);
+    let inject_expired_listen_addr_stmts = forward_stmts("inject_expired_listen_addr", &["addr"]);
+    let inject_listener_closed_stmts = forward_stmts("inject_listener_closed", &["addr", "result"]);
+    let inject_incoming_connection_error_stmts = forward_stmts("inject_incoming_connection_error", &["listen_addr", "send_back_addr", "error"]);
+
     // Build the list of variants to put in the body of `inject_node_event()`.
     //
     // The event type is a construction of nested `#either_ident`s of the events of the children.
@@ -198,8 +242,8 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
         }
 
         Some(match field.ident {
-            Some(ref i) => quote!{ #elem => self.#i.inject_node_event(peer_id, ev) },
-            None => quote!{ #elem => self.#field_n.inject_node_event(peer_id, ev) },
+            Some(ref i) => quote!{ #elem => self.#i.inject_node_event(peer_id, connection, ev) },
+            None => quote!{ #elem => self.#field_n.inject_node_event(peer_id, connection, ev) },
         })
     });
 
@@ -299,9 +343,10 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
                     Async::Ready(#network_behaviour_action::DialPeer { peer_id }) => {
                         return Async::Ready(#network_behaviour_action::DialPeer { peer_id });
                     }
-                    Async::Ready(#network_behaviour_action::SendEvent { peer_id, event }) => {
+                    Async::Ready(#network_behaviour_action::SendEvent { peer_id, connection, event }) => {
                         return Async::Ready(#network_behaviour_action::SendEvent {
                             peer_id,
+                            connection,
                             event: #wrapped_event,
                         });
                     }
@@ -338,10 +383,56 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
                 #(#inject_disconnected_stmts);*
             }
 
+            #[inline]
+            fn inject_connection_established(&mut self, peer_id: &#peer_id, connection: #connection_id, endpoint: &#connected_point) {
+                #(#inject_connection_established_stmts)*
+            }
+
+            #[inline]
+            fn inject_connection_closed(&mut self, peer_id: &#peer_id, connection: #connection_id, endpoint: &#connected_point) {
+                #(#inject_connection_closed_stmts)*
+            }
+
+            #[inline]
+            fn accept_incoming(&mut self, info: #incoming_info) -> bool {
+                true #(&& #accept_incoming_stmts)*
+            }
+
+            #[inline]
+            fn inject_addr_reach_failure(&mut self, peer_id: Option<&#peer_id>, addr: &#multiaddr, error: &dyn std::error::Error) {
+                #(#inject_addr_reach_failure_stmts)*
+            }
+
+            #[inline]
+            fn inject_dial_failure(&mut self, peer_id: &#peer_id) {
+                #(#inject_dial_failure_stmts)*
+            }
+
+            #[inline]
+            fn inject_new_listen_addr(&mut self, addr: &#multiaddr) {
+                #(#inject_new_listen_addr_stmts)*
+            }
+
+            #[inline]
+            fn inject_expired_listen_addr(&mut self, addr: &#multiaddr) {
+                #(#inject_expired_listen_addr_stmts)*
+            }
+
+            #[inline]
+            fn inject_listener_closed(&mut self, addr: &#multiaddr, result: Result<(), &::std::io::Error>) {
+                #(#inject_listener_closed_stmts)*
+            }
+
+            #[inline]
+            fn inject_incoming_connection_error(&mut self, listen_addr: &#multiaddr, send_back_addr: &#multiaddr, error: &::std::io::Error) {
+                #(#inject_incoming_connection_error_stmts)*
+            }
+
             #[inline]
             fn inject_node_event(
                 &mut self,
                 peer_id: #peer_id,
+                connection: #connection_id,
                 event: <Self::ProtocolsHandler as #protocols_handler>::OutEvent
             ) {
                 match event {
@@ -21,8 +21,9 @@
 use crate::service::{MdnsService, MdnsPacket};
 use futures::prelude::*;
 use libp2p_core::protocols_handler::{DummyProtocolsHandler, ProtocolsHandler};
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::swarm::{ConnectedPoint, ConnectionId, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p_core::{Multiaddr, PeerId, multiaddr::Protocol, topology::MemoryTopology, topology::Topology};
+use libp2p_core::topology::{AddressSource, PersistentTopology};
 use smallvec::SmallVec;
 use std::{fmt, io, iter, marker::PhantomData, time::Duration};
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -65,7 +66,14 @@ pub trait MdnsTopology: Topology {
 impl MdnsTopology for MemoryTopology {
     #[inline]
     fn add_mdns_discovered_address(&mut self, peer: PeerId, addr: Multiaddr) {
-        self.add_address(peer, addr)
+        self.add_address(peer, addr, AddressSource::Mdns)
+    }
+}
+
+impl MdnsTopology for PersistentTopology {
+    #[inline]
+    fn add_mdns_discovered_address(&mut self, peer: PeerId, addr: Multiaddr) {
+        self.add_address(peer, addr, AddressSource::Mdns)
     }
 }
 
@@ -88,6 +96,7 @@ where
     fn inject_node_event(
         &mut self,
         _: PeerId,
+        _: ConnectionId,
         _ev: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
     ) {
         void::unreachable(_ev)
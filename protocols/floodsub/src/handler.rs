@@ -23,7 +23,7 @@ use futures::prelude::*;
 use libp2p_core::{
     ProtocolsHandler, ProtocolsHandlerEvent,
     protocols_handler::ProtocolsHandlerUpgrErr,
-    upgrade::{InboundUpgrade, OutboundUpgrade}
+    upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeError}
 };
 use smallvec::SmallVec;
 use std::{fmt, io};
@@ -52,6 +52,24 @@ where
 
     /// Queue of values that we want to send to the remote.
     send_queue: SmallVec<[FloodsubRpc; 16]>,
+
+    /// True if the remote refused to negotiate floodsub on a substream that we opened. We no
+    /// longer open substreams with it.
+    remote_unsupported: bool,
+
+    /// True if we have to report to the behaviour that the remote doesn't support floodsub.
+    report_unsupported: bool,
+}
+
+/// Event produced by the floodsub handler.
+#[derive(Debug)]
+pub enum FloodsubHandlerEvent {
+    /// The remote sent us an RPC.
+    Rpc(FloodsubRpc),
+
+    /// The remote refused to negotiate floodsub on a substream that we opened. Reported at most
+    /// once per connection, after which the messages sent to the handler are discarded.
+    Unsupported,
 }
 
 /// State of an active substream, opened either by us or by the remote.
@@ -89,12 +107,20 @@ where
     TSubstream: AsyncRead + AsyncWrite,
 {
     /// Builds a new `FloodsubHandler`.
+    #[inline]
     pub fn new() -> Self {
+        Self::with_config(FloodsubConfig::new())
+    }
+
+    /// Builds a new `FloodsubHandler` that negotiates the given configuration.
+    pub fn with_config(config: FloodsubConfig) -> Self {
         FloodsubHandler {
-            config: FloodsubConfig::new(),
+            config,
             shutting_down: false,
             substreams: Vec::new(),
             send_queue: SmallVec::new(),
+            remote_unsupported: false,
+            report_unsupported: false,
         }
     }
 }
@@ -104,7 +130,7 @@ where
     TSubstream: AsyncRead + AsyncWrite,
 {
     type InEvent = FloodsubRpc;
-    type OutEvent = FloodsubRpc;
+    type OutEvent = FloodsubHandlerEvent;
     type Error = io::Error;
     type Substream = TSubstream;
     type InboundProtocol = FloodsubConfig;
@@ -139,14 +165,26 @@ where
 
     #[inline]
     fn inject_event(&mut self, message: FloodsubRpc) {
-        self.send_queue.push(message);
+        if !self.remote_unsupported {
+            self.send_queue.push(message);
+        }
     }
 
     #[inline]
     fn inject_inbound_closed(&mut self) {}
 
     #[inline]
-    fn inject_dial_upgrade_error(&mut self, _: Self::OutboundOpenInfo, _: ProtocolsHandlerUpgrErr<<Self::OutboundProtocol as OutboundUpgrade<Self::Substream>>::Error>) {}
+    fn inject_dial_upgrade_error(&mut self, _: Self::OutboundOpenInfo, err: ProtocolsHandlerUpgrErr<<Self::OutboundProtocol as OutboundUpgrade<Self::Substream>>::Error>) {
+        // A failure to negotiate the protocol means that the remote doesn't support floodsub.
+        // Other errors, such as timeouts, are not necessarily permanent and are ignored.
+        if let ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(_)) = err {
+            if !self.remote_unsupported {
+                self.remote_unsupported = true;
+                self.report_unsupported = true;
+                self.send_queue.clear();
+            }
+        }
+    }
 
     #[inline]
     fn connection_keep_alive(&self) -> bool {
@@ -168,6 +206,11 @@ where
         ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent>,
         io::Error,
     > {
+        if self.report_unsupported {
+            self.report_unsupported = false;
+            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(FloodsubHandlerEvent::Unsupported)));
+        }
+
         if !self.send_queue.is_empty() {
             let message = self.send_queue.remove(0);
             return Ok(Async::Ready(
@@ -186,7 +229,7 @@ where
                         Ok(Async::Ready(Some(message))) => {
                             self.substreams
                                 .push(SubstreamState::WaitingInput(substream));
-                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(message)));
+                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(FloodsubHandlerEvent::Rpc(message))));
                         }
                         Ok(Async::Ready(None)) => SubstreamState::Closing(substream),
                         Ok(Async::NotReady) => {
@@ -241,6 +284,7 @@ where
             .field("shutting_down", &self.shutting_down)
             .field("substreams", &self.substreams.len())
             .field("send_queue", &self.send_queue.len())
+            .field("remote_unsupported", &self.remote_unsupported)
             .finish()
     }
 }
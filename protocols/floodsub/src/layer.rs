@@ -19,17 +19,20 @@
 // DEALINGS IN THE SOFTWARE.
 
 use cuckoofilter::CuckooFilter;
-use futures::prelude::*;
-use handler::FloodsubHandler;
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
-use libp2p_core::{protocols_handler::ProtocolsHandler, PeerId};
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::{future, prelude::*};
+use handler::{FloodsubHandler, FloodsubHandlerEvent};
+use libp2p_core::swarm::{ConnectedPoint, ConnectionId, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::{protocols_handler::ProtocolsHandler, PeerId, PublicKey};
 use protocol::{FloodsubMessage, FloodsubRpc, FloodsubSubscription, FloodsubSubscriptionAction};
 use rand;
 use smallvec::SmallVec;
-use std::{collections::VecDeque, iter, marker::PhantomData};
+use std::{cmp, collections::{HashSet, VecDeque}, iter, marker::PhantomData, time::{Duration, Instant}};
 use std::collections::hash_map::{DefaultHasher, HashMap};
 use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
 use topic::{Topic, TopicHash};
+use validation::{MessageValidator, Validation, ValidationResult};
 
 /// Network behaviour that automatically identifies nodes periodically, and returns information
 /// about them.
@@ -40,11 +43,33 @@ pub struct Floodsub<TSubstream> {
     /// Peer id of the local node. Used for the source of the messages that we publish.
     local_peer_id: PeerId,
 
+    /// List of peers that we want to be connected to. We dial them, and dial them again when
+    /// the connection closes.
+    target_peers: FnvHashSet<PeerId>,
+
+    /// Number of consecutive failed attempts at dialing each peer of `target_peers`. Reset when
+    /// we connect to the peer.
+    dial_failures: FnvHashMap<PeerId, u32>,
+
+    /// Peers of `target_peers` that we dial again once their delay has elapsed.
+    scheduled_dials: FnvHashMap<PeerId, Delay>,
+
+    /// Delay before dialing again a peer of `target_peers` that we have been disconnected from.
+    /// Doubled after each consecutive dialing failure.
+    initial_redial_delay: Duration,
+
+    /// Maximum delay before dialing again a peer of `target_peers`.
+    max_redial_delay: Duration,
+
     /// List of peers the network is connected to, and the topics that they're subscribed to.
-    // TODO: filter out peers that don't support floodsub, so that we avoid hammering them with
-    //       opened substreams
+    /// The peers that refused to negotiate floodsub are removed from it, so that we don't keep
+    /// opening substreams with them.
     connected_peers: HashMap<PeerId, SmallVec<[TopicHash; 8]>>,
 
+    /// Peers the network is connected to that refused to negotiate floodsub. Together with
+    /// `connected_peers`, tells whether we are connected to a peer.
+    unsupported_peers: FnvHashSet<PeerId>,
+
     // List of topics we're subscribed to. Necessary to filter out messages that we receive
     // erroneously.
     subscribed_topics: SmallVec<[Topic; 16]>,
@@ -53,6 +78,30 @@ pub struct Floodsub<TSubstream> {
     // we don't dispatch the same message twice if we receive it twice on the network.
     received: CuckooFilter<DefaultHasher>,
 
+    /// Public key of the local node and function that signs the messages we publish, if we sign
+    /// them.
+    signer: Option<(PublicKey, Box<dyn FnMut(&[u8]) -> Vec<u8> + Send>)>,
+
+    /// Function that checks the signatures of the messages we receive, if any.
+    verifier: Option<Box<dyn Fn(&PublicKey, &[u8], &[u8]) -> bool + Send>>,
+
+    /// If true, the messages whose signature can't be checked are dropped.
+    strict_signing: bool,
+
+    /// Validators of the messages of each topic.
+    validators: HashMap<TopicHash, Box<dyn MessageValidator + Send>>,
+
+    /// Messages whose validation is in progress, with the peer that forwarded them.
+    pending_validations: Vec<(PeerId, FloodsubMessage, Box<dyn Future<Item = ValidationResult, Error = ()> + Send>)>,
+
+    /// Maximum number of elements in `pending_validations`. The messages that require an
+    /// asynchronous validation while it is full are ignored.
+    max_pending_validations: usize,
+
+    /// Number of messages rejected by the validators, for each peer that forwarded them. Reset
+    /// when we disconnect from the peer.
+    rejected_messages: HashMap<PeerId, usize>,
+
     /// Marker to pin the generics.
     marker: PhantomData<TSubstream>,
 }
@@ -63,15 +112,107 @@ impl<TSubstream> Floodsub<TSubstream> {
         Floodsub {
             events: VecDeque::new(),
             local_peer_id,
+            target_peers: FnvHashSet::default(),
+            dial_failures: FnvHashMap::default(),
+            scheduled_dials: FnvHashMap::default(),
+            initial_redial_delay: Duration::from_secs(1),
+            max_redial_delay: Duration::from_secs(5 * 60),
             connected_peers: HashMap::new(),
+            unsupported_peers: FnvHashSet::default(),
             subscribed_topics: SmallVec::new(),
             received: CuckooFilter::new(),
+            signer: None,
+            verifier: None,
+            strict_signing: false,
+            validators: HashMap::new(),
+            pending_validations: Vec::new(),
+            max_pending_validations: 1024,
+            rejected_messages: HashMap::new(),
             marker: PhantomData,
         }
     }
+
+    /// Signs the messages that we publish. `sign` is passed the payload returned by
+    /// `FloodsubMessage::signing_payload()` and must return its signature by the local key.
+    ///
+    /// The public key is embedded in the messages, so that the remotes can check the signature.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `public_key` doesn't correspond to the local peer ID.
+    pub fn with_signing(mut self, public_key: PublicKey, sign: impl FnMut(&[u8]) -> Vec<u8> + Send + 'static) -> Self {
+        assert_eq!(public_key.clone().into_peer_id(), self.local_peer_id);
+        self.signer = Some((public_key, Box::new(sign)));
+        self
+    }
+
+    /// Checks the signatures of the messages that we receive. `verify` is passed the public key
+    /// of the source, the payload and the signature. The messages whose signature is invalid,
+    /// or whose key doesn't correspond to their source, are neither dispatched nor propagated.
+    ///
+    /// The signature scheme depends on the type of the public key, which is why checking a
+    /// signature is left to the user.
+    pub fn with_signature_verification(mut self, verify: impl Fn(&PublicKey, &[u8], &[u8]) -> bool + Send + 'static) -> Self {
+        self.verifier = Some(Box::new(verify));
+        self
+    }
+
+    /// If enabled, the messages that are unsigned, or whose signature can't be checked because
+    /// they don't embed a valid key of their source, are dropped as well. Disabled by default.
+    ///
+    /// > **Note**: Without `with_signature_verification`, no signature can be checked and all the
+    /// >           messages are dropped.
+    pub fn with_strict_signing(mut self, strict: bool) -> Self {
+        self.strict_signing = strict;
+        self
+    }
+
+    /// Sets the maximum number of messages whose asynchronous validation can be in progress at
+    /// the same time. The messages received while this limit is reached are ignored, which means
+    /// that they are neither dispatched nor propagated. The default is 1024.
+    pub fn with_max_pending_validations(mut self, max: usize) -> Self {
+        self.max_pending_validations = max;
+        self
+    }
+
+    /// Sets the delay before we dial again a peer of the partial view that we have been
+    /// disconnected from. The delay doubles after each consecutive dialing failure, up to `max`.
+    /// The defaults are one second and five minutes.
+    pub fn with_redial_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_redial_delay = initial;
+        self.max_redial_delay = max;
+        self
+    }
 }
 
 impl<TSubstream> Floodsub<TSubstream> {
+    /// Adds a peer to the list of peers that we want to talk to, and dials it if we aren't
+    /// connected to it. We also dial it again, after a delay, whenever the connection closes or
+    /// dialing fails, until it is removed with `remove_node_from_partial_view`.
+    ///
+    /// Calling this again for a peer of the list that we're not connected to dials it again
+    /// immediately.
+    pub fn add_node_to_partial_view(&mut self, peer_id: PeerId) {
+        let connected = self.connected_peers.contains_key(&peer_id) ||
+            self.unsupported_peers.contains(&peer_id);
+        if !connected {
+            self.scheduled_dials.remove(&peer_id);
+            self.events.push_back(NetworkBehaviourAction::DialPeer { peer_id: peer_id.clone() });
+        }
+        self.target_peers.insert(peer_id);
+    }
+
+    /// Removes a peer from the list of peers that we want to talk to.
+    ///
+    /// This doesn't close the connection with the peer, if any, but we no longer dial it.
+    ///
+    /// Returns true if the peer was in the list.
+    pub fn remove_node_from_partial_view(&mut self, peer_id: &PeerId) -> bool {
+        self.dial_failures.remove(peer_id);
+        self.scheduled_dials.remove(peer_id);
+        self.target_peers.remove(peer_id)
+    }
+
     /// Subscribes to a topic.
     ///
     /// Returns true if the subscription worked. Returns false if we were already subscribed.
@@ -83,12 +224,14 @@ impl<TSubstream> Floodsub<TSubstream> {
         for peer in self.connected_peers.keys() {
             self.events.push_back(NetworkBehaviourAction::SendEvent {
                 peer_id: peer.clone(),
+                connection: None,
                 event: FloodsubRpc {
                     messages: Vec::new(),
                     subscriptions: vec![FloodsubSubscription {
                         topic: topic.hash().clone(),
                         action: FloodsubSubscriptionAction::Subscribe,
                     }],
+                    control: Vec::new(),
                 },
             });
         }
@@ -114,12 +257,14 @@ impl<TSubstream> Floodsub<TSubstream> {
         for peer in self.connected_peers.keys() {
             self.events.push_back(NetworkBehaviourAction::SendEvent {
                 peer_id: peer.clone(),
+                connection: None,
                 event: FloodsubRpc {
                     messages: Vec::new(),
                     subscriptions: vec![FloodsubSubscription {
                         topic: topic.clone(),
                         action: FloodsubSubscriptionAction::Unsubscribe,
                     }],
+                    control: Vec::new(),
                 },
             });
         }
@@ -138,7 +283,7 @@ impl<TSubstream> Floodsub<TSubstream> {
     ///
     /// > **Note**: Doesn't do anything if we're not subscribed to any of the topics.
     pub fn publish_many(&mut self, topic: impl IntoIterator<Item = impl Into<TopicHash>>, data: impl Into<Vec<u8>>) {
-        let message = FloodsubMessage {
+        let mut message = FloodsubMessage {
             source: self.local_peer_id.clone(),
             data: data.into(),
             // If the sequence numbers are predictable, then an attacker could flood the network
@@ -146,6 +291,8 @@ impl<TSubstream> Floodsub<TSubstream> {
             // messages. We therefore use a random number.
             sequence_number: rand::random::<[u8; 20]>().to_vec(),
             topics: topic.into_iter().map(|t| t.into().clone()).collect(),
+            signature: None,
+            key: None,
         };
 
         // Don't publish the message if we're not subscribed ourselves to any of the topics.
@@ -153,7 +300,12 @@ impl<TSubstream> Floodsub<TSubstream> {
             return;
         }
 
-        self.received.add(&message);
+        if let Some((ref public_key, ref mut sign)) = self.signer {
+            message.signature = Some(sign(&message.signing_payload()));
+            message.key = Some(public_key.clone());
+        }
+
+        self.received.add(&message_id(&message));
 
         // Send to peers we know are subscribed to the topic.
         for (peer_id, sub_topic) in self.connected_peers.iter() {
@@ -163,59 +315,148 @@ impl<TSubstream> Floodsub<TSubstream> {
 
             self.events.push_back(NetworkBehaviourAction::SendEvent {
                 peer_id: peer_id.clone(),
+                connection: None,
                 event: FloodsubRpc {
                     subscriptions: Vec::new(),
                     messages: vec![message.clone()],
+                    control: Vec::new(),
                 }
             });
         }
     }
-}
 
-impl<TSubstream, TTopology> NetworkBehaviour<TTopology> for Floodsub<TSubstream>
-where
-    TSubstream: AsyncRead + AsyncWrite,
-{
-    type ProtocolsHandler = FloodsubHandler<TSubstream>;
-    type OutEvent = FloodsubEvent;
+    /// Returns the peers that we are connected to and that are subscribed to the given topic.
+    ///
+    /// This can be used for example to wait for enough peers to be subscribed to a topic before
+    /// publishing on it.
+    pub fn topic_peers<'a>(&'a self, topic: &'a TopicHash) -> impl Iterator<Item = &'a PeerId> + 'a {
+        self.connected_peers
+            .iter()
+            .filter(move |(_, topics)| topics.contains(topic))
+            .map(|(peer_id, _)| peer_id)
+    }
 
-    fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        FloodsubHandler::new()
+    /// Returns the topics that the peers we are connected to are subscribed to, without
+    /// duplicates.
+    pub fn known_topics(&self) -> impl Iterator<Item = &TopicHash> {
+        self.connected_peers
+            .values()
+            .flat_map(|topics| topics.iter())
+            .collect::<HashSet<_>>()
+            .into_iter()
     }
 
-    fn inject_connected(&mut self, id: PeerId, _: ConnectedPoint) {
-        // We need to send our subscriptions to the newly-connected node.
-        for topic in self.subscribed_topics.iter() {
-            self.events.push_back(NetworkBehaviourAction::SendEvent {
-                peer_id: id.clone(),
-                event: FloodsubRpc {
-                    messages: Vec::new(),
-                    subscriptions: vec![FloodsubSubscription {
-                        topic: topic.hash().clone(),
-                        action: FloodsubSubscriptionAction::Subscribe,
-                    }],
-                },
-            });
-        }
+    /// Registers the validator of the messages of a topic, replacing the previous one if any.
+    ///
+    /// The messages that we receive for this topic are only dispatched and propagated once the
+    /// validator has accepted them. If a message belongs to multiple topics, it must be accepted
+    /// by the validators of all of them.
+    pub fn add_validator(&mut self, topic: impl Into<TopicHash>, validator: impl MessageValidator + Send + 'static) {
+        self.validators.insert(topic.into(), Box::new(validator));
+    }
 
-        self.connected_peers.insert(id.clone(), SmallVec::new());
+    /// Unregisters the validator of the messages of a topic.
+    ///
+    /// Returns true if there was one.
+    pub fn remove_validator(&mut self, topic: impl AsRef<TopicHash>) -> bool {
+        self.validators.remove(topic.as_ref()).is_some()
     }
 
-    fn inject_disconnected(&mut self, id: &PeerId, _: ConnectedPoint) {
-        let was_in = self.connected_peers.remove(id);
-        debug_assert!(was_in.is_some());
+    /// Returns the number of messages forwarded by the given peer that the validators rejected
+    /// since we connected to it.
+    pub fn num_rejected_messages(&self, peer_id: &PeerId) -> usize {
+        self.rejected_messages.get(peer_id).cloned().unwrap_or(0)
     }
 
-    fn inject_node_event(
+    /// Starts the validation of a message we received. Returns `None` if the validation is
+    /// asynchronous, in which case the message is added to `pending_validations`. If
+    /// `pending_validations` is full, the message is ignored instead.
+    fn validate(&mut self, propagation_source: &PeerId, message: FloodsubMessage) -> Option<(FloodsubMessage, ValidationResult)> {
+        let mut result = ValidationResult::Accept;
+        let mut pending = Vec::new();
+        for topic in message.topics.iter() {
+            if let Some(validator) = self.validators.get_mut(topic) {
+                match validator.validate(propagation_source, &message) {
+                    Validation::Ready(r) => result = result.and(r),
+                    Validation::Pending(future) => pending.push(future),
+                }
+            }
+        }
+
+        if pending.is_empty() || result == ValidationResult::Reject {
+            return Some((message, result));
+        }
+
+        if self.pending_validations.len() >= self.max_pending_validations {
+            return Some((message, ValidationResult::Ignore));
+        }
+
+        let future = future::join_all(pending.into_iter().map(|f| f.or_else(|()| Ok(ValidationResult::Ignore))))
+            .map(move |results| results.into_iter().fold(result, ValidationResult::and));
+        self.pending_validations.push((propagation_source.clone(), message, Box::new(future)));
+        None
+    }
+
+    /// Acts upon the result of the validation of a message. Accepted messages are dispatched to
+    /// the user, and their propagation is added to `rpcs_to_dispatch`.
+    fn inject_validated(
         &mut self,
-        propagation_source: PeerId,
-        event: FloodsubRpc,
+        propagation_source: &PeerId,
+        message: FloodsubMessage,
+        result: ValidationResult,
+        rpcs_to_dispatch: &mut Vec<(PeerId, FloodsubRpc)>
     ) {
+        match result {
+            ValidationResult::Accept => {},
+            ValidationResult::Reject => {
+                if self.connected_peers.contains_key(propagation_source) {
+                    *self.rejected_messages.entry(propagation_source.clone()).or_insert(0) += 1;
+                }
+                return;
+            },
+            ValidationResult::Ignore => return,
+        }
+
+        // Add the message to be dispatched to the user.
+        if self.subscribed_topics.iter().any(|t| message.topics.iter().any(|u| t.hash() == u)) {
+            let event = FloodsubEvent::Message(message.clone());
+            self.events.push_back(NetworkBehaviourAction::GenerateEvent(event));
+        }
+
+        // Propagate the message to everyone else who is subscribed to any of the topics.
+        for (peer_id, subscr_topics) in self.connected_peers.iter() {
+            if peer_id == propagation_source {
+                continue;
+            }
+
+            if !subscr_topics.iter().any(|t| message.topics.iter().any(|u| t == u)) {
+                continue;
+            }
+
+            if let Some(pos) = rpcs_to_dispatch.iter().position(|(p, _)| p == peer_id) {
+                rpcs_to_dispatch[pos].1.messages.push(message.clone());
+            } else {
+                rpcs_to_dispatch.push((peer_id.clone(), FloodsubRpc {
+                    subscriptions: Vec::new(),
+                    messages: vec![message.clone()],
+                    control: Vec::new(),
+                }));
+            }
+        }
+    }
+
+    /// Processes an RPC received from a remote.
+    fn inject_rpc(&mut self, propagation_source: PeerId, event: FloodsubRpc) {
+        // Ignore the remotes that refused to negotiate floodsub with us, as we can't answer them.
+        if !self.connected_peers.contains_key(&propagation_source) {
+            return;
+        }
+
         // Update connected peers topics
         for subscription in event.subscriptions {
             let mut remote_peer_topics = self.connected_peers
                 .get_mut(&propagation_source)
-                .expect("connected_peers is kept in sync with the peers we are connected to; we are guaranteed to only receive events from connected peers; QED");
+                .expect("we checked above that the peer is in connected_peers; QED");
             match subscription.action {
                 FloodsubSubscriptionAction::Subscribe => {
                     if !remote_peer_topics.contains(&subscription.topic) {
@@ -242,47 +483,144 @@ where
         let mut rpcs_to_dispatch: Vec<(PeerId, FloodsubRpc)> = Vec::new();
 
         for message in event.messages {
-            // Use `self.received` to skip the messages that we have already received in the past.
-            // Note that this can false positive.
-            if !self.received.test_and_add(&message) {
+            // Drop the messages whose signature is invalid before they make it to `self.received`,
+            // otherwise a forged message could prevent the legitimate one from being dispatched.
+            if !self.check_signature(&message) {
                 continue;
             }
 
-            // Add the message to be dispatched to the user.
-            if self.subscribed_topics.iter().any(|t| message.topics.iter().any(|u| t.hash() == u)) {
-                let event = FloodsubEvent::Message(message.clone());
-                self.events.push_back(NetworkBehaviourAction::GenerateEvent(event));
+            // Use `self.received` to skip the messages that we have already received in the past.
+            // Note that this can false positive.
+            if !self.received.test_and_add(&message_id(&message)) {
+                continue;
             }
 
-            // Propagate the message to everyone else who is subscribed to any of the topics.
-            for (peer_id, subscr_topics) in self.connected_peers.iter() {
-                if peer_id == &propagation_source {
-                    continue;
-                }
-
-                if !subscr_topics.iter().any(|t| message.topics.iter().any(|u| t == u)) {
-                    continue;
-                }
-
-                if let Some(pos) = rpcs_to_dispatch.iter().position(|(p, _)| p == peer_id) {
-                    rpcs_to_dispatch[pos].1.messages.push(message.clone());
-                } else {
-                    rpcs_to_dispatch.push((peer_id.clone(), FloodsubRpc {
-                        subscriptions: Vec::new(),
-                        messages: vec![message.clone()],
-                    }));
-                }
+            if let Some((message, result)) = self.validate(&propagation_source, message) {
+                self.inject_validated(&propagation_source, message, result, &mut rpcs_to_dispatch);
             }
         }
 
         for (peer_id, rpc) in rpcs_to_dispatch {
             self.events.push_back(NetworkBehaviourAction::SendEvent {
                 peer_id,
+                connection: None,
                 event: rpc,
             });
         }
     }
 
+    /// Processes the refusal of a remote to negotiate floodsub.
+    fn inject_unsupported(&mut self, peer_id: PeerId) {
+        // We can't send anything to the remote, therefore its subscriptions are meaningless to us.
+        // We also stop dialing it.
+        let topics = match self.connected_peers.remove(&peer_id) {
+            Some(topics) => topics.into_vec(),
+            None => return,
+        };
+        self.unsupported_peers.insert(peer_id.clone());
+        self.remove_node_from_partial_view(&peer_id);
+        self.events.push_back(NetworkBehaviourAction::GenerateEvent(FloodsubEvent::PeerUnsupported {
+            peer_id,
+            topics,
+        }));
+    }
+
+    /// Schedules a new attempt at dialing a peer of the partial view, after a delay that doubles
+    /// with each consecutive dialing failure.
+    fn schedule_redial(&mut self, peer_id: PeerId) {
+        let failures = self.dial_failures.get(&peer_id).cloned().unwrap_or(0);
+        let delay = self.initial_redial_delay
+            .checked_mul(1 << cmp::min(failures, 31))
+            .map_or(self.max_redial_delay, |delay| cmp::min(delay, self.max_redial_delay));
+        self.scheduled_dials.insert(peer_id, Delay::new(Instant::now() + delay));
+    }
+
+    /// Returns true if the signature of a message we received is acceptable.
+    fn check_signature(&self, message: &FloodsubMessage) -> bool {
+        match (&message.signature, &message.key, &self.verifier) {
+            (Some(signature), Some(key), Some(verify)) => {
+                key.clone().into_peer_id() == message.source
+                    && verify(key, &message.signing_payload(), signature)
+            },
+            // The message isn't signed, or we can't check its signature.
+            _ => !self.strict_signing,
+        }
+    }
+}
+
+impl<TSubstream, TTopology> NetworkBehaviour<TTopology> for Floodsub<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type ProtocolsHandler = FloodsubHandler<TSubstream>;
+    type OutEvent = FloodsubEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        FloodsubHandler::new()
+    }
+
+    fn inject_connected(&mut self, id: PeerId, _: ConnectedPoint) {
+        // We need to send our subscriptions to the newly-connected node.
+        for topic in self.subscribed_topics.iter() {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: id.clone(),
+                connection: None,
+                event: FloodsubRpc {
+                    messages: Vec::new(),
+                    subscriptions: vec![FloodsubSubscription {
+                        topic: topic.hash().clone(),
+                        action: FloodsubSubscriptionAction::Subscribe,
+                    }],
+                    control: Vec::new(),
+                },
+            });
+        }
+
+        self.dial_failures.remove(&id);
+        self.scheduled_dials.remove(&id);
+        self.connected_peers.insert(id.clone(), SmallVec::new());
+    }
+
+    fn inject_disconnected(&mut self, id: &PeerId, _: ConnectedPoint) {
+        self.rejected_messages.remove(id);
+        self.unsupported_peers.remove(id);
+
+        // The peer isn't in `connected_peers` if it refused to negotiate floodsub with us.
+        if let Some(topics) = self.connected_peers.remove(id) {
+            self.events.push_back(NetworkBehaviourAction::GenerateEvent(FloodsubEvent::PeerDisconnected {
+                peer_id: id.clone(),
+                topics: topics.into_vec(),
+            }));
+        }
+
+        // We can be disconnected by the remote in case of inactivity for example, so we always
+        // try to reconnect to the peers we want to talk to.
+        if self.target_peers.contains(id) {
+            self.schedule_redial(id.clone());
+        }
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        if !self.target_peers.contains(peer_id) {
+            return;
+        }
+
+        *self.dial_failures.entry(peer_id.clone()).or_insert(0) += 1;
+        self.schedule_redial(peer_id.clone());
+    }
+
+    fn inject_node_event(
+        &mut self,
+        propagation_source: PeerId,
+        _: ConnectionId,
+        event: FloodsubHandlerEvent,
+    ) {
+        match event {
+            FloodsubHandlerEvent::Rpc(rpc) => self.inject_rpc(propagation_source, rpc),
+            FloodsubHandlerEvent::Unsupported => self.inject_unsupported(propagation_source),
+        }
+    }
+
     fn poll(
         &mut self,
         _: &mut PollParameters<TTopology>,
@@ -292,6 +630,45 @@ where
             Self::OutEvent,
         >,
     > {
+        // Dial again the peers of the partial view whose delay has elapsed. An error of the timer
+        // is treated as if the delay had elapsed.
+        let redials = self.scheduled_dials
+            .iter_mut()
+            .filter_map(|(peer_id, delay)| match delay.poll() {
+                Ok(Async::NotReady) => None,
+                Ok(Async::Ready(())) | Err(_) => Some(peer_id.clone()),
+            })
+            .collect::<Vec<_>>();
+        for peer_id in redials {
+            self.scheduled_dials.remove(&peer_id);
+            self.events.push_back(NetworkBehaviourAction::DialPeer { peer_id });
+        }
+
+        // Handle the messages whose validation has finished.
+        let mut rpcs_to_dispatch: Vec<(PeerId, FloodsubRpc)> = Vec::new();
+        let mut n = 0;
+        while n < self.pending_validations.len() {
+            let result = match self.pending_validations[n].2.poll() {
+                Ok(Async::Ready(result)) => result,
+                Ok(Async::NotReady) => {
+                    n += 1;
+                    continue;
+                },
+                Err(()) => ValidationResult::Ignore,
+            };
+            // The order of the pending validations doesn't matter.
+            let (propagation_source, message, _) = self.pending_validations.swap_remove(n);
+            self.inject_validated(&propagation_source, message, result, &mut rpcs_to_dispatch);
+        }
+
+        for (peer_id, rpc) in rpcs_to_dispatch {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                connection: None,
+                event: rpc,
+            });
+        }
+
         if let Some(event) = self.events.pop_front() {
             return Async::Ready(event);
         }
@@ -321,4 +698,363 @@ pub enum FloodsubEvent {
         /// The topic it has subscribed from.
         topic: TopicHash,
     },
+
+    /// We disconnected from a remote. It is no longer subscribed to any topic.
+    PeerDisconnected {
+        /// Remote that we disconnected from.
+        peer_id: PeerId,
+        /// The topics it was subscribed to.
+        topics: Vec<TopicHash>,
+    },
+
+    /// A remote refused to negotiate the floodsub protocol with us. It is no longer considered
+    /// as subscribed to any topic, and is removed from the list of peers that we dial.
+    PeerUnsupported {
+        /// Remote that doesn't support floodsub.
+        peer_id: PeerId,
+        /// The topics it was subscribed to.
+        topics: Vec<TopicHash>,
+    },
+}
+
+/// Returns what identifies a message in `Floodsub::received`: its source and its sequence number.
+/// The signature and the key are left out, so that the same message relayed with different ones
+/// isn't dispatched twice.
+#[inline]
+fn message_id(message: &FloodsubMessage) -> (&PeerId, &[u8]) {
+    (&message.source, &message.sequence_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, prelude::*};
+    use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction};
+    use libp2p_core::{PeerId, PublicKey};
+    use protocol::{FloodsubMessage, FloodsubRpc, FloodsubSubscription, FloodsubSubscriptionAction};
+    use rand;
+    use std::io::Cursor;
+    use std::time::{Duration, Instant};
+    use super::{Floodsub, FloodsubEvent};
+    use topic::TopicBuilder;
+    use validation::{Validation, ValidationResult};
+
+    // A fake signature scheme, where the signature is the payload followed with the key.
+    fn sign(key: &PublicKey, payload: &[u8]) -> Vec<u8> {
+        let mut signature = payload.to_vec();
+        signature.extend(key.clone().into_protobuf_encoding());
+        signature
+    }
+
+    fn verify(key: &PublicKey, payload: &[u8], signature: &[u8]) -> bool {
+        sign(key, payload) == signature
+    }
+
+    #[test]
+    fn signature_checks() {
+        let public_key = PublicKey::Ed25519((0..32).map(|_| rand::random()).collect());
+        let topic = TopicBuilder::new("topic").build();
+
+        let mut message = FloodsubMessage {
+            source: public_key.clone().into_peer_id(),
+            data: b"hello".to_vec(),
+            sequence_number: vec![1],
+            topics: vec![topic.hash().clone()],
+            signature: None,
+            key: None,
+        };
+
+        let lenient = Floodsub::<Cursor<Vec<u8>>>::new(PeerId::random())
+            .with_signature_verification(verify);
+        let strict = Floodsub::<Cursor<Vec<u8>>>::new(PeerId::random())
+            .with_signature_verification(verify)
+            .with_strict_signing(true);
+
+        // Unsigned messages are only accepted in non-strict mode.
+        assert!(lenient.check_signature(&message));
+        assert!(!strict.check_signature(&message));
+
+        message.signature = Some(sign(&public_key, &message.signing_payload()));
+        message.key = Some(public_key.clone());
+        assert!(lenient.check_signature(&message));
+        assert!(strict.check_signature(&message));
+
+        // Tampering with the message invalidates the signature.
+        message.data = b"bye".to_vec();
+        assert!(!lenient.check_signature(&message));
+        assert!(!strict.check_signature(&message));
+
+        // The key must belong to the source.
+        let other_key = PublicKey::Ed25519((0..32).map(|_| rand::random()).collect());
+        message.signature = Some(sign(&other_key, &message.signing_payload()));
+        message.key = Some(other_key);
+        assert!(!lenient.check_signature(&message));
+    }
+
+    #[test]
+    fn validators() {
+        let mut floodsub = Floodsub::<Cursor<Vec<u8>>>::new(PeerId::random());
+        let peer_id = PeerId::random();
+        let endpoint = ConnectedPoint::Dialer { address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap() };
+        NetworkBehaviour::<()>::inject_connected(&mut floodsub, peer_id.clone(), endpoint);
+
+        let sync_topic = TopicBuilder::new("sync").build();
+        let async_topic = TopicBuilder::new("async").build();
+        floodsub.add_validator(&sync_topic, |_: &PeerId, message: &FloodsubMessage| {
+            if message.data.is_empty() { ValidationResult::Reject } else { ValidationResult::Accept }
+        });
+        floodsub.add_validator(&async_topic, |_: &PeerId, _: &FloodsubMessage| {
+            Validation::Pending(Box::new(future::ok(ValidationResult::Ignore)))
+        });
+
+        let message = |data: Vec<u8>, topics: Vec<_>| FloodsubMessage {
+            source: PeerId::random(),
+            data,
+            sequence_number: vec![1],
+            topics,
+            signature: None,
+            key: None,
+        };
+
+        // Synchronous validation.
+        let valid = message(vec![1], vec![sync_topic.hash().clone()]);
+        assert_eq!(floodsub.validate(&peer_id, valid.clone()), Some((valid, ValidationResult::Accept)));
+        let invalid = message(Vec::new(), vec![sync_topic.hash().clone()]);
+        assert_eq!(floodsub.validate(&peer_id, invalid.clone()), Some((invalid.clone(), ValidationResult::Reject)));
+
+        // A rejected message is neither dispatched nor propagated, and is counted against the
+        // peer that forwarded it.
+        let mut rpcs = Vec::new();
+        floodsub.inject_validated(&peer_id, invalid, ValidationResult::Reject, &mut rpcs);
+        assert!(rpcs.is_empty());
+        assert!(floodsub.events.is_empty());
+        assert_eq!(floodsub.num_rejected_messages(&peer_id), 1);
+
+        // Asynchronous validation. The results of all the topics are combined.
+        let both = message(vec![1], vec![sync_topic.hash().clone(), async_topic.hash().clone()]);
+        assert_eq!(floodsub.validate(&peer_id, both), None);
+        assert_eq!(floodsub.pending_validations.len(), 1);
+        assert_eq!(floodsub.pending_validations[0].2.poll(), Ok(Async::Ready(ValidationResult::Ignore)));
+
+        assert!(floodsub.remove_validator(&async_topic));
+        assert!(!floodsub.remove_validator(&async_topic));
+    }
+
+    #[test]
+    fn topic_peers() {
+        let mut floodsub = Floodsub::<Cursor<Vec<u8>>>::new(PeerId::random());
+        let topic1 = TopicBuilder::new("topic1").build();
+        let topic2 = TopicBuilder::new("topic2").build();
+
+        let add_peer = |floodsub: &mut Floodsub<_>, topics: Vec<_>| {
+            let peer_id = PeerId::random();
+            let endpoint = ConnectedPoint::Dialer { address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap() };
+            NetworkBehaviour::<()>::inject_connected(floodsub, peer_id.clone(), endpoint);
+            floodsub.inject_rpc(peer_id.clone(), FloodsubRpc {
+                messages: Vec::new(),
+                subscriptions: topics.into_iter().map(|topic| FloodsubSubscription {
+                    action: FloodsubSubscriptionAction::Subscribe,
+                    topic,
+                }).collect(),
+                control: Vec::new(),
+            });
+            peer_id
+        };
+        let peer1 = add_peer(&mut floodsub, vec![topic1.hash().clone()]);
+        let peer2 = add_peer(&mut floodsub, vec![topic1.hash().clone(), topic2.hash().clone()]);
+        let peer3 = add_peer(&mut floodsub, Vec::new());
+        floodsub.events.clear();
+
+        let mut peers = floodsub.topic_peers(topic1.hash()).cloned().collect::<Vec<_>>();
+        peers.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        let mut expected = vec![peer1.clone(), peer2.clone()];
+        expected.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        assert_eq!(peers, expected);
+        assert_eq!(floodsub.topic_peers(topic2.hash()).collect::<Vec<_>>(), vec![&peer2]);
+        assert_eq!(floodsub.known_topics().count(), 2);
+
+        // A peer that refuses floodsub is no longer subscribed to anything.
+        floodsub.inject_unsupported(peer2.clone());
+        assert_eq!(floodsub.topic_peers(topic2.hash()).count(), 0);
+        match floodsub.events.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(FloodsubEvent::PeerUnsupported { peer_id, topics })) => {
+                assert_eq!(peer_id, peer2);
+                assert_eq!(topics, vec![topic1.hash().clone(), topic2.hash().clone()]);
+            },
+            _ => panic!("expected a PeerUnsupported event"),
+        }
+
+        // Disconnecting reports the topics the peer was subscribed to.
+        let endpoint = ConnectedPoint::Dialer { address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap() };
+        NetworkBehaviour::<()>::inject_disconnected(&mut floodsub, &peer1, endpoint.clone());
+        NetworkBehaviour::<()>::inject_disconnected(&mut floodsub, &peer3, endpoint);
+        match floodsub.events.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(FloodsubEvent::PeerDisconnected { peer_id, topics })) => {
+                assert_eq!(peer_id, peer1);
+                assert_eq!(topics, vec![topic1.hash().clone()]);
+            },
+            _ => panic!("expected a PeerDisconnected event"),
+        }
+        assert_eq!(floodsub.topic_peers(topic1.hash()).count(), 0);
+        assert_eq!(floodsub.known_topics().count(), 0);
+    }
+
+    #[test]
+    fn partial_view() {
+        let mut floodsub = Floodsub::<Cursor<Vec<u8>>>::new(PeerId::random());
+        let peer_id = PeerId::random();
+        let endpoint = ConnectedPoint::Dialer { address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap() };
+        let is_dial = |event: Option<NetworkBehaviourAction<_, _>>| match event {
+            Some(NetworkBehaviourAction::DialPeer { peer_id: ref p }) => p == &peer_id,
+            _ => false,
+        };
+
+        // Adding a peer dials it.
+        floodsub.add_node_to_partial_view(peer_id.clone());
+        assert!(is_dial(floodsub.events.pop_front()));
+
+        // We dial it again after a delay once disconnected.
+        NetworkBehaviour::<()>::inject_connected(&mut floodsub, peer_id.clone(), endpoint.clone());
+        floodsub.add_node_to_partial_view(peer_id.clone());
+        assert!(floodsub.events.is_empty());
+        NetworkBehaviour::<()>::inject_disconnected(&mut floodsub, &peer_id, endpoint.clone());
+        floodsub.events.retain(|e| match e { NetworkBehaviourAction::GenerateEvent(_) => false, _ => true });
+        assert!(floodsub.events.is_empty());
+        assert!(floodsub.scheduled_dials.contains_key(&peer_id));
+
+        // A peer that refuses floodsub is forgotten and no longer dialed.
+        NetworkBehaviour::<()>::inject_connected(&mut floodsub, peer_id.clone(), endpoint.clone());
+        floodsub.inject_unsupported(peer_id.clone());
+        floodsub.inject_rpc(peer_id.clone(), FloodsubRpc {
+            messages: Vec::new(),
+            subscriptions: vec![FloodsubSubscription {
+                action: FloodsubSubscriptionAction::Subscribe,
+                topic: TopicBuilder::new("topic").build().hash().clone(),
+            }],
+            control: Vec::new(),
+        });
+        assert_eq!(floodsub.known_topics().count(), 0);
+        floodsub.events.retain(|e| match e { NetworkBehaviourAction::GenerateEvent(FloodsubEvent::PeerUnsupported { .. }) => false, _ => true });
+        assert!(floodsub.events.is_empty());
+
+        // Adding it back while it is still connected doesn't dial it.
+        floodsub.add_node_to_partial_view(peer_id.clone());
+        assert!(floodsub.events.is_empty());
+        assert!(floodsub.remove_node_from_partial_view(&peer_id));
+
+        NetworkBehaviour::<()>::inject_disconnected(&mut floodsub, &peer_id, endpoint);
+        assert!(floodsub.events.is_empty());
+        assert!(!floodsub.remove_node_from_partial_view(&peer_id));
+
+        // Once disconnected, adding it dials it again.
+        floodsub.add_node_to_partial_view(peer_id.clone());
+        assert!(is_dial(floodsub.events.pop_front()));
+    }
+
+    #[test]
+    fn duplicates_are_detected_by_source_and_sequence_number() {
+        let mut floodsub = Floodsub::<Cursor<Vec<u8>>>::new(PeerId::random());
+        let topic = TopicBuilder::new("topic").build();
+        floodsub.subscribe(topic.clone());
+        let peer_id = PeerId::random();
+        let endpoint = ConnectedPoint::Dialer { address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap() };
+        NetworkBehaviour::<()>::inject_connected(&mut floodsub, peer_id.clone(), endpoint);
+        floodsub.events.clear();
+
+        let message = FloodsubMessage {
+            source: PeerId::random(),
+            data: b"hello".to_vec(),
+            sequence_number: vec![1],
+            topics: vec![topic.hash().clone()],
+            signature: None,
+            key: None,
+        };
+        let mut resigned = message.clone();
+        resigned.signature = Some(vec![1, 2, 3]);
+        let mut next = message.clone();
+        next.sequence_number = vec![2];
+
+        floodsub.inject_rpc(peer_id, FloodsubRpc {
+            messages: vec![message, resigned, next],
+            subscriptions: Vec::new(),
+            control: Vec::new(),
+        });
+        let received = floodsub.events
+            .iter()
+            .filter_map(|event| match event {
+                NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Message(message)) =>
+                    Some(message.sequence_number.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(received, vec![vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn pending_validations_are_capped() {
+        let mut floodsub = Floodsub::<Cursor<Vec<u8>>>::new(PeerId::random())
+            .with_max_pending_validations(2);
+        let topic = TopicBuilder::new("topic").build();
+        floodsub.add_validator(&topic, |_: &PeerId, _: &FloodsubMessage| {
+            Validation::Pending(Box::new(future::empty()))
+        });
+
+        let peer_id = PeerId::random();
+        let message = |seq: u8| FloodsubMessage {
+            source: PeerId::random(),
+            data: vec![1],
+            sequence_number: vec![seq],
+            topics: vec![topic.hash().clone()],
+            signature: None,
+            key: None,
+        };
+
+        assert_eq!(floodsub.validate(&peer_id, message(1)), None);
+        assert_eq!(floodsub.validate(&peer_id, message(2)), None);
+        let dropped = message(3);
+        assert_eq!(floodsub.validate(&peer_id, dropped.clone()), Some((dropped, ValidationResult::Ignore)));
+        assert_eq!(floodsub.pending_validations.len(), 2);
+    }
+
+    #[test]
+    fn redials_back_off() {
+        let mut floodsub = Floodsub::<Cursor<Vec<u8>>>::new(PeerId::random())
+            .with_redial_delay(Duration::from_secs(1), Duration::from_secs(3));
+        let peer_id = PeerId::random();
+        let endpoint = ConnectedPoint::Dialer { address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap() };
+        floodsub.add_node_to_partial_view(peer_id.clone());
+        floodsub.events.clear();
+
+        // Each dialing failure doubles the delay, up to the maximum.
+        for &expected in &[2, 3, 3] {
+            let now = Instant::now();
+            NetworkBehaviour::<()>::inject_dial_failure(&mut floodsub, &peer_id);
+            let delay = floodsub.scheduled_dials[&peer_id].deadline().duration_since(now);
+            assert!(delay >= Duration::from_secs(expected));
+            assert!(delay < Duration::from_secs(expected + 1));
+        }
+        assert!(floodsub.events.is_empty());
+
+        // Connecting resets the delay.
+        NetworkBehaviour::<()>::inject_connected(&mut floodsub, peer_id.clone(), endpoint.clone());
+        assert!(floodsub.scheduled_dials.is_empty());
+        let now = Instant::now();
+        NetworkBehaviour::<()>::inject_disconnected(&mut floodsub, &peer_id, endpoint);
+        let delay = floodsub.scheduled_dials[&peer_id].deadline().duration_since(now);
+        assert!(delay < Duration::from_secs(2));
+
+        // Adding the peer again dials it immediately.
+        floodsub.events.clear();
+        floodsub.add_node_to_partial_view(peer_id.clone());
+        assert!(floodsub.scheduled_dials.is_empty());
+        match floodsub.events.pop_front() {
+            Some(NetworkBehaviourAction::DialPeer { peer_id: ref p }) => assert_eq!(p, &peer_id),
+            _ => panic!("expected a DialPeer action"),
+        }
+
+        // Peers outside of the partial view aren't dialed again.
+        let other = PeerId::random();
+        NetworkBehaviour::<()>::inject_dial_failure(&mut floodsub, &other);
+        assert!(!floodsub.scheduled_dials.contains_key(&other));
+        assert!(!floodsub.dial_failures.contains_key(&other));
+    }
 }
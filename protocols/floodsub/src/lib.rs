@@ -32,6 +32,7 @@ extern crate rand;
 extern crate smallvec;
 extern crate tokio_codec;
 extern crate tokio_io;
+extern crate tokio_timer;
 extern crate unsigned_varint;
 
 pub mod handler;
@@ -40,7 +41,9 @@ pub mod protocol;
 mod layer;
 mod rpc_proto;
 mod topic;
+mod validation;
 
 pub use self::layer::{Floodsub, FloodsubEvent};
-pub use self::protocol::{FloodsubMessage, FloodsubRpc};
+pub use self::protocol::{FloodsubControlAction, FloodsubMessage, FloodsubRpc};
 pub use self::topic::{Topic, TopicBuilder, TopicHash};
+pub use self::validation::{MessageValidator, Validation, ValidationResult};
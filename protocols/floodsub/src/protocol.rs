@@ -21,9 +21,9 @@
 use bytes::{BufMut, BytesMut};
 use crate::rpc_proto;
 use futures::future;
-use libp2p_core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, PeerId};
+use libp2p_core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, PeerId, PublicKey};
 use protobuf::Message as ProtobufMessage;
-use std::{io, iter};
+use std::{borrow::Cow, io, iter};
 use tokio_codec::{Decoder, Encoder, Framed};
 use tokio_io::{AsyncRead, AsyncWrite};
 use topic::TopicHash;
@@ -31,23 +31,42 @@ use unsigned_varint::codec;
 
 /// Implementation of `ConnectionUpgrade` for the floodsub protocol.
 #[derive(Debug, Clone)]
-pub struct FloodsubConfig {}
+pub struct FloodsubConfig {
+    /// Name of the protocol, as negotiated on the wire.
+    protocol_name: Cow<'static, [u8]>,
+}
 
 impl FloodsubConfig {
     /// Builds a new `FloodsubConfig`.
     #[inline]
     pub fn new() -> FloodsubConfig {
-        FloodsubConfig {}
+        FloodsubConfig {
+            protocol_name: Cow::Borrowed(b"/floodsub/1.0.0"),
+        }
+    }
+
+    /// Returns the name of the protocol negotiated on the wire.
+    #[inline]
+    pub fn protocol_name(&self) -> &[u8] {
+        &self.protocol_name
+    }
+
+    /// Modifies the name of the protocol negotiated on the wire. This allows other pubsub
+    /// protocols that share the floodsub RPC format, such as gossipsub, to reuse this upgrade.
+    #[inline]
+    pub fn with_protocol_name(mut self, name: impl Into<Cow<'static, [u8]>>) -> Self {
+        self.protocol_name = name.into();
+        self
     }
 }
 
 impl UpgradeInfo for FloodsubConfig {
-    type Info = &'static [u8];
+    type Info = Cow<'static, [u8]>;
     type InfoIter = iter::Once<Self::Info>;
 
     #[inline]
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(b"/floodsub/1.0.0")
+        iter::once(self.protocol_name.clone())
     }
 }
 
@@ -104,6 +123,12 @@ impl Encoder for FloodsubCodec {
                     .map(TopicHash::into_string)
                     .collect(),
             );
+            if let Some(signature) = message.signature {
+                msg.set_signature(signature);
+            }
+            if let Some(key) = message.key {
+                msg.set_key(key.into_protobuf_encoding());
+            }
             proto.mut_publish().push(msg);
         }
 
@@ -114,6 +139,35 @@ impl Encoder for FloodsubCodec {
             proto.mut_subscriptions().push(subscription);
         }
 
+        if !item.control.is_empty() {
+            let control = proto.mut_control();
+            for action in item.control.into_iter() {
+                match action {
+                    FloodsubControlAction::IHave { topic, message_ids } => {
+                        let mut ihave = rpc_proto::ControlIHave::new();
+                        ihave.set_topicID(topic.into_string());
+                        ihave.set_messageIDs(message_ids.into());
+                        control.mut_ihave().push(ihave);
+                    }
+                    FloodsubControlAction::IWant { message_ids } => {
+                        let mut iwant = rpc_proto::ControlIWant::new();
+                        iwant.set_messageIDs(message_ids.into());
+                        control.mut_iwant().push(iwant);
+                    }
+                    FloodsubControlAction::Graft { topic } => {
+                        let mut graft = rpc_proto::ControlGraft::new();
+                        graft.set_topicID(topic.into_string());
+                        control.mut_graft().push(graft);
+                    }
+                    FloodsubControlAction::Prune { topic } => {
+                        let mut prune = rpc_proto::ControlPrune::new();
+                        prune.set_topicID(topic.into_string());
+                        control.mut_prune().push(prune);
+                    }
+                }
+            }
+        }
+
         let msg_size = proto.compute_size();
         // Reserve enough space for the data and the length. The length has a maximum of 32 bits,
         // which means that 5 bytes is enough for the variable-length integer.
@@ -154,9 +208,47 @@ impl Decoder for FloodsubCodec {
                     .into_iter()
                     .map(|topic| TopicHash::from_raw(topic))
                     .collect(),
+                signature: if publish.has_signature() {
+                    Some(publish.take_signature())
+                } else {
+                    None
+                },
+                // A key that can't be decoded only affects its own message, whose signature then
+                // can't be checked.
+                key: if publish.has_key() {
+                    PublicKey::from_protobuf_encoding(publish.get_key()).ok()
+                } else {
+                    None
+                },
             });
         }
 
+        let mut control = Vec::new();
+        if rpc.has_control() {
+            let mut proto = rpc.take_control();
+            for mut ihave in proto.take_ihave().into_iter() {
+                control.push(FloodsubControlAction::IHave {
+                    topic: TopicHash::from_raw(ihave.take_topicID()),
+                    message_ids: ihave.take_messageIDs().into_vec(),
+                });
+            }
+            for mut iwant in proto.take_iwant().into_iter() {
+                control.push(FloodsubControlAction::IWant {
+                    message_ids: iwant.take_messageIDs().into_vec(),
+                });
+            }
+            for mut graft in proto.take_graft().into_iter() {
+                control.push(FloodsubControlAction::Graft {
+                    topic: TopicHash::from_raw(graft.take_topicID()),
+                });
+            }
+            for mut prune in proto.take_prune().into_iter() {
+                control.push(FloodsubControlAction::Prune {
+                    topic: TopicHash::from_raw(prune.take_topicID()),
+                });
+            }
+        }
+
         Ok(Some(FloodsubRpc {
             messages,
             subscriptions: rpc
@@ -171,6 +263,7 @@ impl Decoder for FloodsubCodec {
                     topic: TopicHash::from_raw(sub.take_topicid()),
                 })
                 .collect(),
+            control,
         }))
     }
 }
@@ -182,6 +275,9 @@ pub struct FloodsubRpc {
     pub messages: Vec<FloodsubMessage>,
     /// List of subscriptions.
     pub subscriptions: Vec<FloodsubSubscription>,
+    /// List of control messages. Floodsub itself doesn't use them, but they are part of the RPC
+    /// format shared with gossipsub.
+    pub control: Vec<FloodsubControlAction>,
 }
 
 /// A message received by the floodsub system.
@@ -200,6 +296,35 @@ pub struct FloodsubMessage {
     ///
     /// Each message can belong to multiple topics at once.
     pub topics: Vec<TopicHash>,
+
+    /// Signature of `signing_payload()` by the key of the source, if the message is signed.
+    pub signature: Option<Vec<u8>>,
+
+    /// Public key of the source, which the signature can be checked against. Must correspond to
+    /// `source`. `None` in a received message whose key couldn't be decoded.
+    pub key: Option<PublicKey>,
+}
+
+impl FloodsubMessage {
+    /// Returns the bytes that the source signs, which are the protobuf encoding of the message
+    /// without its signature and key, prefixed with `libp2p-pubsub:`.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut msg = rpc_proto::Message::new();
+        msg.set_from(self.source.clone().into_bytes());
+        msg.set_data(self.data.clone());
+        msg.set_seqno(self.sequence_number.clone());
+        msg.set_topicIDs(
+            self.topics
+                .iter()
+                .map(|topic| topic.clone().into_string())
+                .collect(),
+        );
+
+        let mut payload = b"libp2p-pubsub:".to_vec();
+        msg.write_to_vec(&mut payload)
+            .expect("there is no situation in which the protobuf message can be invalid");
+        payload
+    }
 }
 
 /// A subscription received by the floodsub system.
@@ -219,3 +344,70 @@ pub enum FloodsubSubscriptionAction {
     /// The remote wants to unsubscribe from the given topic.
     Unsubscribe,
 }
+
+/// A control message, as used by gossipsub to maintain its mesh and to gossip about the messages
+/// it has seen.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FloodsubControlAction {
+    /// The sender has seen the given messages recently.
+    IHave {
+        /// The topic the messages belong to.
+        topic: TopicHash,
+        /// Identifiers of the messages.
+        message_ids: Vec<String>,
+    },
+    /// The sender wants to receive the given messages.
+    IWant {
+        /// Identifiers of the messages.
+        message_ids: Vec<String>,
+    },
+    /// The sender has added the receiver to its mesh for the given topic.
+    Graft {
+        /// The topic of the mesh.
+        topic: TopicHash,
+    },
+    /// The sender has removed the receiver from its mesh for the given topic.
+    Prune {
+        /// The topic of the mesh.
+        topic: TopicHash,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, BytesMut};
+    use crate::rpc_proto;
+    use libp2p_core::{PeerId, PublicKey};
+    use protobuf::Message as ProtobufMessage;
+    use super::FloodsubCodec;
+    use tokio_codec::Decoder;
+
+    fn publish(source: &PeerId, key: Vec<u8>) -> rpc_proto::Message {
+        let mut msg = rpc_proto::Message::new();
+        msg.set_from(source.clone().into_bytes());
+        msg.set_data(b"hello".to_vec());
+        msg.set_seqno(vec![1]);
+        msg.set_signature(vec![2; 64]);
+        msg.set_key(key);
+        msg
+    }
+
+    #[test]
+    fn invalid_key_only_affects_its_message() {
+        let key = PublicKey::Ed25519(vec![1; 32]);
+        let source = key.clone().into_peer_id();
+        let mut proto = rpc_proto::RPC::new();
+        proto.mut_publish().push(publish(&source, key.clone().into_protobuf_encoding()));
+        proto.mut_publish().push(publish(&source, vec![0xff, 0xff, 0xff]));
+        proto.mut_publish().push(publish(&source, key.clone().into_protobuf_encoding()));
+
+        let mut src = BytesMut::with_capacity(proto.compute_size() as usize + 5);
+        proto.write_length_delimited_to_writer(&mut (&mut src).writer()).unwrap();
+        let mut codec = FloodsubCodec { length_prefix: Default::default() };
+        let rpc = codec.decode(&mut src).unwrap().unwrap();
+
+        let keys = rpc.messages.iter().map(|m| m.key.clone()).collect::<Vec<_>>();
+        assert_eq!(keys, vec![Some(key.clone()), None, Some(key)]);
+        assert!(rpc.messages.iter().all(|m| m.source == source && m.signature.is_some()));
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Validation of the messages received from remotes.
+//!
+//! A `MessageValidator` can be registered for each topic with `Floodsub::add_validator`. It looks
+//! at the messages received for that topic before they are dispatched to the user and propagated
+//! to the other peers, either immediately or asynchronously by returning a future.
+
+use futures::prelude::*;
+use libp2p_core::PeerId;
+use protocol::FloodsubMessage;
+
+/// Outcome of the validation of a message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValidationResult {
+    /// The message is valid. It is dispatched to the user and propagated.
+    Accept,
+    /// The message is invalid. It is dropped and counted against the peer that forwarded it.
+    Reject,
+    /// The message is dropped, but the peer that forwarded it isn't held responsible. For
+    /// example, the message may be valid but useless.
+    Ignore,
+}
+
+impl ValidationResult {
+    /// Combines the results of the validators of the topics of a message. A message is rejected
+    /// if any validator rejects it, and ignored if any validator ignores it.
+    pub(crate) fn and(self, other: ValidationResult) -> ValidationResult {
+        match (self, other) {
+            (ValidationResult::Reject, _) | (_, ValidationResult::Reject) => ValidationResult::Reject,
+            (ValidationResult::Ignore, _) | (_, ValidationResult::Ignore) => ValidationResult::Ignore,
+            (ValidationResult::Accept, ValidationResult::Accept) => ValidationResult::Accept,
+        }
+    }
+}
+
+/// Result of `MessageValidator::validate`.
+pub enum Validation {
+    /// The message has been validated immediately.
+    Ready(ValidationResult),
+    /// The message is being validated. An error of the future is treated as
+    /// `ValidationResult::Ignore`.
+    Pending(Box<dyn Future<Item = ValidationResult, Error = ()> + Send>),
+}
+
+impl From<ValidationResult> for Validation {
+    #[inline]
+    fn from(result: ValidationResult) -> Validation {
+        Validation::Ready(result)
+    }
+}
+
+/// Validates the messages received for a topic.
+///
+/// This trait is implemented on closures that take the peer that forwarded the message and the
+/// message, and return a `ValidationResult` or a `Validation`.
+pub trait MessageValidator {
+    /// Validates a message forwarded by `propagation_source`. Note that the publisher of the
+    /// message is `message.source`, which can be a different peer.
+    fn validate(&mut self, propagation_source: &PeerId, message: &FloodsubMessage) -> Validation;
+}
+
+impl<F, R> MessageValidator for F
+where
+    F: FnMut(&PeerId, &FloodsubMessage) -> R,
+    R: Into<Validation>,
+{
+    #[inline]
+    fn validate(&mut self, propagation_source: &PeerId, message: &FloodsubMessage) -> Validation {
+        self(propagation_source, message).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ValidationResult::{Accept, Ignore, Reject};
+
+    #[test]
+    fn combine_results() {
+        assert_eq!(Accept.and(Accept), Accept);
+        assert_eq!(Accept.and(Ignore), Ignore);
+        assert_eq!(Ignore.and(Accept), Ignore);
+        assert_eq!(Ignore.and(Reject), Reject);
+        assert_eq!(Reject.and(Accept), Reject);
+    }
+}
@@ -0,0 +1,929 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use cuckoofilter::CuckooFilter;
+use futures::prelude::*;
+use libp2p_core::swarm::{ConnectedPoint, ConnectionId, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::{protocols_handler::ProtocolsHandler, PeerId};
+use libp2p_floodsub::handler::{FloodsubHandler, FloodsubHandlerEvent};
+use libp2p_floodsub::protocol::{FloodsubConfig, FloodsubControlAction, FloodsubMessage, FloodsubRpc};
+use libp2p_floodsub::protocol::{FloodsubSubscription, FloodsubSubscriptionAction};
+use libp2p_floodsub::{Topic, TopicHash};
+use mcache::{MessageCache, message_id};
+use rand::{self, seq::SliceRandom};
+use smallvec::SmallVec;
+use std::{borrow::Cow, cmp, collections::VecDeque, iter, marker::PhantomData};
+use std::collections::hash_map::{DefaultHasher, HashMap};
+use std::time::{Duration, Instant};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Interval;
+
+/// Network behaviour that implements the gossipsub protocol.
+///
+/// The API is the same as the one of `Floodsub`. Messages are only forwarded to the peers of the
+/// mesh of their topic, and the other peers learn about them through gossip at each heartbeat.
+pub struct Gossipsub<TSubstream> {
+    /// Configuration of the behaviour.
+    config: GossipsubConfig,
+
+    /// Events that need to be yielded to the outside when polling.
+    events: VecDeque<NetworkBehaviourAction<FloodsubRpc, GossipsubEvent>>,
+
+    /// Peer id of the local node. Used for the source of the messages that we publish.
+    local_peer_id: PeerId,
+
+    /// List of peers the network is connected to, and the topics that they're subscribed to.
+    connected_peers: HashMap<PeerId, SmallVec<[TopicHash; 8]>>,
+
+    /// List of topics we're subscribed to.
+    subscribed_topics: SmallVec<[Topic; 16]>,
+
+    /// For each topic we're subscribed to, the peers we forward its messages to.
+    mesh: HashMap<TopicHash, Vec<PeerId>>,
+
+    /// For each topic we published to without being subscribed to it, the peers we send its
+    /// messages to.
+    fanout: HashMap<TopicHash, Vec<PeerId>>,
+
+    /// Last time we published a message to each topic of `fanout`.
+    fanout_last_published: HashMap<TopicHash, Instant>,
+
+    /// Messages we have recently seen, that we gossip about and that remotes can request.
+    mcache: MessageCache,
+
+    /// Identifiers of the messages we have received, so that we don't dispatch the same message
+    /// twice if we receive it twice on the network.
+    received: CuckooFilter<DefaultHasher>,
+
+    /// Interval at which we maintain the mesh and gossip about the recent messages.
+    heartbeat: Interval,
+
+    /// Marker to pin the generics.
+    marker: PhantomData<TSubstream>,
+}
+
+/// Configuration of a `Gossipsub` behaviour.
+#[derive(Debug, Clone)]
+pub struct GossipsubConfig {
+    /// Name of the protocol, as negotiated on the wire.
+    protocol_name: Cow<'static, [u8]>,
+    /// Target number of peers in the mesh of a topic.
+    mesh_n: usize,
+    /// Number of peers in the mesh of a topic below which we graft new peers.
+    mesh_n_low: usize,
+    /// Number of peers in the mesh of a topic above which we prune peers.
+    mesh_n_high: usize,
+    /// Number of heartbeats during which a message stays in the message cache.
+    history_length: usize,
+    /// Number of recent heartbeats whose messages we gossip about.
+    history_gossip: usize,
+    /// Delay before the first heartbeat.
+    heartbeat_initial_delay: Duration,
+    /// Interval between two heartbeats.
+    heartbeat_interval: Duration,
+    /// Duration after which we forget the fanout peers of a topic we no longer publish to.
+    fanout_ttl: Duration,
+}
+
+impl Default for GossipsubConfig {
+    fn default() -> Self {
+        GossipsubConfig {
+            protocol_name: Cow::Borrowed(b"/meshsub/1.0.0"),
+            mesh_n: 6,
+            mesh_n_low: 4,
+            mesh_n_high: 12,
+            history_length: 5,
+            history_gossip: 3,
+            heartbeat_initial_delay: Duration::from_millis(100),
+            heartbeat_interval: Duration::from_secs(1),
+            fanout_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+impl GossipsubConfig {
+    /// Builds the default `GossipsubConfig`.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the name of the protocol negotiated on the wire. The default is `/meshsub/1.0.0`.
+    #[inline]
+    pub fn protocol_name(mut self, name: impl Into<Cow<'static, [u8]>>) -> Self {
+        self.protocol_name = name.into();
+        self
+    }
+
+    /// Sets the number of peers in the mesh of each topic, and the bounds outside of which the
+    /// mesh is adjusted at each heartbeat. The defaults are 6, 4 and 12. The values are adjusted
+    /// so that `low <= target <= high`, and a target of zero is treated as one.
+    #[inline]
+    pub fn mesh_n(mut self, target: usize, low: usize, high: usize) -> Self {
+        self.mesh_n = cmp::max(target, 1);
+        self.mesh_n_low = cmp::min(low, self.mesh_n);
+        self.mesh_n_high = cmp::max(high, self.mesh_n);
+        self
+    }
+
+    /// Sets the number of heartbeats during which a message stays in the message cache, and the
+    /// number of recent heartbeats whose messages we gossip about. The defaults are 5 and 3. The
+    /// second value is capped to the first one, and a history length of zero is treated as one.
+    #[inline]
+    pub fn history(mut self, length: usize, gossip: usize) -> Self {
+        self.history_length = cmp::max(length, 1);
+        self.history_gossip = cmp::min(gossip, self.history_length);
+        self
+    }
+
+    /// Sets the delay before the first heartbeat. The default is 100 milliseconds.
+    #[inline]
+    pub fn heartbeat_initial_delay(mut self, value: Duration) -> Self {
+        self.heartbeat_initial_delay = value;
+        self
+    }
+
+    /// Sets the interval between two heartbeats. The default is one second. A value of zero is
+    /// treated as one millisecond.
+    #[inline]
+    pub fn heartbeat_interval(mut self, value: Duration) -> Self {
+        self.heartbeat_interval = cmp::max(value, Duration::from_millis(1));
+        self
+    }
+
+    /// Sets the duration after which we forget the fanout peers of a topic we published to
+    /// without being subscribed to it. The default is 60 seconds.
+    #[inline]
+    pub fn fanout_ttl(mut self, value: Duration) -> Self {
+        self.fanout_ttl = value;
+        self
+    }
+}
+
+impl<TSubstream> Gossipsub<TSubstream> {
+    /// Creates a `Gossipsub` with the default configuration.
+    #[inline]
+    pub fn new(local_peer_id: PeerId) -> Self {
+        Self::with_config(local_peer_id, Default::default())
+    }
+
+    /// Creates a `Gossipsub` with the given configuration.
+    pub fn with_config(local_peer_id: PeerId, config: GossipsubConfig) -> Self {
+        let heartbeat = Interval::new(
+            Instant::now() + config.heartbeat_initial_delay,
+            config.heartbeat_interval
+        );
+
+        Gossipsub {
+            events: VecDeque::new(),
+            local_peer_id,
+            connected_peers: HashMap::new(),
+            subscribed_topics: SmallVec::new(),
+            mesh: HashMap::new(),
+            fanout: HashMap::new(),
+            fanout_last_published: HashMap::new(),
+            mcache: MessageCache::new(config.history_gossip, config.history_length),
+            received: CuckooFilter::new(),
+            heartbeat,
+            config,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<TSubstream> Gossipsub<TSubstream> {
+    /// Subscribes to a topic, and joins its mesh.
+    ///
+    /// Returns true if the subscription worked. Returns false if we were already subscribed.
+    pub fn subscribe(&mut self, topic: Topic) -> bool {
+        if self.subscribed_topics.iter().any(|t| t.hash() == topic.hash()) {
+            return false;
+        }
+
+        for peer in self.connected_peers.keys() {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: peer.clone(),
+                connection: None,
+                event: FloodsubRpc {
+                    messages: Vec::new(),
+                    subscriptions: vec![FloodsubSubscription {
+                        topic: topic.hash().clone(),
+                        action: FloodsubSubscriptionAction::Subscribe,
+                    }],
+                    control: Vec::new(),
+                },
+            });
+        }
+
+        // The peers we were publishing to become the first peers of the mesh.
+        let mut peers = self.fanout.remove(topic.hash()).unwrap_or_default();
+        self.fanout_last_published.remove(topic.hash());
+        peers.truncate(self.config.mesh_n);
+        let missing = self.config.mesh_n - peers.len();
+        let new_peers = random_peers(&self.connected_peers, topic.hash(), missing, |p| peers.contains(p));
+        peers.extend(new_peers);
+
+        for peer in peers.iter() {
+            self.send_control(peer.clone(), vec![FloodsubControlAction::Graft {
+                topic: topic.hash().clone(),
+            }]);
+        }
+
+        self.mesh.insert(topic.hash().clone(), peers);
+        self.subscribed_topics.push(topic);
+        true
+    }
+
+    /// Unsubscribes from a topic, and leaves its mesh.
+    ///
+    /// Note that this only requires a `TopicHash` and not a full `Topic`.
+    ///
+    /// Returns true if we were subscribed to this topic.
+    pub fn unsubscribe(&mut self, topic: impl AsRef<TopicHash>) -> bool {
+        let topic = topic.as_ref();
+        let pos = match self.subscribed_topics.iter().position(|t| t.hash() == topic) {
+            Some(pos) => pos,
+            None => return false
+        };
+
+        self.subscribed_topics.remove(pos);
+
+        for peer in self.connected_peers.keys() {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: peer.clone(),
+                connection: None,
+                event: FloodsubRpc {
+                    messages: Vec::new(),
+                    subscriptions: vec![FloodsubSubscription {
+                        topic: topic.clone(),
+                        action: FloodsubSubscriptionAction::Unsubscribe,
+                    }],
+                    control: Vec::new(),
+                },
+            });
+        }
+
+        for peer in self.mesh.remove(topic).unwrap_or_default() {
+            self.send_control(peer, vec![FloodsubControlAction::Prune {
+                topic: topic.clone(),
+            }]);
+        }
+
+        true
+    }
+
+    /// Publishes a message to the network.
+    ///
+    /// Contrary to floodsub, we don't need to be subscribed to the topic. If we aren't, the
+    /// message is sent to a random set of peers subscribed to it, which are kept for the next
+    /// messages until we stop publishing to the topic for the duration of the fanout TTL.
+    pub fn publish(&mut self, topic: impl Into<TopicHash>, data: impl Into<Vec<u8>>) {
+        self.publish_many(iter::once(topic), data)
+    }
+
+    /// Publishes a message with multiple topics to the network.
+    pub fn publish_many(&mut self, topic: impl IntoIterator<Item = impl Into<TopicHash>>, data: impl Into<Vec<u8>>) {
+        let message = FloodsubMessage {
+            source: self.local_peer_id.clone(),
+            data: data.into(),
+            // If the sequence numbers are predictable, then an attacker could flood the network
+            // with packets with the predetermined sequence numbers and absorb our legitimate
+            // messages. We therefore use a random number.
+            sequence_number: rand::random::<[u8; 20]>().to_vec(),
+            topics: topic.into_iter().map(|t| t.into().clone()).collect(),
+            signature: None,
+            key: None,
+        };
+
+        self.received.add(&message_id(&message));
+        self.mcache.put(message.clone());
+
+        let now = Instant::now();
+        let mut recipients = Vec::new();
+        for topic in message.topics.iter() {
+            let peers = if let Some(peers) = self.mesh.get(topic) {
+                peers
+            } else {
+                if !self.fanout.contains_key(topic) {
+                    let peers = random_peers(&self.connected_peers, topic, self.config.mesh_n, |_| false);
+                    self.fanout.insert(topic.clone(), peers);
+                }
+                self.fanout_last_published.insert(topic.clone(), now);
+                &self.fanout[topic]
+            };
+
+            for peer in peers.iter() {
+                if !recipients.contains(peer) {
+                    recipients.push(peer.clone());
+                }
+            }
+        }
+
+        for peer_id in recipients {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                connection: None,
+                event: FloodsubRpc {
+                    subscriptions: Vec::new(),
+                    messages: vec![message.clone()],
+                    control: Vec::new(),
+                }
+            });
+        }
+    }
+
+    /// Returns the peers of the mesh of the given topic. Empty if we're not subscribed to it.
+    pub fn mesh_peers(&self, topic: &TopicHash) -> impl Iterator<Item = &PeerId> {
+        self.mesh.get(topic).into_iter().flat_map(|peers| peers.iter())
+    }
+
+    /// Sends control messages to a peer.
+    fn send_control(&mut self, peer_id: PeerId, control: Vec<FloodsubControlAction>) {
+        self.events.push_back(NetworkBehaviourAction::SendEvent {
+            peer_id,
+            connection: None,
+            event: FloodsubRpc {
+                messages: Vec::new(),
+                subscriptions: Vec::new(),
+                control,
+            },
+        });
+    }
+
+    /// Processes an RPC received from a remote.
+    fn inject_rpc(&mut self, propagation_source: PeerId, event: FloodsubRpc) {
+        // Update connected peers topics
+        for subscription in event.subscriptions {
+            let remote_peer_topics = self.connected_peers
+                .get_mut(&propagation_source)
+                .expect("connected_peers is kept in sync with the peers we are connected to; we are guaranteed to only receive events from connected peers; QED");
+            match subscription.action {
+                FloodsubSubscriptionAction::Subscribe => {
+                    if !remote_peer_topics.contains(&subscription.topic) {
+                        remote_peer_topics.push(subscription.topic.clone());
+                    }
+                    self.events.push_back(NetworkBehaviourAction::GenerateEvent(GossipsubEvent::Subscribed {
+                        peer_id: propagation_source.clone(),
+                        topic: subscription.topic,
+                    }));
+                }
+                FloodsubSubscriptionAction::Unsubscribe => {
+                    if let Some(pos) = remote_peer_topics.iter().position(|t| t == &subscription.topic ) {
+                        remote_peer_topics.remove(pos);
+                    }
+                    for peers in self.mesh.get_mut(&subscription.topic).into_iter()
+                        .chain(self.fanout.get_mut(&subscription.topic))
+                    {
+                        peers.retain(|p| p != &propagation_source);
+                    }
+                    self.events.push_back(NetworkBehaviourAction::GenerateEvent(GossipsubEvent::Unsubscribed {
+                        peer_id: propagation_source.clone(),
+                        topic: subscription.topic,
+                    }));
+                }
+            }
+        }
+
+        // List of messages we're going to forward to the peers of our meshes.
+        let mut rpcs_to_dispatch: Vec<(PeerId, FloodsubRpc)> = Vec::new();
+
+        for message in event.messages {
+            // Use `self.received` to skip the messages that we have already received in the past.
+            // Note that this can false positive.
+            if !self.received.test_and_add(&message_id(&message)) {
+                continue;
+            }
+
+            self.mcache.put(message.clone());
+
+            // Add the message to be dispatched to the user.
+            if self.subscribed_topics.iter().any(|t| message.topics.iter().any(|u| t.hash() == u)) {
+                let event = GossipsubEvent::Message(message.clone());
+                self.events.push_back(NetworkBehaviourAction::GenerateEvent(event));
+            }
+
+            // Forward the message to the peers of the meshes of its topics.
+            let mesh_peers = message.topics.iter()
+                .filter_map(|topic| self.mesh.get(topic))
+                .flat_map(|peers| peers.iter());
+            for peer_id in mesh_peers {
+                if peer_id == &propagation_source || peer_id == &message.source {
+                    continue;
+                }
+
+                if let Some(pos) = rpcs_to_dispatch.iter().position(|(p, _)| p == peer_id) {
+                    if !rpcs_to_dispatch[pos].1.messages.contains(&message) {
+                        rpcs_to_dispatch[pos].1.messages.push(message.clone());
+                    }
+                } else {
+                    rpcs_to_dispatch.push((peer_id.clone(), FloodsubRpc {
+                        subscriptions: Vec::new(),
+                        messages: vec![message.clone()],
+                        control: Vec::new(),
+                    }));
+                }
+            }
+        }
+
+        for (peer_id, rpc) in rpcs_to_dispatch {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                connection: None,
+                event: rpc,
+            });
+        }
+
+        // Answer the control messages. The messages requested with `IWANT` and our own control
+        // messages are sent back in a single RPC.
+        let mut response = FloodsubRpc {
+            messages: Vec::new(),
+            subscriptions: Vec::new(),
+            control: Vec::new(),
+        };
+
+        for action in event.control {
+            match action {
+                FloodsubControlAction::IHave { topic, message_ids } => {
+                    if !self.mesh.contains_key(&topic) {
+                        continue;
+                    }
+                    let wanted = message_ids
+                        .into_iter()
+                        .filter(|id| !self.received.contains(id))
+                        .collect::<Vec<_>>();
+                    if !wanted.is_empty() {
+                        response.control.push(FloodsubControlAction::IWant { message_ids: wanted });
+                    }
+                }
+                FloodsubControlAction::IWant { message_ids } => {
+                    for id in message_ids {
+                        if let Some(message) = self.mcache.get(&id) {
+                            if !response.messages.contains(message) {
+                                response.messages.push(message.clone());
+                            }
+                        }
+                    }
+                }
+                FloodsubControlAction::Graft { topic } => {
+                    if let Some(peers) = self.mesh.get_mut(&topic) {
+                        if !peers.contains(&propagation_source) {
+                            peers.push(propagation_source.clone());
+                        }
+                    } else {
+                        // We're not subscribed to this topic.
+                        response.control.push(FloodsubControlAction::Prune { topic });
+                    }
+                }
+                FloodsubControlAction::Prune { topic } => {
+                    if let Some(peers) = self.mesh.get_mut(&topic) {
+                        peers.retain(|p| p != &propagation_source);
+                    }
+                }
+            }
+        }
+
+        if !response.messages.is_empty() || !response.control.is_empty() {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: propagation_source,
+                connection: None,
+                event: response,
+            });
+        }
+    }
+
+    /// Maintains the meshes and the fanout peers, and gossips about the recent messages.
+    fn heartbeat(&mut self) {
+        let mut control: HashMap<PeerId, Vec<FloodsubControlAction>> = HashMap::new();
+
+        // Graft or prune peers so that the number of peers of each mesh stays within the bounds.
+        for (topic, peers) in self.mesh.iter_mut() {
+            if peers.len() < self.config.mesh_n_low {
+                let missing = self.config.mesh_n - peers.len();
+                let new_peers = random_peers(&self.connected_peers, topic, missing, |p| peers.contains(p));
+                for peer in new_peers {
+                    control.entry(peer.clone()).or_insert_with(Vec::new)
+                        .push(FloodsubControlAction::Graft { topic: topic.clone() });
+                    peers.push(peer);
+                }
+            } else if peers.len() > self.config.mesh_n_high {
+                peers.shuffle(&mut rand::thread_rng());
+                for peer in peers.drain(self.config.mesh_n..) {
+                    control.entry(peer).or_insert_with(Vec::new)
+                        .push(FloodsubControlAction::Prune { topic: topic.clone() });
+                }
+            }
+        }
+
+        // Forget the fanout peers of the topics we no longer publish to, and replace the peers
+        // that have unsubscribed.
+        let now = Instant::now();
+        let fanout_ttl = self.config.fanout_ttl;
+        self.fanout_last_published.retain(|_, last| *last + fanout_ttl > now);
+        let fanout_last_published = &self.fanout_last_published;
+        self.fanout.retain(|topic, _| fanout_last_published.contains_key(topic));
+        for (topic, peers) in self.fanout.iter_mut() {
+            let connected_peers = &self.connected_peers;
+            peers.retain(|p| connected_peers.get(p).map(|t| t.contains(topic)).unwrap_or(false));
+            if peers.len() < self.config.mesh_n {
+                let missing = self.config.mesh_n - peers.len();
+                let new_peers = random_peers(connected_peers, topic, missing, |p| peers.contains(p));
+                peers.extend(new_peers);
+            }
+        }
+
+        // Gossip about the recent messages with peers outside of the mesh.
+        for (topic, peers) in self.mesh.iter().chain(self.fanout.iter()) {
+            let message_ids = self.mcache.get_gossip_ids(topic);
+            if message_ids.is_empty() {
+                continue;
+            }
+
+            let gossip_peers = random_peers(&self.connected_peers, topic, self.config.mesh_n, |p| peers.contains(p));
+            for peer in gossip_peers {
+                control.entry(peer).or_insert_with(Vec::new).push(FloodsubControlAction::IHave {
+                    topic: topic.clone(),
+                    message_ids: message_ids.clone(),
+                });
+            }
+        }
+
+        self.mcache.shift();
+
+        for (peer_id, control) in control {
+            self.send_control(peer_id, control);
+        }
+    }
+}
+
+/// Returns up to `n` random peers among the ones subscribed to `topic`, skipping the ones for
+/// which `exclude` returns true.
+fn random_peers(
+    connected_peers: &HashMap<PeerId, SmallVec<[TopicHash; 8]>>,
+    topic: &TopicHash,
+    n: usize,
+    exclude: impl Fn(&PeerId) -> bool
+) -> Vec<PeerId> {
+    let mut peers = connected_peers
+        .iter()
+        .filter(|(peer_id, topics)| topics.contains(topic) && !exclude(peer_id))
+        .map(|(peer_id, _)| peer_id.clone())
+        .collect::<Vec<_>>();
+    peers.shuffle(&mut rand::thread_rng());
+    peers.truncate(n);
+    peers
+}
+
+impl<TSubstream, TTopology> NetworkBehaviour<TTopology> for Gossipsub<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type ProtocolsHandler = FloodsubHandler<TSubstream>;
+    type OutEvent = GossipsubEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        let config = FloodsubConfig::new().with_protocol_name(self.config.protocol_name.clone());
+        FloodsubHandler::with_config(config)
+    }
+
+    fn inject_connected(&mut self, id: PeerId, _: ConnectedPoint) {
+        // We need to send our subscriptions to the newly-connected node.
+        if !self.subscribed_topics.is_empty() {
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: id.clone(),
+                connection: None,
+                event: FloodsubRpc {
+                    messages: Vec::new(),
+                    subscriptions: self.subscribed_topics
+                        .iter()
+                        .map(|topic| FloodsubSubscription {
+                            topic: topic.hash().clone(),
+                            action: FloodsubSubscriptionAction::Subscribe,
+                        })
+                        .collect(),
+                    control: Vec::new(),
+                },
+            });
+        }
+
+        self.connected_peers.insert(id.clone(), SmallVec::new());
+    }
+
+    fn inject_disconnected(&mut self, id: &PeerId, _: ConnectedPoint) {
+        let was_in = self.connected_peers.remove(id);
+        debug_assert!(was_in.is_some());
+
+        for peers in self.mesh.values_mut().chain(self.fanout.values_mut()) {
+            peers.retain(|p| p != id);
+        }
+    }
+
+    fn inject_node_event(
+        &mut self,
+        propagation_source: PeerId,
+        _: ConnectionId,
+        event: FloodsubHandlerEvent,
+    ) {
+        match event {
+            FloodsubHandlerEvent::Rpc(rpc) => self.inject_rpc(propagation_source, rpc),
+            FloodsubHandlerEvent::Unsupported => {
+                // The remote can't receive anything from us; forget about its subscriptions so
+                // that it doesn't get grafted.
+                if let Some(topics) = self.connected_peers.get_mut(&propagation_source) {
+                    topics.clear();
+                }
+                for peers in self.mesh.values_mut().chain(self.fanout.values_mut()) {
+                    peers.retain(|p| p != &propagation_source);
+                }
+            },
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut PollParameters<TTopology>,
+    ) -> Async<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        loop {
+            match self.heartbeat.poll() {
+                Ok(Async::Ready(Some(_))) => self.heartbeat(),
+                Ok(Async::NotReady) => break,
+                // Ignore errors.
+                Ok(Async::Ready(None)) | Err(_) => break,
+            }
+        }
+
+        if let Some(event) = self.events.pop_front() {
+            return Async::Ready(event);
+        }
+
+        Async::NotReady
+    }
+}
+
+/// Event that can happen on the gossipsub behaviour.
+#[derive(Debug)]
+pub enum GossipsubEvent {
+    /// A message has been received.
+    Message(FloodsubMessage),
+
+    /// A remote subscribed to a topic.
+    Subscribed {
+        /// Remote that has subscribed.
+        peer_id: PeerId,
+        /// The topic it has subscribed to.
+        topic: TopicHash,
+    },
+
+    /// A remote unsubscribed from a topic.
+    Unsubscribed {
+        /// Remote that has unsubscribed.
+        peer_id: PeerId,
+        /// The topic it has subscribed from.
+        topic: TopicHash,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction};
+    use libp2p_core::PeerId;
+    use libp2p_floodsub::protocol::{FloodsubControlAction, FloodsubMessage, FloodsubRpc};
+    use libp2p_floodsub::protocol::{FloodsubSubscription, FloodsubSubscriptionAction};
+    use libp2p_floodsub::{TopicBuilder, TopicHash};
+    use mcache::message_id;
+    use std::{io::Cursor, time::Duration};
+    use super::{Gossipsub, GossipsubConfig};
+
+    type TestGossipsub = Gossipsub<Cursor<Vec<u8>>>;
+
+    /// Connects a new peer subscribed to the given topic, and discards the generated events.
+    fn add_peer(gs: &mut TestGossipsub, topic: &TopicHash) -> PeerId {
+        let peer_id = PeerId::random();
+        let endpoint = ConnectedPoint::Dialer { address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap() };
+        NetworkBehaviour::<()>::inject_connected(gs, peer_id.clone(), endpoint);
+        gs.inject_rpc(peer_id.clone(), FloodsubRpc {
+            messages: Vec::new(),
+            subscriptions: vec![FloodsubSubscription {
+                action: FloodsubSubscriptionAction::Subscribe,
+                topic: topic.clone(),
+            }],
+            control: Vec::new(),
+        });
+        gs.events.clear();
+        peer_id
+    }
+
+    /// Returns the control messages that are sent to each peer.
+    fn sent_control(gs: &mut TestGossipsub) -> Vec<(PeerId, FloodsubControlAction)> {
+        let mut control = Vec::new();
+        for event in gs.events.drain(..) {
+            if let NetworkBehaviourAction::SendEvent { peer_id, event, .. } = event {
+                control.extend(event.control.into_iter().map(|c| (peer_id.clone(), c)));
+            }
+        }
+        control
+    }
+
+    #[test]
+    fn subscribe_grafts_mesh_peers() {
+        let mut gs = TestGossipsub::with_config(PeerId::random(), GossipsubConfig::new().mesh_n(3, 2, 4));
+        let topic = TopicBuilder::new("topic").build();
+        for _ in 0..5 {
+            add_peer(&mut gs, topic.hash());
+        }
+
+        assert!(gs.subscribe(topic.clone()));
+        assert!(!gs.subscribe(topic.clone()));
+        assert_eq!(gs.mesh_peers(topic.hash()).count(), 3);
+
+        let grafted = sent_control(&mut gs)
+            .into_iter()
+            .filter(|(_, c)| *c == FloodsubControlAction::Graft { topic: topic.hash().clone() })
+            .map(|(p, _)| p)
+            .collect::<Vec<_>>();
+        assert_eq!(grafted, gs.mesh_peers(topic.hash()).cloned().collect::<Vec<_>>());
+
+        assert!(gs.unsubscribe(&topic));
+        assert_eq!(gs.mesh_peers(topic.hash()).count(), 0);
+        let pruned = sent_control(&mut gs)
+            .into_iter()
+            .filter(|(_, c)| *c == FloodsubControlAction::Prune { topic: topic.hash().clone() })
+            .count();
+        assert_eq!(pruned, 3);
+    }
+
+    #[test]
+    fn zero_heartbeat_interval_is_clamped() {
+        let config = GossipsubConfig::new().heartbeat_interval(Duration::from_secs(0));
+        assert_eq!(config.heartbeat_interval, Duration::from_millis(1));
+        TestGossipsub::with_config(PeerId::random(), config);
+    }
+
+    #[test]
+    fn graft_for_unknown_topic_is_pruned() {
+        let mut gs = TestGossipsub::new(PeerId::random());
+        let topic = TopicBuilder::new("topic").build();
+        let peer = add_peer(&mut gs, topic.hash());
+
+        gs.inject_rpc(peer.clone(), FloodsubRpc {
+            messages: Vec::new(),
+            subscriptions: Vec::new(),
+            control: vec![FloodsubControlAction::Graft { topic: topic.hash().clone() }],
+        });
+        assert_eq!(sent_control(&mut gs), vec![(peer.clone(), FloodsubControlAction::Prune { topic: topic.hash().clone() })]);
+
+        gs.subscribe(topic.clone());
+        gs.events.clear();
+        gs.inject_rpc(peer.clone(), FloodsubRpc {
+            messages: Vec::new(),
+            subscriptions: Vec::new(),
+            control: vec![FloodsubControlAction::Graft { topic: topic.hash().clone() }],
+        });
+        assert!(sent_control(&mut gs).is_empty());
+        assert_eq!(gs.mesh_peers(topic.hash()).collect::<Vec<_>>(), vec![&peer]);
+    }
+
+    #[test]
+    fn publish_without_subscription_uses_fanout() {
+        let mut gs = TestGossipsub::with_config(PeerId::random(), GossipsubConfig::new().mesh_n(2, 1, 3));
+        let topic = TopicBuilder::new("topic").build();
+        for _ in 0..4 {
+            add_peer(&mut gs, topic.hash());
+        }
+
+        gs.publish(&topic, b"first".to_vec());
+        gs.publish(&topic, b"second".to_vec());
+
+        let mut recipients = Vec::new();
+        for event in gs.events.drain(..) {
+            if let NetworkBehaviourAction::SendEvent { peer_id, event, .. } = event {
+                assert_eq!(event.messages.len(), 1);
+                recipients.push(peer_id);
+            }
+        }
+        // Both messages are sent to the same two fanout peers.
+        assert_eq!(recipients.len(), 4);
+        assert_eq!(recipients[..2], recipients[2..]);
+        assert_eq!(gs.fanout[topic.hash()], recipients[..2].to_vec());
+    }
+
+    #[test]
+    fn heartbeat_keeps_mesh_within_bounds() {
+        let mut gs = TestGossipsub::with_config(PeerId::random(), GossipsubConfig::new().mesh_n(2, 1, 3));
+        let topic = TopicBuilder::new("topic").build();
+        let peers = (0..5).map(|_| add_peer(&mut gs, topic.hash())).collect::<Vec<_>>();
+        gs.subscribe(topic.clone());
+        gs.events.clear();
+
+        // Remotes graft us until the mesh exceeds its upper bound.
+        for peer in peers.iter() {
+            gs.inject_rpc(peer.clone(), FloodsubRpc {
+                messages: Vec::new(),
+                subscriptions: Vec::new(),
+                control: vec![FloodsubControlAction::Graft { topic: topic.hash().clone() }],
+            });
+        }
+        assert_eq!(gs.mesh_peers(topic.hash()).count(), 5);
+
+        gs.heartbeat();
+        assert_eq!(gs.mesh_peers(topic.hash()).count(), 2);
+        let pruned = sent_control(&mut gs)
+            .into_iter()
+            .filter(|(_, c)| *c == FloodsubControlAction::Prune { topic: topic.hash().clone() })
+            .count();
+        assert_eq!(pruned, 3);
+
+        // Remotes prune us until the mesh is below its lower bound.
+        let mesh = gs.mesh_peers(topic.hash()).cloned().collect::<Vec<_>>();
+        for peer in mesh {
+            gs.inject_rpc(peer, FloodsubRpc {
+                messages: Vec::new(),
+                subscriptions: Vec::new(),
+                control: vec![FloodsubControlAction::Prune { topic: topic.hash().clone() }],
+            });
+        }
+        assert_eq!(gs.mesh_peers(topic.hash()).count(), 0);
+
+        gs.heartbeat();
+        assert_eq!(gs.mesh_peers(topic.hash()).count(), 2);
+    }
+
+    #[test]
+    fn gossip_and_message_requests() {
+        let mut gs = TestGossipsub::with_config(PeerId::random(), GossipsubConfig::new().mesh_n(1, 1, 1));
+        let topic = TopicBuilder::new("topic").build();
+        let first = add_peer(&mut gs, topic.hash());
+        let second = add_peer(&mut gs, topic.hash());
+        gs.subscribe(topic.clone());
+        gs.events.clear();
+
+        // A peer announces a message that we haven't seen, and we request it.
+        let message = FloodsubMessage {
+            source: PeerId::random(),
+            data: b"hello".to_vec(),
+            sequence_number: vec![1],
+            topics: vec![topic.hash().clone()],
+            signature: None,
+            key: None,
+        };
+        let id = message_id(&message);
+        gs.inject_rpc(first.clone(), FloodsubRpc {
+            messages: Vec::new(),
+            subscriptions: Vec::new(),
+            control: vec![FloodsubControlAction::IHave {
+                topic: topic.hash().clone(),
+                message_ids: vec![id.clone()],
+            }],
+        });
+        assert_eq!(sent_control(&mut gs), vec![(first.clone(), FloodsubControlAction::IWant { message_ids: vec![id.clone()] })]);
+
+        // Once received, the message is announced to the peer outside of the mesh at the next
+        // heartbeat, and can be requested.
+        gs.inject_rpc(first.clone(), FloodsubRpc {
+            messages: vec![message.clone()],
+            subscriptions: Vec::new(),
+            control: Vec::new(),
+        });
+        gs.events.clear();
+        let outside = if gs.mesh_peers(topic.hash()).any(|p| p == &first) { second } else { first };
+        gs.heartbeat();
+        assert_eq!(sent_control(&mut gs), vec![(outside.clone(), FloodsubControlAction::IHave {
+            topic: topic.hash().clone(),
+            message_ids: vec![id.clone()],
+        })]);
+
+        gs.inject_rpc(outside.clone(), FloodsubRpc {
+            messages: Vec::new(),
+            subscriptions: Vec::new(),
+            control: vec![FloodsubControlAction::IWant { message_ids: vec![id] }],
+        });
+        match gs.events.pop_front() {
+            Some(NetworkBehaviourAction::SendEvent { ref peer_id, ref event, .. }) => {
+                assert_eq!(peer_id, &outside);
+                assert_eq!(event.messages, vec![message]);
+            }
+            _ => panic!("expected the requested message to be sent")
+        }
+    }
+}
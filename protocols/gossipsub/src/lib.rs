@@ -0,0 +1,43 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implements the gossipsub protocol, see also the:
+//! [spec](https://github.com/libp2p/specs/tree/master/pubsub/gossipsub).
+//!
+//! Gossipsub uses the same RPC format and the same topics as floodsub, but instead of sending
+//! every message to every peer, each node only forwards messages to a limited set of peers of its
+//! mesh. The peers outside of the mesh are informed of the messages through gossip, and can
+//! request them if they haven't received them already.
+
+extern crate bs58;
+extern crate cuckoofilter;
+extern crate futures;
+extern crate libp2p_core;
+extern crate libp2p_floodsub;
+extern crate rand;
+extern crate smallvec;
+extern crate tokio_io;
+extern crate tokio_timer;
+
+mod layer;
+mod mcache;
+
+pub use self::layer::{Gossipsub, GossipsubConfig, GossipsubEvent};
+pub use libp2p_floodsub::{FloodsubMessage, Topic, TopicBuilder, TopicHash};
@@ -0,0 +1,151 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use bs58;
+use libp2p_floodsub::{FloodsubMessage, TopicHash};
+use std::collections::HashMap;
+
+/// Returns the identifier of a message, which is what `IHAVE` and `IWANT` control messages refer
+/// to.
+pub fn message_id(message: &FloodsubMessage) -> String {
+    let mut id = message.source.to_base58();
+    id.push_str(&bs58::encode(&message.sequence_number).into_string());
+    id
+}
+
+/// Cache of the messages we have recently seen.
+///
+/// The cache is used to answer `IWANT` requests, and to gossip about the recent messages with
+/// `IHAVE` messages. It is split into windows, and `shift` must be called at each heartbeat to
+/// open a new window and to drop the messages of the oldest one.
+#[derive(Debug, Clone)]
+pub struct MessageCache {
+    /// Messages in the cache, indexed by their identifier.
+    messages: HashMap<String, FloodsubMessage>,
+    /// Identifiers and topics of the messages of each window. The first window is the most recent.
+    history: Vec<Vec<(String, Vec<TopicHash>)>>,
+    /// Number of recent windows whose messages we gossip about.
+    gossip: usize,
+}
+
+impl MessageCache {
+    /// Creates a new cache that keeps the messages of `history_length` windows, and gossips about
+    /// the messages of the `history_gossip` most recent ones.
+    pub fn new(history_gossip: usize, history_length: usize) -> MessageCache {
+        debug_assert!(history_gossip <= history_length);
+        MessageCache {
+            messages: HashMap::new(),
+            history: (0..history_length).map(|_| Vec::new()).collect(),
+            gossip: history_gossip,
+        }
+    }
+
+    /// Adds a message to the current window. Does nothing if it is already in the cache.
+    pub fn put(&mut self, message: FloodsubMessage) {
+        let id = message_id(&message);
+        if self.messages.contains_key(&id) {
+            return;
+        }
+
+        if let Some(window) = self.history.first_mut() {
+            window.push((id.clone(), message.topics.clone()));
+            self.messages.insert(id, message);
+        }
+    }
+
+    /// Returns the message with the given identifier, if it is still in the cache.
+    pub fn get(&self, id: &str) -> Option<&FloodsubMessage> {
+        self.messages.get(id)
+    }
+
+    /// Returns the identifiers of the messages of the recent windows that belong to `topic`.
+    pub fn get_gossip_ids(&self, topic: &TopicHash) -> Vec<String> {
+        self.history
+            .iter()
+            .take(self.gossip)
+            .flat_map(|window| window.iter())
+            .filter(|(_, topics)| topics.contains(topic))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Opens a new window, and removes the messages of the oldest one from the cache.
+    pub fn shift(&mut self) {
+        if let Some(window) = self.history.pop() {
+            for (id, _) in window {
+                self.messages.remove(&id);
+            }
+            self.history.insert(0, Vec::new());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::PeerId;
+    use libp2p_floodsub::{FloodsubMessage, TopicHash};
+    use super::{MessageCache, message_id};
+
+    fn message(seq: u8, topic: &str) -> FloodsubMessage {
+        FloodsubMessage {
+            source: PeerId::random(),
+            data: vec![seq],
+            sequence_number: vec![seq],
+            topics: vec![TopicHash::from_raw(topic.to_owned())],
+            signature: None,
+            key: None,
+        }
+    }
+
+    #[test]
+    fn gossip_only_recent_windows() {
+        let mut cache = MessageCache::new(2, 3);
+        let topic = TopicHash::from_raw("topic".to_owned());
+
+        let first = message(1, "topic");
+        cache.put(first.clone());
+        cache.put(message(2, "other"));
+        assert_eq!(cache.get_gossip_ids(&topic), vec![message_id(&first)]);
+
+        cache.shift();
+        let second = message(3, "topic");
+        cache.put(second.clone());
+        assert_eq!(cache.get_gossip_ids(&topic), vec![message_id(&second), message_id(&first)]);
+
+        // The first message is no longer gossiped about, but can still be requested.
+        cache.shift();
+        assert_eq!(cache.get_gossip_ids(&topic), vec![message_id(&second)]);
+        assert_eq!(cache.get(&message_id(&first)), Some(&first));
+
+        // Now it is gone.
+        cache.shift();
+        assert!(cache.get(&message_id(&first)).is_none());
+        assert_eq!(cache.get(&message_id(&second)), Some(&second));
+    }
+
+    #[test]
+    fn put_ignores_duplicates() {
+        let mut cache = MessageCache::new(1, 1);
+        let msg = message(1, "topic");
+        cache.put(msg.clone());
+        cache.put(msg.clone());
+        assert_eq!(cache.get_gossip_ids(&msg.topics[0]).len(), 1);
+    }
+}
@@ -24,7 +24,7 @@ use crate::protocol::{IdentifyInfo, IdentifySender, IdentifySenderFuture};
 use crate::topology::IdentifyTopology;
 use futures::prelude::*;
 use libp2p_core::protocols_handler::{ProtocolsHandler, ProtocolsHandlerSelect, ProtocolsHandlerUpgrErr};
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::swarm::{ConnectedPoint, ConnectionId, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p_core::{Multiaddr, PeerId, either::EitherOutput};
 use smallvec::SmallVec;
 use std::{collections::HashMap, collections::VecDeque, io};
@@ -38,8 +38,8 @@ pub struct Identify<TSubstream> {
     protocol_version: String,
     /// Agent version to send back to remotes.
     agent_version: String,
-    /// For each peer we're connected to, the observed address to send back to it.
-    observed_addresses: HashMap<PeerId, Multiaddr>,
+    /// For each connection we have, the observed address to send back to the remote.
+    observed_addresses: HashMap<ConnectionId, Multiaddr>,
     /// List of senders to answer, with the observed multiaddr.
     to_answer: SmallVec<[(IdentifySender<TSubstream>, Multiaddr); 4]>,
     /// List of futures that send back information back to remotes.
@@ -74,22 +74,27 @@ where
         IdentifyListenHandler::new().select(PeriodicIdHandler::new())
     }
 
-    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+    fn inject_connected(&mut self, _: PeerId, _: ConnectedPoint) {}
+
+    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {}
+
+    fn inject_connection_established(&mut self, _: &PeerId, connection: ConnectionId, endpoint: &ConnectedPoint) {
         let observed = match endpoint {
             ConnectedPoint::Dialer { address } => address,
             ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr,
         };
 
-        self.observed_addresses.insert(peer_id, observed);
+        self.observed_addresses.insert(connection, observed.clone());
     }
 
-    fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
-        self.observed_addresses.remove(peer_id);
+    fn inject_connection_closed(&mut self, _: &PeerId, connection: ConnectionId, _: &ConnectedPoint) {
+        self.observed_addresses.remove(&connection);
     }
 
     fn inject_node_event(
         &mut self,
         peer_id: PeerId,
+        connection: ConnectionId,
         event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
     ) {
         match event {
@@ -106,10 +111,10 @@ where
                     });
             }
             EitherOutput::First(sender) => {
-                let observed = self.observed_addresses.get(&peer_id)
-                    .expect("We only receive events from nodes we're connected to. We insert \
-                             into the hashmap when we connect to a node and remove only when we \
-                             disconnect; QED");
+                let observed = self.observed_addresses.get(&connection)
+                    .expect("We only receive events from connections that are open. We insert \
+                             into the hashmap when a connection is established and remove only \
+                             when it is closed; QED");
                 self.to_answer.push((sender, observed.clone()));
             }
             EitherOutput::Second(PeriodicIdHandlerEvent::IdentificationError(err)) => {
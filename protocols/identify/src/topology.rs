@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use libp2p_core::{Multiaddr, PeerId};
-use libp2p_core::topology::{MemoryTopology, Topology};
+use libp2p_core::topology::{AddressSource, MemoryTopology, PersistentTopology, Topology};
 
 /// Trait required on the topology for the identify system to store addresses.
 pub trait IdentifyTopology: Topology {
@@ -37,7 +37,18 @@ impl IdentifyTopology for MemoryTopology {
         TIter: Iterator<Item = Multiaddr>,
     {
         for addr in addr {
-            self.add_address(peer.clone(), addr);
+            self.add_address(peer.clone(), addr, AddressSource::Identify);
+        }
+    }
+}
+
+impl IdentifyTopology for PersistentTopology {
+    fn add_identify_discovered_addrs<TIter>(&mut self, peer: &PeerId, addr: TIter)
+    where
+        TIter: Iterator<Item = Multiaddr>,
+    {
+        for addr in addr {
+            self.add_address(peer.clone(), addr, AddressSource::Identify);
         }
     }
 }
@@ -21,26 +21,33 @@
 use fnv::{FnvHashMap, FnvHashSet};
 use futures::{prelude::*, stream};
 use handler::{KademliaHandler, KademliaHandlerEvent, KademliaHandlerIn, KademliaRequestId};
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use kbucket::{BucketsIter, KBucketsPeerId, KBucketsTable, UpdateOutcome};
+use libp2p_core::swarm::{ConnectedPoint, ConnectionId, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p_core::{protocols_handler::ProtocolsHandler, topology::Topology, Multiaddr, PeerId};
 use multihash::Multihash;
-use protocol::{KadConnectionType, KadPeer};
+use protocol::{KadConnectionType, KadPeer, KademliaProtocolConfig};
+use providers::Providers;
 use query::{QueryConfig, QueryState, QueryStatePollOut, QueryTarget};
 use rand;
+use record::{MemoryRecordStore, Record, RecordStore};
+use validation::{AcceptAllValidator, RecordValidator};
 use smallvec::SmallVec;
-use std::{cmp::Ordering, marker::PhantomData, time::Duration, time::Instant};
+use std::{borrow::Cow, cmp::{self, Ordering}, error, fmt, marker::PhantomData, time::Duration, time::Instant};
 use tokio_io::{AsyncRead, AsyncWrite};
-use tokio_timer::Interval;
+use tokio_timer::{Delay, Interval};
 use topology::KademliaTopology;
 
 /// Network behaviour that handles Kademlia.
-pub struct Kademlia<TSubstream> {
+pub struct Kademlia<TSubstream, TRecordStore = MemoryRecordStore> {
     /// Peer ID of the local node.
     local_peer_id: PeerId,
 
-    /// All the iterative queries we are currently performing, with their ID. The last parameter
-    /// is the list of accumulated providers for `GET_PROVIDERS` queries.
-    active_queries: FnvHashMap<QueryId, (QueryState, QueryPurpose, Vec<PeerId>)>,
+    /// All the iterative queries we are currently performing, with their ID.
+    active_queries: FnvHashMap<QueryId, ActiveQuery>,
+
+    /// `PUT_VALUE` requests that we sent to the peers closest to a key, waiting for their
+    /// answers. Indexed by the ID of the query that found these peers.
+    pending_puts: FnvHashMap<QueryId, PendingPut>,
 
     /// List of queries to start once we are inside `poll()`.
     queries_to_starts: SmallVec<[(QueryId, QueryTarget, QueryPurpose); 8]>,
@@ -48,6 +55,24 @@ pub struct Kademlia<TSubstream> {
     /// List of peers the swarm is connected to.
     connected_peers: FnvHashSet<PeerId>,
 
+    /// Routing table of the peers that have recently been responsive.
+    kbuckets: KBucketsTable<PeerId, ()>,
+
+    /// Peers at the head of a full bucket that we are pinging in order to know whether they are
+    /// still alive, with the ID of the ping and the time after which we give up on it.
+    pending_pings: FnvHashMap<PeerId, (QueryId, Instant)>,
+
+    /// Fires at the earliest deadline of `pending_pings`, so that `poll()` gets called when a
+    /// ping times out. `None` if there is no pending ping.
+    ping_timeout: Option<Delay>,
+
+    /// Number of consecutive failed attempts at dialing each peer of the routing table.
+    dial_failures: FnvHashMap<PeerId, usize>,
+
+    /// Number of consecutive dialing failures after which a peer is removed from the routing
+    /// table.
+    max_dial_failures: usize,
+
     /// Contains a list of peer IDs which we are not connected to, and an RPC query to send to them
     /// once they connect.
     pending_rpcs: SmallVec<[(PeerId, KademliaHandlerIn<QueryId>); 8]>,
@@ -55,8 +80,9 @@ pub struct Kademlia<TSubstream> {
     /// Identifier for the next query that we start.
     next_query_id: QueryId,
 
-    /// Requests received by a remote that we should fulfill as soon as possible.
-    remote_requests: SmallVec<[(PeerId, KademliaRequestId, QueryTarget); 4]>,
+    /// Requests received by a remote that we should fulfill as soon as possible, with the
+    /// connection they were received on.
+    remote_requests: SmallVec<[(PeerId, ConnectionId, KademliaRequestId, QueryTarget); 4]>,
 
     /// List of multihashes that we're providing.
     ///
@@ -67,6 +93,42 @@ pub struct Kademlia<TSubstream> {
     /// Interval to send `ADD_PROVIDER` messages to everyone.
     refresh_add_providers: stream::Fuse<Interval>,
 
+    /// Period of `refresh_add_providers`.
+    provider_republish_interval: Duration,
+
+    /// Provider records received from remotes.
+    providers: Providers,
+
+    /// Storage for the records that remotes asked us to store.
+    records: TRecordStore,
+
+    /// Records that we publish ourselves, indexed by their key. Kept apart from `records`, so
+    /// that remotes can neither replace them nor take up the space they need.
+    published: FnvHashMap<Multihash, Record>,
+
+    /// Verifies the records received from remotes, and selects among them.
+    validator: Box<dyn RecordValidator + Send>,
+
+    /// Interval to remove the expired records and provider records, and to publish again the
+    /// records of `published`.
+    republish_records: stream::Fuse<Interval>,
+
+    /// Bootstrap procedures in progress, indexed by the ID of the query that looks up our own ID.
+    bootstraps: FnvHashMap<QueryId, Bootstrap>,
+
+    /// Interval to bootstrap again.
+    refresh_bootstrap: stream::Fuse<Interval>,
+
+    /// Duration after which a record received from a remote expires.
+    record_ttl: Duration,
+
+    /// Number of records to obtain before a `GET_VALUE` query is considered successful.
+    get_quorum: usize,
+
+    /// Number of peers that must have stored a record before a `PUT_VALUE` is considered
+    /// successful.
+    put_quorum: usize,
+
     /// `α` in the Kademlia reference papers. Designates the maximum number of queries that we
     /// perform in parallel.
     parallelism: usize,
@@ -77,15 +139,41 @@ pub struct Kademlia<TSubstream> {
     /// Timeout for each individual RPC query.
     rpc_timeout: Duration,
 
+    /// Timeout for each iterative query as a whole.
+    query_timeout: Duration,
+
+    /// Number of disjoint paths that each iterative query uses.
+    disjoint_paths: usize,
+
+    /// If true, the peers, providers and records found by the queries started by the user are
+    /// reported as they arrive.
+    query_progress: bool,
+
+    /// Configuration of the Kademlia protocol, passed to the handlers.
+    protocol_config: KademliaProtocolConfig,
+
+    /// Whether we accept requests from remotes.
+    mode: KademliaMode,
+
+    /// If true, the handlers that we create accept requests from remotes. Always true in server
+    /// mode, and only becomes true in automatic mode once one of our external addresses has been
+    /// confirmed.
+    is_server: bool,
+
+    /// In automatic mode, the distinct remotes that have successfully dialed us while we were a
+    /// client.
+    dialed_by: FnvHashSet<PeerId>,
+
+    /// Number of distinct remotes that must have dialed us before we switch to server mode in
+    /// automatic mode.
+    auto_mode_confirmations: usize,
+
     /// Events to return when polling.
     queued_events: SmallVec<[NetworkBehaviourAction<KademliaHandlerIn<QueryId>, KademliaOut>; 32]>,
 
     /// List of addresses to add to the topology as soon as we are in `poll()`.
     add_to_topology: SmallVec<[(PeerId, Multiaddr, KadConnectionType); 32]>,
 
-    /// List of providers to add to the topology as soon as we are in `poll()`.
-    add_provider: SmallVec<[(Multihash, PeerId); 32]>,
-
     /// Marker to pin the generics.
     marker: PhantomData<TSubstream>,
 }
@@ -94,67 +182,395 @@ pub struct Kademlia<TSubstream> {
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct QueryId(usize);
 
+/// An iterative query that we are performing.
+#[derive(Debug)]
+struct ActiveQuery {
+    /// The state machine of the query.
+    state: QueryState,
+    /// Reason why we are performing the query.
+    purpose: QueryPurpose,
+    /// Providers accumulated during a `GET_PROVIDERS` query.
+    provider_peers: Vec<PeerId>,
+    /// Records accumulated during a `GET_VALUE` query.
+    records: Vec<Record>,
+    /// Closer peers that have already been reported with a `QueryProgress` event.
+    reported_peers: FnvHashSet<PeerId>,
+}
+
 /// Reason why we have this query in the list of queries.
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum QueryPurpose {
-    /// The query was created for the Kademlia initialization process.
-    Initialization,
+    /// The query is part of the bootstrap procedure with the given ID.
+    Bootstrap(QueryId),
     /// The user requested this query to be performed. It should be reported when finished.
     UserRequest,
     /// We should add an `ADD_PROVIDER` message to the peers of the outcome.
     AddProvider(Multihash),
+    /// We should send a `PUT_VALUE` message to the peers of the outcome, and report the result
+    /// to the user.
+    PutValue(Record),
+    /// Same as `PutValue`, but for a record that we publish again periodically. The result isn't
+    /// reported.
+    RepublishValue(Record),
+}
+
+/// A bootstrap procedure: a lookup of our own ID, followed by a lookup of a random ID in each of
+/// the buckets that are not empty.
+#[derive(Debug)]
+struct Bootstrap {
+    /// Queries of the procedure that haven't finished yet.
+    pending: SmallVec<[QueryId; 8]>,
+    /// Peers found by the queries that have finished.
+    peers: FnvHashSet<PeerId>,
+}
+
+/// `PUT_VALUE` requests sent to the peers closest to a key.
+#[derive(Debug)]
+struct PendingPut {
+    /// Key of the record being stored.
+    key: Multihash,
+    /// Whether the outcome should be reported to the user.
+    report: bool,
+    /// Peers we are still waiting for an answer from.
+    pending: SmallVec<[PeerId; 20]>,
+    /// Peers that have stored the record.
+    success: Vec<PeerId>,
+}
+
+/// Configuration of a `Kademlia` behaviour.
+#[derive(Debug, Clone)]
+pub struct KademliaConfig {
+    /// Configuration of the protocol, including its name.
+    protocol_config: KademliaProtocolConfig,
+    /// Whether we accept requests from remotes.
+    mode: KademliaMode,
+    /// Number of distinct remotes that must dial us before automatic mode switches to server.
+    auto_mode_confirmations: usize,
+    /// `k` in the Kademlia reference papers.
+    k_value: usize,
+    /// `α` in the Kademlia reference papers.
+    parallelism: usize,
+    /// Timeout for each individual RPC query.
+    rpc_timeout: Duration,
+    /// Timeout for each iterative query as a whole.
+    query_timeout: Duration,
+    /// Number of disjoint paths that each iterative query uses.
+    disjoint_paths: usize,
+    /// Whether to report the results of the queries as they arrive.
+    query_progress: bool,
+    /// Interval at which we send `ADD_PROVIDER` messages for the keys we provide.
+    provider_republish_interval: Duration,
+    /// Duration after which a provider record received from a remote expires.
+    provider_ttl: Duration,
+    /// Maximum number of providers stored for a single key.
+    max_providers_per_key: usize,
+    /// Interval at which we publish again the records that we are the publisher of.
+    record_republish_interval: Duration,
+    /// Interval at which we bootstrap again.
+    bootstrap_interval: Duration,
+    /// Duration after which a record received from a remote expires.
+    record_ttl: Duration,
+    /// Number of records to obtain before a `GET_VALUE` query is successful.
+    get_quorum: usize,
+    /// Number of peers that must store a record before a `PUT_VALUE` is successful.
+    put_quorum: usize,
+    /// Number of consecutive dialing failures after which a peer is removed from the routing
+    /// table.
+    max_dial_failures: usize,
+}
+
+/// Whether a `Kademlia` behaviour accepts requests from remotes.
+///
+/// Nodes that can't be reached from the outside, for example because they are behind a NAT, or
+/// that are short-lived should run in client mode, so that they don't end up in the routing
+/// tables of other nodes.
+///
+/// > **Note**: The mode only applies to connections opened after it has been decided. In
+/// >           automatic mode, the connections opened while we were a client stay that way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KademliaMode {
+    /// Only send requests, and refuse the requests of remotes.
+    Client,
+    /// Send requests and answer the requests of remotes.
+    Server,
+    /// Start as a client, and switch to server mode once we know an external address and enough
+    /// distinct remotes have successfully dialed us. See
+    /// `KademliaConfig::auto_mode_confirmations`.
+    Auto,
+}
+
+impl Default for KademliaConfig {
+    fn default() -> Self {
+        KademliaConfig {
+            protocol_config: Default::default(),
+            mode: KademliaMode::Server,
+            auto_mode_confirmations: 3,
+            k_value: 20,
+            parallelism: 3,
+            rpc_timeout: Duration::from_secs(8),
+            query_timeout: Duration::from_secs(60),
+            disjoint_paths: 1,
+            query_progress: false,
+            provider_republish_interval: Duration::from_secs(12 * 60 * 60),
+            provider_ttl: Duration::from_secs(24 * 60 * 60),
+            max_providers_per_key: 20,
+            record_republish_interval: Duration::from_secs(60 * 60),
+            bootstrap_interval: Duration::from_secs(5 * 60),
+            record_ttl: Duration::from_secs(36 * 60 * 60),
+            get_quorum: 1,
+            put_quorum: 1,
+            max_dial_failures: 3,
+        }
+    }
+}
+
+impl KademliaConfig {
+    /// Builds the default `KademliaConfig`.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the name of the protocol negotiated on the wire. The default is `/ipfs/kad/1.0.0`.
+    ///
+    /// Nodes only talk to the nodes that use the same name, which allows running a private DHT
+    /// isolated from the public one.
+    #[inline]
+    pub fn protocol_name(mut self, name: impl Into<Cow<'static, [u8]>>) -> Self {
+        self.protocol_config = self.protocol_config.with_protocol_name(name);
+        self
+    }
+
+    /// Sets `k`, the maximum number of peers in each bucket of the routing table, which is also
+    /// the number of closest peers that queries look for and that we return to the remotes. The
+    /// default is 20. A value of zero is treated as one.
+    #[inline]
+    pub fn k_value(mut self, value: usize) -> Self {
+        self.k_value = cmp::max(value, 1);
+        self
+    }
+
+    /// Sets `α`, the maximum number of RPC queries that a query performs in parallel. The default
+    /// is 3. A value of zero is treated as one.
+    #[inline]
+    pub fn parallelism(mut self, value: usize) -> Self {
+        self.parallelism = cmp::max(value, 1);
+        self
+    }
+
+    /// Sets whether we accept requests from remotes. The default is `KademliaMode::Server`.
+    #[inline]
+    pub fn mode(mut self, value: KademliaMode) -> Self {
+        self.mode = value;
+        self
+    }
+
+    /// Sets the number of distinct remotes that must have successfully dialed us, which confirms
+    /// that we are reachable, before `KademliaMode::Auto` switches to server mode. The default is
+    /// three. A value of zero is treated as one.
+    #[inline]
+    pub fn auto_mode_confirmations(mut self, value: usize) -> Self {
+        self.auto_mode_confirmations = cmp::max(value, 1);
+        self
+    }
+
+    /// Sets the timeout for each individual RPC query. The default is 8 seconds.
+    #[inline]
+    pub fn rpc_timeout(mut self, value: Duration) -> Self {
+        self.rpc_timeout = value;
+        self
+    }
+
+    /// Sets the timeout for each iterative query as a whole. Once it has elapsed, the query is
+    /// finished with the results obtained so far. The default is 60 seconds.
+    #[inline]
+    pub fn query_timeout(mut self, value: Duration) -> Self {
+        self.query_timeout = value;
+        self
+    }
+
+    /// Sets the number of disjoint paths that each iterative query uses, as described in the
+    /// S/Kademlia paper. A peer is only ever queried as part of one path, and the results of all
+    /// the paths are merged. The default is one, which corresponds to regular Kademlia. A value
+    /// of zero is treated as one.
+    #[inline]
+    pub fn disjoint_paths(mut self, value: usize) -> Self {
+        self.disjoint_paths = cmp::max(value, 1);
+        self
+    }
+
+    /// If enabled, each peer, provider or record found by a query started with `find_node`,
+    /// `get_providers` or `get_value` is reported with a `QueryProgress` event as soon as it
+    /// arrives, in addition to the final result. Disabled by default.
+    #[inline]
+    pub fn query_progress(mut self, enabled: bool) -> Self {
+        self.query_progress = enabled;
+        self
+    }
+
+    /// Sets the interval at which we send `ADD_PROVIDER` messages for the keys registered with
+    /// `add_providing`. Should be lower than the provider TTL of the remotes. The default is 12
+    /// hours. A value of zero is treated as one millisecond.
+    #[inline]
+    pub fn provider_republish_interval(mut self, value: Duration) -> Self {
+        self.provider_republish_interval = cmp::max(value, Duration::from_millis(1));
+        self
+    }
+
+    /// Sets the duration after which the provider records received from remotes expire. The
+    /// default is 24 hours.
+    #[inline]
+    pub fn provider_ttl(mut self, value: Duration) -> Self {
+        self.provider_ttl = value;
+        self
+    }
+
+    /// Sets the maximum number of providers that we store for a single key. The provider records
+    /// received for a key that already has this many providers are ignored. The default is 20.
+    #[inline]
+    pub fn max_providers_per_key(mut self, value: usize) -> Self {
+        self.max_providers_per_key = value;
+        self
+    }
+
+    /// Sets the interval at which we publish again the records that we are the publisher of.
+    /// The default is one hour. A value of zero is treated as one millisecond.
+    #[inline]
+    pub fn record_republish_interval(mut self, value: Duration) -> Self {
+        self.record_republish_interval = cmp::max(value, Duration::from_millis(1));
+        self
+    }
+
+    /// Sets the interval at which we automatically bootstrap again, in order to keep the routing
+    /// table up to date. The default is 5 minutes. A value of zero is treated as one millisecond.
+    #[inline]
+    pub fn bootstrap_interval(mut self, value: Duration) -> Self {
+        self.bootstrap_interval = cmp::max(value, Duration::from_millis(1));
+        self
+    }
+
+    /// Sets the duration after which the records received from remotes expire. The default is
+    /// 36 hours.
+    #[inline]
+    pub fn record_ttl(mut self, value: Duration) -> Self {
+        self.record_ttl = value;
+        self
+    }
+
+    /// Sets the number of records to obtain before a `GET_VALUE` query is successful. The
+    /// default is one. A value of zero is treated as one.
+    #[inline]
+    pub fn get_quorum(mut self, value: usize) -> Self {
+        self.get_quorum = cmp::max(value, 1);
+        self
+    }
+
+    /// Sets the number of peers that must store a record before a `PUT_VALUE` is successful. The
+    /// default is one. A value of zero is treated as one.
+    #[inline]
+    pub fn put_quorum(mut self, value: usize) -> Self {
+        self.put_quorum = cmp::max(value, 1);
+        self
+    }
+
+    /// Sets the number of consecutive dialing failures after which a peer is removed from the
+    /// routing table. The default is three. A value of zero is treated as one.
+    #[inline]
+    pub fn max_dial_failures(mut self, value: usize) -> Self {
+        self.max_dial_failures = cmp::max(value, 1);
+        self
+    }
 }
 
 impl<TSubstream> Kademlia<TSubstream> {
     /// Creates a `Kademlia`.
     #[inline]
     pub fn new(local_peer_id: PeerId) -> Self {
-        Self::new_inner(local_peer_id, true)
+        Self::with_config(local_peer_id, Default::default())
     }
 
     /// Creates a `Kademlia`.
     ///
-    /// Contrary to `new`, doesn't perform the initialization queries that store our local ID into
-    /// the DHT.
+    /// Contrary to `new`, doesn't bootstrap immediately. The periodic bootstrap still happens, and
+    /// `bootstrap` can be called at any time.
     #[inline]
     pub fn without_init(local_peer_id: PeerId) -> Self {
-        Self::new_inner(local_peer_id, false)
+        Self::new_inner(local_peer_id, false, Default::default(), Default::default())
     }
 
-    /// Inner implementation of the constructors.
-    fn new_inner(local_peer_id: PeerId, initialize: bool) -> Self {
-        let parallelism = 3;
+    /// Creates a `Kademlia` with the given configuration.
+    #[inline]
+    pub fn with_config(local_peer_id: PeerId, config: KademliaConfig) -> Self {
+        Self::new_inner(local_peer_id, true, config, Default::default())
+    }
+}
+
+impl<TSubstream, TRecordStore> Kademlia<TSubstream, TRecordStore>
+where
+    TRecordStore: RecordStore,
+{
+    /// Creates a `Kademlia` with the given configuration, that stores its records in the given
+    /// `RecordStore`.
+    #[inline]
+    pub fn with_record_store(local_peer_id: PeerId, config: KademliaConfig, records: TRecordStore) -> Self {
+        Self::new_inner(local_peer_id, true, config, records)
+    }
+
+    /// Sets the validator of the records received from remotes. By default, all the records are
+    /// accepted.
+    #[inline]
+    pub fn with_record_validator(mut self, validator: impl RecordValidator + Send + 'static) -> Self {
+        self.validator = Box::new(validator);
+        self
+    }
 
+    /// Inner implementation of the constructors.
+    fn new_inner(local_peer_id: PeerId, initialize: bool, config: KademliaConfig, records: TRecordStore) -> Self {
         let mut behaviour = Kademlia {
             local_peer_id: local_peer_id.clone(),
             queued_events: SmallVec::new(),
             queries_to_starts: SmallVec::new(),
             active_queries: Default::default(),
+            pending_puts: Default::default(),
             connected_peers: Default::default(),
-            pending_rpcs: SmallVec::with_capacity(parallelism),
+            kbuckets: KBucketsTable::new(local_peer_id.clone(), config.rpc_timeout, config.k_value),
+            pending_pings: Default::default(),
+            ping_timeout: None,
+            dial_failures: Default::default(),
+            max_dial_failures: config.max_dial_failures,
+            pending_rpcs: SmallVec::with_capacity(config.parallelism),
             next_query_id: QueryId(0),
             remote_requests: SmallVec::new(),
             providing_keys: SmallVec::new(),
-            refresh_add_providers: Interval::new_interval(Duration::from_secs(60)).fuse(),     // TODO: constant
-            parallelism,
-            num_results: 20,
-            rpc_timeout: Duration::from_secs(8),
+            refresh_add_providers: Interval::new_interval(config.provider_republish_interval).fuse(),
+            provider_republish_interval: config.provider_republish_interval,
+            providers: Providers::new(config.provider_ttl, config.max_providers_per_key),
+            records,
+            published: Default::default(),
+            validator: Box::new(AcceptAllValidator),
+            republish_records: Interval::new_interval(config.record_republish_interval).fuse(),
+            bootstraps: Default::default(),
+            refresh_bootstrap: Interval::new_interval(config.bootstrap_interval).fuse(),
+            record_ttl: config.record_ttl,
+            get_quorum: config.get_quorum,
+            put_quorum: config.put_quorum,
+            parallelism: config.parallelism,
+            num_results: config.k_value,
+            rpc_timeout: config.rpc_timeout,
+            query_timeout: config.query_timeout,
+            disjoint_paths: config.disjoint_paths,
+            query_progress: config.query_progress,
+            protocol_config: config.protocol_config,
+            mode: config.mode,
+            is_server: config.mode == KademliaMode::Server,
+            dialed_by: Default::default(),
+            auto_mode_confirmations: config.auto_mode_confirmations,
             add_to_topology: SmallVec::new(),
-            add_provider: SmallVec::new(),
             marker: PhantomData,
         };
 
         if initialize {
-            // As part of the initialization process, we start one `FIND_NODE` for each bit of the
-            // possible range of peer IDs.
-            for n in 0..256 {
-                let peer_id = match gen_random_id(&local_peer_id, n) {
-                    Ok(p) => p,
-                    Err(()) => continue,
-                };
-
-                behaviour.start_query(QueryTarget::FindPeer(peer_id), QueryPurpose::Initialization);
-            }
+            behaviour.bootstrap();
         }
 
         behaviour
@@ -170,7 +586,7 @@ impl<TSubstream> Kademlia<TSubstream> {
     }
 
     /// Builds the answer to a request.
-    fn build_result<TUserData, TTopology>(&self, query: QueryTarget, request_id: KademliaRequestId, parameters: &mut PollParameters<TTopology>)
+    fn build_result<TUserData, TTopology>(&mut self, query: QueryTarget, request_id: KademliaRequestId, parameters: &mut PollParameters<TTopology>)
         -> KademliaHandlerIn<TUserData>
     where TTopology: KademliaTopology
     {
@@ -178,10 +594,11 @@ impl<TSubstream> Kademlia<TSubstream> {
 
         match query {
             QueryTarget::FindPeer(key) => {
-                let mut topology = parameters.topology();
+                let topology = parameters.topology();
                 // TODO: insert local_kad_peer somewhere?
-                let closer_peers = topology
-                    .closest_peers(key.as_ref(), self.num_results)
+                let closer_peers = self
+                    .closest_peers(key.as_ref(), topology)
+                    .into_iter()
                     .map(|peer_id| build_kad_peer(peer_id, topology, &self.connected_peers))
                     .collect();
 
@@ -191,18 +608,19 @@ impl<TSubstream> Kademlia<TSubstream> {
                 }
             },
             QueryTarget::GetProviders(key) => {
-                let mut topology = parameters.topology();
+                let topology = parameters.topology();
                 // TODO: insert local_kad_peer somewhere?
-                let closer_peers = topology
-                    .closest_peers(&key, self.num_results)
+                let closer_peers = self
+                    .closest_peers(&key, topology)
+                    .into_iter()
                     .map(|peer_id| build_kad_peer(peer_id, topology, &self.connected_peers))
                     .collect();
 
                 let local_node_is_providing = self.providing_keys.iter().any(|k| k.as_ref() == &key);
 
-                let provider_peers = topology
-                    .get_providers(&key)
-                    .map(|peer_id| build_kad_peer(peer_id, topology, &self.connected_peers))
+                let provider_peers = self.providers
+                    .get(&key, Instant::now())
+                    .map(|peer_id| build_kad_peer(peer_id.clone(), topology, &self.connected_peers))
                     .chain(if local_node_is_providing {
                         Some(local_kad_peer)
                     } else {
@@ -216,24 +634,220 @@ impl<TSubstream> Kademlia<TSubstream> {
                     request_id,
                 }
             },
+            QueryTarget::GetValue(key) => {
+                let topology = parameters.topology();
+                let closer_peers = self
+                    .closest_peers(&key, topology)
+                    .into_iter()
+                    .map(|peer_id| build_kad_peer(peer_id, topology, &self.connected_peers))
+                    .collect();
+
+                let record = self.local_record(&key);
+
+                KademliaHandlerIn::GetValueRes {
+                    record,
+                    closer_peers,
+                    request_id,
+                }
+            },
+        }
+    }
+
+    /// Returns the record we hold for the given key, either because we published it or because a
+    /// remote asked us to store it.
+    fn local_record(&self, key: &Multihash) -> Option<Record> {
+        if let Some(record) = self.published.get(key) {
+            return Some(record.clone());
+        }
+
+        let now = Instant::now();
+        self.records
+            .get(key)
+            .filter(|record| !record.is_expired(now))
+            .map(|record| record.into_owned())
+    }
+
+    /// Stores a record that a remote sent us with a `PUT_VALUE`. Returns false if the record is
+    /// invalid, if we publish a record with the same key ourselves, or if the store refuses it.
+    fn store_remote_record(&mut self, mut record: Record) -> bool {
+        if self.published.contains_key(&record.key) || self.validator.validate(&record).is_err() {
+            return false;
+        }
+
+        record.expires = Some(Instant::now() + self.record_ttl);
+        self.records.put(record).is_ok()
+    }
+
+    /// Sends a `PUT_VALUE` message to each of the `peers`, and registers a `PendingPut` for the
+    /// answers.
+    fn send_put_value(&mut self, query_id: QueryId, record: Record, peers: impl Iterator<Item = PeerId>, report: bool) {
+        let mut put = PendingPut {
+            key: record.key.clone(),
+            report,
+            pending: SmallVec::new(),
+            success: Vec::new(),
+        };
+
+        for peer_id in peers {
+            put.pending.push(peer_id.clone());
+            self.queued_events.push(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                connection: None,
+                event: KademliaHandlerIn::PutValueReq {
+                    record: record.clone(),
+                    user_data: query_id,
+                },
+            });
+        }
+
+        self.pending_puts.insert(query_id, put);
+        self.finish_put_if_done(query_id);
+    }
+
+    /// Reports the outcome of a `PUT_VALUE` if all the peers have answered or if the quorum has
+    /// been reached.
+    fn finish_put_if_done(&mut self, query_id: QueryId) {
+        let done = match self.pending_puts.get(&query_id) {
+            Some(put) => put.pending.is_empty() || put.success.len() >= self.put_quorum,
+            None => false,
+        };
+
+        if !done {
+            return;
+        }
+
+        let put = self.pending_puts.remove(&query_id)
+            .expect("we just checked that the entry exists; QED");
+        if put.report {
+            let result = if put.success.len() >= self.put_quorum {
+                Ok(put.success)
+            } else {
+                Err(PutValueError::QuorumFailed {
+                    success: put.success,
+                    quorum: self.put_quorum,
+                })
+            };
+
+            self.queued_events.push(NetworkBehaviourAction::GenerateEvent(
+                KademliaOut::PutValueResult { id: query_id, key: put.key, result }
+            ));
+        }
+    }
+
+    /// Processes the answer of `source` to the `GET_VALUE` request of the query `user_data`.
+    fn inject_get_value_res(&mut self, source: PeerId, record: Option<Record>, closer_peers: Vec<KadPeer>, user_data: QueryId) {
+        for peer in closer_peers.iter() {
+            for addr in peer.multiaddrs.iter() {
+                self.add_to_topology
+                    .push((peer.node_id.clone(), addr.clone(), peer.connection_ty));
+            }
+        }
+        // It is possible that we obtain a response for a query that has finished, which is
+        // why we may not find an entry in `self.active_queries`.
+        let record = record.filter(|record| {
+            let expected = self.active_queries
+                .get(&user_data)
+                .map(|query| &record.key == query.state.target().as_hash())
+                .unwrap_or(false);
+            expected && self.validator.validate(record).is_ok()
+        });
+        self.report_progress(user_data, closer_peers.iter()
+            .map(|peer| QueryProgress::CloserPeer(peer.node_id.clone()))
+            .chain(record.clone().map(QueryProgress::Record)));
+        if let Some(query) = self.active_queries.get_mut(&user_data) {
+            if let Some(record) = record {
+                query.records.push(record);
+            }
+            query.state.inject_rpc_result(&source, closer_peers.into_iter().map(|kp| kp.node_id))
+        }
+    }
+
+    /// Marks `source` as having stored the record of the `PUT_VALUE` `user_data`.
+    fn inject_put_value_res(&mut self, source: PeerId, user_data: QueryId) {
+        if let Some(put) = self.pending_puts.get_mut(&user_data) {
+            if let Some(pos) = put.pending.iter().position(|p| p == &source) {
+                put.pending.remove(pos);
+                put.success.push(source);
+            }
+        }
+        self.finish_put_if_done(user_data);
+    }
+
+    /// Marks `peer_id` as having failed to answer the `PUT_VALUE` requests that were sent to it.
+    fn inject_put_failure(&mut self, peer_id: &PeerId) {
+        let failed = self.pending_puts
+            .iter_mut()
+            .filter_map(|(query_id, put)| {
+                let pos = put.pending.iter().position(|p| p == peer_id)?;
+                put.pending.remove(pos);
+                Some(*query_id)
+            })
+            .collect::<SmallVec<[_; 8]>>();
+
+        for query_id in failed {
+            self.finish_put_if_done(query_id);
         }
     }
 }
 
-impl<TSubstream> Kademlia<TSubstream> {
+impl<TSubstream, TRecordStore> Kademlia<TSubstream, TRecordStore>
+where
+    TRecordStore: RecordStore,
+{
     /// Starts an iterative `FIND_NODE` request.
     ///
     /// This will eventually produce an event containing the nodes of the DHT closest to the
-    /// requested `PeerId`.
+    /// requested `PeerId`, with the returned `QueryId`.
     #[inline]
-    pub fn find_node(&mut self, peer_id: PeerId) {
-        self.start_query(QueryTarget::FindPeer(peer_id), QueryPurpose::UserRequest);
+    pub fn find_node(&mut self, peer_id: PeerId) -> QueryId {
+        self.start_query(QueryTarget::FindPeer(peer_id), QueryPurpose::UserRequest)
     }
 
     /// Starts an iterative `GET_PROVIDERS` request.
+    ///
+    /// This will eventually produce a `GetProvidersResult` event with the returned `QueryId`.
     #[inline]
-    pub fn get_providers(&mut self, key: Multihash) {
-        self.start_query(QueryTarget::GetProviders(key), QueryPurpose::UserRequest);
+    pub fn get_providers(&mut self, key: Multihash) -> QueryId {
+        self.start_query(QueryTarget::GetProviders(key), QueryPurpose::UserRequest)
+    }
+
+    /// Cancels a query started with `find_node`, `get_providers`, `get_value` or `put_value`.
+    ///
+    /// No result is produced for a cancelled query, and the answers that arrive later are
+    /// ignored. Returns `false` if the query had already finished.
+    pub fn cancel_query(&mut self, id: QueryId) -> bool {
+        let mut found = false;
+        if let Some(pos) = self.queries_to_starts.iter().position(|(query_id, _, _)| *query_id == id) {
+            self.queries_to_starts.remove(pos);
+            found = true;
+        }
+        found |= self.active_queries.remove(&id).is_some();
+        found |= self.pending_puts.remove(&id).is_some();
+        found |= self.bootstraps.remove(&id).is_some();
+
+        // The RPCs of the query that are waiting for a peer to connect are no longer needed.
+        self.pending_rpcs.retain(|(_, rpc)| rpc_query_id(rpc) != Some(id));
+        found
+    }
+
+    /// Bootstraps the local node: looks up our own ID, then refreshes each bucket of the routing
+    /// table that isn't empty by looking up a random ID that belongs to it.
+    ///
+    /// This will eventually produce a `BootstrapResult` event with the returned `QueryId`. This
+    /// is done automatically when the behaviour is created with `new`, and periodically
+    /// afterwards.
+    pub fn bootstrap(&mut self) -> QueryId {
+        let id = self.next_query_id;
+        let target = QueryTarget::FindPeer(self.local_peer_id.clone());
+        self.start_query(target, QueryPurpose::Bootstrap(id));
+
+        let mut pending = SmallVec::new();
+        pending.push(id);
+        self.bootstraps.insert(id, Bootstrap {
+            pending,
+            peers: Default::default(),
+        });
+        id
     }
 
     /// Register the local node as the provider for the given key.
@@ -250,7 +864,7 @@ impl<TSubstream> Kademlia<TSubstream> {
         }
 
         // Trigger the next refresh now.
-        self.refresh_add_providers = Interval::new(Instant::now(), Duration::from_secs(60)).fuse();
+        self.refresh_add_providers = Interval::new(Instant::now(), self.provider_republish_interval).fuse();
     }
 
     /// Cancels a registration done with `add_providing`.
@@ -263,175 +877,355 @@ impl<TSubstream> Kademlia<TSubstream> {
         }
     }
 
+    /// Starts an iterative `GET_VALUE` request.
+    ///
+    /// This will eventually produce a `GetValueResult` event containing the records found, with
+    /// the returned `QueryId`. The query stops as soon as the number of records configured with
+    /// `KademliaConfig::get_quorum` has been obtained. A record held by the local node counts
+    /// towards this number.
+    #[inline]
+    pub fn get_value(&mut self, key: Multihash) -> QueryId {
+        self.start_query(QueryTarget::GetValue(key), QueryPurpose::UserRequest)
+    }
+
+    /// Stores a record in the DHT.
+    ///
+    /// The record is stored locally, then sent with `PUT_VALUE` messages to the nodes closest to
+    /// the key. This will eventually produce a `PutValueResult` event with the returned `QueryId`.
+    /// The record is published again periodically, until `remove_published` is called.
+    ///
+    /// The records that we publish are kept apart from the `RecordStore`. They never expire, and
+    /// the `PUT_VALUE` messages of remotes can't replace them.
+    ///
+    /// > **Note**: `FIND_NODE` messages can only target peer IDs, therefore the nodes closest to
+    /// >           the key are found with a `GET_VALUE` iterative request.
+    pub fn put_value(&mut self, key: Multihash, value: Vec<u8>) -> QueryId {
+        self.put_value_inner(key, value, None)
+    }
+
+    /// Same as `put_value`, but the record is signed.
+    ///
+    /// `signature` must be the signature by the local node of the `signing_payload()` of a
+    /// `Record` with the same key and value, and whose publisher is the local peer ID.
+    #[inline]
+    pub fn put_signed_value(&mut self, key: Multihash, value: Vec<u8>, signature: Vec<u8>) -> QueryId {
+        self.put_value_inner(key, value, Some(signature))
+    }
+
+    /// Inner implementation of `put_value` and `put_signed_value`.
+    fn put_value_inner(&mut self, key: Multihash, value: Vec<u8>, signature: Option<Vec<u8>>) -> QueryId {
+        let mut record = Record::new(key, value);
+        record.publisher = Some(self.local_peer_id.clone());
+        record.signature = signature;
+
+        // A record received from a remote for the same key is now superseded by ours.
+        self.records.remove(&record.key);
+        self.published.insert(record.key.clone(), record.clone());
+
+        let target = QueryTarget::GetValue(record.key.clone());
+        self.start_query(target, QueryPurpose::PutValue(record))
+    }
+
+    /// Stops publishing the record with the given key, if we publish one.
+    ///
+    /// The record is no longer published again periodically or returned to remotes. The copies
+    /// that remotes have already stored remain until they expire.
+    pub fn remove_published(&mut self, key: &Multihash) -> Option<Record> {
+        self.published.remove(key)
+    }
+
+    /// Returns the records that we publish ourselves with `put_value`.
+    #[inline]
+    pub fn published(&self) -> impl Iterator<Item = &Record> {
+        self.published.values()
+    }
+
+    /// Returns the store of the records that remotes asked us to store.
+    #[inline]
+    pub fn records(&self) -> &TRecordStore {
+        &self.records
+    }
+
+    /// Returns the store of the records that remotes asked us to store.
+    #[inline]
+    pub fn records_mut(&mut self) -> &mut TRecordStore {
+        &mut self.records
+    }
+
+    /// Returns true if we currently accept requests from remotes. See `KademliaMode`.
+    #[inline]
+    pub fn is_server(&self) -> bool {
+        self.is_server
+    }
+
+    /// Returns an iterator to the buckets of the routing table, ordered by proximity to the local
+    /// node.
+    #[inline]
+    pub fn kbuckets(&mut self) -> BucketsIter<'_, PeerId, ()> {
+        self.kbuckets.buckets()
+    }
+
+    /// Adds a peer that we know how to reach to the routing table, for example a bootstrap node.
+    /// The addresses are added to the topology the next time the behaviour is polled.
+    ///
+    /// If the bucket of the peer is full, the peer is only inserted if the oldest node of the
+    /// bucket doesn't answer a ping.
+    pub fn add_bootstrap_node(&mut self, peer_id: PeerId, addresses: impl IntoIterator<Item = Multiaddr>) {
+        for addr in addresses {
+            self.add_to_topology.push((peer_id.clone(), addr, KadConnectionType::NotConnected));
+        }
+        self.update_kbuckets(peer_id);
+    }
+
+    /// Removes a peer from the routing table. Returns `true` if the peer was in it.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) -> bool {
+        self.pending_pings.remove(peer_id);
+        self.dial_failures.remove(peer_id);
+        self.kbuckets.remove(peer_id).is_some()
+    }
+
+    /// Returns the `k` peers of the routing table that are the closest to `key`, ordered by
+    /// distance. Doesn't perform any network query.
+    pub fn closest_known_peers(&mut self, key: &Multihash) -> impl Iterator<Item = PeerId> {
+        self.kbuckets.find_closest(key).take(self.num_results)
+    }
+
     /// Internal function that starts a query.
-    fn start_query(&mut self, target: QueryTarget, purpose: QueryPurpose) {
+    fn start_query(&mut self, target: QueryTarget, purpose: QueryPurpose) -> QueryId {
         let query_id = self.next_query_id.clone();
         self.next_query_id.0 += 1;
         self.queries_to_starts.push((query_id, target, purpose));
+        query_id
     }
-}
-
-impl<TSubstream, TTopology> NetworkBehaviour<TTopology> for Kademlia<TSubstream>
-where
-    TSubstream: AsyncRead + AsyncWrite,
-    TTopology: KademliaTopology,
-{
-    type ProtocolsHandler = KademliaHandler<TSubstream, QueryId>;
-    type OutEvent = KademliaOut;
 
-    fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        KademliaHandler::dial_and_listen()
-    }
+    /// Called when a query that is part of the bootstrap procedure `bootstrap_id` has finished.
+    fn inject_bootstrap_query_finished(
+        &mut self,
+        bootstrap_id: QueryId,
+        query_id: QueryId,
+        peers: impl Iterator<Item = PeerId>,
+    ) {
+        let mut refresh_targets = Vec::new();
+        {
+            let bootstrap = match self.bootstraps.get_mut(&bootstrap_id) {
+                Some(bootstrap) => bootstrap,
+                None => return,
+            };
+            bootstrap.pending.retain(|id| *id != query_id);
+            bootstrap.peers.extend(peers);
+
+            // Once our own ID has been looked up, we refresh the buckets that aren't empty.
+            if query_id == bootstrap_id {
+                for (n, bucket) in self.kbuckets.buckets().enumerate() {
+                    if bucket.num_entries() == 0 {
+                        continue;
+                    }
+                    if let Ok(target) = gen_random_id(&self.local_peer_id, n) {
+                        refresh_targets.push(target);
+                    }
+                }
+            }
+        }
 
-    fn inject_connected(&mut self, id: PeerId, _: ConnectedPoint) {
-        if let Some(pos) = self.pending_rpcs.iter().position(|(p, _)| p == &id) {
-            let (_, rpc) = self.pending_rpcs.remove(pos);
-            self.queued_events.push(NetworkBehaviourAction::SendEvent {
-                peer_id: id.clone(),
-                event: rpc,
-            });
+        for target in refresh_targets {
+            let id = self.start_query(QueryTarget::FindPeer(target), QueryPurpose::Bootstrap(bootstrap_id));
+            if let Some(bootstrap) = self.bootstraps.get_mut(&bootstrap_id) {
+                bootstrap.pending.push(id);
+            }
         }
 
-        self.connected_peers.insert(id);
+        let finished = self.bootstraps
+            .get(&bootstrap_id)
+            .map(|bootstrap| bootstrap.pending.is_empty())
+            .unwrap_or(false);
+        if finished {
+            let bootstrap = self.bootstraps.remove(&bootstrap_id)
+                .expect("we just checked that the entry exists; QED");
+            let event = KademliaOut::BootstrapResult {
+                id: bootstrap_id,
+                num_peers: bootstrap.peers.len(),
+            };
+            self.queued_events.push(NetworkBehaviourAction::GenerateEvent(event));
+        }
     }
 
-    fn inject_disconnected(&mut self, id: &PeerId, _: ConnectedPoint) {
-        let was_in = self.connected_peers.remove(id);
-        debug_assert!(was_in);
+    /// Reports the peers, providers and records obtained by a query started by the user, if
+    /// progress events are enabled. Closer peers that have already been reported are skipped.
+    fn report_progress(&mut self, id: QueryId, progress: impl IntoIterator<Item = QueryProgress>) {
+        if !self.query_progress {
+            return;
+        }
 
-        for (query, _, _) in self.active_queries.values_mut() {
-            query.inject_rpc_error(id);
+        let query = match self.active_queries.get_mut(&id) {
+            Some(query) => query,
+            None => return,
+        };
+        if query.purpose != QueryPurpose::UserRequest {
+            return;
         }
-    }
 
-    fn inject_node_event(&mut self, source: PeerId, event: KademliaHandlerEvent<QueryId>) {
-        match event {
-            KademliaHandlerEvent::FindNodeReq { key, request_id } => {
-                self.remote_requests.push((source, request_id, QueryTarget::FindPeer(key)));
-                return;
-            }
-            KademliaHandlerEvent::FindNodeRes {
-                closer_peers,
-                user_data,
-            } => {
-                // It is possible that we obtain a response for a query that has finished, which is
-                // why we may not find an entry in `self.active_queries`.
-                for peer in closer_peers.iter() {
-                    for addr in peer.multiaddrs.iter() {
-                        self.add_to_topology
-                            .push((peer.node_id.clone(), addr.clone(), peer.connection_ty));
-                    }
-                }
-                if let Some((query, _, _)) = self.active_queries.get_mut(&user_data) {
-                    query.inject_rpc_result(&source, closer_peers.into_iter().map(|kp| kp.node_id))
-                }
-            }
-            KademliaHandlerEvent::GetProvidersReq { key, request_id } => {
-                self.remote_requests.push((source, request_id, QueryTarget::GetProviders(key)));
-                return;
-            }
-            KademliaHandlerEvent::GetProvidersRes {
-                closer_peers,
-                provider_peers,
-                user_data,
-            } => {
-                for peer in closer_peers.iter().chain(provider_peers.iter()) {
-                    for addr in peer.multiaddrs.iter() {
-                        self.add_to_topology
-                            .push((peer.node_id.clone(), addr.clone(), peer.connection_ty));
-                    }
-                }
-                // It is possible that we obtain a response for a query that has finished, which is
-                // why we may not find an entry in `self.active_queries`.
-                if let Some((query, _, providers)) = self.active_queries.get_mut(&user_data) {
-                    for peer in provider_peers {
-                        providers.push(peer.node_id);
-                    }
-                    query.inject_rpc_result(&source, closer_peers.into_iter().map(|kp| kp.node_id))
-                }
-            }
-            KademliaHandlerEvent::QueryError { user_data, .. } => {
-                // It is possible that we obtain a response for a query that has finished, which is
-                // why we may not find an entry in `self.active_queries`.
-                if let Some((query, _, _)) = self.active_queries.get_mut(&user_data) {
-                    query.inject_rpc_error(&source)
-                }
-            }
-            KademliaHandlerEvent::AddProvider { key, provider_peer } => {
-                for addr in provider_peer.multiaddrs.iter() {
-                    self.add_to_topology
-                        .push((provider_peer.node_id.clone(), addr.clone(), provider_peer.connection_ty));
-                }
-                self.add_provider.push((key, provider_peer.node_id));
-                return;
+        for progress in progress {
+            let is_new = match &progress {
+                QueryProgress::CloserPeer(peer) => query.reported_peers.insert(peer.clone()),
+                QueryProgress::Provider(peer) => !query.provider_peers.contains(peer),
+                QueryProgress::Record(_) => true,
+            };
+            if is_new {
+                let event = KademliaOut::QueryProgress { id, progress };
+                self.queued_events.push(NetworkBehaviourAction::GenerateEvent(event));
             }
-        };
-    }
-
-    fn poll(
-        &mut self,
-        parameters: &mut PollParameters<TTopology>,
-    ) -> Async<
-        NetworkBehaviourAction<
-            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
-            Self::OutEvent,
-        >,
-    > {
-        // Flush the changes to the topology that we want to make.
-        for (peer_id, addr, connection_ty) in self.add_to_topology.drain() {
-            parameters.topology().add_kad_discovered_address(peer_id, addr, connection_ty);
         }
-        self.add_to_topology.shrink_to_fit();
-        for (key, provider) in self.add_provider.drain() {
-            parameters.topology().add_provider(key, provider);
-        }
-        self.add_provider.shrink_to_fit();
+    }
 
-        // Handle `refresh_add_providers`.
-        match self.refresh_add_providers.poll() {
-            Ok(Async::NotReady) => {},
-            Ok(Async::Ready(Some(_))) => {
-                for provided in self.providing_keys.clone().into_iter() {
-                    let purpose = QueryPurpose::AddProvider(provided.as_ref().clone());
-                    self.start_query(QueryTarget::FindPeer(provided), purpose);
+    /// Returns the `k` peers closest to `target`. The peers of the routing table come first, and
+    /// the topology is used to complete the list if necessary.
+    fn closest_peers<TTopology>(&mut self, target: &Multihash, topology: &mut TTopology) -> Vec<PeerId>
+    where
+        TTopology: KademliaTopology,
+    {
+        let mut peers = self.kbuckets
+            .find_closest(target)
+            .take(self.num_results)
+            .collect::<Vec<_>>();
+
+        if peers.len() < self.num_results {
+            for peer in topology.closest_peers(target, self.num_results) {
+                if !peers.contains(&peer) {
+                    peers.push(peer);
                 }
-            },
-            // Ignore errors.
-            Ok(Async::Ready(None)) | Err(_) => {},
+            }
+            peers.sort_by(|a, b| b.distance_with(target).cmp(&a.distance_with(target)));
+            peers.truncate(self.num_results);
         }
 
-        // Start queries that are waiting to start.
-        for (query_id, query_target, query_purpose) in self.queries_to_starts.drain() {
-            let known_closest_peers = parameters
-                .topology()
-                .closest_peers(query_target.as_hash(), self.num_results);
+        peers
+    }
+
+    /// Starts the queries that are waiting in `queries_to_starts`.
+    fn start_queries<TTopology>(&mut self, topology: &mut TTopology)
+    where
+        TTopology: KademliaTopology,
+    {
+        let queries_to_starts = self.queries_to_starts.drain().collect::<Vec<_>>();
+        for (query_id, query_target, query_purpose) in queries_to_starts {
+            let known_closest_peers = self.closest_peers(query_target.as_hash(), topology);
+
+            // A record that we hold ourselves counts towards the quorum of a `GET_VALUE`.
+            let mut records = Vec::new();
+            if let (QueryTarget::GetValue(key), QueryPurpose::UserRequest) = (&query_target, &query_purpose) {
+                records.extend(self.local_record(key));
+            }
+
             self.active_queries.insert(
                 query_id,
-                (
-                    QueryState::new(QueryConfig {
+                ActiveQuery {
+                    state: QueryState::new(QueryConfig {
                         target: query_target,
                         parallelism: self.parallelism,
                         num_results: self.num_results,
                         rpc_timeout: self.rpc_timeout,
+                        timeout: self.query_timeout,
+                        disjoint_paths: self.disjoint_paths,
                         known_closest_peers,
                     }),
-                    query_purpose,
-                    Vec::new()      // TODO: insert ourselves if we provide the data?
-                )
+                    purpose: query_purpose,
+                    provider_peers: Vec::new(),      // TODO: insert ourselves if we provide the data?
+                    records,
+                    reported_peers: Default::default(),
+                }
             );
         }
         self.queries_to_starts.shrink_to_fit();
+    }
 
-        // Handle remote queries.
-        if !self.remote_requests.is_empty() {
-            let (peer_id, request_id, query) = self.remote_requests.remove(0);
-            let result = self.build_result(query, request_id, parameters);
-            return Async::Ready(NetworkBehaviourAction::SendEvent {
-                peer_id,
-                event: result,
+    /// Records that `peer` has shown signs of life. If its bucket is full, pings the oldest
+    /// node of the bucket in order to know whether it should be replaced.
+    fn update_kbuckets(&mut self, peer: PeerId) {
+        if let UpdateOutcome::NeedPing(oldest) = self.kbuckets.update(peer, ()) {
+            self.ping(oldest);
+        }
+    }
+
+    /// Sends a `PING` to `peer`, dialing it if necessary. If it doesn't answer, it is removed
+    /// from the routing table.
+    fn ping(&mut self, peer: PeerId) {
+        if self.pending_pings.contains_key(&peer) {
+            return;
+        }
+
+        let ping_id = self.next_query_id;
+        self.next_query_id.0 += 1;
+        self.pending_pings.insert(peer.clone(), (ping_id, Instant::now() + self.rpc_timeout));
+
+        let rpc = KademliaHandlerIn::PingReq { user_data: ping_id };
+        if self.connected_peers.contains(&peer) {
+            self.queued_events.push(NetworkBehaviourAction::SendEvent {
+                peer_id: peer,
+                connection: None,
+                event: rpc,
             });
+        } else {
+            self.pending_rpcs.push((peer.clone(), rpc));
+            self.queued_events.push(NetworkBehaviourAction::DialPeer { peer_id: peer });
         }
+    }
 
+    /// Removes from the routing table the peers whose ping has timed out, and makes sure that
+    /// the current task is woken up at the next ping deadline.
+    fn poll_ping_timeouts(&mut self) {
+        // Peers that didn't answer a ping in time are considered dead. The routing table itself
+        // replaces them with the pending nodes once the timeout has elapsed.
+        loop {
+            let now = Instant::now();
+            let timed_out = self.pending_pings
+                .iter()
+                .filter(|(_, (_, deadline))| *deadline <= now)
+                .map(|(peer, _)| peer.clone())
+                .collect::<Vec<_>>();
+            for peer in timed_out {
+                self.inject_ping_failure(&peer);
+            }
+
+            let next_deadline = match self.pending_pings.values().map(|(_, deadline)| *deadline).min() {
+                Some(deadline) => deadline,
+                None => {
+                    self.ping_timeout = None;
+                    break;
+                }
+            };
+            let ping_timeout = self.ping_timeout.get_or_insert_with(|| Delay::new(next_deadline));
+            if ping_timeout.deadline() != next_deadline {
+                ping_timeout.reset(next_deadline);
+            }
+            match ping_timeout.poll() {
+                Ok(Async::NotReady) => break,
+                Ok(Async::Ready(())) => {},
+                // Without a working timer, the timeouts are only checked when we are polled.
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// In automatic mode, starts accepting requests once we know an external address and enough
+    /// distinct remotes have dialed us, which confirms that we are reachable.
+    fn update_mode(&mut self, has_external_address: bool) {
+        if self.mode != KademliaMode::Auto || self.is_server {
+            return;
+        }
+
+        if has_external_address && self.dialed_by.len() >= self.auto_mode_confirmations {
+            self.is_server = true;
+            self.dialed_by = Default::default();
+        }
+    }
+
+    /// Returns the events queued by other parts of this struct, drives the active queries, and
+    /// processes the queries that have finished. `external_addresses` returns the addresses of
+    /// the local node that we advertise in `ADD_PROVIDER` messages.
+    fn poll_queries(
+        &mut self,
+        mut external_addresses: impl FnMut() -> Vec<Multiaddr>,
+    ) -> Async<NetworkBehaviourAction<KademliaHandlerIn<QueryId>, KademliaOut>> {
         loop {
             // Handle events queued by other parts of this struct
             if !self.queued_events.is_empty() {
@@ -442,9 +1236,15 @@ where
             // If iterating finds a query that is finished, stores it here and stops looping.
             let mut finished_query = None;
 
-            'queries_iter: for (&query_id, (query, _, _)) in self.active_queries.iter_mut() {
+            'queries_iter: for (&query_id, query) in self.active_queries.iter_mut() {
+                // A `GET_VALUE` is finished as soon as we have enough records.
+                if query.purpose == QueryPurpose::UserRequest && query.records.len() >= self.get_quorum {
+                    finished_query = Some(query_id);
+                    break 'queries_iter;
+                }
+
                 loop {
-                    match query.poll() {
+                    match query.state.poll() {
                         Async::Ready(QueryStatePollOut::Finished) => {
                             finished_query = Some(query_id);
                             break 'queries_iter;
@@ -457,6 +1257,7 @@ where
                             if self.connected_peers.contains(&peer_id) {
                                 return Async::Ready(NetworkBehaviourAction::SendEvent {
                                     peer_id: peer_id.clone(),
+                                    connection: None,
                                     event: rpc,
                                 });
                             } else {
@@ -476,28 +1277,50 @@ where
             }
 
             if let Some(finished_query) = finished_query {
-                let (query, purpose, provider_peers) = self
+                let ActiveQuery { state: query, purpose, provider_peers, records, .. } = self
                     .active_queries
                     .remove(&finished_query)
                     .expect("finished_query was gathered when iterating active_queries; QED.");
                 match purpose {
-                    QueryPurpose::Initialization => {},
+                    QueryPurpose::Bootstrap(bootstrap_id) => {
+                        let peers = query.into_closest_peers();
+                        self.inject_bootstrap_query_finished(bootstrap_id, finished_query, peers);
+                    },
                     QueryPurpose::UserRequest => {
                         let event = match query.target().clone() {
                             QueryTarget::FindPeer(key) => {
                                 debug_assert!(provider_peers.is_empty());
                                 KademliaOut::FindNodeResult {
+                                    id: finished_query,
                                     key,
                                     closer_peers: query.into_closest_peers().collect(),
                                 }
                             },
                             QueryTarget::GetProviders(key) => {
                                 KademliaOut::GetProvidersResult {
+                                    id: finished_query,
                                     key,
                                     closer_peers: query.into_closest_peers().collect(),
                                     provider_peers,
                                 }
                             },
+                            QueryTarget::GetValue(key) => {
+                                let result = if records.is_empty() {
+                                    Err(GetValueError::NotFound)
+                                } else if records.len() < self.get_quorum {
+                                    Err(GetValueError::QuorumFailed {
+                                        records,
+                                        quorum: self.get_quorum,
+                                    })
+                                } else {
+                                    let mut records = records;
+                                    let best = self.validator.select(&records);
+                                    records.swap(0, best);
+                                    Ok(records)
+                                };
+
+                                KademliaOut::GetValueResult { id: finished_query, key, result }
+                            },
                         };
 
                         break Async::Ready(NetworkBehaviourAction::GenerateEvent(event));
@@ -506,21 +1329,335 @@ where
                         for closest in query.into_closest_peers() {
                             let event = NetworkBehaviourAction::SendEvent {
                                 peer_id: closest,
+                                connection: None,
                                 event: KademliaHandlerIn::AddProvider {
                                     key: key.clone(),
-                                    provider_peer: self.build_local_kad_peer(parameters.external_addresses()),
+                                    provider_peer: self.build_local_kad_peer(external_addresses()),
                                 },
                             };
 
                             self.queued_events.push(event);
                         }
                     },
+                    QueryPurpose::PutValue(record) => {
+                        self.send_put_value(finished_query, record, query.into_closest_peers(), true);
+                    },
+                    QueryPurpose::RepublishValue(record) => {
+                        self.send_put_value(finished_query, record, query.into_closest_peers(), false);
+                    },
                 }
             } else {
                 break Async::NotReady;
             }
         }
     }
+
+    /// Removes `peer` from the routing table because it failed to answer a ping. The node that
+    /// was waiting for a place in its bucket, if any, takes its place.
+    fn inject_ping_failure(&mut self, peer: &PeerId) {
+        if self.pending_pings.remove(peer).is_some() {
+            self.dial_failures.remove(peer);
+            self.kbuckets.remove(peer);
+        }
+    }
+}
+
+impl<TSubstream, TTopology, TRecordStore> NetworkBehaviour<TTopology> for Kademlia<TSubstream, TRecordStore>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+    TTopology: KademliaTopology,
+    TRecordStore: RecordStore,
+{
+    type ProtocolsHandler = KademliaHandler<TSubstream, QueryId>;
+    type OutEvent = KademliaOut;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        let handler = if self.is_server {
+            KademliaHandler::dial_and_listen()
+        } else {
+            KademliaHandler::dial_only()
+        };
+
+        handler.with_protocol_config(self.protocol_config.clone())
+    }
+
+    fn inject_connected(&mut self, id: PeerId, endpoint: ConnectedPoint) {
+        self.dial_failures.remove(&id);
+
+        // A remote that managed to dial us confirms that we are reachable.
+        if self.mode == KademliaMode::Auto && !self.is_server {
+            if let ConnectedPoint::Listener { .. } = endpoint {
+                self.dialed_by.insert(id.clone());
+            }
+        }
+
+        while let Some(pos) = self.pending_rpcs.iter().position(|(p, _)| p == &id) {
+            let (_, rpc) = self.pending_rpcs.remove(pos);
+            self.queued_events.push(NetworkBehaviourAction::SendEvent {
+                peer_id: id.clone(),
+                connection: None,
+                event: rpc,
+            });
+        }
+
+        self.connected_peers.insert(id);
+    }
+
+    fn inject_disconnected(&mut self, id: &PeerId, _: ConnectedPoint) {
+        let was_in = self.connected_peers.remove(id);
+        debug_assert!(was_in);
+        self.dial_failures.remove(id);
+
+        for query in self.active_queries.values_mut() {
+            query.state.inject_rpc_error(id);
+        }
+        self.inject_put_failure(id);
+        self.inject_ping_failure(id);
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        // The RPCs waiting for this peer to connect will never be sent.
+        self.pending_rpcs.retain(|(id, _)| id != peer_id);
+
+        for query in self.active_queries.values_mut() {
+            query.state.inject_rpc_error(peer_id);
+        }
+        self.inject_put_failure(peer_id);
+        self.inject_ping_failure(peer_id);
+
+        // Peers that we repeatedly fail to reach are removed from the routing table. The failures
+        // are only counted for the peers of the routing table, which bounds their number.
+        if !self.kbuckets.contains(peer_id) {
+            self.dial_failures.remove(peer_id);
+            return;
+        }
+        let failures = {
+            let failures = self.dial_failures.entry(peer_id.clone()).or_insert(0);
+            *failures += 1;
+            *failures
+        };
+        if failures >= self.max_dial_failures {
+            self.dial_failures.remove(peer_id);
+            self.kbuckets.remove(peer_id);
+        }
+    }
+
+    fn inject_node_event(&mut self, source: PeerId, connection: ConnectionId, event: KademliaHandlerEvent<QueryId>) {
+        // An answer to one of our requests shows that the remote is alive and accepts requests.
+        // Peers that only send us requests might be clients, and aren't put in the routing table.
+        match event {
+            KademliaHandlerEvent::FindNodeRes { .. } |
+            KademliaHandlerEvent::GetProvidersRes { .. } |
+            KademliaHandlerEvent::GetValueRes { .. } |
+            KademliaHandlerEvent::PutValueRes { .. } |
+            KademliaHandlerEvent::PingRes { .. } => self.update_kbuckets(source.clone()),
+            _ => {},
+        }
+
+        match event {
+            KademliaHandlerEvent::PingReq { request_id } => {
+                // The request id is only valid for the connection that received the request.
+                self.queued_events.push(NetworkBehaviourAction::SendEvent {
+                    peer_id: source,
+                    connection: Some(connection),
+                    event: KademliaHandlerIn::PingRes { request_id },
+                });
+            }
+            KademliaHandlerEvent::PingRes { user_data } => {
+                if self.pending_pings.get(&source).map(|(id, _)| *id) == Some(user_data) {
+                    self.pending_pings.remove(&source);
+                }
+            }
+            KademliaHandlerEvent::FindNodeReq { key, request_id } => {
+                self.remote_requests.push((source, connection, request_id, QueryTarget::FindPeer(key)));
+                return;
+            }
+            KademliaHandlerEvent::FindNodeRes {
+                closer_peers,
+                user_data,
+            } => {
+                // It is possible that we obtain a response for a query that has finished, which is
+                // why we may not find an entry in `self.active_queries`.
+                for peer in closer_peers.iter() {
+                    for addr in peer.multiaddrs.iter() {
+                        self.add_to_topology
+                            .push((peer.node_id.clone(), addr.clone(), peer.connection_ty));
+                    }
+                }
+                self.report_progress(user_data, closer_peers.iter()
+                    .map(|peer| QueryProgress::CloserPeer(peer.node_id.clone())));
+                if let Some(query) = self.active_queries.get_mut(&user_data) {
+                    query.state.inject_rpc_result(&source, closer_peers.into_iter().map(|kp| kp.node_id))
+                }
+            }
+            KademliaHandlerEvent::GetProvidersReq { key, request_id } => {
+                self.remote_requests.push((source, connection, request_id, QueryTarget::GetProviders(key)));
+                return;
+            }
+            KademliaHandlerEvent::GetProvidersRes {
+                closer_peers,
+                provider_peers,
+                user_data,
+            } => {
+                for peer in closer_peers.iter().chain(provider_peers.iter()) {
+                    for addr in peer.multiaddrs.iter() {
+                        self.add_to_topology
+                            .push((peer.node_id.clone(), addr.clone(), peer.connection_ty));
+                    }
+                }
+                self.report_progress(user_data, closer_peers.iter()
+                    .map(|peer| QueryProgress::CloserPeer(peer.node_id.clone()))
+                    .chain(provider_peers.iter().map(|peer| QueryProgress::Provider(peer.node_id.clone()))));
+                // It is possible that we obtain a response for a query that has finished, which is
+                // why we may not find an entry in `self.active_queries`.
+                if let Some(query) = self.active_queries.get_mut(&user_data) {
+                    for peer in provider_peers {
+                        if !query.provider_peers.contains(&peer.node_id) {
+                            query.provider_peers.push(peer.node_id);
+                        }
+                    }
+                    query.state.inject_rpc_result(&source, closer_peers.into_iter().map(|kp| kp.node_id))
+                }
+            }
+            KademliaHandlerEvent::QueryError { user_data, .. } => {
+                if self.pending_pings.get(&source).map(|(id, _)| *id) == Some(user_data) {
+                    self.inject_ping_failure(&source);
+                }
+                // It is possible that we obtain a response for a query that has finished, which is
+                // why we may not find an entry in `self.active_queries`.
+                if let Some(query) = self.active_queries.get_mut(&user_data) {
+                    query.state.inject_rpc_error(&source)
+                }
+                if let Some(put) = self.pending_puts.get_mut(&user_data) {
+                    put.pending.retain(|p| p != &source);
+                }
+                self.finish_put_if_done(user_data);
+            }
+            KademliaHandlerEvent::AddProvider { key, provider_peer } => {
+                // Only the provider itself is allowed to announce that it is providing a key.
+                if provider_peer.node_id != source {
+                    return;
+                }
+                if !self.providers.add(key, provider_peer.node_id.clone(), Instant::now()) {
+                    return;
+                }
+                for addr in provider_peer.multiaddrs.iter() {
+                    self.add_to_topology
+                        .push((provider_peer.node_id.clone(), addr.clone(), provider_peer.connection_ty));
+                }
+                return;
+            }
+            KademliaHandlerEvent::GetValueReq { key, request_id } => {
+                self.remote_requests.push((source, connection, request_id, QueryTarget::GetValue(key)));
+                return;
+            }
+            KademliaHandlerEvent::GetValueRes {
+                record,
+                closer_peers,
+                user_data,
+            } => {
+                self.inject_get_value_res(source, record, closer_peers, user_data);
+            }
+            KademliaHandlerEvent::PutValueReq { record, request_id } => {
+                let key = record.key.clone();
+                let value = record.value.clone();
+                let answer = if self.store_remote_record(record) {
+                    KademliaHandlerIn::PutValueRes { key, value, request_id }
+                } else {
+                    KademliaHandlerIn::Reset(request_id)
+                };
+                // The request id is only valid for the connection that received the request.
+                self.queued_events.push(NetworkBehaviourAction::SendEvent {
+                    peer_id: source,
+                    connection: Some(connection),
+                    event: answer,
+                });
+            }
+            KademliaHandlerEvent::PutValueRes { user_data, .. } => {
+                self.inject_put_value_res(source, user_data);
+            }
+        };
+    }
+
+    fn poll(
+        &mut self,
+        parameters: &mut PollParameters<TTopology>,
+    ) -> Async<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        let has_external_address = parameters.external_addresses().len() != 0;
+        self.update_mode(has_external_address);
+
+        // Flush the changes to the topology that we want to make.
+        for (peer_id, addr, connection_ty) in self.add_to_topology.drain() {
+            parameters.topology().add_kad_discovered_address(peer_id, addr, connection_ty);
+        }
+        self.add_to_topology.shrink_to_fit();
+
+        self.poll_ping_timeouts();
+
+        // Handle `republish_records`.
+        match self.republish_records.poll() {
+            Ok(Async::NotReady) => {},
+            Ok(Async::Ready(Some(_))) => {
+                let now = Instant::now();
+                self.records.remove_expired(now);
+                self.providers.remove_expired(now);
+                let to_republish = self.published.values().cloned().collect::<Vec<_>>();
+                for record in to_republish {
+                    let target = QueryTarget::GetValue(record.key.clone());
+                    self.start_query(target, QueryPurpose::RepublishValue(record));
+                }
+            },
+            // Ignore errors.
+            Ok(Async::Ready(None)) | Err(_) => {},
+        }
+
+        // Handle `refresh_bootstrap`.
+        match self.refresh_bootstrap.poll() {
+            Ok(Async::NotReady) => {},
+            Ok(Async::Ready(Some(_))) => {
+                if self.bootstraps.is_empty() {
+                    self.bootstrap();
+                }
+            },
+            // Ignore errors.
+            Ok(Async::Ready(None)) | Err(_) => {},
+        }
+
+        // Handle `refresh_add_providers`.
+        match self.refresh_add_providers.poll() {
+            Ok(Async::NotReady) => {},
+            Ok(Async::Ready(Some(_))) => {
+                for provided in self.providing_keys.clone().into_iter() {
+                    let purpose = QueryPurpose::AddProvider(provided.as_ref().clone());
+                    self.start_query(QueryTarget::FindPeer(provided), purpose);
+                }
+            },
+            // Ignore errors.
+            Ok(Async::Ready(None)) | Err(_) => {},
+        }
+
+        // Start queries that are waiting to start.
+        self.start_queries(parameters.topology());
+
+        // Handle remote queries.
+        if !self.remote_requests.is_empty() {
+            let (peer_id, connection, request_id, query) = self.remote_requests.remove(0);
+            let result = self.build_result(query, request_id, parameters);
+            // The request id is only valid for the connection that received the request.
+            return Async::Ready(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                connection: Some(connection),
+                event: result,
+            });
+        }
+
+        self.poll_queries(|| parameters.external_addresses().collect())
+    }
 }
 
 /// Output event of the `Kademlia` behaviour.
@@ -528,6 +1665,8 @@ where
 pub enum KademliaOut {
     /// Result of a `FIND_NODE` iterative query.
     FindNodeResult {
+        /// The query, as returned by `find_node`.
+        id: QueryId,
         /// The key that we looked for in the query.
         key: PeerId,
         /// List of peers ordered from closest to furthest away.
@@ -536,6 +1675,8 @@ pub enum KademliaOut {
 
     /// Result of a `GET_PROVIDERS` iterative query.
     GetProvidersResult {
+        /// The query, as returned by `get_providers`.
+        id: QueryId,
         /// The key that we looked for in the query.
         key: Multihash,
         /// The peers that are providing the requested key.
@@ -543,8 +1684,108 @@ pub enum KademliaOut {
         /// List of peers ordered from closest to furthest away.
         closer_peers: Vec<PeerId>,
     },
+
+    /// Result of a `GET_VALUE` iterative query.
+    GetValueResult {
+        /// The query, as returned by `get_value`.
+        id: QueryId,
+        /// The key that we looked for in the query.
+        key: Multihash,
+        /// The records found, or the reason why the query failed. The record selected by the
+        /// `RecordValidator` comes first.
+        result: Result<Vec<Record>, GetValueError>,
+    },
+
+    /// Result of a `PUT_VALUE` operation started with `put_value`.
+    PutValueResult {
+        /// The operation, as returned by `put_value`.
+        id: QueryId,
+        /// The key of the record.
+        key: Multihash,
+        /// The peers that have stored the record, or the reason why the operation failed.
+        result: Result<Vec<PeerId>, PutValueError>,
+    },
+
+    /// A bootstrap procedure has finished.
+    BootstrapResult {
+        /// The procedure, as returned by `bootstrap`.
+        id: QueryId,
+        /// Number of distinct peers found by the lookups of the procedure.
+        num_peers: usize,
+    },
+
+    /// Something was found by a query that hasn't finished yet. Only produced if enabled with
+    /// `KademliaConfig::query_progress`.
+    QueryProgress {
+        /// The query, as returned by `find_node`, `get_providers` or `get_value`.
+        id: QueryId,
+        /// What was found.
+        progress: QueryProgress,
+    },
+}
+
+/// Something found by a query that hasn't finished yet.
+#[derive(Debug, Clone)]
+pub enum QueryProgress {
+    /// A peer that a remote reported as being close to the target.
+    CloserPeer(PeerId),
+    /// A peer providing the key of a `GET_PROVIDERS` query.
+    Provider(PeerId),
+    /// A record found by a `GET_VALUE` query.
+    Record(Record),
+}
+
+/// Error that can happen during a `GET_VALUE` iterative query.
+#[derive(Debug, Clone)]
+pub enum GetValueError {
+    /// No record was found.
+    NotFound,
+    /// Some records were found, but fewer than the configured quorum.
+    QuorumFailed {
+        /// The records that were found.
+        records: Vec<Record>,
+        /// The quorum that wasn't reached.
+        quorum: usize,
+    },
+}
+
+impl fmt::Display for GetValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GetValueError::NotFound => write!(f, "No record found for the key"),
+            GetValueError::QuorumFailed { records, quorum } => {
+                write!(f, "Found {} records, but the quorum is {}", records.len(), quorum)
+            },
+        }
+    }
+}
+
+impl error::Error for GetValueError {}
+
+/// Error that can happen when storing a record with `put_value`.
+#[derive(Debug, Clone)]
+pub enum PutValueError {
+    /// Fewer peers than the configured quorum have stored the record.
+    QuorumFailed {
+        /// The peers that have stored the record.
+        success: Vec<PeerId>,
+        /// The quorum that wasn't reached.
+        quorum: usize,
+    },
+}
+
+impl fmt::Display for PutValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PutValueError::QuorumFailed { success, quorum } => {
+                write!(f, "The record was stored by {} peers, but the quorum is {}", success.len(), quorum)
+            },
+        }
+    }
 }
 
+impl error::Error for PutValueError {}
+
 // Generates a random `PeerId` that belongs to the given bucket.
 //
 // Returns an error if `bucket_num` is out of range.
@@ -573,6 +1814,11 @@ fn gen_random_id(my_id: &PeerId, bucket_num: usize) -> Result<PeerId, ()> {
         }
     }
 
+    // The highest random bit must differ from ours, otherwise the ID belongs to a closer bucket.
+    let top_byte = my_id_len - bucket_num / 8 - 1;
+    let top_bit = 1 << (bucket_num % 8);
+    random_id[top_byte] = (random_id[top_byte] & !top_bit) | (!my_id.as_bytes()[top_byte] & top_bit);
+
     let peer_id = PeerId::from_bytes(random_id[..my_id_len].to_owned())
         .expect("randomly-generated peer ID should always be valid");
     Ok(peer_id)
@@ -599,3 +1845,584 @@ where TTopology: Topology
         connection_ty,
     }
 }
+
+/// Returns the query that an RPC request was sent for, or `None` if the message isn't a request
+/// of ours.
+fn rpc_query_id(rpc: &KademliaHandlerIn<QueryId>) -> Option<QueryId> {
+    match rpc {
+        KademliaHandlerIn::PingReq { user_data } |
+        KademliaHandlerIn::FindNodeReq { user_data, .. } |
+        KademliaHandlerIn::GetProvidersReq { user_data, .. } |
+        KademliaHandlerIn::GetValueReq { user_data, .. } |
+        KademliaHandlerIn::PutValueReq { user_data, .. } => Some(*user_data),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::PeerId;
+    use multihash::{Hash, Multihash};
+    use record::{MemoryRecordStore, Record, RecordStore};
+    use futures::{future, Async, Poll};
+    use handler::KademliaHandlerIn;
+    use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction};
+    use libp2p_core::topology::MemoryTopology;
+    use libp2p_core::PublicKey;
+    use std::{cmp, time::{Duration, Instant}};
+    use kbucket::KBucketsPeerId;
+    use super::{GetValueError, Kademlia, KademliaConfig, KademliaMode, KademliaOut, PutValueError, QueryProgress, QueryPurpose};
+    use super::{gen_random_id, rpc_query_id};
+    use tokio::net::TcpStream;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Timeout;
+
+    fn build_kademlia(local_peer_id: PeerId, max_records: usize) -> Kademlia<TcpStream, MemoryRecordStore> {
+        let store = MemoryRecordStore::new(max_records, 1024);
+        Kademlia::with_record_store(local_peer_id, KademliaConfig::default(), store)
+    }
+
+    #[test]
+    fn remote_records_do_not_replace_published_ones() {
+        let mut kad = build_kademlia(PeerId::random(), 16);
+        let key = Multihash::random(Hash::SHA2256);
+        kad.put_value(key.clone(), vec![1]);
+
+        let mut remote = Record::new(key.clone(), vec![2]);
+        remote.publisher = Some(PeerId::random());
+        assert!(!kad.store_remote_record(remote));
+        assert!(kad.records().get(&key).is_none());
+
+        let record = kad.local_record(&key).unwrap();
+        assert_eq!(record.value, vec![1]);
+        assert_eq!(record.expires, None);
+    }
+
+    #[test]
+    fn remote_records_do_not_take_up_published_capacity() {
+        let mut kad = build_kademlia(PeerId::random(), 1);
+        assert!(kad.store_remote_record(Record::new(Multihash::random(Hash::SHA2256), vec![1])));
+        assert!(!kad.store_remote_record(Record::new(Multihash::random(Hash::SHA2256), vec![2])));
+
+        let key = Multihash::random(Hash::SHA2256);
+        kad.put_value(key.clone(), vec![3]);
+        assert!(kad.queued_events.is_empty());
+        assert_eq!(kad.published().map(|r| r.key.clone()).collect::<Vec<_>>(), vec![key.clone()]);
+        assert_eq!(kad.local_record(&key).unwrap().value, vec![3]);
+
+        assert!(kad.remove_published(&key).is_some());
+        assert!(kad.local_record(&key).is_none());
+    }
+
+    #[test]
+    fn remote_records_claiming_our_peer_id_are_not_published() {
+        let local_peer_id = PeerId::random();
+        let mut kad = build_kademlia(local_peer_id.clone(), 16);
+        let mut remote = Record::new(Multihash::random(Hash::SHA2256), vec![1]);
+        remote.publisher = Some(local_peer_id);
+        assert!(kad.store_remote_record(remote.clone()));
+
+        assert_eq!(kad.published().count(), 0);
+        let stored = kad.records().get(&remote.key).unwrap().into_owned();
+        assert!(stored.expires.is_some());
+    }
+
+    #[test]
+    fn provider_settings_come_from_the_config() {
+        let config = KademliaConfig::new()
+            .provider_ttl(Duration::from_secs(10))
+            .max_providers_per_key(1)
+            .provider_republish_interval(Duration::from_secs(5));
+        let kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+        assert_eq!(kad.provider_republish_interval, Duration::from_secs(5));
+
+        let mut providers = kad.providers;
+        let key = Multihash::random(Hash::SHA2256);
+        let now = Instant::now();
+        assert!(providers.add(key.clone(), PeerId::random(), now));
+        assert!(!providers.add(key.clone(), PeerId::random(), now));
+        assert_eq!(providers.get(&key, now + Duration::from_secs(10)).count(), 0);
+    }
+
+    #[test]
+    fn zero_republish_intervals_are_clamped() {
+        let config = KademliaConfig::new()
+            .provider_republish_interval(Duration::from_secs(0))
+            .record_republish_interval(Duration::from_secs(0));
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+        assert_eq!(kad.provider_republish_interval, Duration::from_millis(1));
+        kad.add_providing(PeerId::random());
+    }
+
+    #[test]
+    fn unresponsive_peer_is_evicted_after_ping_timeout() {
+        let config = KademliaConfig::new().k_value(1).rpc_timeout(Duration::from_millis(50));
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+
+        // Fill buckets until a new peer lands in a full one, which pings its current node.
+        let mut pending = PeerId::random();
+        kad.update_kbuckets(pending.clone());
+        while kad.pending_pings.is_empty() {
+            pending = PeerId::random();
+            kad.update_kbuckets(pending.clone());
+        }
+        let pinged = kad.pending_pings.keys().next().unwrap().clone();
+        assert!(kad.kbuckets.contains(&pinged));
+
+        // The timer wakes us up once the ping has timed out, without any other event.
+        let evicted = future::poll_fn(|| -> Result<_, ()> {
+            kad.poll_ping_timeouts();
+            if kad.pending_pings.is_empty() {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        });
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(Timeout::new(evicted, Duration::from_secs(5))).unwrap();
+
+        assert!(kad.ping_timeout.is_none());
+        assert!(!kad.kbuckets.contains(&pinged));
+        assert!(kad.kbuckets.contains(&pending));
+    }
+
+    #[test]
+    fn dial_failures_are_only_counted_for_the_routing_table() {
+        let config = KademliaConfig::new().max_dial_failures(2);
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+
+        let stranger = PeerId::random();
+        NetworkBehaviour::<MemoryTopology>::inject_dial_failure(&mut kad, &stranger);
+        assert!(kad.dial_failures.is_empty());
+
+        let known = PeerId::random();
+        kad.update_kbuckets(known.clone());
+        NetworkBehaviour::<MemoryTopology>::inject_dial_failure(&mut kad, &known);
+        assert_eq!(kad.dial_failures.get(&known), Some(&1));
+        NetworkBehaviour::<MemoryTopology>::inject_dial_failure(&mut kad, &known);
+        assert!(kad.dial_failures.is_empty());
+        assert!(!kad.kbuckets.contains(&known));
+    }
+
+    #[test]
+    fn routing_table_entries_can_be_managed_manually() {
+        let config = KademliaConfig::new().k_value(2);
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+        assert_eq!(kad.kbuckets().map(|b| b.num_entries()).sum::<usize>(), 0);
+
+        let peers = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        for peer in peers.iter() {
+            kad.add_bootstrap_node(peer.clone(), vec!["/ip4/1.2.3.4/tcp/5".parse().unwrap()]);
+        }
+        assert_eq!(kad.add_to_topology.len(), 3);
+        let entries = kad.kbuckets().flat_map(|b| b.entries().cloned().collect::<Vec<_>>()).collect::<Vec<_>>();
+        for peer in entries.iter() {
+            assert!(peers.contains(peer));
+        }
+
+        // At most `k` peers are returned, ordered by distance to the key.
+        let key = peers[0].clone().into();
+        let closest = kad.closest_known_peers(&key).collect::<Vec<_>>();
+        assert_eq!(closest.len(), cmp::min(entries.len(), 2));
+        assert_eq!(closest[0], peers[0]);
+
+        assert!(kad.remove_peer(&peers[0]));
+        assert!(!kad.remove_peer(&peers[0]));
+        assert!(kad.closest_known_peers(&key).all(|p| p != peers[0]));
+    }
+
+    #[test]
+    fn cancelled_query_drops_its_pending_rpcs() {
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), KademliaConfig::default());
+        let mut topology = MemoryTopology::empty(PublicKey::Ed25519(vec![1; 32]));
+        let cancelled = kad.find_node(PeerId::random());
+        let other = kad.find_node(PeerId::random());
+        kad.start_queries(&mut topology);
+
+        let peer = PeerId::random();
+        for &id in &[cancelled, other] {
+            let rpc = KademliaHandlerIn::FindNodeReq { key: PeerId::random(), user_data: id };
+            kad.pending_rpcs.push((peer.clone(), rpc));
+        }
+
+        assert!(kad.cancel_query(cancelled));
+        assert!(!kad.active_queries.contains_key(&cancelled));
+        assert_eq!(kad.pending_rpcs.len(), 1);
+        assert_eq!(rpc_query_id(&kad.pending_rpcs[0].1), Some(other));
+
+        // Cancelling a query that doesn't exist any more is a no-op.
+        assert!(!kad.cancel_query(cancelled));
+        assert!(kad.active_queries.contains_key(&other));
+        assert_eq!(kad.pending_rpcs.len(), 1);
+
+        // Queries can also be cancelled before they start.
+        let not_started = kad.get_providers(Multihash::random(Hash::SHA2256));
+        assert!(kad.cancel_query(not_started));
+        assert!(kad.queries_to_starts.is_empty());
+    }
+
+    #[test]
+    fn query_progress_is_reported_once_per_peer() {
+        let config = KademliaConfig::new().query_progress(true);
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+        let mut topology = MemoryTopology::empty(PublicKey::Ed25519(vec![1; 32]));
+        let id = kad.find_node(PeerId::random());
+        let bootstrap = kad.bootstrap();
+        kad.start_queries(&mut topology);
+        kad.queued_events.clear();
+
+        let peer = PeerId::random();
+        kad.report_progress(id, vec![QueryProgress::CloserPeer(peer.clone())]);
+        kad.report_progress(id, vec![QueryProgress::CloserPeer(peer.clone())]);
+        assert_eq!(kad.queued_events.len(), 1);
+        match &kad.queued_events[0] {
+            NetworkBehaviourAction::GenerateEvent(KademliaOut::QueryProgress {
+                id: reported,
+                progress: QueryProgress::CloserPeer(reported_peer),
+            }) => {
+                assert_eq!(*reported, id);
+                assert_eq!(*reported_peer, peer);
+            },
+            _ => panic!("unexpected event"),
+        }
+
+        // Only the queries started by the user report their progress.
+        kad.report_progress(bootstrap, vec![QueryProgress::CloserPeer(PeerId::random())]);
+        assert_eq!(kad.queued_events.len(), 1);
+    }
+
+    #[test]
+    fn query_progress_is_disabled_by_default() {
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), KademliaConfig::default());
+        let mut topology = MemoryTopology::empty(PublicKey::Ed25519(vec![1; 32]));
+        let id = kad.find_node(PeerId::random());
+        kad.start_queries(&mut topology);
+        kad.queued_events.clear();
+
+        kad.report_progress(id, vec![QueryProgress::CloserPeer(PeerId::random())]);
+        assert!(kad.queued_events.is_empty());
+    }
+
+    fn listener_endpoint() -> ConnectedPoint {
+        ConnectedPoint::Listener {
+            listen_addr: "/ip4/0.0.0.0/tcp/4001".parse().unwrap(),
+            send_back_addr: "/ip4/1.2.3.4/tcp/5678".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn auto_mode_switches_to_server_once_reachability_is_confirmed() {
+        let config = KademliaConfig::new().mode(KademliaMode::Auto).auto_mode_confirmations(2);
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+        assert!(!kad.is_server());
+
+        // Connections that we opened ourselves don't show that we are reachable.
+        let dialer = ConnectedPoint::Dialer { address: "/ip4/1.2.3.4/tcp/5678".parse().unwrap() };
+        NetworkBehaviour::<MemoryTopology>::inject_connected(&mut kad, PeerId::random(), dialer);
+        kad.update_mode(true);
+        assert!(!kad.is_server());
+
+        // The same remote dialing us twice only counts once.
+        let remote = PeerId::random();
+        NetworkBehaviour::<MemoryTopology>::inject_connected(&mut kad, remote.clone(), listener_endpoint());
+        NetworkBehaviour::<MemoryTopology>::inject_connected(&mut kad, remote, listener_endpoint());
+        kad.update_mode(true);
+        assert!(!kad.is_server());
+
+        NetworkBehaviour::<MemoryTopology>::inject_connected(&mut kad, PeerId::random(), listener_endpoint());
+        kad.update_mode(true);
+        assert!(kad.is_server());
+        assert!(kad.dialed_by.is_empty());
+    }
+
+    #[test]
+    fn auto_mode_requires_an_external_address() {
+        let config = KademliaConfig::new().mode(KademliaMode::Auto).auto_mode_confirmations(1);
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+        NetworkBehaviour::<MemoryTopology>::inject_connected(&mut kad, PeerId::random(), listener_endpoint());
+        kad.update_mode(false);
+        assert!(!kad.is_server());
+        kad.update_mode(true);
+        assert!(kad.is_server());
+    }
+
+    #[test]
+    fn client_mode_never_switches_to_server() {
+        let config = KademliaConfig::new().mode(KademliaMode::Client).auto_mode_confirmations(1);
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+        NetworkBehaviour::<MemoryTopology>::inject_connected(&mut kad, PeerId::random(), listener_endpoint());
+        kad.update_mode(true);
+        assert!(!kad.is_server());
+        assert!(kad.dialed_by.is_empty());
+    }
+
+    #[test]
+    fn random_ids_belong_to_the_requested_bucket() {
+        let local_peer_id = PeerId::random();
+        for _ in 0..64 {
+            let peer = PeerId::random();
+            let bucket = 255 - (local_peer_id.distance_with(&peer) as usize - 256);
+            let target = gen_random_id(&local_peer_id, bucket).unwrap();
+            assert_eq!(local_peer_id.distance_with(&target), local_peer_id.distance_with(&peer));
+        }
+        assert!(gen_random_id(&local_peer_id, 256).is_err());
+    }
+
+    #[test]
+    fn bootstrap_refreshes_non_empty_buckets() {
+        let local_peer_id = PeerId::random();
+        let mut kad: Kademlia<TcpStream> = Kademlia::without_init(local_peer_id.clone());
+        let known = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+        for peer in known.iter() {
+            kad.update_kbuckets(peer.clone());
+        }
+        let non_empty_buckets = kad.kbuckets().filter(|b| b.num_entries() != 0).count();
+
+        // The procedure starts by looking up our own ID.
+        let id = kad.bootstrap();
+        assert_eq!(kad.queries_to_starts.len(), 1);
+        assert_eq!(kad.queries_to_starts[0].1.as_hash(), local_peer_id.as_ref());
+        assert_eq!(kad.queries_to_starts[0].2, QueryPurpose::Bootstrap(id));
+        kad.queries_to_starts.clear();
+
+        // Once it has finished, each bucket that isn't empty is refreshed with a random ID that
+        // belongs to it.
+        let found = PeerId::random();
+        kad.inject_bootstrap_query_finished(id, id, vec![found.clone()].into_iter());
+        assert!(kad.queued_events.is_empty());
+        assert_eq!(kad.queries_to_starts.len(), non_empty_buckets);
+        for (_, target, purpose) in kad.queries_to_starts.iter() {
+            assert_eq!(*purpose, QueryPurpose::Bootstrap(id));
+            let distance = local_peer_id.distance_with(target.as_hash());
+            assert!(known.iter().any(|peer| local_peer_id.distance_with(peer) == distance));
+        }
+
+        // The result is reported once all the refreshes have finished, with the distinct peers
+        // that they found.
+        let refreshes = kad.queries_to_starts.drain().map(|(query_id, _, _)| query_id).collect::<Vec<_>>();
+        for query_id in refreshes {
+            assert!(kad.queued_events.is_empty());
+            kad.inject_bootstrap_query_finished(id, query_id, vec![found.clone()].into_iter());
+        }
+        assert_eq!(kad.queued_events.len(), 1);
+        match &kad.queued_events[0] {
+            NetworkBehaviourAction::GenerateEvent(KademliaOut::BootstrapResult { id: reported, num_peers }) => {
+                assert_eq!(*reported, id);
+                assert_eq!(*num_peers, 1);
+            },
+            _ => panic!("unexpected event"),
+        }
+        assert!(kad.bootstraps.is_empty());
+    }
+
+    #[test]
+    fn zero_bootstrap_interval_is_clamped() {
+        let config = KademliaConfig::new().bootstrap_interval(Duration::from_secs(0));
+        assert_eq!(config.bootstrap_interval, Duration::from_millis(1));
+        let _: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+    }
+
+    #[test]
+    fn bootstrap_with_empty_routing_table_finishes_after_own_lookup() {
+        let mut kad: Kademlia<TcpStream> = Kademlia::without_init(PeerId::random());
+        let id = kad.bootstrap();
+        kad.queries_to_starts.clear();
+
+        kad.inject_bootstrap_query_finished(id, id, Vec::new().into_iter());
+        assert!(kad.queries_to_starts.is_empty());
+        match &kad.queued_events[..] {
+            [NetworkBehaviourAction::GenerateEvent(KademliaOut::BootstrapResult { id: reported, num_peers: 0 })] => {
+                assert_eq!(*reported, id);
+            },
+            _ => panic!("unexpected events"),
+        }
+    }
+
+    #[test]
+    fn cancelled_bootstrap_is_not_reported() {
+        let mut kad: Kademlia<TcpStream> = Kademlia::without_init(PeerId::random());
+        let id = kad.bootstrap();
+        assert!(kad.cancel_query(id));
+        assert!(kad.queries_to_starts.is_empty());
+
+        kad.inject_bootstrap_query_finished(id, id, Vec::new().into_iter());
+        assert!(kad.queued_events.is_empty());
+    }
+
+    /// Builds a `Kademlia` whose routing table contains `peers`, without the initial bootstrap.
+    fn build_with_peers(config: KademliaConfig, peers: &[PeerId]) -> Kademlia<TcpStream> {
+        let mut kad: Kademlia<TcpStream> = Kademlia::with_config(PeerId::random(), config);
+        kad.queries_to_starts.clear();
+        for peer in peers {
+            kad.update_kbuckets(peer.clone());
+        }
+        kad
+    }
+
+    /// Drives the queries of `kad` until they can't progress, and returns the events generated
+    /// for the user. The same runtime must be used for a given `kad`, as its timers are
+    /// registered with it.
+    fn poll_query_events(rt: &mut Runtime, kad: &mut Kademlia<TcpStream>) -> Vec<KademliaOut> {
+        rt.block_on(future::lazy(|| -> Result<_, ()> {
+            let mut events = Vec::new();
+            while let Async::Ready(action) = kad.poll_queries(Vec::new) {
+                if let NetworkBehaviourAction::GenerateEvent(event) = action {
+                    events.push(event);
+                }
+            }
+            Ok(events)
+        })).unwrap()
+    }
+
+    #[test]
+    fn get_value_finishes_once_the_quorum_is_reached() {
+        let peers = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let mut kad = build_with_peers(KademliaConfig::new().get_quorum(2), &peers);
+        let mut rt = Runtime::new().unwrap();
+        let mut topology = MemoryTopology::empty(PublicKey::Ed25519(vec![1; 32]));
+        let key = Multihash::random(Hash::SHA2256);
+        let id = kad.get_value(key.clone());
+        kad.start_queries(&mut topology);
+        assert!(poll_query_events(&mut rt, &mut kad).is_empty());
+
+        let record = Record::new(key.clone(), b"hello".to_vec());
+        kad.inject_get_value_res(peers[0].clone(), Some(record.clone()), Vec::new(), id);
+        assert!(poll_query_events(&mut rt, &mut kad).is_empty());
+
+        // A record for another key doesn't count towards the quorum.
+        let other = Record::new(Multihash::random(Hash::SHA2256), b"hello".to_vec());
+        kad.inject_get_value_res(peers[1].clone(), Some(other), Vec::new(), id);
+        assert!(poll_query_events(&mut rt, &mut kad).is_empty());
+
+        // The result is reported without waiting for the remaining peers.
+        kad.inject_get_value_res(peers[2].clone(), Some(record.clone()), Vec::new(), id);
+        match &poll_query_events(&mut rt, &mut kad)[..] {
+            [KademliaOut::GetValueResult { id: reported, key: reported_key, result: Ok(records) }] => {
+                assert_eq!(*reported, id);
+                assert_eq!(*reported_key, key);
+                assert_eq!(*records, vec![record.clone(), record]);
+            },
+            _ => panic!("unexpected events"),
+        }
+        assert!(kad.active_queries.is_empty());
+    }
+
+    #[test]
+    fn get_value_fails_if_the_quorum_is_not_reached() {
+        let peers = (0..2).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let mut kad = build_with_peers(KademliaConfig::new().get_quorum(3), &peers);
+        let mut rt = Runtime::new().unwrap();
+        let mut topology = MemoryTopology::empty(PublicKey::Ed25519(vec![1; 32]));
+        let key = Multihash::random(Hash::SHA2256);
+        let id = kad.get_value(key.clone());
+        kad.start_queries(&mut topology);
+        assert!(poll_query_events(&mut rt, &mut kad).is_empty());
+
+        let record = Record::new(key.clone(), b"hello".to_vec());
+        kad.inject_get_value_res(peers[0].clone(), Some(record.clone()), Vec::new(), id);
+        kad.inject_get_value_res(peers[1].clone(), Some(record.clone()), Vec::new(), id);
+        match &poll_query_events(&mut rt, &mut kad)[..] {
+            [KademliaOut::GetValueResult { id: reported, result: Err(GetValueError::QuorumFailed { records, quorum: 3 }), .. }] => {
+                assert_eq!(*reported, id);
+                assert_eq!(*records, vec![record.clone(), record]);
+            },
+            _ => panic!("unexpected events"),
+        }
+    }
+
+    #[test]
+    fn get_value_reports_the_records_found_before_the_timeout() {
+        let peers = (0..2).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let config = KademliaConfig::new().get_quorum(2).query_timeout(Duration::from_millis(100));
+        let mut kad = build_with_peers(config, &peers);
+        let mut rt = Runtime::new().unwrap();
+        let mut topology = MemoryTopology::empty(PublicKey::Ed25519(vec![1; 32]));
+        let key = Multihash::random(Hash::SHA2256);
+        let id = kad.get_value(key.clone());
+        kad.start_queries(&mut topology);
+        assert!(poll_query_events(&mut rt, &mut kad).is_empty());
+
+        // The second peer never answers.
+        let record = Record::new(key.clone(), b"hello".to_vec());
+        kad.inject_get_value_res(peers[0].clone(), Some(record.clone()), Vec::new(), id);
+        assert!(poll_query_events(&mut rt, &mut kad).is_empty());
+
+        // The query finishes once it has timed out.
+        let event = rt.block_on(future::poll_fn(|| -> Poll<_, ()> {
+            match kad.poll_queries(Vec::new) {
+                Async::Ready(NetworkBehaviourAction::GenerateEvent(event)) => Ok(Async::Ready(event)),
+                Async::Ready(_) => panic!("unexpected action"),
+                Async::NotReady => Ok(Async::NotReady),
+            }
+        })).unwrap();
+        match event {
+            KademliaOut::GetValueResult { id: reported, result: Err(GetValueError::QuorumFailed { records, quorum: 2 }), .. } => {
+                assert_eq!(reported, id);
+                assert_eq!(records, vec![record]);
+            },
+            _ => panic!("unexpected events"),
+        }
+    }
+
+    #[test]
+    fn put_value_finishes_once_the_quorum_is_reached() {
+        let peers = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let mut kad = build_with_peers(KademliaConfig::new().put_quorum(2), &[]);
+        let key = Multihash::random(Hash::SHA2256);
+        let id = kad.put_value(key.clone(), b"hello".to_vec());
+        kad.queries_to_starts.clear();
+
+        // Simulates the end of the query that looked for the closest peers.
+        kad.send_put_value(id, Record::new(key.clone(), b"hello".to_vec()), peers.clone().into_iter(), true);
+        assert_eq!(kad.queued_events.len(), 3);
+        kad.queued_events.clear();
+
+        // Duplicate answers and answers from peers that weren't asked don't count.
+        kad.inject_put_value_res(peers[0].clone(), id);
+        kad.inject_put_value_res(peers[0].clone(), id);
+        kad.inject_put_value_res(PeerId::random(), id);
+        assert!(kad.queued_events.is_empty());
+
+        // The result is reported without waiting for the remaining peer.
+        kad.inject_put_value_res(peers[1].clone(), id);
+        match &kad.queued_events[..] {
+            [NetworkBehaviourAction::GenerateEvent(KademliaOut::PutValueResult { id: reported, key: reported_key, result: Ok(success) })] => {
+                assert_eq!(*reported, id);
+                assert_eq!(*reported_key, key);
+                assert_eq!(*success, vec![peers[0].clone(), peers[1].clone()]);
+            },
+            _ => panic!("unexpected events"),
+        }
+        assert!(kad.pending_puts.is_empty());
+
+        // A late answer is ignored.
+        kad.queued_events.clear();
+        kad.inject_put_value_res(peers[2].clone(), id);
+        assert!(kad.queued_events.is_empty());
+    }
+
+    #[test]
+    fn put_value_fails_if_the_quorum_is_not_reached() {
+        let peers = (0..2).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let mut kad = build_with_peers(KademliaConfig::new().put_quorum(2), &[]);
+        let key = Multihash::random(Hash::SHA2256);
+        let id = kad.put_value(key.clone(), b"hello".to_vec());
+        kad.queries_to_starts.clear();
+        kad.send_put_value(id, Record::new(key.clone(), b"hello".to_vec()), peers.clone().into_iter(), true);
+        kad.queued_events.clear();
+
+        kad.inject_put_value_res(peers[0].clone(), id);
+        assert!(kad.queued_events.is_empty());
+
+        // The outcome is known as soon as the last peer fails.
+        NetworkBehaviour::<MemoryTopology>::inject_dial_failure(&mut kad, &peers[1]);
+        match &kad.queued_events[..] {
+            [NetworkBehaviourAction::GenerateEvent(KademliaOut::PutValueResult { id: reported, result: Err(PutValueError::QuorumFailed { success, quorum: 2 }), .. })] => {
+                assert_eq!(*reported, id);
+                assert_eq!(*success, vec![peers[0].clone()]);
+            },
+            _ => panic!("unexpected events"),
+        }
+        assert!(kad.pending_puts.is_empty());
+    }
+}
@@ -26,6 +26,7 @@ use protocol::{
     KadInStreamSink, KadOutStreamSink, KadPeer, KadRequestMsg, KadResponseMsg,
     KademliaProtocolConfig,
 };
+use record::Record;
 use std::{error, fmt, io};
 use tokio_io::{AsyncRead, AsyncWrite};
 
@@ -129,6 +130,18 @@ where
 /// Event produced by the Kademlia handler.
 #[derive(Debug)]
 pub enum KademliaHandlerEvent<TUserData> {
+    /// The remote checks whether we are alive. Needs to be answered with a `PingRes`.
+    PingReq {
+        /// Identifier of the request. Needs to be passed back when answering.
+        request_id: KademliaRequestId,
+    },
+
+    /// Response to an `KademliaHandlerIn::PingReq`.
+    PingRes {
+        /// The user data passed to the `PingReq`.
+        user_data: TUserData,
+    },
+
     /// Request for the list of nodes whose IDs are the closest to `key`. The number of nodes
     /// returned is not specified, but should be around 20.
     FindNodeReq {
@@ -180,6 +193,42 @@ pub enum KademliaHandlerEvent<TUserData> {
         /// Known provider for this key.
         provider_peer: KadPeer,
     },
+
+    /// Request to get a value from the dht records.
+    GetValueReq {
+        /// Key for which we should look in the dht.
+        key: Multihash,
+        /// Identifier of the request. Needs to be passed back when answering.
+        request_id: KademliaRequestId,
+    },
+
+    /// Response to a `KademliaHandlerIn::GetValueReq`.
+    GetValueRes {
+        /// The record found, if any.
+        record: Option<Record>,
+        /// Nodes closest to the key.
+        closer_peers: Vec<KadPeer>,
+        /// The user data passed to the `GetValueReq`.
+        user_data: TUserData,
+    },
+
+    /// Request to put a value in the dht records.
+    PutValueReq {
+        /// The record to store.
+        record: Record,
+        /// Identifier of the request. Needs to be passed back when answering.
+        request_id: KademliaRequestId,
+    },
+
+    /// Response to a `KademliaHandlerIn::PutValueReq`.
+    PutValueRes {
+        /// The key of the record that was stored.
+        key: Multihash,
+        /// The value of the record that was stored.
+        value: Vec<u8>,
+        /// The user data passed to the `PutValueReq`.
+        user_data: TUserData,
+    },
 }
 
 /// Error that can happen when requesting an RPC query.
@@ -228,6 +277,24 @@ impl From<ProtocolsHandlerUpgrErr<io::Error>> for KademliaHandlerQueryErr {
 
 /// Event to send to the handler.
 pub enum KademliaHandlerIn<TUserData> {
+    /// Refuses a request made by the remote, closing the substream it was received on without
+    /// answering.
+    Reset(KademliaRequestId),
+
+    /// Checks whether the remote is alive.
+    PingReq {
+        /// Custom user data. Passed back in the out event when the results arrive.
+        user_data: TUserData,
+    },
+
+    /// Response to a `PingReq`.
+    PingRes {
+        /// Identifier of the request that was made by the remote.
+        ///
+        /// It is a logic error to use an id of the handler of a different node.
+        request_id: KademliaRequestId,
+    },
+
     /// Request for the list of nodes whose IDs are the closest to `key`. The number of nodes
     /// returned is not specified, but should be around 20.
     FindNodeReq {
@@ -278,6 +345,46 @@ pub enum KademliaHandlerIn<TUserData> {
         /// Known provider for this key.
         provider_peer: KadPeer,
     },
+
+    /// Request to get a value from the dht records.
+    GetValueReq {
+        /// The key of the record.
+        key: Multihash,
+        /// Custom user data. Passed back in the out event when the results arrive.
+        user_data: TUserData,
+    },
+
+    /// Response to a `GetValueReq`.
+    GetValueRes {
+        /// The record found, if any.
+        record: Option<Record>,
+        /// Nodes closest to the key.
+        closer_peers: Vec<KadPeer>,
+        /// Identifier of the request that was made by the remote.
+        ///
+        /// It is a logic error to use an id of the handler of a different node.
+        request_id: KademliaRequestId,
+    },
+
+    /// Request to put a value into the dht records.
+    PutValueReq {
+        /// The record to store.
+        record: Record,
+        /// Custom user data. Passed back in the out event when the results arrive.
+        user_data: TUserData,
+    },
+
+    /// Response to a `PutValueReq`.
+    PutValueRes {
+        /// The key of the record that was stored.
+        key: Multihash,
+        /// The value of the record that was stored.
+        value: Vec<u8>,
+        /// Identifier of the request that was made by the remote.
+        ///
+        /// It is a logic error to use an id of the handler of a different node.
+        request_id: KademliaRequestId,
+    },
 }
 
 /// Unique identifier for a request. Must be passed back in order to answer a request from
@@ -315,6 +422,14 @@ where
         KademliaHandler::with_allow_listening(true)
     }
 
+    /// Modifies the configuration of the Kademlia protocol, for example to use a different
+    /// protocol name.
+    #[inline]
+    pub fn with_protocol_config(mut self, config: KademliaProtocolConfig) -> Self {
+        self.config = config;
+        self
+    }
+
     fn with_allow_listening(allow_listening: bool) -> Self {
         KademliaHandler {
             config: Default::default(),
@@ -326,6 +441,31 @@ where
     }
 }
 
+impl<TSubstream, TUserData> KademliaHandler<TSubstream, TUserData>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    /// Sends `msg` as the answer to the request of the remote identified by `request_id`.
+    ///
+    /// Does nothing if the substream the request was received on is gone.
+    fn answer_pending_request(&mut self, request_id: KademliaRequestId, msg: KadResponseMsg) {
+        let pos = self.substreams.iter().position(|state| match state {
+            SubstreamState::InWaitingUser(ref conn_id, _) => conn_id == &request_id.connec_unique_id,
+            _ => false,
+        });
+
+        if let Some(pos) = pos {
+            let (conn_id, substream) = match self.substreams.remove(pos) {
+                SubstreamState::InWaitingUser(conn_id, substream) => (conn_id, substream),
+                _ => unreachable!(),
+            };
+
+            self.substreams
+                .push(SubstreamState::InPendingSend(conn_id, substream, msg));
+        }
+    }
+}
+
 impl<TSubstream, TUserData> Default for KademliaHandler<TSubstream, TUserData>
 where
     TSubstream: AsyncRead + AsyncWrite,
@@ -353,7 +493,7 @@ where
     #[inline]
     fn listen_protocol(&self) -> Self::InboundProtocol {
         if self.allow_listening {
-            upgrade::EitherUpgrade::A(self.config)
+            upgrade::EitherUpgrade::A(self.config.clone())
         } else {
             upgrade::EitherUpgrade::B(upgrade::DeniedUpgrade)
         }
@@ -397,37 +537,40 @@ where
     #[inline]
     fn inject_event(&mut self, message: KademliaHandlerIn<TUserData>) {
         match message {
-            KademliaHandlerIn::FindNodeReq { key, user_data } => {
-                let msg = KadRequestMsg::FindNode { key: key.clone() };
-                self.substreams
-                    .push(SubstreamState::OutPendingOpen(msg, Some(user_data.clone())));
-            }
-            KademliaHandlerIn::FindNodeRes {
-                closer_peers,
-                request_id,
-            } => {
+            KademliaHandlerIn::Reset(request_id) => {
                 let pos = self.substreams.iter().position(|state| match state {
-                    SubstreamState::InWaitingUser(ref conn_id, _)
-                        if conn_id == &request_id.connec_unique_id =>
-                    {
-                        true
-                    }
+                    SubstreamState::InWaitingUser(ref conn_id, _) => conn_id == &request_id.connec_unique_id,
                     _ => false,
                 });
 
                 if let Some(pos) = pos {
-                    let (conn_id, substream) = match self.substreams.remove(pos) {
-                        SubstreamState::InWaitingUser(conn_id, substream) => (conn_id, substream),
+                    let substream = match self.substreams.remove(pos) {
+                        SubstreamState::InWaitingUser(_, substream) => substream,
                         _ => unreachable!(),
                     };
 
-                    let msg = KadResponseMsg::FindNode {
-                        closer_peers: closer_peers.clone(),
-                    };
-                    self.substreams
-                        .push(SubstreamState::InPendingSend(conn_id, substream, msg));
+                    self.substreams.push(SubstreamState::InClosing(substream));
                 }
             }
+            KademliaHandlerIn::PingReq { user_data } => {
+                self.substreams
+                    .push(SubstreamState::OutPendingOpen(KadRequestMsg::Ping, Some(user_data)));
+            }
+            KademliaHandlerIn::PingRes { request_id } => {
+                self.answer_pending_request(request_id, KadResponseMsg::Pong);
+            }
+            KademliaHandlerIn::FindNodeReq { key, user_data } => {
+                let msg = KadRequestMsg::FindNode { key: key.clone() };
+                self.substreams
+                    .push(SubstreamState::OutPendingOpen(msg, Some(user_data.clone())));
+            }
+            KademliaHandlerIn::FindNodeRes {
+                closer_peers,
+                request_id,
+            } => {
+                let msg = KadResponseMsg::FindNode { closer_peers };
+                self.answer_pending_request(request_id, msg);
+            }
             KademliaHandlerIn::GetProvidersReq { key, user_data } => {
                 let msg = KadRequestMsg::GetProviders { key: key.clone() };
                 self.substreams
@@ -438,28 +581,11 @@ where
                 provider_peers,
                 request_id,
             } => {
-                let pos = self.substreams.iter().position(|state| match state {
-                    SubstreamState::InWaitingUser(ref conn_id, _)
-                        if conn_id == &request_id.connec_unique_id =>
-                    {
-                        true
-                    }
-                    _ => false,
-                });
-
-                if let Some(pos) = pos {
-                    let (conn_id, substream) = match self.substreams.remove(pos) {
-                        SubstreamState::InWaitingUser(conn_id, substream) => (conn_id, substream),
-                        _ => unreachable!(),
-                    };
-
-                    let msg = KadResponseMsg::GetProviders {
-                        closer_peers: closer_peers.clone(),
-                        provider_peers: provider_peers.clone(),
-                    };
-                    self.substreams
-                        .push(SubstreamState::InPendingSend(conn_id, substream, msg));
-                }
+                let msg = KadResponseMsg::GetProviders {
+                    closer_peers,
+                    provider_peers,
+                };
+                self.answer_pending_request(request_id, msg);
             }
             KademliaHandlerIn::AddProvider { key, provider_peer } => {
                 let msg = KadRequestMsg::AddProvider {
@@ -469,6 +595,35 @@ where
                 self.substreams
                     .push(SubstreamState::OutPendingOpen(msg, None));
             }
+            KademliaHandlerIn::GetValueReq { key, user_data } => {
+                let msg = KadRequestMsg::GetValue { key };
+                self.substreams
+                    .push(SubstreamState::OutPendingOpen(msg, Some(user_data)));
+            }
+            KademliaHandlerIn::GetValueRes {
+                record,
+                closer_peers,
+                request_id,
+            } => {
+                let msg = KadResponseMsg::GetValue {
+                    record,
+                    closer_peers,
+                };
+                self.answer_pending_request(request_id, msg);
+            }
+            KademliaHandlerIn::PutValueReq { record, user_data } => {
+                let msg = KadRequestMsg::PutValue { record };
+                self.substreams
+                    .push(SubstreamState::OutPendingOpen(msg, Some(user_data)));
+            }
+            KademliaHandlerIn::PutValueRes {
+                key,
+                value,
+                request_id,
+            } => {
+                let msg = KadResponseMsg::PutValue { key, value };
+                self.answer_pending_request(request_id, msg);
+            }
         }
     }
 
@@ -526,7 +681,7 @@ where
             let mut substream = self.substreams.swap_remove(n);
 
             loop {
-                match advance_substream(substream, self.config) {
+                match advance_substream(substream, self.config.clone()) {
                     (Some(new_state), Some(event), _) => {
                         self.substreams.push(new_state);
                         return Ok(Async::Ready(event));
@@ -748,14 +903,9 @@ fn process_kad_request<TUserData>(
     connec_unique_id: UniqueConnecId,
 ) -> Result<KademliaHandlerEvent<TUserData>, io::Error> {
     match event {
-        KadRequestMsg::Ping => {
-            // TODO: implement; although in practice the PING message is never
-            //       used, so we may consider removing it altogether
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "the PING Kademlia message is not implemented",
-            ))
-        }
+        KadRequestMsg::Ping => Ok(KademliaHandlerEvent::PingReq {
+            request_id: KademliaRequestId { connec_unique_id },
+        }),
         KadRequestMsg::FindNode { key } => Ok(KademliaHandlerEvent::FindNodeReq {
             key,
             request_id: KademliaRequestId { connec_unique_id },
@@ -767,6 +917,14 @@ fn process_kad_request<TUserData>(
         KadRequestMsg::AddProvider { key, provider_peer } => {
             Ok(KademliaHandlerEvent::AddProvider { key, provider_peer })
         }
+        KadRequestMsg::GetValue { key } => Ok(KademliaHandlerEvent::GetValueReq {
+            key,
+            request_id: KademliaRequestId { connec_unique_id },
+        }),
+        KadRequestMsg::PutValue { record } => Ok(KademliaHandlerEvent::PutValueReq {
+            record,
+            request_id: KademliaRequestId { connec_unique_id },
+        }),
     }
 }
 
@@ -777,13 +935,7 @@ fn process_kad_response<TUserData>(
 ) -> KademliaHandlerEvent<TUserData> {
     // TODO: must check that the response corresponds to the request
     match event {
-        KadResponseMsg::Pong => {
-            // We never send out pings.
-            KademliaHandlerEvent::QueryError {
-                error: KademliaHandlerQueryErr::UnexpectedMessage,
-                user_data,
-            }
-        }
+        KadResponseMsg::Pong => KademliaHandlerEvent::PingRes { user_data },
         KadResponseMsg::FindNode { closer_peers } => KademliaHandlerEvent::FindNodeRes {
             closer_peers,
             user_data,
@@ -796,5 +948,18 @@ fn process_kad_response<TUserData>(
             provider_peers,
             user_data,
         },
+        KadResponseMsg::GetValue {
+            record,
+            closer_peers,
+        } => KademliaHandlerEvent::GetValueRes {
+            record,
+            closer_peers,
+            user_data,
+        },
+        KadResponseMsg::PutValue { key, value } => KademliaHandlerEvent::PutValueRes {
+            key,
+            value,
+            user_data,
+        },
     }
 }
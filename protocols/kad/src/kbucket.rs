@@ -27,15 +27,15 @@
 //! a constant number of entries. Storing a key in the k-buckets table adds it to the bucket
 //! corresponding to its distance with the reference key.
 
-use arrayvec::ArrayVec;
 use bigint::U512;
+use libp2p_core::PeerId;
 use multihash::Multihash;
-use std::mem;
+use std::{cmp, mem};
 use std::slice::IterMut as SliceIterMut;
 use std::time::{Duration, Instant};
 use std::vec::IntoIter as VecIntoIter;
 
-/// Maximum number of nodes in a bucket.
+/// Default maximum number of nodes in a bucket, `k` in the Kademlia reference papers.
 pub const MAX_NODES_PER_BUCKET: usize = 20;
 
 /// Table of k-buckets.
@@ -47,15 +47,17 @@ pub struct KBucketsTable<Id, Val> {
     tables: Vec<KBucket<Id, Val>>,
      // The timeout when pinging the first node after which we consider it unresponsive.
     ping_timeout: Duration,
+    /// Maximum number of nodes in each bucket.
+    max_nodes_per_bucket: usize,
 }
 
 /// An individual table that stores peers or values.
 #[derive(Debug, Clone)]
 struct KBucket<Id, Val> {
     /// Nodes are always ordered from oldest to newest.
-    /// Note that we will very often move elements to the end of this. No benchmarking has been
-    /// performed, but it is very likely that a `ArrayVec` is the most performant data structure.
-    nodes: ArrayVec<[Node<Id, Val>; MAX_NODES_PER_BUCKET]>,
+    /// Note that we will very often move elements to the end of this. The number of elements is
+    /// bounded by the `max_nodes_per_bucket` of the table.
+    nodes: Vec<Node<Id, Val>>,
 
     /// Node received when the bucket was full. Will be added to the list if the first node doesn't
     /// respond in time to our ping. The second element is the time when the pending node was added.
@@ -90,9 +92,11 @@ impl<Id, Val> KBucket<Id, Val> {
 }
 
 /// Trait that must be implemented on types that can be used as an identifier in a k-bucket.
-pub trait KBucketsPeerId: Eq + Clone {
+///
+/// `TOther` is the type of the values that the distance can be computed with.
+pub trait KBucketsPeerId<TOther = Self>: Eq + Clone {
     /// Computes the XOR of this value and another one. The lower the closer.
-    fn distance_with(&self, other: &Self) -> u32;
+    fn distance_with(&self, other: &TOther) -> u32;
 
     /// Returns then number of bits that are necessary to store the distance between peer IDs.
     /// Used for pre-allocations.
@@ -118,22 +122,48 @@ impl KBucketsPeerId for Multihash {
     }
 }
 
+impl KBucketsPeerId for PeerId {
+    #[inline]
+    fn distance_with(&self, other: &Self) -> u32 {
+        self.as_ref().distance_with(other.as_ref())
+    }
+
+    #[inline]
+    fn max_distance() -> usize {
+        <Multihash as KBucketsPeerId>::max_distance()
+    }
+}
+
+impl KBucketsPeerId<Multihash> for PeerId {
+    #[inline]
+    fn distance_with(&self, other: &Multihash) -> u32 {
+        self.as_ref().distance_with(other)
+    }
+
+    #[inline]
+    fn max_distance() -> usize {
+        <Multihash as KBucketsPeerId>::max_distance()
+    }
+}
+
 impl<Id, Val> KBucketsTable<Id, Val>
 where
     Id: KBucketsPeerId,
 {
-    /// Builds a new routing table.
-    pub fn new(my_id: Id, ping_timeout: Duration) -> Self {
+    /// Builds a new routing table whose buckets hold at most `max_nodes_per_bucket` nodes. A
+    /// value of zero is treated as one.
+    pub fn new(my_id: Id, ping_timeout: Duration, max_nodes_per_bucket: usize) -> Self {
         KBucketsTable {
             my_id: my_id,
             tables: (0..Id::max_distance())
                 .map(|_| KBucket {
-                    nodes: ArrayVec::new(),
+                    nodes: Vec::new(),
                     pending_node: None,
                     last_update: Instant::now(),
                 })
                 .collect(),
             ping_timeout: ping_timeout,
+            max_nodes_per_bucket: cmp::max(max_nodes_per_bucket, 1),
         }
     }
 
@@ -160,18 +190,27 @@ where
         &self.my_id
     }
 
-    /// Finds the `num` nodes closest to `id`, ordered by distance.
-    pub fn find_closest(&mut self, id: &Id) -> VecIntoIter<Id>
+    /// Returns `true` if `id` is in the table, either as a node of its bucket or as the node
+    /// waiting for a place in it.
+    pub fn contains(&self, id: &Id) -> bool {
+        let table = match self.bucket_num(id) {
+            Some(n) => &self.tables[n],
+            None => return false,
+        };
+
+        table.nodes.iter().any(|n| n.id == *id) ||
+            table.pending_node.as_ref().map_or(false, |(n, _)| n.id == *id)
+    }
+
+    /// Finds the nodes closest to `id`, ordered by distance.
+    pub fn find_closest<TOther>(&mut self, id: &TOther) -> VecIntoIter<Id>
     where
-        Id: Clone,
+        Id: Clone + KBucketsPeerId<TOther>,
     {
         // TODO: optimize
         let mut out = Vec::new();
         for table in self.tables.iter_mut() {
             table.flush(self.ping_timeout);
-            if table.last_update.elapsed() > self.ping_timeout {
-                continue; // ignore bucket with expired nodes
-            }
             for node in table.nodes.iter() {
                 out.push(node.id.clone());
             }
@@ -181,15 +220,15 @@ where
     }
 
     /// Same as `find_closest`, but includes the local peer as well.
-    pub fn find_closest_with_self(&mut self, id: &Id) -> VecIntoIter<Id>
+    pub fn find_closest_with_self<TOther>(&mut self, id: &TOther) -> VecIntoIter<Id>
     where
-        Id: Clone,
+        Id: Clone + KBucketsPeerId<TOther>,
     {
         // TODO: optimize
-        let mut intermediate: Vec<_> = self.find_closest(&id).collect();
+        let mut intermediate: Vec<_> = self.find_closest(id).collect();
         if let Some(pos) = intermediate
             .iter()
-            .position(|e| e.distance_with(&id) >= self.my_id.distance_with(&id))
+            .position(|e| e.distance_with(id) >= self.my_id.distance_with(id))
         {
             if intermediate[pos] != self.my_id {
                 intermediate.insert(pos, self.my_id.clone());
@@ -203,6 +242,7 @@ where
     /// Marks the node as "most recent" in its bucket and modifies the value associated to it.
     /// This function should be called whenever we receive a communication from a node.
     pub fn update(&mut self, id: Id, value: Val) -> UpdateOutcome<Id, Val> {
+        let max_nodes = self.max_nodes_per_bucket;
         let table = match self.bucket_num(&id) {
             Some(n) => &mut self.tables[n],
             None => return UpdateOutcome::FailSelfUpdate,
@@ -217,13 +257,13 @@ where
             if pos == 0 {
                 // If it's the first node of the bucket that we update, then we drop the node that
                 // was waiting for a ping.
-                table.nodes.truncate(MAX_NODES_PER_BUCKET - 1);
+                table.nodes.truncate(max_nodes - 1);
                 table.pending_node = None;
             }
             table.nodes.push(existing);
             table.last_update = Instant::now();
             UpdateOutcome::Refreshed(old_val)
-        } else if table.nodes.len() < MAX_NODES_PER_BUCKET {
+        } else if table.nodes.len() < max_nodes {
             // Node not yet in the bucket, but there's plenty of space.
             table.nodes.push(Node {
                 id: id,
@@ -249,6 +289,27 @@ where
             }
         }
     }
+
+    /// Removes the node from the table, for example because it didn't answer a ping. If a node
+    /// was pending in the same bucket, it takes the place of the removed node.
+    ///
+    /// Returns the value of the removed node, or `None` if it wasn't in the table.
+    pub fn remove(&mut self, id: &Id) -> Option<Val> {
+        let table = match self.bucket_num(id) {
+            Some(n) => &mut self.tables[n],
+            None => return None,
+        };
+
+        table.flush(self.ping_timeout);
+
+        let pos = table.nodes.iter().position(|n| n.id == *id)?;
+        let removed = table.nodes.remove(pos);
+        if let Some((pending_node, _)) = table.pending_node.take() {
+            table.nodes.push(pending_node);
+        }
+        table.last_update = Instant::now();
+        Some(removed.value)
+    }
 }
 
 /// Return value of the `update()` method.
@@ -304,12 +365,25 @@ impl<'a, Id: 'a, Val: 'a> Bucket<'a, Id, Val> {
         self.0.nodes.len()
     }
 
+    /// Returns the IDs of the entries of this bucket, ordered from the least recently updated to
+    /// the most recently updated.
+    #[inline]
+    pub fn entries(&self) -> impl Iterator<Item = &Id> {
+        self.0.nodes.iter().map(|node| &node.id)
+    }
+
     /// Returns true if this bucket has a pending node.
     #[inline]
     pub fn has_pending(&self) -> bool {
         self.0.pending_node.is_some()
     }
 
+    /// Returns the ID of the pending node, if any.
+    #[inline]
+    pub fn pending(&self) -> Option<&Id> {
+        self.0.pending_node.as_ref().map(|(node, _)| &node.id)
+    }
+
     /// Returns the time when any of the values in this bucket was last updated.
     ///
     /// If the bucket is empty, this returns the time when the whole table was created.
@@ -350,7 +424,7 @@ mod tests {
             ))
         };
 
-        let mut table = KBucketsTable::new(my_id, Duration::from_secs(5));
+        let mut table = KBucketsTable::new(my_id, Duration::from_secs(5), MAX_NODES_PER_BUCKET);
         let _ = table.update(other_id.clone(), ());
 
         let res = table.find_closest(&other_id).collect::<Vec<_>>();
@@ -367,7 +441,7 @@ mod tests {
             Multihash::from_bytes(bytes).unwrap()
         };
 
-        let mut table = KBucketsTable::new(my_id.clone(), Duration::from_secs(5));
+        let mut table = KBucketsTable::new(my_id.clone(), Duration::from_secs(5), MAX_NODES_PER_BUCKET);
         match table.update(my_id, ()) {
             UpdateOutcome::FailSelfUpdate => (),
             _ => panic!(),
@@ -393,7 +467,7 @@ mod tests {
             })
             .collect::<Vec<_>>();
 
-        let mut table = KBucketsTable::new(my_id, Duration::from_secs(5));
+        let mut table = KBucketsTable::new(my_id, Duration::from_secs(5), MAX_NODES_PER_BUCKET);
         let before_update = table.buckets().map(|b| b.last_update()).collect::<Vec<_>>();
 
         thread::sleep(Duration::from_secs(2));
@@ -412,6 +486,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn remove_promotes_pending() {
+        let my_id = {
+            let mut bytes = vec![random(); 34];
+            bytes[0] = 18;
+            bytes[1] = 32;
+            Multihash::from_bytes(bytes).unwrap()
+        };
+
+        let mut fill_ids = (0..MAX_NODES_PER_BUCKET + 1)
+            .map(|n| {
+                let mut id = my_id.clone().into_bytes();
+                id[2] ^= 0x80; // Flip the first bit so that we get in the most distant bucket.
+                id[33] = id[33].wrapping_add(n as u8);
+                Multihash::from_bytes(id).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let pending = fill_ids.pop().unwrap();
+
+        let mut table = KBucketsTable::new(my_id, Duration::from_secs(60), MAX_NODES_PER_BUCKET);
+        for id in fill_ids.iter() {
+            assert_eq!(table.update(id.clone(), ()), UpdateOutcome::Added);
+        }
+        assert_eq!(table.update(pending.clone(), ()), UpdateOutcome::NeedPing(fill_ids[0].clone()));
+        assert_eq!(table.buckets().nth(255).unwrap().pending(), Some(&pending));
+        assert!(table.buckets().nth(255).unwrap().entries().eq(fill_ids.iter()));
+
+        // The node that we had to ping is dead.
+        assert_eq!(table.remove(&fill_ids[0]), Some(()));
+        assert_eq!(table.remove(&fill_ids[0]), None);
+        assert!(!table.buckets().nth(255).unwrap().has_pending());
+        assert_eq!(table.buckets().nth(255).unwrap().num_entries(), MAX_NODES_PER_BUCKET);
+        assert!(table.find_closest(&pending).any(|id| id == pending));
+    }
+
+    #[test]
+    fn bucket_size_is_configurable() {
+        let my_id = {
+            let mut bytes = vec![random(); 34];
+            bytes[0] = 18;
+            bytes[1] = 32;
+            Multihash::from_bytes(bytes).unwrap()
+        };
+
+        let ids = (0..6)
+            .map(|n| {
+                let mut id = my_id.clone().into_bytes();
+                id[2] ^= 0x80; // Flip the first bit so that we get in the most distant bucket.
+                id[33] = id[33].wrapping_add(n as u8);
+                Multihash::from_bytes(id).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut table = KBucketsTable::new(my_id, Duration::from_secs(60), 5);
+        for id in ids.iter().take(5) {
+            assert_eq!(table.update(id.clone(), ()), UpdateOutcome::Added);
+        }
+        assert_eq!(table.update(ids[5].clone(), ()), UpdateOutcome::NeedPing(ids[0].clone()));
+        assert_eq!(table.buckets().nth(255).unwrap().num_entries(), 5);
+        assert_eq!(table.find_closest(&ids[5]).count(), 5);
+    }
+
     #[test]
     fn full_kbucket() {
         let my_id = {
@@ -434,7 +570,7 @@ mod tests {
         let first_node = fill_ids[0].clone();
         let second_node = fill_ids[1].clone();
 
-        let mut table = KBucketsTable::new(my_id.clone(), Duration::from_secs(1));
+        let mut table = KBucketsTable::new(my_id.clone(), Duration::from_secs(1), MAX_NODES_PER_BUCKET);
 
         for (num, id) in fill_ids.drain(..MAX_NODES_PER_BUCKET).enumerate() {
             assert_eq!(table.update(id, ()), UpdateOutcome::Added);
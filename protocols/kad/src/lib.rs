@@ -56,7 +56,6 @@
 //   `KademliaSystem`.
 //
 
-extern crate arrayvec;
 extern crate bigint;
 extern crate bs58;
 extern crate bytes;
@@ -82,16 +81,21 @@ extern crate void;
 #[cfg(test)]
 extern crate tokio;
 
-pub use self::behaviour::{Kademlia, KademliaOut};
+pub use self::behaviour::{Kademlia, KademliaConfig, KademliaMode, KademliaOut, GetValueError, PutValueError, QueryId, QueryProgress};
 pub use self::kbucket::KBucketsPeerId;
 pub use self::protocol::KadConnectionType;
+pub use self::record::{MemoryRecordStore, Record, RecordStorageError, RecordStore};
 pub use self::topology::KademliaTopology;
+pub use self::validation::{AcceptAllValidator, PublicKeyValidator, RecordValidationError, RecordValidator, SignedRecordValidator};
 
 pub mod handler;
 pub mod kbucket;
 pub mod protocol;
+pub mod record;
+pub mod validation;
 
 mod behaviour;
 mod protobuf_structs;
+mod providers;
 mod query;
 mod topology;
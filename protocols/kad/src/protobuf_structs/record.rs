@@ -24,9 +24,9 @@ use protobuf::ProtobufEnum as ProtobufEnum_imported_for_functions;
 #[derive(PartialEq,Clone,Default)]
 pub struct Record {
     // message fields
-    key: ::protobuf::SingularField<::std::string::String>,
+    key: ::protobuf::SingularField<::std::vec::Vec<u8>>,
     value: ::protobuf::SingularField<::std::vec::Vec<u8>>,
-    author: ::protobuf::SingularField<::std::string::String>,
+    author: ::protobuf::SingularField<::std::vec::Vec<u8>>,
     signature: ::protobuf::SingularField<::std::vec::Vec<u8>>,
     timeReceived: ::protobuf::SingularField<::std::string::String>,
     // special fields
@@ -39,7 +39,7 @@ impl Record {
         ::std::default::Default::default()
     }
 
-    // optional string key = 1;
+    // optional bytes key = 1;
 
     pub fn clear_key(&mut self) {
         self.key.clear();
@@ -50,13 +50,13 @@ impl Record {
     }
 
     // Param is passed by value, moved
-    pub fn set_key(&mut self, v: ::std::string::String) {
+    pub fn set_key(&mut self, v: ::std::vec::Vec<u8>) {
         self.key = ::protobuf::SingularField::some(v);
     }
 
     // Mutable pointer to the field.
     // If field is not initialized, it is initialized with default value first.
-    pub fn mut_key(&mut self) -> &mut ::std::string::String {
+    pub fn mut_key(&mut self) -> &mut ::std::vec::Vec<u8> {
         if self.key.is_none() {
             self.key.set_default();
         }
@@ -64,14 +64,14 @@ impl Record {
     }
 
     // Take field
-    pub fn take_key(&mut self) -> ::std::string::String {
-        self.key.take().unwrap_or_else(|| ::std::string::String::new())
+    pub fn take_key(&mut self) -> ::std::vec::Vec<u8> {
+        self.key.take().unwrap_or_else(|| ::std::vec::Vec::new())
     }
 
-    pub fn get_key(&self) -> &str {
+    pub fn get_key(&self) -> &[u8] {
         match self.key.as_ref() {
             Some(v) => &v,
-            None => "",
+            None => &[],
         }
     }
 
@@ -111,7 +111,7 @@ impl Record {
         }
     }
 
-    // optional string author = 3;
+    // optional bytes author = 3;
 
     pub fn clear_author(&mut self) {
         self.author.clear();
@@ -122,13 +122,13 @@ impl Record {
     }
 
     // Param is passed by value, moved
-    pub fn set_author(&mut self, v: ::std::string::String) {
+    pub fn set_author(&mut self, v: ::std::vec::Vec<u8>) {
         self.author = ::protobuf::SingularField::some(v);
     }
 
     // Mutable pointer to the field.
     // If field is not initialized, it is initialized with default value first.
-    pub fn mut_author(&mut self) -> &mut ::std::string::String {
+    pub fn mut_author(&mut self) -> &mut ::std::vec::Vec<u8> {
         if self.author.is_none() {
             self.author.set_default();
         }
@@ -136,14 +136,14 @@ impl Record {
     }
 
     // Take field
-    pub fn take_author(&mut self) -> ::std::string::String {
-        self.author.take().unwrap_or_else(|| ::std::string::String::new())
+    pub fn take_author(&mut self) -> ::std::vec::Vec<u8> {
+        self.author.take().unwrap_or_else(|| ::std::vec::Vec::new())
     }
 
-    pub fn get_author(&self) -> &str {
+    pub fn get_author(&self) -> &[u8] {
         match self.author.as_ref() {
             Some(v) => &v,
-            None => "",
+            None => &[],
         }
     }
 
@@ -230,13 +230,13 @@ impl ::protobuf::Message for Record {
             let (field_number, wire_type) = is.read_tag_unpack()?;
             match field_number {
                 1 => {
-                    ::protobuf::rt::read_singular_string_into(wire_type, is, &mut self.key)?;
+                    ::protobuf::rt::read_singular_bytes_into(wire_type, is, &mut self.key)?;
                 },
                 2 => {
                     ::protobuf::rt::read_singular_bytes_into(wire_type, is, &mut self.value)?;
                 },
                 3 => {
-                    ::protobuf::rt::read_singular_string_into(wire_type, is, &mut self.author)?;
+                    ::protobuf::rt::read_singular_bytes_into(wire_type, is, &mut self.author)?;
                 },
                 4 => {
                     ::protobuf::rt::read_singular_bytes_into(wire_type, is, &mut self.signature)?;
@@ -257,13 +257,13 @@ impl ::protobuf::Message for Record {
     fn compute_size(&self) -> u32 {
         let mut my_size = 0;
         if let Some(ref v) = self.key.as_ref() {
-            my_size += ::protobuf::rt::string_size(1, &v);
+            my_size += ::protobuf::rt::bytes_size(1, &v);
         }
         if let Some(ref v) = self.value.as_ref() {
             my_size += ::protobuf::rt::bytes_size(2, &v);
         }
         if let Some(ref v) = self.author.as_ref() {
-            my_size += ::protobuf::rt::string_size(3, &v);
+            my_size += ::protobuf::rt::bytes_size(3, &v);
         }
         if let Some(ref v) = self.signature.as_ref() {
             my_size += ::protobuf::rt::bytes_size(4, &v);
@@ -278,13 +278,13 @@ impl ::protobuf::Message for Record {
 
     fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
         if let Some(ref v) = self.key.as_ref() {
-            os.write_string(1, &v)?;
+            os.write_bytes(1, &v)?;
         }
         if let Some(ref v) = self.value.as_ref() {
             os.write_bytes(2, &v)?;
         }
         if let Some(ref v) = self.author.as_ref() {
-            os.write_string(3, &v)?;
+            os.write_bytes(3, &v)?;
         }
         if let Some(ref v) = self.signature.as_ref() {
             os.write_bytes(4, &v)?;
@@ -334,7 +334,7 @@ impl ::protobuf::Message for Record {
         unsafe {
             descriptor.get(|| {
                 let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
                     "key",
                     |m: &Record| { &m.key },
                     |m: &mut Record| { &mut m.key },
@@ -344,7 +344,7 @@ impl ::protobuf::Message for Record {
                     |m: &Record| { &m.value },
                     |m: &mut Record| { &mut m.value },
                 ));
-                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
                     "author",
                     |m: &Record| { &m.author },
                     |m: &mut Record| { &mut m.author },
@@ -404,8 +404,8 @@ impl ::protobuf::reflect::ProtobufValue for Record {
 
 static file_descriptor_proto_data: &'static [u8] = b"\
     \n\x0crecord.proto\x12\trecord.pb\"\x8a\x01\n\x06Record\x12\x10\n\x03key\
-    \x18\x01\x20\x01(\tR\x03key\x12\x14\n\x05value\x18\x02\x20\x01(\x0cR\x05\
-    value\x12\x16\n\x06author\x18\x03\x20\x01(\tR\x06author\x12\x1c\n\tsigna\
+    \x18\x01\x20\x01(\x0cR\x03key\x12\x14\n\x05value\x18\x02\x20\x01(\x0cR\x05\
+    value\x12\x16\n\x06author\x18\x03\x20\x01(\x0cR\x06author\x12\x1c\n\tsigna\
     ture\x18\x04\x20\x01(\x0cR\tsignature\x12\"\n\x0ctimeReceived\x18\x05\
     \x20\x01(\tR\x0ctimeReceivedJ\xac\x05\n\x06\x12\x04\0\0\x14\x01\n\x08\n\
     \x01\x0c\x12\x03\0\0\x12\n\x08\n\x01\x02\x12\x03\x01\x08\x11\nX\n\x02\
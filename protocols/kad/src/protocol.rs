@@ -32,6 +32,8 @@ use libp2p_core::{InboundUpgrade, Multiaddr, OutboundUpgrade, PeerId, UpgradeInf
 use multihash::Multihash;
 use protobuf::{self, Message};
 use protobuf_structs;
+use record::Record;
+use std::borrow::Cow;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::iter;
 use tokio_codec::Framed;
@@ -127,21 +129,90 @@ impl Into<protobuf_structs::dht::Message_Peer> for KadPeer {
     }
 }
 
+// Builds a `Record` from its raw protobuf equivalent.
+fn record_from_proto(record: &mut protobuf_structs::record::Record) -> Result<Record, IoError> {
+    let key = Multihash::from_bytes(record.take_key())
+        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+    let publisher = if record.get_author().is_empty() {
+        None
+    } else {
+        let publisher = PeerId::from_bytes(record.take_author())
+            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "invalid record author"))?;
+        Some(publisher)
+    };
+
+    Ok(Record {
+        key,
+        value: record.take_value(),
+        publisher,
+        signature: if record.get_signature().is_empty() {
+            None
+        } else {
+            Some(record.take_signature())
+        },
+        // The expiration is decided by the receiver of the record.
+        expires: None,
+    })
+}
+
+// Turns a `Record` into its raw protobuf equivalent.
+fn record_to_proto(record: Record) -> protobuf_structs::record::Record {
+    let mut out = protobuf_structs::record::Record::new();
+    out.set_key(record.key.into_bytes());
+    out.set_value(record.value);
+    if let Some(publisher) = record.publisher {
+        out.set_author(publisher.into_bytes());
+    }
+    if let Some(signature) = record.signature {
+        out.set_signature(signature);
+    }
+    out
+}
+
 /// Configuration for a Kademlia connection upgrade. When applied to a connection, turns this
 /// connection into a `Stream + Sink` whose items are of type `KadRequestMsg` and `KadResponseMsg`.
 // TODO: if, as suspected, we can confirm with Protocol Labs that each open Kademlia substream does
 //       only one request, then we can change the output of the `InboundUpgrade` and
 //       `OutboundUpgrade` to be just a single message
-#[derive(Debug, Default, Copy, Clone)]
-pub struct KademliaProtocolConfig;
+#[derive(Debug, Clone)]
+pub struct KademliaProtocolConfig {
+    /// Name of the protocol, as negotiated on the wire.
+    protocol_name: Cow<'static, [u8]>,
+}
+
+impl KademliaProtocolConfig {
+    /// Returns the name of the protocol negotiated on the wire.
+    #[inline]
+    pub fn protocol_name(&self) -> &[u8] {
+        &self.protocol_name
+    }
+
+    /// Modifies the name of the protocol negotiated on the wire. Nodes only talk to the nodes
+    /// that use the same name, which allows running a DHT isolated from the public one.
+    #[inline]
+    pub fn with_protocol_name(mut self, name: impl Into<Cow<'static, [u8]>>) -> Self {
+        self.protocol_name = name.into();
+        self
+    }
+}
+
+impl Default for KademliaProtocolConfig {
+    #[inline]
+    fn default() -> Self {
+        KademliaProtocolConfig {
+            protocol_name: Cow::Borrowed(b"/ipfs/kad/1.0.0"),
+        }
+    }
+}
 
 impl UpgradeInfo for KademliaProtocolConfig {
-    type Info = &'static [u8];
+    type Info = Cow<'static, [u8]>;
     type InfoIter = iter::Once<Self::Info>;
 
     #[inline]
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(b"/ipfs/kad/1.0.0")
+        iter::once(self.protocol_name.clone())
     }
 }
 
@@ -250,6 +321,18 @@ pub enum KadRequestMsg {
         /// Known provider for this key.
         provider_peer: KadPeer,
     },
+
+    /// Request to get a value from the dht records.
+    GetValue {
+        /// The key we are searching for.
+        key: Multihash,
+    },
+
+    /// Request to put a value into the dht records.
+    PutValue {
+        /// The record to store.
+        record: Record,
+    },
 }
 
 /// Response that we can send to a peer or that we received from a peer.
@@ -271,6 +354,22 @@ pub enum KadResponseMsg {
         /// Known providers for this key.
         provider_peers: Vec<KadPeer>,
     },
+
+    /// Response to a `GetValue`.
+    GetValue {
+        /// The record found for the key, if any.
+        record: Option<Record>,
+        /// Nodes closest to the key.
+        closer_peers: Vec<KadPeer>,
+    },
+
+    /// Response to a `PutValue`. Echoes the record that was stored.
+    PutValue {
+        /// The key of the record.
+        key: Multihash,
+        /// The value of the record.
+        value: Vec<u8>,
+    },
 }
 
 // Turns a type-safe Kadmelia message into the corresponding raw protobuf message.
@@ -303,6 +402,20 @@ fn req_msg_to_proto(kad_msg: KadRequestMsg) -> protobuf_structs::dht::Message {
             msg.mut_providerPeers().push(provider_peer.into());
             msg
         }
+        KadRequestMsg::GetValue { key } => {
+            let mut msg = protobuf_structs::dht::Message::new();
+            msg.set_field_type(protobuf_structs::dht::Message_MessageType::GET_VALUE);
+            msg.set_clusterLevelRaw(10);
+            msg.set_key(key.into_bytes());
+            msg
+        }
+        KadRequestMsg::PutValue { record } => {
+            let mut msg = protobuf_structs::dht::Message::new();
+            msg.set_field_type(protobuf_structs::dht::Message_MessageType::PUT_VALUE);
+            msg.set_key(record.key.as_bytes().to_vec());
+            msg.set_record(record_to_proto(record));
+            msg
+        }
     }
 }
 
@@ -338,6 +451,25 @@ fn resp_msg_to_proto(kad_msg: KadResponseMsg) -> protobuf_structs::dht::Message
             }
             msg
         }
+        KadResponseMsg::GetValue { record, closer_peers } => {
+            let mut msg = protobuf_structs::dht::Message::new();
+            msg.set_field_type(protobuf_structs::dht::Message_MessageType::GET_VALUE);
+            msg.set_clusterLevelRaw(9);
+            for peer in closer_peers {
+                msg.mut_closerPeers().push(peer.into());
+            }
+            if let Some(record) = record {
+                msg.set_record(record_to_proto(record));
+            }
+            msg
+        }
+        KadResponseMsg::PutValue { key, value } => {
+            let mut msg = protobuf_structs::dht::Message::new();
+            msg.set_field_type(protobuf_structs::dht::Message_MessageType::PUT_VALUE);
+            msg.set_key(key.as_bytes().to_vec());
+            msg.set_record(record_to_proto(Record::new(key, value)));
+            msg
+        }
     }
 }
 
@@ -347,17 +479,14 @@ fn proto_to_req_msg(mut message: protobuf_structs::dht::Message) -> Result<KadRe
         protobuf_structs::dht::Message_MessageType::PING => Ok(KadRequestMsg::Ping),
 
         protobuf_structs::dht::Message_MessageType::PUT_VALUE => {
-            Err(IoError::new(
-                IoErrorKind::InvalidData,
-                "received a PUT_VALUE message, but this is not supported by rust-libp2p yet",
-            ))
+            let record = record_from_proto(message.mut_record())?;
+            Ok(KadRequestMsg::PutValue { record })
         }
 
         protobuf_structs::dht::Message_MessageType::GET_VALUE => {
-            Err(IoError::new(
-                IoErrorKind::InvalidData,
-                "received a GET_VALUE message, but this is not supported by rust-libp2p yet",
-            ))
+            let key = Multihash::from_bytes(message.take_key())
+                .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+            Ok(KadRequestMsg::GetValue { key })
         }
 
         protobuf_structs::dht::Message_MessageType::FIND_NODE => {
@@ -405,10 +534,19 @@ fn proto_to_resp_msg(
         protobuf_structs::dht::Message_MessageType::PING => Ok(KadResponseMsg::Pong),
 
         protobuf_structs::dht::Message_MessageType::GET_VALUE => {
-            Err(IoError::new(
-                IoErrorKind::InvalidData,
-                "received a GET_VALUE message, but this is not supported by rust-libp2p yet",
-            ))
+            let record = if message.has_record() {
+                Some(record_from_proto(message.mut_record())?)
+            } else {
+                None
+            };
+
+            let closer_peers = message
+                .mut_closerPeers()
+                .iter_mut()
+                .filter_map(|peer| KadPeer::from_peer(peer).ok())
+                .collect::<Vec<_>>();
+
+            Ok(KadResponseMsg::GetValue { record, closer_peers })
         }
 
         protobuf_structs::dht::Message_MessageType::FIND_NODE => {
@@ -439,10 +577,12 @@ fn proto_to_resp_msg(
             })
         }
 
-        protobuf_structs::dht::Message_MessageType::PUT_VALUE => Err(IoError::new(
-            IoErrorKind::InvalidData,
-            "received an unexpected PUT_VALUE message",
-        )),
+        protobuf_structs::dht::Message_MessageType::PUT_VALUE => {
+            let key = Multihash::from_bytes(message.take_key())
+                .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+            let value = message.mut_record().take_value();
+            Ok(KadResponseMsg::PutValue { key, value })
+        }
 
         protobuf_structs::dht::Message_MessageType::ADD_PROVIDER => Err(IoError::new(
             IoErrorKind::InvalidData,
@@ -456,6 +596,81 @@ mod tests {
     extern crate libp2p_tcp;
     extern crate tokio;
 
+    use self::tokio::runtime::current_thread::Runtime;
+    use futures::{Future, Stream};
+    use libp2p_core::{upgrade, transport, PeerId, Transport};
+    use multihash::{Hash, Multihash};
+    use protobuf::{self, Message};
+    use record::Record;
+    use super::{KadRequestMsg, KadResponseMsg, KademliaProtocolConfig, proto_to_req_msg};
+    use super::{proto_to_resp_msg, req_msg_to_proto, resp_msg_to_proto};
+
+    #[test]
+    fn record_messages_roundtrip() {
+        let mut record = Record::new(Multihash::random(Hash::SHA2256), vec![4, 5, 6]);
+        record.publisher = Some(PeerId::random());
+        record.signature = Some(vec![7, 8, 9]);
+
+        let requests = vec![
+            KadRequestMsg::GetValue { key: record.key.clone() },
+            KadRequestMsg::PutValue { record: record.clone() },
+        ];
+        for request in requests {
+            let bytes = req_msg_to_proto(request.clone()).write_to_bytes().unwrap();
+            let decoded = proto_to_req_msg(protobuf::parse_from_bytes(&bytes).unwrap()).unwrap();
+            assert_eq!(decoded, request);
+        }
+
+        let responses = vec![
+            KadResponseMsg::GetValue { record: Some(record.clone()), closer_peers: Vec::new() },
+            KadResponseMsg::GetValue { record: None, closer_peers: Vec::new() },
+            KadResponseMsg::PutValue { key: record.key.clone(), value: record.value.clone() },
+        ];
+        for response in responses {
+            let bytes = resp_msg_to_proto(response.clone()).write_to_bytes().unwrap();
+            let decoded = proto_to_resp_msg(protobuf::parse_from_bytes(&bytes).unwrap()).unwrap();
+            assert_eq!(decoded, response);
+        }
+    }
+
+    /// Opens an in-memory connection between a dialer using `dialer_config` and a listener using
+    /// `listener_config`, and returns whether each side successfully negotiated the protocol.
+    fn negotiate(dialer_config: KademliaProtocolConfig, listener_config: KademliaProtocolConfig)
+        -> (bool, bool)
+    {
+        let (dialer, listener) = transport::connector();
+        let (listener, _) = listener.listen_on("/memory".parse().unwrap()).unwrap_or_else(|_| panic!());
+
+        let inbound = listener
+            .into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(conn, _)| conn.unwrap().0)
+            .and_then(move |conn| upgrade::apply_inbound(conn, listener_config).map_err(|_| ()).then(Ok))
+            .map(|result| result.is_ok());
+        let outbound = dialer
+            .dial("/memory".parse().unwrap())
+            .unwrap_or_else(|_| panic!())
+            .and_then(move |conn| upgrade::apply_outbound(conn, dialer_config).map_err(|_| ()).then(Ok))
+            .map(|result| result.is_ok());
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(outbound.join(inbound)).unwrap()
+    }
+
+    #[test]
+    fn same_protocol_name_negotiates() {
+        let config = KademliaProtocolConfig::default().with_protocol_name(&b"/private/kad/1.0.0"[..]);
+        assert_eq!(negotiate(config.clone(), config), (true, true));
+    }
+
+    #[test]
+    fn different_protocol_names_are_isolated() {
+        let public = KademliaProtocolConfig::default();
+        let private = KademliaProtocolConfig::default().with_protocol_name(&b"/private/kad/1.0.0"[..]);
+        assert_eq!(negotiate(public.clone(), private.clone()), (false, false));
+        assert_eq!(negotiate(private, public), (false, false));
+    }
+
     /*// TODO: restore
     use self::libp2p_tcp::TcpConfig;
     use self::tokio::runtime::current_thread::Runtime;
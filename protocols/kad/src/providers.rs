@@ -0,0 +1,147 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Storage of the provider records received from remotes.
+//!
+//! Each provider record expires after a time-to-live, and the number of providers stored for a
+//! single key is capped in order to protect against floods of `ADD_PROVIDER` messages.
+
+use fnv::FnvHashMap;
+use libp2p_core::PeerId;
+use multihash::Multihash;
+use smallvec::SmallVec;
+use std::time::{Duration, Instant};
+
+/// Provider records, indexed by key.
+#[derive(Debug, Clone)]
+pub struct Providers {
+    /// For each key, the providers with the moment their record expires.
+    providers: FnvHashMap<Multihash, SmallVec<[(PeerId, Instant); 8]>>,
+    /// Duration after which a provider record expires.
+    ttl: Duration,
+    /// Maximum number of providers stored for a single key.
+    max_per_key: usize,
+}
+
+impl Providers {
+    /// Creates an empty `Providers`.
+    #[inline]
+    pub fn new(ttl: Duration, max_per_key: usize) -> Self {
+        Providers {
+            providers: Default::default(),
+            ttl,
+            max_per_key,
+        }
+    }
+
+    /// Registers `provider` as a provider of `key`, or refreshes its record if it is already
+    /// known.
+    ///
+    /// Returns `false` if the provider is unknown and the maximum number of providers for this
+    /// key has been reached, in which case the record is dropped.
+    pub fn add(&mut self, key: Multihash, provider: PeerId, now: Instant) -> bool {
+        let expires = now + self.ttl;
+        let list = self.providers.entry(key).or_insert_with(SmallVec::new);
+        list.retain(|(_, e)| *e > now);
+
+        if let Some(entry) = list.iter_mut().find(|(p, _)| *p == provider) {
+            entry.1 = expires;
+            return true;
+        }
+
+        if list.len() >= self.max_per_key {
+            return false;
+        }
+
+        list.push((provider, expires));
+        true
+    }
+
+    /// Returns the providers of `key` whose record hasn't expired.
+    pub fn get<'a>(&'a self, key: &Multihash, now: Instant) -> impl Iterator<Item = &'a PeerId> + 'a {
+        self.providers
+            .get(key)
+            .into_iter()
+            .flat_map(|list| list.iter())
+            .filter(move |(_, expires)| *expires > now)
+            .map(|(provider, _)| provider)
+    }
+
+    /// Removes all the records that have expired.
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.providers.retain(|_, list| {
+            list.retain(|(_, expires)| *expires > now);
+            !list.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::PeerId;
+    use multihash::{Hash, Multihash};
+    use std::time::{Duration, Instant};
+    use super::Providers;
+
+    #[test]
+    fn providers_expire() {
+        let mut providers = Providers::new(Duration::from_secs(10), 20);
+        let key = Multihash::random(Hash::SHA2256);
+        let provider = PeerId::random();
+        let now = Instant::now();
+
+        assert!(providers.add(key.clone(), provider.clone(), now));
+        assert_eq!(providers.get(&key, now).collect::<Vec<_>>(), vec![&provider]);
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(providers.get(&key, later).count(), 0);
+        providers.remove_expired(later);
+        assert!(providers.providers.is_empty());
+    }
+
+    #[test]
+    fn refresh_extends_expiration() {
+        let mut providers = Providers::new(Duration::from_secs(10), 20);
+        let key = Multihash::random(Hash::SHA2256);
+        let provider = PeerId::random();
+        let now = Instant::now();
+
+        providers.add(key.clone(), provider.clone(), now);
+        providers.add(key.clone(), provider.clone(), now + Duration::from_secs(5));
+        assert_eq!(providers.get(&key, now + Duration::from_secs(12)).count(), 1);
+    }
+
+    #[test]
+    fn max_per_key_enforced() {
+        let mut providers = Providers::new(Duration::from_secs(10), 2);
+        let key = Multihash::random(Hash::SHA2256);
+        let now = Instant::now();
+        let first = PeerId::random();
+
+        assert!(providers.add(key.clone(), first.clone(), now));
+        assert!(providers.add(key.clone(), PeerId::random(), now));
+        assert!(!providers.add(key.clone(), PeerId::random(), now));
+        // Known providers can still refresh their record.
+        assert!(providers.add(key.clone(), first, now));
+        // Slots are freed once records expire.
+        assert!(providers.add(key.clone(), PeerId::random(), now + Duration::from_secs(10)));
+    }
+}
@@ -29,7 +29,7 @@ use kbucket::KBucketsPeerId;
 use libp2p_core::PeerId;
 use multihash::Multihash;
 use smallvec::SmallVec;
-use std::time::{Duration, Instant};
+use std::{cmp, time::Duration, time::Instant};
 use tokio_timer::Delay;
 
 /// State of a query iterative process.
@@ -42,20 +42,20 @@ use tokio_timer::Delay;
 /// `FIND_NODE` queries you don't need more than that. However for `FIND_VALUE` and
 /// `GET_PROVIDERS`, you need to extract yourself the value or list of providers from RPC requests
 /// received by remotes as this is not handled by the `QueryState`.
+///
+/// As described in the S/Kademlia paper, the query can iterate over multiple disjoint paths. Each
+/// peer is only ever part of one path, which makes it harder for a malicious node to steer the
+/// whole query towards nodes that it controls. The results of all the paths are merged.
 #[derive(Debug)]
 pub struct QueryState {
     /// Target we're looking for.
     target: QueryTarget,
 
-    /// Stage of the query. See the documentation of `QueryStage`.
-    stage: QueryStage,
-
-    /// Ordered list of the peers closest to the result we're looking for.
-    /// Entries that are `InProgress` shouldn't be removed from the list before they complete.
-    /// Must never contain two entries with the same peer IDs.
-    closest_peers: SmallVec<[(PeerId, QueryPeerState); 32]>,
+    /// The disjoint paths of the query. Always contains at least one element, and a peer can
+    /// never appear in more than one path.
+    paths: SmallVec<[QueryPath; 1]>,
 
-    /// Allowed level of parallelism.
+    /// Allowed level of parallelism, for each path.
     parallelism: usize,
 
     /// Number of results to produce.
@@ -63,6 +63,21 @@ pub struct QueryState {
 
     /// Timeout for each individual RPC query.
     rpc_timeout: Duration,
+
+    /// Timeout for the query as a whole.
+    timeout: Delay,
+}
+
+/// One of the paths of a query.
+#[derive(Debug)]
+struct QueryPath {
+    /// Stage of the path. See the documentation of `QueryStage`.
+    stage: QueryStage,
+
+    /// Ordered list of the peers closest to the result we're looking for.
+    /// Entries that are `InProgress` shouldn't be removed from the list before they complete.
+    /// Must never contain two entries with the same peer IDs.
+    closest_peers: SmallVec<[(PeerId, QueryPeerState); 32]>,
 }
 
 /// Configuration for a query.
@@ -75,7 +90,7 @@ pub struct QueryConfig<TIter> {
     /// target.
     pub known_closest_peers: TIter,
 
-    /// Allowed level of parallelism.
+    /// Allowed level of parallelism, for each path.
     pub parallelism: usize,
 
     /// Number of results to produce.
@@ -83,6 +98,14 @@ pub struct QueryConfig<TIter> {
 
     /// Timeout for each individual RPC query.
     pub rpc_timeout: Duration,
+
+    /// Timeout for the query as a whole. Once it has elapsed, the query is finished with the
+    /// results obtained so far.
+    pub timeout: Duration,
+
+    /// Number of disjoint paths to iterate over. The known closest peers are distributed between
+    /// the paths. A value of 1 performs a regular Kademlia query, and 0 is treated as 1.
+    pub disjoint_paths: usize,
 }
 
 /// Stage of the query.
@@ -106,20 +129,37 @@ impl QueryState {
     ///
     /// You should call `poll()` this function returns in order to know what to do.
     pub fn new(config: QueryConfig<impl IntoIterator<Item = PeerId>>) -> QueryState {
+        let num_paths = cmp::max(config.disjoint_paths, 1);
+        let mut paths = (0..num_paths)
+            .map(|_| QueryPath {
+                stage: QueryStage::Iterating {
+                    no_closer_in_a_row: 0,
+                },
+                closest_peers: SmallVec::new(),
+            })
+            .collect::<SmallVec<[_; 1]>>();
+
+        // The known peers are ordered by distance, so distributing them in turn gives each path
+        // a fair share of the closest ones.
+        let known_closest_peers = config
+            .known_closest_peers
+            .into_iter()
+            .take(config.num_results)
+            .enumerate();
+        for (n, peer_id) in known_closest_peers {
+            let already_known = paths.iter().any(|path| path.contains(&peer_id));
+            if !already_known {
+                paths[n % num_paths].closest_peers.push((peer_id, QueryPeerState::NotContacted));
+            }
+        }
+
         QueryState {
             target: config.target,
-            stage: QueryStage::Iterating {
-                no_closer_in_a_row: 0,
-            },
-            closest_peers: config
-                .known_closest_peers
-                .into_iter()
-                .map(|peer_id| (peer_id, QueryPeerState::NotContacted))
-                .take(config.num_results)
-                .collect(),
+            paths,
             parallelism: config.parallelism,
             num_results: config.num_results,
             rpc_timeout: config.rpc_timeout,
+            timeout: Delay::new(Instant::now() + config.timeout),
         }
     }
 
@@ -132,6 +172,9 @@ impl QueryState {
     /// After `poll()` returned `SendRpc`, this method should be called when the node sends back
     /// the result of the query.
     ///
+    /// The closer peers are added to the path the node belongs to, except for the ones that
+    /// already are part of another path.
+    ///
     /// Note that if this query is a `FindValue` query and a node returns a record, feel free to
     /// immediately drop the query altogether and use the record.
     ///
@@ -141,8 +184,22 @@ impl QueryState {
         result_source: &PeerId,
         closer_peers: impl IntoIterator<Item = PeerId>,
     ) {
+        let path_index = match self.paths.iter().position(|path| path.contains(result_source)) {
+            Some(index) => index,
+            None => return,
+        };
+
+        // Peers that are already part of a path are ignored, so that paths stay disjoint.
+        let closer_peers = closer_peers
+            .into_iter()
+            .filter(|peer_id| !self.paths.iter().any(|path| path.contains(peer_id)))
+            .collect::<SmallVec<[_; 32]>>();
+
+        let target = &self.target;
+        let path = &mut self.paths[path_index];
+
         // Mark the peer as succeeded.
-        for (peer_id, state) in self.closest_peers.iter_mut() {
+        for (peer_id, state) in path.closest_peers.iter_mut() {
             if peer_id == result_source {
                 if let state @ QueryPeerState::InProgress(_) = state {
                     *state = QueryPeerState::Succeeded;
@@ -153,43 +210,43 @@ impl QueryState {
         // Add the entries in `closest_peers`.
         if let QueryStage::Iterating {
             ref mut no_closer_in_a_row,
-        } = self.stage
+        } = path.stage
         {
             // We increment now, and reset to 0 if we find a closer node.
             *no_closer_in_a_row += 1;
 
             for elem_to_add in closer_peers {
-                let target = &self.target;
-                let insert_pos = self.closest_peers.iter().position(|(id, _)| {
+                if path.closest_peers.iter().any(|(id, _)| id == &elem_to_add) {
+                    continue;
+                }
+
+                let insert_pos = path.closest_peers.iter().position(|(id, _)| {
                     let a = target.as_hash().distance_with(id.as_ref());
                     let b = target.as_hash().distance_with(elem_to_add.as_ref());
                     a >= b
                 });
 
                 if let Some(insert_pos) = insert_pos {
-                    // Make sure we don't insert duplicates.
-                    if self.closest_peers[insert_pos].0 != elem_to_add {
-                        if insert_pos == 0 {
-                            *no_closer_in_a_row = 0;
-                        }
-                        self.closest_peers
-                            .insert(insert_pos, (elem_to_add, QueryPeerState::NotContacted));
+                    if insert_pos == 0 {
+                        *no_closer_in_a_row = 0;
                     }
-                } else if self.closest_peers.len() < self.num_results {
-                    self.closest_peers
+                    path.closest_peers
+                        .insert(insert_pos, (elem_to_add, QueryPeerState::NotContacted));
+                } else if path.closest_peers.len() < self.num_results {
+                    path.closest_peers
                         .push((elem_to_add, QueryPeerState::NotContacted));
                 }
             }
         }
 
         // Handle if `no_closer_in_a_row` is too high.
-        let freeze = if let QueryStage::Iterating { no_closer_in_a_row } = self.stage {
+        let freeze = if let QueryStage::Iterating { no_closer_in_a_row } = path.stage {
             no_closer_in_a_row >= self.parallelism
         } else {
             false
         };
         if freeze {
-            self.stage = QueryStage::Frozen;
+            path.stage = QueryStage::Frozen;
         }
     }
 
@@ -202,8 +259,9 @@ impl QueryState {
     /// After this function returns, you should call `poll()` again.
     pub fn inject_rpc_error(&mut self, id: &PeerId) {
         let state = self
-            .closest_peers
+            .paths
             .iter_mut()
+            .flat_map(|path| path.closest_peers.iter_mut())
             .filter_map(
                 |(peer_id, state)| {
                     if peer_id == id {
@@ -226,9 +284,75 @@ impl QueryState {
 
     /// Polls this individual query.
     pub fn poll(&mut self) -> Async<QueryStatePollOut> {
+        // The query is finished, whatever its state, once it has timed out. If the timer fails,
+        // the query simply never times out.
+        match self.timeout.poll() {
+            Ok(Async::Ready(_)) => return Async::Ready(QueryStatePollOut::Finished),
+            Ok(Async::NotReady) | Err(_) => {},
+        }
+
+        // The query is finished once all of its paths are finished.
+        let mut all_finished = true;
+
+        for path in self.paths.iter_mut() {
+            match path.poll(&self.target, self.parallelism, self.num_results, self.rpc_timeout) {
+                Async::Ready(QueryStatePollOut::Finished) => {},
+                Async::NotReady => all_finished = false,
+                ready => return ready,
+            }
+        }
+
+        if all_finished {
+            Async::Ready(QueryStatePollOut::Finished)
+        } else {
+            Async::NotReady
+        }
+    }
+
+    /// Consumes the query and returns the known closest peers, merged from all the paths.
+    ///
+    /// > **Note**: This can be called at any time, but you normally only do that once the query
+    /// >           is finished.
+    pub fn into_closest_peers(self) -> impl Iterator<Item = PeerId> {
+        let target = self.target;
+        let mut peers = self.paths
+            .into_iter()
+            .flat_map(|path| path.closest_peers.into_iter())
+            .filter_map(|(peer_id, state)| {
+                if let QueryPeerState::Succeeded = state {
+                    Some(peer_id)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| {
+            let a = target.as_hash().distance_with(a.as_ref());
+            let b = target.as_hash().distance_with(b.as_ref());
+            b.cmp(&a)
+        });
+        peers.into_iter().take(self.num_results)
+    }
+}
+
+impl QueryPath {
+    /// Returns true if the peer is part of this path.
+    #[inline]
+    fn contains(&self, peer_id: &PeerId) -> bool {
+        self.closest_peers.iter().any(|(id, _)| id == peer_id)
+    }
+
+    /// Polls this path. Returns `Finished` if the path can't make any more progress.
+    fn poll<'a>(
+        &'a mut self,
+        target: &'a QueryTarget,
+        parallelism: usize,
+        num_results: usize,
+        rpc_timeout: Duration,
+    ) -> Async<QueryStatePollOut<'a>> {
         // While iterating over peers, count the number of queries currently being processed.
         // This is used to not go over the limit of parallel requests.
-        // If this is still 0 at the end of the function, that means the query is finished.
+        // If this is still 0 at the end of the function, that means the path is finished.
         let mut active_counter = 0;
 
         // While iterating over peers, count the number of queries in a row (from closer to further
@@ -236,9 +360,6 @@ impl QueryState {
         // Contains `None` if the chain is broken.
         let mut succeeded_counter = Some(0);
 
-        // Extract `self.num_results` to avoid borrowing errors with closures.
-        let num_results = self.num_results;
-
         for &mut (ref peer_id, ref mut state) in self.closest_peers.iter_mut() {
             // Start by "killing" the query if it timed out.
             {
@@ -268,7 +389,7 @@ impl QueryState {
                 _ => (),
             };
 
-            // We have enough results; the query is done.
+            // We have enough results; the path is done.
             if succeeded_counter
                 .as_ref()
                 .map(|&c| c >= num_results)
@@ -280,21 +401,22 @@ impl QueryState {
             // Dial the node if it needs dialing.
             let need_connect = match state {
                 QueryPeerState::NotContacted => match self.stage {
-                    QueryStage::Iterating { .. } => active_counter < self.parallelism,
-                    QueryStage::Frozen => match self.target {
+                    QueryStage::Iterating { .. } => active_counter < parallelism,
+                    QueryStage::Frozen => match target {
                         QueryTarget::FindPeer(_) => true,
                         QueryTarget::GetProviders(_) => false,
+                        QueryTarget::GetValue(_) => false,
                     },
                 },
                 _ => false,
             };
 
             if need_connect {
-                let delay = Delay::new(Instant::now() + self.rpc_timeout);
+                let delay = Delay::new(Instant::now() + rpc_timeout);
                 *state = QueryPeerState::InProgress(delay);
                 return Async::Ready(QueryStatePollOut::SendRpc {
                     peer_id,
-                    query_target: &self.target,
+                    query_target: target,
                 });
             }
         }
@@ -307,23 +429,6 @@ impl QueryState {
             Async::Ready(QueryStatePollOut::Finished)
         }
     }
-
-    /// Consumes the query and returns the known closest peers.
-    ///
-    /// > **Note**: This can be called at any time, but you normally only do that once the query
-    /// >           is finished.
-    pub fn into_closest_peers(self) -> impl Iterator<Item = PeerId> {
-        self.closest_peers
-            .into_iter()
-            .filter_map(|(peer_id, state)| {
-                if let QueryPeerState::Succeeded = state {
-                    Some(peer_id)
-                } else {
-                    None
-                }
-            })
-            .take(self.num_results)
-    }
 }
 
 /// Outcome of polling a query.
@@ -370,6 +475,8 @@ pub enum QueryTarget {
     FindPeer(PeerId),
     /// Find the peers that provide a certain value.
     GetProviders(Multihash),
+    /// Find the record stored under a certain key.
+    GetValue(Multihash),
 }
 
 impl QueryTarget {
@@ -390,6 +497,10 @@ impl QueryTarget {
                 key,
                 user_data,
             },
+            QueryTarget::GetValue(key) => KademliaHandlerIn::GetValueReq {
+                key,
+                user_data,
+            },
         }
     }
 
@@ -398,6 +509,7 @@ impl QueryTarget {
         match self {
             QueryTarget::FindPeer(peer) => peer.as_ref(),
             QueryTarget::GetProviders(key) => key,
+            QueryTarget::GetValue(key) => key,
         }
     }
 }
@@ -434,6 +546,8 @@ mod tests {
             parallelism: 3,
             num_results: 100,
             rpc_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            disjoint_paths: 1,
         });
 
         tokio::run(futures::future::poll_fn(move || {
@@ -458,6 +572,8 @@ mod tests {
             parallelism: 3,
             num_results: 100,
             rpc_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            disjoint_paths: 1,
         })));
 
         // Let's do a first polling round to obtain the `SendRpc` request.
@@ -501,6 +617,8 @@ mod tests {
             parallelism: 3,
             num_results: 100,
             rpc_timeout: Duration::from_millis(100),
+            timeout: Duration::from_secs(60),
+            disjoint_paths: 1,
         })));
 
         // Let's do a first polling round to obtain the `SendRpc` request.
@@ -546,4 +664,61 @@ mod tests {
             }
         }));
     }
+
+    #[test]
+    fn query_timeout_works() {
+        let random_id = PeerId::random();
+
+        let mut query = QueryState::new(QueryConfig {
+            target: QueryTarget::FindPeer(PeerId::random()),
+            known_closest_peers: iter::once(random_id.clone()),
+            parallelism: 3,
+            num_results: 100,
+            rpc_timeout: Duration::from_secs(10),
+            timeout: Duration::from_millis(100),
+            disjoint_paths: 1,
+        });
+
+        // The query must finish once it has timed out, even though the RPC is still in progress.
+        let mut rpc_sent = false;
+        tokio::run(futures::future::poll_fn(move || {
+            loop {
+                match try_ready!(Ok(query.poll())) {
+                    QueryStatePollOut::SendRpc { peer_id, .. } if peer_id == &random_id && !rpc_sent => {
+                        rpc_sent = true;
+                    }
+                    QueryStatePollOut::Finished if rpc_sent => return Ok(Async::Ready(())),
+                    _ => panic!(),
+                }
+            }
+        }));
+    }
+
+    #[test]
+    fn disjoint_paths_never_share_peers() {
+        let known_peers = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+
+        let mut query = QueryState::new(QueryConfig {
+            target: QueryTarget::FindPeer(PeerId::random()),
+            known_closest_peers: known_peers.clone(),
+            parallelism: 3,
+            num_results: 100,
+            rpc_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            disjoint_paths: 2,
+        });
+
+        // The known peers are distributed between the paths.
+        assert_eq!(query.paths.len(), 2);
+        assert!(query.paths[0].contains(&known_peers[0]));
+        assert!(query.paths[1].contains(&known_peers[1]));
+        assert!(!query.paths[0].contains(&known_peers[1]));
+
+        // A peer of the second path returned by a peer of the first path isn't added to it.
+        let new_peer = PeerId::random();
+        query.inject_rpc_result(&known_peers[0], vec![known_peers[1].clone(), new_peer.clone()]);
+        assert!(!query.paths[0].contains(&known_peers[1]));
+        assert!(query.paths[0].contains(&new_peer));
+        assert!(!query.paths[1].contains(&new_peer));
+    }
 }
@@ -0,0 +1,240 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Records stored in the DHT with `PUT_VALUE` and retrieved with `GET_VALUE`.
+//!
+//! The `RecordStore` trait abstracts over the storage of the records that remotes asked the local
+//! node to store. The records that the local node publishes itself are kept separately by the
+//! `Kademlia` behaviour. The `MemoryRecordStore` is a simple implementation that keeps everything
+//! in memory.
+
+use fnv::FnvHashMap;
+use libp2p_core::PeerId;
+use multihash::Multihash;
+use std::borrow::Cow;
+use std::{error, fmt};
+use std::time::Instant;
+
+/// A record stored in the DHT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Key of the record.
+    pub key: Multihash,
+    /// Value of the record.
+    pub value: Vec<u8>,
+    /// The peer that originally published the record, if known.
+    pub publisher: Option<PeerId>,
+    /// Signature of the publisher over the result of `signing_payload()`, if the record is
+    /// signed.
+    pub signature: Option<Vec<u8>>,
+    /// When the record expires and must no longer be returned. `None` if the record never
+    /// expires, which is the case for the records that the local node publishes itself.
+    pub expires: Option<Instant>,
+}
+
+impl Record {
+    /// Builds a new record with no publisher, no signature and no expiration.
+    #[inline]
+    pub fn new(key: Multihash, value: Vec<u8>) -> Self {
+        Record {
+            key,
+            value,
+            publisher: None,
+            signature: None,
+            expires: None,
+        }
+    }
+
+    /// Returns the bytes that the publisher signs: the key, followed with the value and the
+    /// publisher's peer ID if any.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = self.key.as_bytes().to_vec();
+        payload.extend_from_slice(&self.value);
+        if let Some(publisher) = &self.publisher {
+            payload.extend_from_slice(publisher.as_bytes());
+        }
+        payload
+    }
+
+    /// Returns true if the record has expired at the given time.
+    #[inline]
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.expires.map(|t| now >= t).unwrap_or(false)
+    }
+}
+
+/// Storage of the records held by the local node.
+pub trait RecordStore {
+    /// Returns the record stored for the given key, if any.
+    fn get(&self, key: &Multihash) -> Option<Cow<'_, Record>>;
+
+    /// Stores a record, replacing any existing record with the same key.
+    fn put(&mut self, record: Record) -> Result<(), RecordStorageError>;
+
+    /// Removes the record with the given key, if any.
+    fn remove(&mut self, key: &Multihash);
+
+    /// Returns an iterator to all the records in the store.
+    fn records<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, Record>> + 'a>;
+
+    /// Removes all the records that have expired at the given time.
+    fn remove_expired(&mut self, now: Instant) {
+        let expired = self.records()
+            .filter(|r| r.is_expired(now))
+            .map(|r| r.key.clone())
+            .collect::<Vec<_>>();
+        for key in expired {
+            self.remove(&key);
+        }
+    }
+}
+
+/// Error that can happen when storing a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordStorageError {
+    /// The store is full and doesn't accept new records.
+    AtCapacity,
+    /// The value of the record is larger than what the store accepts.
+    ValueTooLarge,
+}
+
+impl fmt::Display for RecordStorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordStorageError::AtCapacity => write!(f, "The record store is full"),
+            RecordStorageError::ValueTooLarge => write!(f, "The value of the record is too large"),
+        }
+    }
+}
+
+impl error::Error for RecordStorageError {}
+
+/// In-memory implementation of `RecordStore`.
+#[derive(Debug, Clone)]
+pub struct MemoryRecordStore {
+    /// The records, indexed by their key.
+    records: FnvHashMap<Multihash, Record>,
+    /// Maximum number of records.
+    max_records: usize,
+    /// Maximum size in bytes of the value of a record.
+    max_value_bytes: usize,
+}
+
+impl MemoryRecordStore {
+    /// Creates a store that holds at most `max_records` records, each with a value of at most
+    /// `max_value_bytes` bytes.
+    #[inline]
+    pub fn new(max_records: usize, max_value_bytes: usize) -> Self {
+        MemoryRecordStore {
+            records: Default::default(),
+            max_records,
+            max_value_bytes,
+        }
+    }
+}
+
+impl Default for MemoryRecordStore {
+    #[inline]
+    fn default() -> Self {
+        MemoryRecordStore::new(1024, 64 * 1024)
+    }
+}
+
+impl RecordStore for MemoryRecordStore {
+    fn get(&self, key: &Multihash) -> Option<Cow<'_, Record>> {
+        self.records.get(key).map(Cow::Borrowed)
+    }
+
+    fn put(&mut self, record: Record) -> Result<(), RecordStorageError> {
+        if record.value.len() > self.max_value_bytes {
+            return Err(RecordStorageError::ValueTooLarge);
+        }
+
+        if self.records.len() >= self.max_records && !self.records.contains_key(&record.key) {
+            return Err(RecordStorageError::AtCapacity);
+        }
+
+        self.records.insert(record.key.clone(), record);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &Multihash) {
+        self.records.remove(key);
+    }
+
+    fn records<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, Record>> + 'a> {
+        Box::new(self.records.values().map(Cow::Borrowed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use multihash::{Hash, Multihash};
+    use std::time::{Duration, Instant};
+    use super::{MemoryRecordStore, Record, RecordStorageError, RecordStore};
+
+    #[test]
+    fn put_get_remove() {
+        let mut store = MemoryRecordStore::default();
+        let key = Multihash::random(Hash::SHA2256);
+        let record = Record::new(key.clone(), vec![1, 2, 3]);
+
+        assert!(store.get(&key).is_none());
+        store.put(record.clone()).unwrap();
+        assert_eq!(store.get(&key).unwrap().into_owned(), record);
+        store.remove(&key);
+        assert!(store.get(&key).is_none());
+    }
+
+    #[test]
+    fn limits_enforced() {
+        let mut store = MemoryRecordStore::new(1, 2);
+        let key1 = Multihash::random(Hash::SHA2256);
+        let key2 = Multihash::random(Hash::SHA2256);
+
+        assert_eq!(store.put(Record::new(key1.clone(), vec![0; 3])), Err(RecordStorageError::ValueTooLarge));
+        assert!(store.put(Record::new(key1.clone(), vec![0; 2])).is_ok());
+        assert_eq!(store.put(Record::new(key2, vec![0])), Err(RecordStorageError::AtCapacity));
+        // Replacing an existing record is always allowed.
+        assert!(store.put(Record::new(key1, vec![1])).is_ok());
+    }
+
+    #[test]
+    fn expired_records_removed() {
+        let mut store = MemoryRecordStore::default();
+        let now = Instant::now();
+
+        let mut expired = Record::new(Multihash::random(Hash::SHA2256), vec![1]);
+        expired.expires = Some(now);
+        let mut alive = Record::new(Multihash::random(Hash::SHA2256), vec![2]);
+        alive.expires = Some(now + Duration::from_secs(60));
+        let permanent = Record::new(Multihash::random(Hash::SHA2256), vec![3]);
+
+        store.put(expired.clone()).unwrap();
+        store.put(alive.clone()).unwrap();
+        store.put(permanent.clone()).unwrap();
+
+        store.remove_expired(now);
+        assert!(store.get(&expired.key).is_none());
+        assert!(store.get(&alive.key).is_some());
+        assert!(store.get(&permanent.key).is_some());
+    }
+}
@@ -19,7 +19,8 @@
 // DEALINGS IN THE SOFTWARE.
 
 use kbucket::KBucketsPeerId;
-use libp2p_core::{Multiaddr, PeerId, topology::MemoryTopology, topology::Topology};
+use libp2p_core::{Multiaddr, PeerId, topology::AddressSource, topology::MemoryTopology};
+use libp2p_core::topology::{PersistentTopology, Topology};
 use multihash::Multihash;
 use protocol::KadConnectionType;
 use std::vec;
@@ -29,9 +30,6 @@ pub trait KademliaTopology: Topology {
     /// Iterator returned by `closest_peers`.
     type ClosestPeersIter: Iterator<Item = PeerId>;
 
-    /// Iterator returned by `get_providers`.
-    type GetProvidersIter: Iterator<Item = PeerId>;
-
     /// Adds an address discovered through Kademlia to the topology.
     ///
     /// > **Note**: Keep in mind that `peer` can be the local peer.
@@ -45,29 +43,15 @@ pub trait KademliaTopology: Topology {
     ///
     /// > **Note**: The results should include the local node.
     fn closest_peers(&mut self, target: &Multihash, max: usize) -> Self::ClosestPeersIter;
-
-    /// Registers the given peer as provider of the resource with the given ID.
-    ///
-    /// > **Note**: There is no `remove_provider` method. Implementations must include a
-    /// >           time-to-live system so that entries disappear after a while.
-    // TODO: specify the TTL? it has to match the timeout in the behaviour somehow, but this could
-    //       also be handled by the user
-    fn add_provider(&mut self, key: Multihash, peer_id: PeerId);
-
-    /// Returns the list of providers that have been registered with `add_provider`.
-    ///
-    /// If the local node is a provider for `key`, our local peer ID should also be returned.
-    fn get_providers(&mut self, key: &Multihash) -> Self::GetProvidersIter;
 }
 
 // TODO: stupid idea to implement on `MemoryTopology`
 impl KademliaTopology for MemoryTopology {
     type ClosestPeersIter = vec::IntoIter<PeerId>;
-    type GetProvidersIter = vec::IntoIter<PeerId>;
 
     fn add_kad_discovered_address(&mut self, peer: PeerId, addr: Multiaddr, _: KadConnectionType) {
         if &peer != self.local_peer_id() {
-            self.add_address(peer, addr)
+            self.add_address(peer, addr, AddressSource::Kademlia)
         }
     }
 
@@ -76,12 +60,20 @@ impl KademliaTopology for MemoryTopology {
         list.sort_by(|a, b| target.distance_with(b.as_ref()).cmp(&target.distance_with(a.as_ref())));
         list.into_iter()
     }
+}
+
+impl KademliaTopology for PersistentTopology {
+    type ClosestPeersIter = vec::IntoIter<PeerId>;
 
-    fn add_provider(&mut self, _: Multihash, _: PeerId) {
-        unimplemented!()
+    fn add_kad_discovered_address(&mut self, peer: PeerId, addr: Multiaddr, _: KadConnectionType) {
+        if &peer != self.local_peer_id() {
+            self.add_address(peer, addr, AddressSource::Kademlia)
+        }
     }
 
-    fn get_providers(&mut self, _: &Multihash) -> Self::GetProvidersIter {
-        unimplemented!()
+    fn closest_peers(&mut self, target: &Multihash, _: usize) -> Self::ClosestPeersIter {
+        let mut list = self.peers().cloned().collect::<Vec<_>>();
+        list.sort_by(|a, b| target.distance_with(b.as_ref()).cmp(&target.distance_with(a.as_ref())));
+        list.into_iter()
     }
 }
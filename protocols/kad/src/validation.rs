@@ -0,0 +1,237 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Validation of the records received from remotes.
+//!
+//! Anyone can store any value in the DHT, therefore the records obtained from remotes must be
+//! verified before they are stored or returned to the user. A `RecordValidator` decides whether
+//! a record is valid, and which record to pick when a `GET_VALUE` query finds several of them.
+//!
+//! Two validators are provided:
+//!
+//! - `PublicKeyValidator` for public key records, known as `/pk/<multihash>` records in other
+//!   implementations. Their key is the peer ID of a node and their value is the public key of
+//!   that node.
+//! - `SignedRecordValidator` for records signed by their publisher.
+
+use fnv::FnvHashMap;
+use libp2p_core::{PeerId, PublicKey};
+use record::Record;
+use std::{error, fmt};
+
+/// Verifies the records received from remotes.
+pub trait RecordValidator {
+    /// Returns `Ok` if the record is valid. Invalid records are neither stored nor returned to
+    /// the user.
+    fn validate(&self, record: &Record) -> Result<(), RecordValidationError>;
+
+    /// Selects the best among valid records that have the same key, and returns its index in
+    /// `records`. The list is never empty.
+    ///
+    /// The default implementation selects the first record.
+    fn select(&self, records: &[Record]) -> usize {
+        let _ = records;
+        0
+    }
+}
+
+/// Validator that accepts all the records. This is the default.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AcceptAllValidator;
+
+impl RecordValidator for AcceptAllValidator {
+    #[inline]
+    fn validate(&self, _: &Record) -> Result<(), RecordValidationError> {
+        Ok(())
+    }
+}
+
+/// Validator for public key records.
+///
+/// A record is only valid if its key is the multihash of a peer ID and its value is the
+/// protobuf encoding of the public key of that peer.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PublicKeyValidator;
+
+impl RecordValidator for PublicKeyValidator {
+    fn validate(&self, record: &Record) -> Result<(), RecordValidationError> {
+        let public_key = PublicKey::from_protobuf_encoding(&record.value)
+            .map_err(|_| RecordValidationError::InvalidPublicKey)?;
+        let peer_id = PeerId::from_multihash(record.key.clone())
+            .map_err(|_| RecordValidationError::PublicKeyMismatch)?;
+
+        if peer_id.is_public_key(&public_key) == Some(true) {
+            Ok(())
+        } else {
+            Err(RecordValidationError::PublicKeyMismatch)
+        }
+    }
+}
+
+/// Validator for records signed by their publisher.
+///
+/// A record is only valid if it has a publisher whose public key has been registered with
+/// `add_public_key`, and a signature of `Record::signing_payload()` that `verify` accepts.
+///
+/// The signature scheme depends on the type of the public key, therefore checking a signature
+/// is left to the `verify` function, which is passed the public key, the payload and the
+/// signature.
+pub struct SignedRecordValidator<TVerify> {
+    /// Public keys of the publishers we know about.
+    public_keys: FnvHashMap<PeerId, PublicKey>,
+    /// Checks a signature.
+    verify: TVerify,
+}
+
+impl<TVerify> SignedRecordValidator<TVerify>
+where
+    TVerify: Fn(&PublicKey, &[u8], &[u8]) -> bool,
+{
+    /// Creates a validator that doesn't know any public key yet.
+    #[inline]
+    pub fn new(verify: TVerify) -> Self {
+        SignedRecordValidator {
+            public_keys: Default::default(),
+            verify,
+        }
+    }
+
+    /// Registers the public key of a publisher. The records published by the peer ID that
+    /// corresponds to this key are checked against it.
+    pub fn add_public_key(&mut self, public_key: PublicKey) {
+        let peer_id = public_key.clone().into_peer_id();
+        self.public_keys.insert(peer_id, public_key);
+    }
+}
+
+impl<TVerify> RecordValidator for SignedRecordValidator<TVerify>
+where
+    TVerify: Fn(&PublicKey, &[u8], &[u8]) -> bool,
+{
+    fn validate(&self, record: &Record) -> Result<(), RecordValidationError> {
+        let (publisher, signature) = match (&record.publisher, &record.signature) {
+            (Some(publisher), Some(signature)) => (publisher, signature),
+            _ => return Err(RecordValidationError::NotSigned),
+        };
+
+        let public_key = self.public_keys
+            .get(publisher)
+            .ok_or(RecordValidationError::UnknownPublisher)?;
+
+        if (self.verify)(public_key, &record.signing_payload(), signature) {
+            Ok(())
+        } else {
+            Err(RecordValidationError::InvalidSignature)
+        }
+    }
+}
+
+impl<TVerify> fmt::Debug for SignedRecordValidator<TVerify> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SignedRecordValidator")
+            .field("public_keys", &self.public_keys)
+            .finish()
+    }
+}
+
+/// Reason why a record is invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordValidationError {
+    /// The value of a public key record isn't a valid public key.
+    InvalidPublicKey,
+    /// The public key in a public key record doesn't correspond to the key of the record.
+    PublicKeyMismatch,
+    /// The record doesn't have a publisher or a signature.
+    NotSigned,
+    /// The public key of the publisher of the record isn't known.
+    UnknownPublisher,
+    /// The signature of the record is invalid.
+    InvalidSignature,
+}
+
+impl fmt::Display for RecordValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordValidationError::InvalidPublicKey => write!(f, "Invalid public key in the record"),
+            RecordValidationError::PublicKeyMismatch => {
+                write!(f, "The public key doesn't match the key of the record")
+            },
+            RecordValidationError::NotSigned => write!(f, "The record isn't signed"),
+            RecordValidationError::UnknownPublisher => {
+                write!(f, "The public key of the publisher is unknown")
+            },
+            RecordValidationError::InvalidSignature => write!(f, "Invalid signature"),
+        }
+    }
+}
+
+impl error::Error for RecordValidationError {}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::{PeerId, PublicKey};
+    use multihash::{Hash, Multihash};
+    use rand;
+    use record::Record;
+    use super::{PublicKeyValidator, RecordValidationError, RecordValidator, SignedRecordValidator};
+
+    #[test]
+    fn public_key_records() {
+        let public_key = PublicKey::Ed25519((0..32).map(|_| rand::random()).collect());
+        let peer_id = public_key.clone().into_peer_id();
+
+        let valid = Record::new(peer_id.as_ref().clone(), public_key.clone().into_protobuf_encoding());
+        assert_eq!(PublicKeyValidator.validate(&valid), Ok(()));
+
+        let wrong_key = Record::new(PeerId::random().as_ref().clone(), public_key.into_protobuf_encoding());
+        assert_eq!(PublicKeyValidator.validate(&wrong_key), Err(RecordValidationError::PublicKeyMismatch));
+
+        let garbage = Record::new(peer_id.as_ref().clone(), vec![1, 2, 3]);
+        assert_eq!(PublicKeyValidator.validate(&garbage), Err(RecordValidationError::InvalidPublicKey));
+    }
+
+    #[test]
+    fn signed_records() {
+        // A fake signature scheme, where the signature is the payload followed with the key.
+        let verify = |key: &PublicKey, payload: &[u8], signature: &[u8]| {
+            let key = key.clone().into_protobuf_encoding();
+            signature.len() == payload.len() + key.len()
+                && &signature[..payload.len()] == payload
+                && &signature[payload.len()..] == &key[..]
+        };
+        let mut validator = SignedRecordValidator::new(verify);
+
+        let public_key = PublicKey::Ed25519((0..32).map(|_| rand::random()).collect());
+        let mut record = Record::new(Multihash::random(Hash::SHA2256), vec![1, 2, 3]);
+        assert_eq!(validator.validate(&record), Err(RecordValidationError::NotSigned));
+
+        record.publisher = Some(public_key.clone().into_peer_id());
+        let mut signature = record.signing_payload();
+        signature.extend(public_key.clone().into_protobuf_encoding());
+        record.signature = Some(signature);
+        assert_eq!(validator.validate(&record), Err(RecordValidationError::UnknownPublisher));
+
+        validator.add_public_key(public_key);
+        assert_eq!(validator.validate(&record), Ok(()));
+
+        record.value = vec![4, 5, 6];
+        assert_eq!(validator.validate(&record), Err(RecordValidationError::InvalidSignature));
+    }
+}
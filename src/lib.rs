@@ -145,6 +145,7 @@ pub extern crate libp2p_dns as dns;
 pub extern crate libp2p_identify as identify;
 pub extern crate libp2p_kad as kad;
 pub extern crate libp2p_floodsub as floodsub;
+pub extern crate libp2p_gossipsub as gossipsub;
 pub extern crate libp2p_mplex as mplex;
 #[cfg(not(any(target_os = "emscripten", target_os = "unknown")))]
 pub extern crate libp2p_mdns as mdns;